@@ -0,0 +1,176 @@
+//! Other readings of scalar values than the unsigned integers the wire format gives, shown next to
+//! them since without a schema there's no telling which was meant, and the other readings of
+//! length-prefixed data than the one the heuristics settled on.
+
+use nom::combinator::all_consuming;
+use nom::{AsBytes, InputLength};
+use protocat::{check_groups, protobuf, Span, WireType};
+
+use crate::text::{self, StringEncoding};
+use crate::{packed_values, plural, Error, Interpretation, Options};
+
+/// Decodes a zigzag varint, as sint32 and sint64 fields are encoded.
+pub fn zigzag(v: u64) -> i64 {
+    (v >> 1) as i64 ^ -((v & 1) as i64)
+}
+
+/// The other readings of a varint shown with `--show-alternates`, as notes like `sint: -2`.
+pub fn varint(v: u64, show: bool) -> Vec<String> {
+    let mut notes = Vec::new();
+    // Negative int32 and int64 values are sign-extended to ten bytes, so either way they are
+    // negative as an int64.
+    if show && (v as i64) < 0 {
+        notes.push(format!("int: {}", v as i64));
+    }
+    // Zero is zero either way.
+    if show && v != 0 {
+        notes.push(format!("sint: {}", zigzag(v)));
+    }
+    notes
+}
+
+/// When to show fixed32 and fixed64 values as floats and doubles too, for `--floats`.
+#[derive(Copy, Clone, Debug, PartialEq, clap::ValueEnum)]
+pub enum FloatDisplay {
+    /// Only when the bits look like a number someone would store, rather than a hash, ID or bit set.
+    Plausible,
+    /// Always.
+    Always,
+}
+
+/// Whether a float is in the range where real measurements, prices and coordinates live. Zero is
+/// left out because it is so much more often an integer, as are subnormals, which is what small
+/// integers look like as floats.
+fn plausible(value: f64, max: f64) -> bool {
+    value.is_normal() && (1e-9..max).contains(&value.abs())
+}
+
+/// Gives a float's shortest round-tripping digits a decimal point, so it can't be mistaken for an
+/// integer. Infinities and NaN are left as they are.
+fn with_point(digits: String) -> String {
+    if digits.ends_with(|c: char| c.is_ascii_digit()) && !digits.contains('.') { digits + ".0" } else { digits }
+}
+
+/// The float a fixed32 value reads as, if it is to be shown.
+pub fn float(v: u32, display: Option<FloatDisplay>) -> Option<String> {
+    let value = f32::from_bits(v);
+    match display? {
+        FloatDisplay::Plausible if !plausible(value as f64, 1e9) => None,
+        _ => Some(with_point(value.to_string())),
+    }
+}
+
+/// The double a fixed64 value reads as, if it is to be shown.
+pub fn double(v: u64, display: Option<FloatDisplay>) -> Option<String> {
+    let value = f64::from_bits(v);
+    match display? {
+        FloatDisplay::Plausible if !plausible(value, 1e15) => None,
+        _ => Some(with_point(value.to_string())),
+    }
+}
+
+/// How much of a string to show in a note about reading data as one.
+const PREVIEW_CHARS: usize = 24;
+
+/// How many of a packed list's values to show in a note about reading data as one.
+const PREVIEW_VALUES: usize = 4;
+
+/// Readings of length-prefixed data less likely than this, as a fraction, aren't worth mentioning.
+const MIN_CONFIDENCE: f64 = 0.05;
+
+/// One way of reading length-prefixed data, with a rough weight for how likely it is to be right.
+struct Reading {
+    kind: &'static str,
+    description: String,
+    weight: f64,
+}
+
+fn preview_string(s: &str) -> String {
+    if s.chars().count() <= PREVIEW_CHARS {
+        return text::quote(s);
+    }
+    text::quote(&s.chars().take(PREVIEW_CHARS).collect::<String>()) + "..."
+}
+
+fn preview_packed(values: &[u64], wire_type: WireType) -> String {
+    let shown = &values[..values.len().min(PREVIEW_VALUES)];
+    let list = shown.iter().map(u64::to_string).collect::<Vec<_>>().join(", ");
+    let more = if values.len() > shown.len() { ", ..." } else { "" };
+    match wire_type {
+        WireType::Int64 => format!("packed fixed64 [{}{}]", list, more),
+        WireType::Int32 => format!("packed fixed32 [{}{}]", list, more),
+        _ => format!("packed varints [{}{}]", list, more),
+    }
+}
+
+fn packed_kind(wire_type: WireType) -> &'static str {
+    match wire_type {
+        WireType::Int64 => "fixed64",
+        WireType::Int32 => "fixed32",
+        _ => "varints",
+    }
+}
+
+/// Every plausible reading of length-prefixed data, weighted by how much it looks like what it is
+/// read as. Text that also parses as a message or a list of varints is much more often text, and
+/// messages with field numbers in the thousands are rarely written by hand.
+fn readings(data: Span, options: &Options) -> Vec<Reading> {
+    let bytes = data.as_bytes();
+    let clean = text::is_clean(bytes, options.string_encoding);
+    let mut readings = Vec::new();
+    if let Ok((_, fields)) = all_consuming(protobuf::<_, Error<_>>(false))(data) {
+        if check_groups(&fields).is_ok() {
+            let small = fields.iter().filter(|field| field.tag_number <= 100).count();
+            let weight = 0.3 + 0.7 * small as f64 / fields.len().max(1) as f64;
+            readings.push(Reading{
+                kind: "submessage",
+                description: format!("submessage of {} field{}", fields.len(), plural(fields.len())),
+                weight: if clean { weight / 2.0 } else { weight },
+            });
+        }
+    }
+    if let Some((s, encoding)) = text::decode(bytes, options.string_encoding, options.printable_threshold) {
+        let description = match encoding {
+            StringEncoding::Utf8 => format!("string {}", preview_string(&s)),
+            encoding => format!("string {} ({})", preview_string(&s), encoding.name()),
+        };
+        readings.push(Reading{kind: "string", description, weight: if clean { 1.0 } else { 0.5 }});
+    } else if let Some((s, invalid)) = text::lossy_utf8(bytes).filter(|(s, _)| options.lossy_utf8 && text::mostly_printable(s, options.printable_threshold)) {
+        let description = format!("string {} ({} invalid UTF-8 sequence{})", preview_string(&s), invalid, plural(invalid));
+        readings.push(Reading{kind: "string", description, weight: 0.3});
+    }
+    for (wire_type, weight) in [(WireType::VarInt, if clean { 0.05 } else { 0.4 }), (WireType::Int64, 0.2), (WireType::Int32, 0.2)] {
+        if let Some(values) = packed_values(data, wire_type, options).filter(|values| !values.is_empty()) {
+            readings.push(Reading{kind: packed_kind(wire_type), description: preview_packed(&values, wire_type), weight});
+        }
+    }
+    readings
+}
+
+/// The readings of length-prefixed data shown with `--show-alternates` when there is more than one,
+/// as a note like `submessage of 1 field 33%, or string "hi" 67%`, with the one it was shown as
+/// first and each one's share of the confidence.
+pub fn length_prefixed(data: Span, shown: &Interpretation, options: &Options) -> Option<String> {
+    // Empty data is every kind at once, and there's nothing to tell.
+    if !options.show_alternates || data.input_len() == 0 {
+        return None;
+    }
+    let kind = match shown {
+        Interpretation::Message(_) => "submessage",
+        Interpretation::String(..) | Interpretation::LossyString(..) => "string",
+        Interpretation::Packed(_, wire_type) => packed_kind(*wire_type),
+        Interpretation::Bytes => return None,
+    };
+    let mut readings = readings(data, options);
+    let total: f64 = readings.iter().map(|reading| reading.weight).sum();
+    readings.retain(|reading| reading.weight / total >= MIN_CONFIDENCE || reading.kind == kind);
+    if readings.len() < 2 {
+        return None;
+    }
+    // Sorting is stable, so the rest stay in the order of the heuristics.
+    readings.sort_by_key(|reading| reading.kind != kind);
+    let notes: Vec<_> = readings.iter()
+        .map(|reading| format!("{} {:.0}%", reading.description, reading.weight / total * 100.0))
+        .collect();
+    Some(notes.join(", or "))
+}
@@ -0,0 +1,487 @@
+//! `encode`: assembling protocat's own text output, or protoscope, back into the wire format, for
+//! crafting test payloads by hand.
+//!
+//! The two syntaxes share a lot, so one parser reads both. A field is its number, a colon and a
+//! value: an integer is a varint, a float is a double, `i32` and `i64` suffixes make fixed-width
+//! values and `z` a zigzag varint, a string in quotes or a list of hex bytes in brackets is
+//! length-prefixed, and fields in braces make a submessage. Protoscope's bare values, which add
+//! their bytes without a tag, like the string in `2: {"text"}`, may appear anywhere, and so may
+//! `N:SGROUP` and `N:EGROUP` tags and `#` comments. The notes protocat puts after values in
+//! parentheses are skipped, except for the `float` and `double` notes of `--floats` and the
+//! `(fixed64)` and `(fixed32)` of packed lists, which give the wire type back; so are the offsets
+//! of `--show-offsets` and the `//` sizes of `--show-sizes`.
+
+use std::convert::TryFrom;
+
+use crate::encode::{write_tag, write_varint};
+use crate::payload::MAX_NESTING;
+use crate::{WireType, MAX_FIELD_NUMBER};
+
+/// The syntax of `encode`'s input, for `--from`.
+#[derive(Copy, Clone, Debug, PartialEq, clap::ValueEnum)]
+pub enum Syntax {
+    /// JSON if it starts like an object, and otherwise protocat's text output or protoscope.
+    Auto,
+    /// protocat's text output or protoscope.
+    #[value(alias = "protoscope")]
+    Protocat,
+    /// Protobuf text format, whose fields may be named with a schema.
+    Text,
+    /// proto3 JSON.
+    Json,
+}
+
+impl Syntax {
+    /// Settles `auto` by the shape of the input.
+    pub fn resolve(self, source: &[u8]) -> Self {
+        match self {
+            Syntax::Auto if source.trim_ascii_start().starts_with(b"{") => Syntax::Json,
+            Syntax::Auto => Syntax::Protocat,
+            syntax => syntax,
+        }
+    }
+}
+
+/// Assembles a message from its text.
+pub fn assemble(source: &str) -> Result<Vec<u8>, String> {
+    let mut parser = Parser{source: source.as_bytes(), pos: 0};
+    let mut out = Vec::new();
+    parser.items(&mut out, false, 0)?;
+    Ok(out)
+}
+
+/// Assembles a single field value, such as `42`, `"text"` or `{1: 2}`, giving its wire type and
+/// its bytes, length included.
+pub fn value(source: &str) -> Result<Scalar, String> {
+    let mut parser = Parser{source: source.as_bytes(), pos: 0};
+    let value = parser.value(0)?;
+    match parser.peek() {
+        None => Ok(value),
+        Some(_) => parser.error("expected the end of the value"),
+    }
+}
+
+/// A value's wire type and encoded bytes.
+pub type Scalar = (WireType, Vec<u8>);
+
+struct Parser<'a> {
+    source: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error<T>(&self, message: &str) -> Result<T, String> {
+        let line = self.source[..self.pos].iter().filter(|&&b| b == b'\n').count() + 1;
+        Err(format!("line {}: {}", line, message))
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_space();
+        self.source.get(self.pos).copied()
+    }
+
+    /// Skips whitespace, `#` comments and the `//` sizes of `--show-sizes`.
+    fn skip_space(&mut self) {
+        loop {
+            match self.source.get(self.pos) {
+                Some(b' ' | b'\t' | b'\n' | b'\r') => self.pos += 1,
+                Some(b'#') => self.skip_line(),
+                Some(b'/') if self.source.get(self.pos + 1) == Some(&b'/') => self.skip_line(),
+                _ => return,
+            }
+        }
+    }
+
+    fn skip_line(&mut self) {
+        while !matches!(self.source.get(self.pos), None | Some(b'\n')) {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        if self.peek() == Some(byte) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// A run of characters that can make up a number or keyword, such as `-1.5e-3i64`.
+    fn word(&mut self) -> Option<String> {
+        self.skip_space();
+        let start = self.pos;
+        if self.source.get(self.pos) == Some(&b'-') {
+            self.pos += 1;
+        }
+        while let Some(&byte) = self.source.get(self.pos) {
+            let exponent_sign = matches!(byte, b'-' | b'+') && matches!(self.source[self.pos - 1], b'e' | b'E')
+                && !self.source[start..self.pos].starts_with(b"0x");
+            if !(byte.is_ascii_alphanumeric() || byte == b'.' || byte == b'_' || exponent_sign) {
+                break;
+            }
+            self.pos += 1;
+        }
+        if self.pos == start {
+            None
+        } else {
+            Some(String::from_utf8_lossy(&self.source[start..self.pos]).into_owned())
+        }
+    }
+
+    /// Assembles items up to the closing brace, if `braced`, or the end of input.
+    fn items(&mut self, out: &mut Vec<u8>, braced: bool, depth: usize) -> Result<(), String> {
+        if depth > MAX_NESTING {
+            return self.error("nested too deeply");
+        }
+        loop {
+            match self.peek() {
+                None if braced => return self.error("expected '}'"),
+                None => return Ok(()),
+                Some(b'}') if braced => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                Some(b'[') if self.skip_offsets() => {}
+                // Notes on submessages come after their opening brace.
+                Some(b'(') => {
+                    self.notes()?;
+                }
+                Some(b'-' | b'0'..=b'9') => {
+                    let start = self.pos;
+                    let word = self.word().expect("a digit starts a word");
+                    if self.eat(b':') {
+                        self.field(&word, out, depth)?;
+                    } else {
+                        self.pos = start;
+                        out.extend(self.scalar()?.1);
+                    }
+                }
+                Some(_) => out.extend(self.bare(depth)?),
+            }
+        }
+    }
+
+    /// Skips a `--show-offsets` prefix like `[12+3]`, if there is one here.
+    fn skip_offsets(&mut self) -> bool {
+        let rest = &self.source[self.pos..];
+        let len = rest.iter().position(|&b| b == b']').filter(|&end| {
+            let inside = &rest[1..end];
+            inside.iter().all(|b| b.is_ascii_digit() || *b == b'+') && inside.iter().filter(|&&b| b == b'+').count() == 1
+        });
+        if let Some(end) = len {
+            self.pos += end + 1;
+        }
+        len.is_some()
+    }
+
+    /// Assembles the field with this number, the colon after it already read.
+    fn field(&mut self, number: &str, out: &mut Vec<u8>, depth: usize) -> Result<(), String> {
+        let number = match number.parse::<u64>() {
+            Ok(number) if (1..=MAX_FIELD_NUMBER).contains(&number) => number,
+            _ => return self.error(&format!("invalid field number {}", number)),
+        };
+        let start = self.pos;
+        let group = match self.word().as_deref() {
+            Some("SGROUP") => Some(WireType::StartGroup),
+            Some("EGROUP") => Some(WireType::EndGroup),
+            _ => None,
+        };
+        if let Some(wire_type) = group {
+            write_tag(out, number, wire_type);
+            return Ok(());
+        }
+        self.pos = start;
+        let (wire_type, value) = self.value(depth)?;
+        write_tag(out, number, wire_type);
+        out.extend(value);
+        Ok(())
+    }
+
+    /// A field's value, with its length in front if it is length-prefixed.
+    fn value(&mut self, depth: usize) -> Result<Scalar, String> {
+        let (wire_type, value) = match self.peek() {
+            Some(b'{') => (WireType::LengthPrefixed, self.bare(depth)?),
+            Some(b'"' | b'`') => (WireType::LengthPrefixed, length_prefixed(self.bare(depth)?)),
+            Some(b'[') => {
+                let (wire_type, value) = self.list()?;
+                (wire_type, length_prefixed(value))
+            }
+            _ => self.scalar()?,
+        };
+        // The float and double notes give the value as a float, but the integer before them holds the
+        // same bits.
+        Ok(match (wire_type, self.notes()?) {
+            (WireType::VarInt, Some(wire_type @ WireType::Int64)) => (wire_type, fixed_width(&value, 8).or_else(|err| self.error(&err))?),
+            (WireType::VarInt, Some(wire_type)) => (wire_type, fixed_width(&value, 4).or_else(|err| self.error(&err))?),
+            (wire_type, _) => (wire_type, value),
+        })
+    }
+
+    /// Skips the notes in parentheses after a value, returning the wire type one of them gives.
+    fn notes(&mut self) -> Result<Option<WireType>, String> {
+        let mut wire_type = None;
+        while self.eat(b'(') {
+            let start = self.pos;
+            let mut depth = 1;
+            while depth > 0 {
+                match self.source.get(self.pos) {
+                    None => return self.error("unterminated note"),
+                    Some(b'(') => depth += 1,
+                    Some(b')') => depth -= 1,
+                    // Notes can quote strings, which can have parentheses of their own.
+                    Some(b'"') => {
+                        self.pos += 1;
+                        while !matches!(self.source.get(self.pos), None | Some(b'"')) {
+                            self.pos += if self.source[self.pos] == b'\\' { 2 } else { 1 };
+                        }
+                    }
+                    _ => {}
+                }
+                self.pos += 1;
+            }
+            let note = &self.source[start..self.pos - 1];
+            if note.starts_with(b"float:") {
+                wire_type = Some(WireType::Int32);
+            } else if note.starts_with(b"double:") {
+                wire_type = Some(WireType::Int64);
+            }
+        }
+        Ok(wire_type)
+    }
+
+    /// A value without a tag: a string, a hex literal, a length-prefixed block in braces or a
+    /// scalar, giving its bytes.
+    fn bare(&mut self, depth: usize) -> Result<Vec<u8>, String> {
+        match self.peek() {
+            Some(b'{') => {
+                self.pos += 1;
+                let mut content = Vec::new();
+                self.items(&mut content, true, depth + 1)?;
+                Ok(length_prefixed(content))
+            }
+            Some(b'"') => {
+                // Adjacent strings are concatenated.
+                let mut out = Vec::new();
+                while self.peek() == Some(b'"') {
+                    self.string(&mut out)?;
+                }
+                Ok(out)
+            }
+            Some(b'`') => {
+                self.pos += 1;
+                let end = self.source[self.pos..].iter().position(|&b| b == b'`');
+                let digits = match end {
+                    Some(end) => String::from_utf8_lossy(&self.source[self.pos..self.pos + end]).into_owned(),
+                    None => return self.error("unterminated hex literal"),
+                };
+                let bytes = crate::hex::decode(&digits).map_or_else(|| self.error("invalid hex literal"), Ok)?;
+                self.pos += digits.len() + 1;
+                Ok(bytes)
+            }
+            Some(b'-' | b'0'..=b'9' | b'a'..=b'z') => Ok(self.scalar()?.1),
+            Some(byte) => self.error(&format!("unexpected '{}'", byte as char)),
+            None => self.error("expected a value"),
+        }
+    }
+
+    /// A bracketed list: hex bytes, as protocat prints raw data, or packed values as `--packed`
+    /// prints them. Fixed-width ones have a `(fixed64)` or `(fixed32)` note after the list, but
+    /// varints are only told from bytes by having an element too big for a byte's two digits, and
+    /// otherwise taken for bytes.
+    fn list(&mut self) -> Result<Scalar, String> {
+        self.pos += 1;
+        let mut words = Vec::new();
+        if !self.eat(b']') {
+            loop {
+                match self.word() {
+                    Some(word) => words.push(word),
+                    None => return self.error("expected a list element"),
+                }
+                if self.eat(b']') {
+                    break;
+                }
+                if !self.eat(b',') {
+                    return self.error("expected ',' or ']'");
+                }
+            }
+        }
+        let start = self.pos;
+        // The width of fixed-width values, zero for varints and None for bytes.
+        let width = match (self.eat(b'('), self.word().as_deref(), self.eat(b')')) {
+            (true, Some("fixed64"), true) => Some(8),
+            (true, Some("fixed32"), true) => Some(4),
+            _ => {
+                self.pos = start;
+                words.iter().any(|word| word.len() > 2).then_some(0)
+            }
+        };
+        let mut out = Vec::new();
+        for word in words {
+            match (width, word.parse::<u64>()) {
+                (Some(0), Ok(value)) => write_varint(&mut out, value),
+                (Some(8), Ok(value)) => out.extend_from_slice(&value.to_le_bytes()),
+                (Some(4), Ok(value)) if value <= u32::MAX as u64 => out.extend_from_slice(&(value as u32).to_le_bytes()),
+                (Some(_), _) => return self.error(&format!("invalid packed value {}", word)),
+                (None, _) => match u8::from_str_radix(&word, 16) {
+                    Ok(byte) => out.push(byte),
+                    Err(_) => return self.error(&format!("invalid byte {}", word)),
+                },
+            }
+        }
+        Ok((WireType::LengthPrefixed, out))
+    }
+
+    /// A number or `true` or `false`, encoded as its suffix or form says.
+    fn scalar(&mut self) -> Result<Scalar, String> {
+        let word = match self.word() {
+            Some(word) => word,
+            None => return self.error("expected a value"),
+        };
+        match number(&word) {
+            Some(scalar) => Ok(scalar),
+            None => self.error(&format!("invalid value {}", word)),
+        }
+    }
+
+    fn string(&mut self, out: &mut Vec<u8>) -> Result<(), String> {
+        self.pos += 1;
+        loop {
+            let byte = match self.source.get(self.pos) {
+                None | Some(b'\n') => return self.error("unterminated string"),
+                Some(&byte) => byte,
+            };
+            self.pos += 1;
+            if byte == b'"' {
+                return Ok(());
+            }
+            if byte != b'\\' {
+                out.push(byte);
+                continue;
+            }
+            let escape = match self.source.get(self.pos) {
+                Some(&escape) => escape,
+                None => return self.error("unterminated string"),
+            };
+            self.pos += 1;
+            match escape {
+                b'n' => out.push(b'\n'),
+                b'r' => out.push(b'\r'),
+                b't' => out.push(b'\t'),
+                b'0'..=b'7' => {
+                    let start = self.pos - 1;
+                    while self.pos - start < 3 && matches!(self.source.get(self.pos), Some(b'0'..=b'7')) {
+                        self.pos += 1;
+                    }
+                    let digits = std::str::from_utf8(&self.source[start..self.pos]).expect("octal digits are ASCII");
+                    out.push(u32::from_str_radix(digits, 8).expect("digits are octal") as u8);
+                }
+                b'x' => out.push(self.hex_escape(2)? as u8),
+                b'u' => {
+                    // `\u{...}`, as protocat writes, or protoscope's and text format's `\uXXXX`.
+                    let code = if self.source.get(self.pos) == Some(&b'{') {
+                        self.pos += 1;
+                        let code = self.hex_escape(6)?;
+                        if self.source.get(self.pos) != Some(&b'}') {
+                            return self.error("malformed escape");
+                        }
+                        self.pos += 1;
+                        code
+                    } else {
+                        self.hex_escape(4)?
+                    };
+                    let c = char::try_from(code).or_else(|_| self.error("escape isn't a character"))?;
+                    out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                other => out.push(other),
+            }
+        }
+    }
+
+    /// Reads one to `max` hex digits of an escape.
+    fn hex_escape(&mut self, max: usize) -> Result<u32, String> {
+        let start = self.pos;
+        while self.pos - start < max && self.source.get(self.pos).is_some_and(|b| b.is_ascii_hexdigit()) {
+            self.pos += 1;
+        }
+        if self.pos == start {
+            return self.error("malformed escape");
+        }
+        let digits = std::str::from_utf8(&self.source[start..self.pos]).expect("hex digits are ASCII");
+        Ok(u32::from_str_radix(digits, 16).expect("digits are hex"))
+    }
+}
+
+/// Data with its length in front.
+fn length_prefixed(data: Vec<u8>) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_varint(&mut out, data.len() as u64);
+    out.extend(data);
+    out
+}
+
+/// Parses an integer in decimal or `0x` hex, with an optional minus sign, as its 64-bit two's
+/// complement.
+fn integer(word: &str) -> Option<u64> {
+    let (negative, digits) = match word.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, word),
+    };
+    let magnitude = match digits.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()?,
+        None => digits.parse::<u64>().ok()?,
+    };
+    match negative {
+        true if magnitude > 1 << 63 => None,
+        true => Some(magnitude.wrapping_neg()),
+        false => Some(magnitude),
+    }
+}
+
+fn is_float(word: &str) -> bool {
+    let digits = word.trim_start_matches('-');
+    !digits.starts_with("0x") && (digits.contains(['.', 'e', 'E']) || matches!(digits, "inf" | "nan"))
+}
+
+/// Encodes a number the way its suffix or form says.
+fn number(word: &str) -> Option<Scalar> {
+    let varint = |value: u64| {
+        let mut out = Vec::new();
+        write_varint(&mut out, value);
+        (WireType::VarInt, out)
+    };
+    if let Some(word) = word.strip_suffix("i64") {
+        let bits = if is_float(word) { word.parse::<f64>().ok()?.to_bits() } else { integer(word)? };
+        return Some((WireType::Int64, bits.to_le_bytes().to_vec()));
+    }
+    if let Some(word) = word.strip_suffix("i32") {
+        let bits = if is_float(word) {
+            word.parse::<f32>().ok()?.to_bits()
+        } else {
+            let value = integer(word)? as i64;
+            // Negative values are int32s, and positive ones uint32s.
+            u32::try_from(value).ok().or_else(|| i32::try_from(value).ok().map(|value| value as u32))?
+        };
+        return Some((WireType::Int32, bits.to_le_bytes().to_vec()));
+    }
+    if let Some(word) = word.strip_suffix('z') {
+        let value = integer(word)? as i64;
+        return Some(varint(((value << 1) ^ (value >> 63)) as u64));
+    }
+    match word {
+        "true" => Some(varint(1)),
+        "false" => Some(varint(0)),
+        word if is_float(word) => Some((WireType::Int64, word.parse::<f64>().ok()?.to_bits().to_le_bytes().to_vec())),
+        word => integer(word).map(varint),
+    }
+}
+
+/// A varint value, as the integer before a float or double note, rewritten as the fixed-width
+/// value of `width` bytes it stands for.
+fn fixed_width(varint: &[u8], width: usize) -> Result<Vec<u8>, String> {
+    let value = varint.iter().rev().fold(0u64, |value, &byte| value << 7 | (byte & 0x7f) as u64);
+    if width == 4 && value > u32::MAX as u64 {
+        return Err(format!("{} doesn't fit in a fixed32", value));
+    }
+    Ok(value.to_le_bytes()[..width].to_vec())
+}
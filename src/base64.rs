@@ -0,0 +1,60 @@
+//! Base64 decoding, accepting both the standard and URL-safe alphabets, with or without padding,
+//! since gRPC metadata and various tools disagree about which to use; and encoding, in the padded
+//! standard alphabet that proto3 JSON uses.
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn value(c: u8) -> Option<u32> {
+    match c {
+        b'A'..=b'Z' => Some((c - b'A') as u32),
+        b'a'..=b'z' => Some((c - b'a') as u32 + 26),
+        b'0'..=b'9' => Some((c - b'0') as u32 + 52),
+        b'+' | b'-' => Some(62),
+        b'/' | b'_' => Some(63),
+        _ => None,
+    }
+}
+
+/// Decodes base64 text, ignoring whitespace. Returns `None` if it isn't valid base64.
+pub fn decode(text: &str) -> Option<Vec<u8>> {
+    let digits: Vec<u8> = text.bytes().filter(|c| !c.is_ascii_whitespace()).collect();
+    let digits = {
+        let unpadded = digits.iter().rposition(|&c| c != b'=').map_or(0, |i| i + 1);
+        if digits.len() - unpadded > 2 || (digits.len() != unpadded && !digits.len().is_multiple_of(4)) {
+            return None;
+        }
+        &digits[..unpadded]
+    };
+    if digits.len() % 4 == 1 {
+        return None;
+    }
+
+    let mut out = Vec::with_capacity(digits.len() / 4 * 3 + 2);
+    for chunk in digits.chunks(4) {
+        let mut group = 0;
+        for (i, &c) in chunk.iter().enumerate() {
+            group |= value(c)? << (18 - 6 * i);
+        }
+        let bytes = group.to_be_bytes();
+        out.extend_from_slice(&bytes[1..chunk.len()]);
+    }
+    Some(out)
+}
+
+/// Encodes data as padded standard base64.
+pub fn encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let mut bytes = [0; 4];
+        bytes[1..=chunk.len()].copy_from_slice(chunk);
+        let group = u32::from_be_bytes(bytes);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(group >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
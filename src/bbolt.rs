@@ -0,0 +1,171 @@
+//! A reader for bbolt (formerly BoltDB) database files, the storage format of etcd and hence of
+//! Kubernetes cluster state.
+//!
+//! A database is a B+tree of pages. Its root is a bucket whose keys name further buckets, each of
+//! which is either its own tree of pages or, when small, stored inline in its parent's value.
+
+use std::collections::HashSet;
+use std::convert::TryInto;
+
+const MAGIC: u32 = 0xED0C_DAED;
+const VERSION: u32 = 2;
+
+const PAGE_HEADER_SIZE: usize = 16;
+const ELEMENT_SIZE: usize = 16;
+const BUCKET_HEADER_SIZE: usize = 16;
+
+const BRANCH_PAGE: u16 = 0x01;
+const LEAF_PAGE: u16 = 0x02;
+
+const BUCKET_LEAF_FLAG: u32 = 0x01;
+
+pub struct Db<'a> {
+    data: &'a [u8],
+    page_size: usize,
+    root: Bucket<'a>,
+}
+
+/// Where a bucket's tree lives.
+#[derive(Clone, Copy)]
+pub enum Bucket<'a> {
+    Root(u64),
+    /// A single leaf page embedded in the parent's value.
+    Inline(&'a [u8]),
+}
+
+pub struct Entry<'a> {
+    pub key: &'a [u8],
+    pub value: &'a [u8],
+    pub is_bucket: bool,
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn u64_at(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(data.get(offset..offset + 8)?.try_into().ok()?))
+}
+
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+/// Reads a meta page, returning its page size, root bucket page and transaction id if it is valid.
+fn meta(data: &[u8], offset: usize) -> Option<(usize, u64, u64)> {
+    let meta = data.get(offset + PAGE_HEADER_SIZE..offset + PAGE_HEADER_SIZE + 64)?;
+    if u32_at(meta, 0)? != MAGIC || u32_at(meta, 4)? != VERSION || fnv1a(&meta[..56]) != u64_at(meta, 56)? {
+        return None;
+    }
+    Some((u32_at(meta, 8)? as usize, u64_at(meta, 16)?, u64_at(meta, 48)?))
+}
+
+impl<'a> Db<'a> {
+    pub fn open(data: &'a [u8]) -> Result<Self, String> {
+        // There are two meta pages, written alternately; the newest valid one is current. The second
+        // one is found using the page size from the first, or failing that, the usual 4 KiB.
+        let first = meta(data, 0);
+        let second = meta(data, first.map_or(4096, |(page_size, _, _)| page_size));
+        let (page_size, root, _) = match (first, second) {
+            (Some(a), Some(b)) => if b.2 > a.2 { b } else { a },
+            (Some(m), None) | (None, Some(m)) => m,
+            (None, None) => return Err("not a bbolt database".to_string()),
+        };
+        if page_size < PAGE_HEADER_SIZE + ELEMENT_SIZE {
+            return Err(format!("invalid page size {}", page_size));
+        }
+        Ok(Db{data, page_size, root: Bucket::Root(root)})
+    }
+
+    pub fn root(&self) -> Bucket<'a> {
+        self.root
+    }
+
+    fn page(&self, id: u64) -> Result<&'a [u8], String> {
+        let start = (id as usize).checked_mul(self.page_size).filter(|&start| start < self.data.len());
+        let start = start.ok_or_else(|| format!("page {} is past the end of the file", id))?;
+        let overflow = u32_at(self.data, start + 12).unwrap_or(0) as usize;
+        let len = overflow.saturating_add(1).saturating_mul(self.page_size);
+        Ok(&self.data[start..start.saturating_add(len).min(self.data.len())])
+    }
+
+    /// Looks up a nested bucket by the names leading to it from `bucket`.
+    pub fn bucket(&self, bucket: Bucket<'a>, names: &[&[u8]]) -> Result<Option<Bucket<'a>>, String> {
+        let mut bucket = bucket;
+        for name in names {
+            let entry = self.entries(bucket)?.into_iter().find(|entry| entry.is_bucket && entry.key == *name);
+            bucket = match entry {
+                Some(entry) => self.as_bucket(entry.value)?,
+                None => return Ok(None),
+            };
+        }
+        Ok(Some(bucket))
+    }
+
+    /// Interprets the value of a bucket entry.
+    pub fn as_bucket(&self, value: &'a [u8]) -> Result<Bucket<'a>, String> {
+        match u64_at(value, 0) {
+            Some(0) => Ok(Bucket::Inline(&value[BUCKET_HEADER_SIZE.min(value.len())..])),
+            Some(root) => Ok(Bucket::Root(root)),
+            None => Err("truncated bucket header".to_string()),
+        }
+    }
+
+    /// Lists the entries of a bucket in key order.
+    pub fn entries(&self, bucket: Bucket<'a>) -> Result<Vec<Entry<'a>>, String> {
+        let mut entries = Vec::new();
+        let root = match bucket {
+            Bucket::Inline(page) => {
+                leaf(page, &mut entries)?;
+                return Ok(entries);
+            }
+            Bucket::Root(root) => root,
+        };
+
+        // Walked with an explicit stack, and never visiting a page twice, so that corrupt files with
+        // cycles or absurd depths can't hang or overflow the call stack.
+        let mut stack = vec![root];
+        let mut visited = HashSet::new();
+        while let Some(id) = stack.pop() {
+            if !visited.insert(id) {
+                return Err(format!("page {} is referenced more than once", id));
+            }
+            let page = self.page(id)?;
+            match u16_at(page, 8) {
+                Some(BRANCH_PAGE) => {
+                    let count = u16_at(page, 10).unwrap_or(0) as usize;
+                    let children: Vec<u64> = (0..count)
+                        .map(|i| u64_at(page, PAGE_HEADER_SIZE + i * ELEMENT_SIZE + 8).ok_or("truncated branch page"))
+                        .collect::<Result<_, _>>()?;
+                    stack.extend(children.into_iter().rev());
+                }
+                Some(LEAF_PAGE) => leaf(page, &mut entries)?,
+                _ => return Err(format!("page {} is not part of a bucket", id)),
+            }
+        }
+        Ok(entries)
+    }
+}
+
+fn leaf<'a>(page: &'a [u8], entries: &mut Vec<Entry<'a>>) -> Result<(), String> {
+    if u16_at(page, 8) != Some(LEAF_PAGE) {
+        return Err("inline bucket is not a leaf page".to_string());
+    }
+    let count = u16_at(page, 10).unwrap_or(0) as usize;
+    for i in 0..count {
+        let element = PAGE_HEADER_SIZE + i * ELEMENT_SIZE;
+        let truncated = || "truncated leaf page".to_string();
+        let flags = u32_at(page, element).ok_or_else(truncated)?;
+        let pos = element + u32_at(page, element + 4).ok_or_else(truncated)? as usize;
+        let key_size = u32_at(page, element + 8).ok_or_else(truncated)? as usize;
+        let value_size = u32_at(page, element + 12).ok_or_else(truncated)? as usize;
+        let key = page.get(pos..pos + key_size).ok_or_else(truncated)?;
+        let value = page.get(pos + key_size..pos + key_size + value_size).ok_or_else(truncated)?;
+        entries.push(Entry{key, value, is_bucket: flags & BUCKET_LEAF_FLAG != 0});
+    }
+    Ok(())
+}
@@ -0,0 +1,144 @@
+//! Size budgets for `protocat lint`, so growth in payloads can be caught in CI from captured samples.
+//!
+//! A rule is written `[PATH:]KIND=LIMIT`. `message` limits the size of the whole input, `field` the
+//! size of any length-delimited value, and `repeated` how many times a field number may occur in
+//! one message. A rule with a path applies to the fields at that path only, in place of the rule of
+//! the same kind without one.
+
+use std::collections::BTreeMap;
+
+use nom::combinator::all_consuming;
+use nom::InputLength;
+
+use crate::path::parse_path;
+use crate::units::format_size;
+use crate::{plural, protobuf, Error, ProtoField, Span, WireValue};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Kind {
+    Message,
+    Field,
+    Repeated,
+}
+
+#[derive(Clone, Debug)]
+pub struct Rule {
+    path: Option<Vec<u64>>,
+    kind: Kind,
+    limit: u64,
+}
+
+impl Rule {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let (path, rule) = match text.split_once(':') {
+            Some((path, rule)) => (Some(parse_path(path)?), rule),
+            None => (None, text),
+        };
+        let (kind, limit) = rule.split_once('=').ok_or_else(|| format!("expected [PATH:]KIND=LIMIT, got '{}'", text))?;
+        let kind = match kind {
+            "message" if path.is_some() => return Err("message budgets are for whole inputs; use field with a path".to_string()),
+            "message" => Kind::Message,
+            "field" => Kind::Field,
+            "repeated" => Kind::Repeated,
+            _ => return Err(format!("unknown budget '{}', the choices are message, field and repeated", kind)),
+        };
+        let limit = match kind {
+            Kind::Repeated => limit.parse().map_err(|_| format!("invalid count '{}'", limit))?,
+            _ => parse_size(limit).ok_or_else(|| format!("invalid size '{}'", limit))?,
+        };
+        Ok(Rule{path, kind, limit})
+    }
+}
+
+/// Parses a byte count with an optional unit, decimal (KB, MB, GB) or binary (KiB, MiB, GiB).
+pub fn parse_size(text: &str) -> Option<u64> {
+    let split = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let multiplier = match unit.trim() {
+        "" | "B" => 1,
+        "KB" => 1000,
+        "KiB" => 1 << 10,
+        "MB" => 1_000_000,
+        "MiB" => 1 << 20,
+        "GB" => 1_000_000_000,
+        "GiB" => 1 << 30,
+        _ => return None,
+    };
+    number.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+/// The fields over one budget at one path.
+pub struct Violation {
+    pub path: String,
+    pub kind: Kind,
+    pub limit: u64,
+    pub count: usize,
+    pub largest: u64,
+}
+
+impl Violation {
+    pub fn describe(&self) -> String {
+        let path = if self.path.is_empty() { "input" } else { &self.path };
+        match self.kind {
+            Kind::Message => format!("{}: {} is over the {} message budget", path, format_size(self.largest), format_size(self.limit)),
+            Kind::Field => format!(
+                "{}: {} field{} over the {} field budget, the largest {}",
+                path, self.count, plural(self.count), format_size(self.limit), format_size(self.largest),
+            ),
+            Kind::Repeated => format!(
+                "{}: repeated {} time{} in one message, over the budget of {}{}",
+                path, self.largest, plural(self.largest as usize), self.limit,
+                if self.count > 1 { format!(", in {} messages", self.count) } else { String::new() },
+            ),
+        }
+    }
+}
+
+/// Checks a parsed input against the rules, returning the violations ordered by path.
+pub fn check(size: usize, fields: Vec<ProtoField<Span>>, rules: &[Rule]) -> Vec<Violation> {
+    let limit = |kind: Kind, path: &[u64]| {
+        let specific = rules.iter().find(|rule| rule.kind == kind && rule.path.as_deref() == Some(path));
+        specific.or_else(|| rules.iter().find(|rule| rule.kind == kind && rule.path.is_none())).map(|rule| rule.limit)
+    };
+    let mut violations: BTreeMap<(Vec<u64>, Kind), Violation> = BTreeMap::new();
+    let mut over = |path: &[u64], kind: Kind, limit: u64, value: u64| {
+        let violation = violations.entry((path.to_vec(), kind)).or_insert_with(|| Violation{
+            path: path.iter().map(u64::to_string).collect::<Vec<_>>().join("."),
+            kind, limit, count: 0, largest: 0,
+        });
+        violation.count += 1;
+        violation.largest = violation.largest.max(value);
+    };
+
+    if let Some(max) = limit(Kind::Message, &[]).filter(|&max| size as u64 > max) {
+        over(&[], Kind::Message, max, size as u64);
+    }
+    // Submessages are walked with an explicit stack, as in print_message, each entry holding a
+    // message's fields and the path to it.
+    let mut stack = vec![(fields, Vec::new())];
+    while let Some((fields, path)) = stack.pop() {
+        let mut counts: BTreeMap<u64, u64> = BTreeMap::new();
+        for field in fields {
+            *counts.entry(field.tag_number).or_default() += 1;
+            let mut field_path = path.clone();
+            field_path.push(field.tag_number);
+            if let WireValue::LengthPrefixed(d) = field.value {
+                let len = d.input_len() as u64;
+                if let Some(max) = limit(Kind::Field, &field_path).filter(|&max| len > max) {
+                    over(&field_path, Kind::Field, max, len);
+                }
+                if let Ok((_, fields)) = all_consuming(protobuf::<_, Error<_>>(false))(d) {
+                    stack.push((fields, field_path));
+                }
+            }
+        }
+        for (tag_number, count) in counts {
+            let mut field_path = path.clone();
+            field_path.push(tag_number);
+            if let Some(max) = limit(Kind::Repeated, &field_path).filter(|&max| count > max) {
+                over(&field_path, Kind::Repeated, max, count);
+            }
+        }
+    }
+    violations.into_values().collect()
+}
@@ -0,0 +1,157 @@
+//! Schemas bundled into protocat, for protocols common enough to be worth decoding with no setup.
+
+use std::collections::HashSet;
+
+use crate::proto;
+use crate::schema::Schema;
+
+pub struct Builtin {
+    pub name: &'static str,
+    /// The message types a payload is likely to be, best guess first.
+    pub roots: &'static [&'static str],
+    /// Import paths of the `.proto` files to load; their imports are loaded too.
+    files: &'static [&'static str],
+    /// `bytes` fields holding serialized messages, as (message, field, message type).
+    embedded: &'static [(&'static str, &'static str, &'static str)],
+}
+
+static BUILTINS: &[Builtin] = &[
+    Builtin{name: "otlp", roots: &[
+        "opentelemetry.proto.collector.trace.v1.ExportTraceServiceRequest",
+        "opentelemetry.proto.collector.metrics.v1.ExportMetricsServiceRequest",
+        "opentelemetry.proto.collector.logs.v1.ExportLogsServiceRequest",
+    ], files: &[
+        "opentelemetry/proto/collector/trace/v1/trace_service.proto",
+        "opentelemetry/proto/collector/metrics/v1/metrics_service.proto",
+        "opentelemetry/proto/collector/logs/v1/logs_service.proto",
+    ], embedded: &[]},
+    Builtin{name: "prometheus", roots: &["prometheus.WriteRequest"], files: &["prometheus/remote.proto"], embedded: &[]},
+    Builtin{name: "descriptor", roots: &[
+        "google.protobuf.FileDescriptorSet",
+        "google.protobuf.FileDescriptorProto",
+        "google.protobuf.compiler.CodeGeneratorRequest",
+        "google.protobuf.compiler.CodeGeneratorResponse",
+    ], files: &["google/protobuf/compiler/plugin.proto"], embedded: &[]},
+    Builtin{name: "wkt", roots: &[
+        "google.protobuf.Struct",
+        "google.protobuf.Any",
+        "google.protobuf.Timestamp",
+        "google.protobuf.Duration",
+        "google.protobuf.FieldMask",
+        "google.protobuf.Type",
+        "google.protobuf.Enum",
+        "google.protobuf.Api",
+    ], files: &[
+        "google/protobuf/any.proto",
+        "google/protobuf/api.proto",
+        "google/protobuf/duration.proto",
+        "google/protobuf/empty.proto",
+        "google/protobuf/field_mask.proto",
+        "google/protobuf/source_context.proto",
+        "google/protobuf/struct.proto",
+        "google/protobuf/timestamp.proto",
+        "google/protobuf/type.proto",
+        "google/protobuf/wrappers.proto",
+    ], embedded: &[]},
+    Builtin{name: "grpc-health", roots: &[
+        "grpc.health.v1.HealthCheckResponse",
+        "grpc.health.v1.HealthCheckRequest",
+        "grpc.health.v1.HealthListResponse",
+    ], files: &["grpc/health/v1/health.proto"], embedded: &[]},
+    Builtin{name: "grpc-reflection", roots: &[
+        "grpc.reflection.v1.ServerReflectionResponse",
+        "grpc.reflection.v1.ServerReflectionRequest",
+    ], files: &["grpc/reflection/v1/reflection.proto", "google/protobuf/descriptor.proto"], embedded: &[
+        ("grpc.reflection.v1.FileDescriptorResponse", "file_descriptor_proto", "google.protobuf.FileDescriptorProto"),
+    ]},
+    Builtin{name: "google-rpc", roots: &[
+        "google.rpc.Status",
+        "google.rpc.ErrorInfo",
+        "google.rpc.BadRequest",
+        "google.rpc.QuotaFailure",
+        "google.rpc.PreconditionFailure",
+        "google.rpc.RetryInfo",
+        "google.rpc.DebugInfo",
+        "google.rpc.RequestInfo",
+        "google.rpc.ResourceInfo",
+        "google.rpc.Help",
+        "google.rpc.LocalizedMessage",
+    ], files: &["google/rpc/status.proto", "google/rpc/code.proto", "google/rpc/error_details.proto"], embedded: &[]},
+];
+
+/// Every bundled `.proto` source by import path.
+static SOURCES: &[(&str, &str)] = &[
+    ("google/protobuf/any.proto", include_str!("../proto/google/protobuf/any.proto")),
+    ("google/protobuf/api.proto", include_str!("../proto/google/protobuf/api.proto")),
+    ("google/protobuf/compiler/plugin.proto", include_str!("../proto/google/protobuf/compiler/plugin.proto")),
+    ("google/protobuf/descriptor.proto", include_str!("../proto/google/protobuf/descriptor.proto")),
+    ("google/protobuf/duration.proto", include_str!("../proto/google/protobuf/duration.proto")),
+    ("google/protobuf/empty.proto", include_str!("../proto/google/protobuf/empty.proto")),
+    ("google/protobuf/field_mask.proto", include_str!("../proto/google/protobuf/field_mask.proto")),
+    ("google/protobuf/source_context.proto", include_str!("../proto/google/protobuf/source_context.proto")),
+    ("google/protobuf/struct.proto", include_str!("../proto/google/protobuf/struct.proto")),
+    ("google/protobuf/timestamp.proto", include_str!("../proto/google/protobuf/timestamp.proto")),
+    ("google/protobuf/type.proto", include_str!("../proto/google/protobuf/type.proto")),
+    ("google/protobuf/wrappers.proto", include_str!("../proto/google/protobuf/wrappers.proto")),
+    ("google/rpc/code.proto", include_str!("../proto/google/rpc/code.proto")),
+    ("google/rpc/error_details.proto", include_str!("../proto/google/rpc/error_details.proto")),
+    ("google/rpc/status.proto", include_str!("../proto/google/rpc/status.proto")),
+    ("grpc/health/v1/health.proto", include_str!("../proto/grpc/health/v1/health.proto")),
+    ("grpc/reflection/v1/reflection.proto", include_str!("../proto/grpc/reflection/v1/reflection.proto")),
+    ("opentelemetry/proto/common/v1/common.proto", include_str!("../proto/opentelemetry/proto/common/v1/common.proto")),
+    ("opentelemetry/proto/resource/v1/resource.proto", include_str!("../proto/opentelemetry/proto/resource/v1/resource.proto")),
+    ("opentelemetry/proto/trace/v1/trace.proto", include_str!("../proto/opentelemetry/proto/trace/v1/trace.proto")),
+    ("opentelemetry/proto/metrics/v1/metrics.proto", include_str!("../proto/opentelemetry/proto/metrics/v1/metrics.proto")),
+    ("opentelemetry/proto/logs/v1/logs.proto", include_str!("../proto/opentelemetry/proto/logs/v1/logs.proto")),
+    ("opentelemetry/proto/collector/trace/v1/trace_service.proto",
+        include_str!("../proto/opentelemetry/proto/collector/trace/v1/trace_service.proto")),
+    ("opentelemetry/proto/collector/metrics/v1/metrics_service.proto",
+        include_str!("../proto/opentelemetry/proto/collector/metrics/v1/metrics_service.proto")),
+    ("opentelemetry/proto/collector/logs/v1/logs_service.proto",
+        include_str!("../proto/opentelemetry/proto/collector/logs/v1/logs_service.proto")),
+    ("prometheus/types.proto", include_str!("../proto/prometheus/types.proto")),
+    ("prometheus/remote.proto", include_str!("../proto/prometheus/remote.proto")),
+];
+
+/// The bundled source of a `.proto` file by import path, which also lets `.proto` files on disk
+/// import the well-known types without them being on the include path, as with protoc.
+pub fn bundled(path: &str) -> Option<&'static str> {
+    SOURCES.iter().find(|(bundled, _)| *bundled == path).map(|(_, source)| *source)
+}
+
+pub fn names() -> impl Iterator<Item = &'static str> {
+    BUILTINS.iter().map(|builtin| builtin.name)
+}
+
+/// Parses and resolves the named builtin schema.
+pub fn load(name: &str) -> Result<(&'static Builtin, Schema), String> {
+    let builtin = BUILTINS.iter().find(|builtin| builtin.name == name).ok_or_else(|| {
+        let names: Vec<&str> = BUILTINS.iter().map(|builtin| builtin.name).collect();
+        format!("no builtin schema {}, the choices are {}", name, names.join(", "))
+    })?;
+
+    let mut files = Vec::new();
+    let mut pending: Vec<&str> = builtin.files.to_vec();
+    let mut seen = HashSet::new();
+    while let Some(path) = pending.pop() {
+        if !seen.insert(path) {
+            continue;
+        }
+        let source = bundled(path).ok_or_else(|| format!("{} isn't bundled", path))?;
+        let file = proto::parse(path, source)?;
+        pending.extend(file.dependencies.iter().filter_map(|dependency| {
+            SOURCES.iter().find(|(bundled, _)| bundled == dependency).map(|(bundled, _)| *bundled)
+        }));
+        files.push(file);
+    }
+    proto::resolve(&mut files)?;
+
+    let mut schema = Schema::default();
+    for file in files {
+        schema.add_file(file);
+    }
+    for (message, field, type_name) in builtin.embedded {
+        schema.embed(message, field, type_name);
+    }
+    Ok((builtin, schema))
+}
@@ -0,0 +1,78 @@
+//! ANSI colors for the text output, so that deep dumps can be scanned by eye: tag numbers, values of
+//! each wire type, strings and the braces of submessages each get their own.
+
+use std::fmt::Display;
+use std::io::IsTerminal;
+
+use crate::{Options, WireType, WireValue};
+
+/// When to color the output, for `--color`.
+#[derive(Copy, Clone, Debug, PartialEq, clap::ValueEnum)]
+pub enum ColorChoice {
+    /// When stdout is a terminal and NO_COLOR isn't set.
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Settles `auto` to `always` or `never` for this run.
+    pub fn resolve(self) -> Self {
+        match self {
+            ColorChoice::Auto if std::env::var_os("NO_COLOR").is_none() && std::io::stdout().is_terminal() => ColorChoice::Always,
+            ColorChoice::Auto => ColorChoice::Never,
+            choice => choice,
+        }
+    }
+}
+
+/// What a piece of output is, which picks its color.
+#[derive(Copy, Clone)]
+pub enum Style {
+    Tag,
+    Varint,
+    Fixed,
+    String,
+    Bytes,
+    Brace,
+}
+
+impl Style {
+    /// The style of values of the given wire type.
+    pub fn of(wire_type: WireType) -> Self {
+        match wire_type {
+            WireType::VarInt => Style::Varint,
+            WireType::Int64 | WireType::Int32 => Style::Fixed,
+            _ => Style::Bytes,
+        }
+    }
+
+    /// The style of a scalar value.
+    pub fn of_value<I>(value: &WireValue<I>) -> Self {
+        match value {
+            WireValue::VarInt(_) => Style::Varint,
+            WireValue::Int64(_) | WireValue::Int32(_) => Style::Fixed,
+            _ => Style::Bytes,
+        }
+    }
+
+    fn code(self) -> &'static str {
+        match self {
+            Style::Tag => "1;34",
+            Style::Varint => "33",
+            Style::Fixed => "35",
+            Style::String => "32",
+            Style::Bytes => "31",
+            Style::Brace => "1",
+        }
+    }
+}
+
+/// Wraps text in the escapes for its style, if the output is colored.
+pub fn paint(options: &Options, style: Style, text: impl Display) -> String {
+    if options.color == ColorChoice::Always {
+        format!("\x1b[{}m{}\x1b[0m", style.code(), text)
+    } else {
+        text.to_string()
+    }
+}
@@ -0,0 +1,116 @@
+//! `compat-check`: comparing protocat's parse of a message against `protoc --decode_raw`.
+//!
+//! protocat's usual output picks between strings, bytes and submessages with heuristics protoc
+//! doesn't have, so the comparison renders protocat's parse in text format the way protoc would.
+//! Both outputs are normalized before comparing, so differences in protoc's escaping between
+//! versions don't count as divergences.
+
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+use crate::textformat::{self, escape};
+
+/// The outcome of decoding one input both ways.
+pub enum Outcome {
+    /// Both decoded it to the same number of lines.
+    Match(usize),
+    /// The outputs first differ at this (1-based) line, with each side's line there.
+    Diverged{line: usize, ours: Option<String>, protoc: Option<String>},
+    /// Only one side could parse the input.
+    OnlyParsedBy(&'static str, String),
+    /// Neither side could parse the input.
+    NeitherParsed,
+}
+
+/// Checks that protoc is runnable, so a missing protoc can be reported once instead of per file.
+pub fn protoc_available() -> bool {
+    Command::new("protoc").arg("--version").stdout(Stdio::null()).stderr(Stdio::null()).status()
+        .is_ok_and(|status| status.success())
+}
+
+pub fn compare(data: &[u8]) -> io::Result<Outcome> {
+    let ours = textformat::render(data, None).map(|lines| lines.iter().map(|line| normalize(line)).collect::<Vec<_>>());
+    let protoc = decode_raw(data)?;
+    Ok(match (ours, protoc) {
+        (Some(ours), Ok(protoc)) => {
+            let position = (0..ours.len().max(protoc.len())).find(|&i| ours.get(i) != protoc.get(i));
+            match position {
+                None => Outcome::Match(ours.len()),
+                Some(i) => Outcome::Diverged{line: i + 1, ours: ours.get(i).cloned(), protoc: protoc.get(i).cloned()},
+            }
+        }
+        (Some(_), Err(err)) => Outcome::OnlyParsedBy("protocat", format!("protoc failed: {}", err)),
+        (None, Ok(_)) => Outcome::OnlyParsedBy("protoc", "protocat failed to parse it".to_string()),
+        (None, Err(_)) => Outcome::NeitherParsed,
+    })
+}
+
+/// Runs `protoc --decode_raw` on the data, returning its normalized output, or its error message
+/// if it rejected the data.
+fn decode_raw(data: &[u8]) -> io::Result<Result<Vec<String>, String>> {
+    let mut child = Command::new("protoc").arg("--decode_raw")
+        .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped())
+        .spawn()?;
+    // Written from a thread, since protoc may fill its stdout pipe before it's read all of stdin.
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let data = data.to_vec();
+    let writer = std::thread::spawn(move || stdin.write_all(&data));
+    let output = child.wait_with_output()?;
+    // protoc stops reading when it fails to parse, so a broken pipe here is its error to report.
+    let _ = writer.join().expect("writer thread panicked");
+    if !output.status.success() {
+        return Ok(Err(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+    }
+    Ok(Ok(String::from_utf8_lossy(&output.stdout).lines().map(normalize).collect()))
+}
+
+/// Re-escapes the string on a protoc output line, and drops its indentation.
+fn normalize(line: &str) -> String {
+    let line = line.trim();
+    match line.find(": \"") {
+        Some(start) if line.ends_with('"') => {
+            let quoted = &line[start + 3..line.len() - 1];
+            format!("{}: \"{}\"", &line[..start], escape(&unescape(quoted)))
+        }
+        _ => line.to_string(),
+    }
+}
+
+/// Undoes C escaping, taking octal and hex escapes as bytes and passing anything else through.
+fn unescape(escaped: &str) -> Vec<u8> {
+    let bytes = escaped.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'\\' || i + 1 == bytes.len() {
+            out.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        i += 1;
+        let digits = |radix: u32, max: usize, from: usize| {
+            bytes[from..].iter().take(max).take_while(|b| (b.to_ascii_lowercase() as char).is_digit(radix)).count()
+        };
+        match bytes[i] {
+            b'n' => { out.push(b'\n'); i += 1 }
+            b'r' => { out.push(b'\r'); i += 1 }
+            b't' => { out.push(b'\t'); i += 1 }
+            b'a' => { out.push(0x07); i += 1 }
+            b'b' => { out.push(0x08); i += 1 }
+            b'f' => { out.push(0x0c); i += 1 }
+            b'v' => { out.push(0x0b); i += 1 }
+            b'0'..=b'7' => {
+                let len = digits(8, 3, i);
+                out.push(u32::from_str_radix(&escaped[i..i + len], 8).map_or(0, |value| value as u8));
+                i += len;
+            }
+            b'x' if digits(16, 2, i + 1) > 0 => {
+                let len = digits(16, 2, i + 1);
+                out.push(u8::from_str_radix(&escaped[i + 1..i + 1 + len], 16).unwrap_or(0));
+                i += 1 + len;
+            }
+            other => { out.push(other); i += 1 }
+        }
+    }
+    out
+}
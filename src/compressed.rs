@@ -0,0 +1,56 @@
+//! `--decompress`: length-delimited values that are messages compressed with gzip, zlib or zstd,
+//! recognized by the magic numbers they start with.
+
+use std::io::Read;
+
+/// How much a value may decompress to, so that a small bomb can't take all the memory there is.
+const MAX_DECOMPRESSED: u64 = 64 << 20;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Zlib,
+    Zstd,
+}
+
+impl Compression {
+    pub fn name(self) -> &'static str {
+        match self {
+            Compression::Gzip => "gzip",
+            Compression::Zlib => "zlib",
+            Compression::Zstd => "zstd",
+        }
+    }
+
+    /// The compression `data` starts with the header of, if any.
+    fn detect(data: &[u8]) -> Option<Self> {
+        match data {
+            [0x1f, 0x8b, ..] => Some(Compression::Gzip),
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Some(Compression::Zstd),
+            // A zlib header is deflate with a window of at most 32 KiB, and a checksum that makes it a
+            // multiple of 31.
+            &[cmf, flg, ..] if cmf & 0x0f == 8 && cmf >> 4 <= 7 && (u16::from(cmf) << 8 | u16::from(flg)).is_multiple_of(31) => Some(Compression::Zlib),
+            _ => None,
+        }
+    }
+
+    fn decompress(self, data: &[u8]) -> Option<Vec<u8>> {
+        let mut out = Vec::new();
+        let read = match self {
+            Compression::Gzip => flate2::read::GzDecoder::new(data).take(MAX_DECOMPRESSED + 1).read_to_end(&mut out),
+            Compression::Zlib => flate2::read::ZlibDecoder::new(data).take(MAX_DECOMPRESSED + 1).read_to_end(&mut out),
+            Compression::Zstd => ruzstd::decoding::StreamingDecoder::new(data).ok()?.take(MAX_DECOMPRESSED + 1).read_to_end(&mut out),
+        };
+        read.ok().filter(|&len| len as u64 <= MAX_DECOMPRESSED)?;
+        Some(out)
+    }
+}
+
+/// What a value decompresses to and how it was compressed, if it's compressed and what it holds
+/// parses as a message.
+pub fn message(data: &[u8]) -> Option<(Compression, Vec<u8>)> {
+    let compression = Compression::detect(data)?;
+    let inflated = compression.decompress(data)?;
+    crate::parse_message(&inflated, false).ok()?;
+    Some((compression, inflated))
+}
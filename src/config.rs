@@ -0,0 +1,110 @@
+//! The config file, which describes custom container formats and extends the dictionary of message
+//! types that `--suggest-types` draws on. It's read from `--config`, `PROTOCAT_CONFIG`, or
+//! `protocat/config.yaml` in the user's config directory if that exists.
+//!
+//! ```yaml
+//! dictionary:
+//!   - protos/acme/events.proto
+//! containers:
+//!   - name: acme-log
+//!     header: 16         # file header to skip
+//!     length: u32be      # varint, or u8, u16, u32 or u64 with le or be
+//!     after-length: 4    # e.g. a header CRC
+//!     trailer: 4         # e.g. a data CRC
+//! ```
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::framing::{Container, LengthField};
+use crate::yaml::{self, Item};
+
+#[derive(Default)]
+pub struct Config {
+    pub containers: Vec<Container>,
+    /// `.proto` files whose message types are suggested alongside the bundled ones.
+    pub dictionary: Vec<String>,
+}
+
+impl Config {
+    pub fn container(&self, name: &str) -> Result<&Container, String> {
+        self.containers.iter().find(|container| container.name == name).ok_or_else(|| {
+            if self.containers.is_empty() {
+                return format!("no container {}; containers are defined in the config file", name);
+            }
+            let names: Vec<&str> = self.containers.iter().map(|container| container.name.as_str()).collect();
+            format!("no container {}, the choices are {}", name, names.join(", "))
+        })
+    }
+}
+
+/// Loads the given config file, or the default one. Only a missing default is not an error.
+pub fn load(path: Option<&str>) -> Result<Config, String> {
+    let (path, required) = match path {
+        Some(path) => (PathBuf::from(path), true),
+        None => match default_path() {
+            Some(path) => (path, false),
+            None => return Ok(Config::default()),
+        },
+    };
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(_) if !required && !path.exists() => return Ok(Config::default()),
+        Err(err) => return Err(format!("{}: {}", path.display(), err)),
+    };
+    parse(&text, path.parent()).map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+fn default_path() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(dir.join("protocat").join("config.yaml"))
+}
+
+/// Parses a config file; relative paths in it are taken relative to `base`, its directory.
+fn parse(text: &str, base: Option<&Path>) -> Result<Config, String> {
+    let mut config = Config::default();
+    for (key, items) in yaml::lists(text)? {
+        if key == "dictionary" {
+            for item in items {
+                match item {
+                    Item::Scalar(path) => config.dictionary.push(base.map_or(path.clone(), |base| base.join(&path).to_string_lossy().into_owned())),
+                    Item::Mapping(_) => return Err("dictionary entries have to be paths".to_string()),
+                }
+            }
+            continue;
+        }
+        if key != "containers" {
+            return Err(format!("unknown key {}", if key.is_empty() { "(a list with no key)" } else { &key }));
+        }
+        for item in items {
+            let pairs = match item {
+                Item::Mapping(pairs) => pairs,
+                Item::Scalar(_) => return Err("a container has to be a mapping".to_string()),
+            };
+            let mut container = Container{name: String::new(), header: 0, skip: 0, length: LengthField::Varint, after_length: 0, trailer: 0};
+            for pair in pairs {
+                let error = |message: String| format!("line {}: {}", pair.line, message);
+                let size = || pair.value.parse::<usize>().map_err(|_| error(format!("{} has to be a byte count", pair.key)));
+                match pair.key.as_str() {
+                    "name" => container.name = pair.value.clone(),
+                    "header" => container.header = size()?,
+                    "skip" => container.skip = size()?,
+                    "length" => {
+                        container.length = LengthField::parse(&pair.value)
+                            .ok_or_else(|| error(format!("unknown length field {}", pair.value)))?;
+                    }
+                    "after-length" | "after_length" => container.after_length = size()?,
+                    "trailer" => container.trailer = size()?,
+                    _ => return Err(error(format!("unknown key {}", pair.key))),
+                }
+            }
+            if container.name.is_empty() {
+                return Err("every container needs a name".to_string());
+            }
+            config.containers.push(container);
+        }
+    }
+    Ok(config)
+}
@@ -0,0 +1,97 @@
+//! Testee mode for the official protobuf conformance test runner.
+//!
+//! The runner writes each `conformance.ConformanceRequest` to stdin prefixed with its length as a
+//! little-endian u32, and expects a `conformance.ConformanceResponse` framed the same way on stdout.
+//! Since protocat has no schema for the test messages, it can only parse binary payloads and
+//! re-serialize what it decoded; everything else is reported as skipped.
+
+use std::io::{self, Read, Write};
+
+use nom::combinator::all_consuming;
+use nom::AsBytes;
+
+use crate::encode::{encode_message, write_bytes};
+use crate::{describe_error, protobuf, Error, Span, WireValue};
+
+// ConformanceRequest field numbers.
+const REQUEST_PROTOBUF_PAYLOAD: u64 = 1;
+const REQUEST_OUTPUT_FORMAT: u64 = 3;
+const REQUEST_MESSAGE_TYPE: u64 = 4;
+
+// ConformanceResponse field numbers.
+const RESPONSE_PARSE_ERROR: u64 = 1;
+const RESPONSE_RUNTIME_ERROR: u64 = 2;
+const RESPONSE_PROTOBUF_PAYLOAD: u64 = 3;
+const RESPONSE_SKIPPED: u64 = 5;
+
+// conformance.WireFormat values.
+const WIRE_FORMAT_PROTOBUF: u64 = 1;
+
+/// Runs requests from stdin until the runner closes it.
+pub fn run() -> io::Result<()> {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut input = stdin.lock();
+    let mut output = stdout.lock();
+
+    loop {
+        let mut len = [0u8; 4];
+        match input.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(err) => return Err(err),
+        }
+        let mut request = vec![0u8; u32::from_le_bytes(len) as usize];
+        input.read_exact(&mut request)?;
+
+        let response = respond(&request);
+        output.write_all(&(response.len() as u32).to_le_bytes())?;
+        output.write_all(&response)?;
+        output.flush()?;
+    }
+}
+
+fn respond(request: &[u8]) -> Vec<u8> {
+    let mut response = Vec::new();
+    let fields = match all_consuming(protobuf::<_, Error<_>>(false))(Span::new(request)) {
+        Ok((_, fields)) => fields,
+        Err(_) => {
+            write_bytes(&mut response, RESPONSE_RUNTIME_ERROR, b"malformed ConformanceRequest");
+            return response;
+        }
+    };
+
+    let mut payload = None;
+    let mut output_format = 0;
+    let mut message_type: &[u8] = b"";
+    for field in fields.iter() {
+        match (field.tag_number, field.value) {
+            (REQUEST_PROTOBUF_PAYLOAD, WireValue::LengthPrefixed(d)) => payload = Some(d),
+            (REQUEST_OUTPUT_FORMAT, WireValue::VarInt(v)) => output_format = v,
+            (REQUEST_MESSAGE_TYPE, WireValue::LengthPrefixed(d)) => message_type = d.fragment(),
+            _ => {}
+        }
+    }
+
+    // The runner first asks for the list of tests expected to fail; an empty set serializes to nothing.
+    if message_type == b"conformance.FailureSet" {
+        write_bytes(&mut response, RESPONSE_PROTOBUF_PAYLOAD, b"");
+        return response;
+    }
+
+    let payload = match payload {
+        Some(payload) if output_format == WIRE_FORMAT_PROTOBUF => payload,
+        _ => {
+            write_bytes(&mut response, RESPONSE_SKIPPED, b"protocat only handles binary wire format input and output");
+            return response;
+        }
+    };
+
+    match all_consuming(protobuf::<_, Error<_>>(false))(Span::new(payload.as_bytes())) {
+        Ok((_, fields)) => write_bytes(&mut response, RESPONSE_PROTOBUF_PAYLOAD, &encode_message(&fields)),
+        Err(nom::Err::Error(err)) | Err(nom::Err::Failure(err)) =>
+            write_bytes(&mut response, RESPONSE_PARSE_ERROR, describe_error(&err).as_bytes()),
+        Err(nom::Err::Incomplete(_)) => unreachable!("complete parsers never return Incomplete"),
+    }
+    response
+}
@@ -0,0 +1,260 @@
+//! Decoding of `google/protobuf/descriptor.proto` messages, using protocat's own wire parser.
+//!
+//! Only the parts protocat has a use for are kept; options are kept as raw bytes so that custom
+//! options survive even though their definitions aren't known.
+
+use nom::AsBytes;
+
+use crate::{parse_message, ProtoField, Span, WireValue};
+
+#[derive(Clone, Debug, Default)]
+pub struct FileDescriptor {
+    pub name: String,
+    pub package: String,
+    pub dependencies: Vec<String>,
+    pub messages: Vec<MessageDescriptor>,
+    pub enums: Vec<EnumDescriptor>,
+    pub services: Vec<ServiceDescriptor>,
+    pub extensions: Vec<FieldDescriptor>,
+    pub options: Vec<u8>,
+    pub syntax: String,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct MessageDescriptor {
+    pub name: String,
+    pub fields: Vec<FieldDescriptor>,
+    pub nested_messages: Vec<MessageDescriptor>,
+    pub enums: Vec<EnumDescriptor>,
+    pub extensions: Vec<FieldDescriptor>,
+    pub oneofs: Vec<String>,
+    pub options: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct FieldDescriptor {
+    pub name: String,
+    pub number: u64,
+    pub label: u64,
+    pub ty: u64,
+    /// Fully qualified with a leading dot, for message and enum fields.
+    pub type_name: String,
+    pub extendee: String,
+    pub default_value: Option<String>,
+    pub oneof_index: Option<u64>,
+    pub json_name: Option<String>,
+    pub proto3_optional: bool,
+    /// The packed option, or the syntax's default once the field is added to a `Schema`.
+    pub packed: Option<bool>,
+    pub options: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct EnumDescriptor {
+    pub name: String,
+    pub values: Vec<(String, i32)>,
+    pub options: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ServiceDescriptor {
+    pub name: String,
+    pub methods: Vec<MethodDescriptor>,
+    pub options: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct MethodDescriptor {
+    pub name: String,
+    pub input_type: String,
+    pub output_type: String,
+    pub client_streaming: bool,
+    pub server_streaming: bool,
+    pub options: Vec<u8>,
+}
+
+/// The names of `FieldDescriptorProto.Type` values, indexed by number.
+pub const TYPE_NAMES: [&str; 19] = [
+    "", "double", "float", "int64", "uint64", "int32", "fixed64", "fixed32", "bool", "string",
+    "group", "message", "bytes", "uint32", "enum", "sfixed32", "sfixed64", "sint32", "sint64",
+];
+
+pub const LABEL_OPTIONAL: u64 = 1;
+pub const LABEL_REQUIRED: u64 = 2;
+pub const LABEL_REPEATED: u64 = 3;
+
+pub const TYPE_GROUP: u64 = 10;
+pub const TYPE_MESSAGE: u64 = 11;
+pub const TYPE_ENUM: u64 = 14;
+
+fn string(data: Span) -> String {
+    String::from_utf8_lossy(data.as_bytes()).into_owned()
+}
+
+/// Parses a submessage, failing with a message naming what it was meant to be.
+fn fields<'a>(data: Span<'a>, what: &str) -> Result<Vec<ProtoField<Span<'a>>>, String> {
+    parse_message(data.fragment(), false).map_err(|err| format!("malformed {}: {}", what, err))
+}
+
+impl FileDescriptor {
+    pub fn decode(data: &[u8]) -> Result<Self, String> {
+        let fields = parse_message(data, false).map_err(|err| format!("malformed FileDescriptorProto: {}", err))?;
+        let mut file = FileDescriptor::default();
+        for field in fields {
+            match (field.tag_number, field.value) {
+                (1, WireValue::LengthPrefixed(d)) => file.name = string(d),
+                (2, WireValue::LengthPrefixed(d)) => file.package = string(d),
+                (3, WireValue::LengthPrefixed(d)) => file.dependencies.push(string(d)),
+                (4, WireValue::LengthPrefixed(d)) => file.messages.push(MessageDescriptor::decode(d)?),
+                (5, WireValue::LengthPrefixed(d)) => file.enums.push(EnumDescriptor::decode(d)?),
+                (6, WireValue::LengthPrefixed(d)) => file.services.push(ServiceDescriptor::decode(d)?),
+                (7, WireValue::LengthPrefixed(d)) => file.extensions.push(FieldDescriptor::decode(d)?),
+                (8, WireValue::LengthPrefixed(d)) => file.options = d.as_bytes().to_vec(),
+                (12, WireValue::LengthPrefixed(d)) => file.syntax = string(d),
+                _ => {}
+            }
+        }
+        Ok(file)
+    }
+}
+
+impl MessageDescriptor {
+    fn decode(data: Span) -> Result<Self, String> {
+        let mut message = MessageDescriptor::default();
+        for field in fields(data, "DescriptorProto")? {
+            match (field.tag_number, field.value) {
+                (1, WireValue::LengthPrefixed(d)) => message.name = string(d),
+                (2, WireValue::LengthPrefixed(d)) => message.fields.push(FieldDescriptor::decode(d)?),
+                (3, WireValue::LengthPrefixed(d)) => message.nested_messages.push(MessageDescriptor::decode(d)?),
+                (4, WireValue::LengthPrefixed(d)) => message.enums.push(EnumDescriptor::decode(d)?),
+                (6, WireValue::LengthPrefixed(d)) => message.extensions.push(FieldDescriptor::decode(d)?),
+                (7, WireValue::LengthPrefixed(d)) => message.options = d.as_bytes().to_vec(),
+                (8, WireValue::LengthPrefixed(d)) => {
+                    for oneof in fields(d, "OneofDescriptorProto")? {
+                        if let (1, WireValue::LengthPrefixed(name)) = (oneof.tag_number, oneof.value) {
+                            message.oneofs.push(string(name));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl FieldDescriptor {
+    fn decode(data: Span) -> Result<Self, String> {
+        let mut result = FieldDescriptor::default();
+        for field in fields(data, "FieldDescriptorProto")? {
+            match (field.tag_number, field.value) {
+                (1, WireValue::LengthPrefixed(d)) => result.name = string(d),
+                (2, WireValue::LengthPrefixed(d)) => result.extendee = string(d),
+                (3, WireValue::VarInt(v)) => result.number = v,
+                (4, WireValue::VarInt(v)) => result.label = v,
+                (5, WireValue::VarInt(v)) => result.ty = v,
+                (6, WireValue::LengthPrefixed(d)) => result.type_name = string(d),
+                (7, WireValue::LengthPrefixed(d)) => result.default_value = Some(string(d)),
+                (8, WireValue::LengthPrefixed(d)) => {
+                    for option in fields(d, "FieldOptions")? {
+                        if let (2, WireValue::VarInt(v)) = (option.tag_number, option.value) {
+                            result.packed = Some(v != 0);
+                        }
+                    }
+                    result.options = d.as_bytes().to_vec();
+                }
+                (9, WireValue::VarInt(v)) => result.oneof_index = Some(v),
+                (10, WireValue::LengthPrefixed(d)) => result.json_name = Some(string(d)),
+                (17, WireValue::VarInt(v)) => result.proto3_optional = v != 0,
+                _ => {}
+            }
+        }
+        Ok(result)
+    }
+
+    pub fn type_name(&self) -> &str {
+        match self.ty {
+            TYPE_GROUP | TYPE_MESSAGE | TYPE_ENUM if !self.type_name.is_empty() => self.type_name.trim_start_matches('.'),
+            ty => TYPE_NAMES.get(ty as usize).copied().unwrap_or("unknown"),
+        }
+    }
+
+    /// The field's name in proto3 JSON: its json_name option, or else its name in lowerCamelCase
+    /// the way protoc derives it.
+    pub fn json_name(&self) -> String {
+        if let Some(json_name) = &self.json_name {
+            return json_name.clone();
+        }
+        let mut name = String::with_capacity(self.name.len());
+        let mut capitalize = false;
+        for c in self.name.chars() {
+            if c == '_' {
+                capitalize = true;
+            } else if capitalize {
+                name.push(c.to_ascii_uppercase());
+                capitalize = false;
+            } else {
+                name.push(c);
+            }
+        }
+        name
+    }
+}
+
+impl EnumDescriptor {
+    fn decode(data: Span) -> Result<Self, String> {
+        let mut result = EnumDescriptor::default();
+        for field in fields(data, "EnumDescriptorProto")? {
+            match (field.tag_number, field.value) {
+                (1, WireValue::LengthPrefixed(d)) => result.name = string(d),
+                (2, WireValue::LengthPrefixed(d)) => {
+                    let (mut name, mut number) = (String::new(), 0);
+                    for value in fields(d, "EnumValueDescriptorProto")? {
+                        match (value.tag_number, value.value) {
+                            (1, WireValue::LengthPrefixed(d)) => name = string(d),
+                            (2, WireValue::VarInt(v)) => number = v as i32,
+                            _ => {}
+                        }
+                    }
+                    result.values.push((name, number));
+                }
+                (3, WireValue::LengthPrefixed(d)) => result.options = d.as_bytes().to_vec(),
+                _ => {}
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl ServiceDescriptor {
+    fn decode(data: Span) -> Result<Self, String> {
+        let mut result = ServiceDescriptor::default();
+        for field in fields(data, "ServiceDescriptorProto")? {
+            match (field.tag_number, field.value) {
+                (1, WireValue::LengthPrefixed(d)) => result.name = string(d),
+                (2, WireValue::LengthPrefixed(d)) => result.methods.push(MethodDescriptor::decode(d)?),
+                (3, WireValue::LengthPrefixed(d)) => result.options = d.as_bytes().to_vec(),
+                _ => {}
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl MethodDescriptor {
+    fn decode(data: Span) -> Result<Self, String> {
+        let mut result = MethodDescriptor::default();
+        for field in fields(data, "MethodDescriptorProto")? {
+            match (field.tag_number, field.value) {
+                (1, WireValue::LengthPrefixed(d)) => result.name = string(d),
+                (2, WireValue::LengthPrefixed(d)) => result.input_type = string(d),
+                (3, WireValue::LengthPrefixed(d)) => result.output_type = string(d),
+                (4, WireValue::LengthPrefixed(d)) => result.options = d.as_bytes().to_vec(),
+                (5, WireValue::VarInt(v)) => result.client_streaming = v != 0,
+                (6, WireValue::VarInt(v)) => result.server_streaming = v != 0,
+                _ => {}
+            }
+        }
+        Ok(result)
+    }
+}
@@ -0,0 +1,180 @@
+//! `diff`: comparing two messages field by field, so that two serialized configs can be told apart
+//! without diffing their dumps by hand.
+//!
+//! Both messages are decoded with the usual heuristics, and their fields are matched up by number:
+//! a field's occurrences are compared in order, so the nth value of field 2 on one side against the
+//! nth on the other, and submessages on both sides are compared in turn. Paths are dotted tag
+//! numbers with an index on fields that occur more than once, like `2[1].3`.
+
+use nom::AsBytes;
+
+use crate::warning::Warnings;
+use crate::{encoded_bytes, format_packed, interpret, text, Cursor, Interpretation, Options, ProtoField, Span, WireValue};
+
+/// A decoded value.
+#[derive(Clone, PartialEq)]
+enum Node {
+    Scalar(String),
+    Message(Vec<(u64, Node)>),
+}
+
+/// One difference between the two messages.
+pub enum Change {
+    Added(String, String),
+    Removed(String, String),
+    Changed(String, String, String),
+    /// The same fields, written in a different order.
+    Reordered(String),
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Change::Added(path, value) => write!(f, "+ {}: {}", path, value),
+            Change::Removed(path, value) => write!(f, "- {}: {}", path, value),
+            Change::Changed(path, old, new) => write!(f, "~ {}: {} -> {}", path, old, new),
+            Change::Reordered(path) => write!(f, "~ {}: same fields in a different order", path),
+        }
+    }
+}
+
+/// Decodes a message's fields into nodes, gathering groups up into messages. Groups nested deeper
+/// than `--max-depth` are kept as the bytes of their fields, as submessages are by `interpret`.
+fn decode(mut level: Cursor, path: &mut Vec<u64>, options: &Options, warnings: &mut Warnings) -> Vec<(u64, Node)> {
+    let mut nodes = Vec::new();
+    while let Some(index) = level.next() {
+        let field = level.list.fields[index];
+        path.push(field.tag_number);
+        let node = match field.value {
+            WireValue::VarInt(v) | WireValue::Int64(v) => Some(Node::Scalar(v.to_string())),
+            WireValue::Int32(v) => Some(Node::Scalar(v.to_string())),
+            WireValue::StartGroup => {
+                let (content, _) = level.list.group(index);
+                if path.len() > options.max_depth {
+                    let data = encoded_bytes(&level.list.fields[content]);
+                    Some(Node::Scalar(options.bytes_format.format(&data)))
+                } else {
+                    Some(Node::Message(decode(Cursor::new(level.list.clone(), content), path, options, warnings)))
+                }
+            }
+            WireValue::EndGroup => None,
+            WireValue::LengthPrefixed(d) => Some(match interpret(d, options, path, warnings) {
+                Interpretation::Message(fields) => Node::Message(decode(Cursor::message(fields), path, options, warnings)),
+                Interpretation::String(str, _) | Interpretation::LossyString(str, _) => Node::Scalar(text::quote(&str)),
+                Interpretation::Packed(values, wire_type) => Node::Scalar(format_packed(&values, wire_type)),
+                Interpretation::Bytes => Node::Scalar(options.bytes_format.format(d.as_bytes())),
+            }),
+        };
+        path.pop();
+        nodes.extend(node.map(|node| (field.tag_number, node)));
+    }
+    nodes
+}
+
+/// A node on one line, with a message's fields in braces.
+fn render(node: &Node) -> String {
+    match node {
+        Node::Scalar(text) => text.clone(),
+        Node::Message(fields) => {
+            let fields: Vec<String> = fields.iter().map(|(number, node)| format!("{}: {}", number, render(node))).collect();
+            format!("{{{}}}", fields.join(", "))
+        }
+    }
+}
+
+/// A node rendered with the fields of every message sorted, so that nodes which differ only in
+/// field order render the same.
+fn canonical(node: &Node) -> String {
+    match node {
+        Node::Scalar(text) => text.clone(),
+        Node::Message(fields) => {
+            let mut fields: Vec<String> = fields.iter().map(|(number, node)| format!("{:020}: {}", number, canonical(node))).collect();
+            fields.sort();
+            format!("{{{}}}", fields.join(", "))
+        }
+    }
+}
+
+/// Each field number in a message, in ascending order, with its values in wire order.
+fn by_number(fields: &[(u64, Node)]) -> Vec<(u64, Vec<&Node>)> {
+    let mut numbers: Vec<(u64, Vec<&Node>)> = Vec::new();
+    for (number, node) in fields {
+        match numbers.iter_mut().find(|(n, _)| n == number) {
+            Some((_, nodes)) => nodes.push(node),
+            None => numbers.push((*number, vec![node])),
+        }
+    }
+    numbers.sort_by_key(|&(number, _)| number);
+    numbers
+}
+
+/// The values of a field, from the result of `by_number`.
+fn values<'n>(numbers: &[(u64, Vec<&'n Node>)], number: u64) -> Vec<&'n Node> {
+    numbers.iter().find(|(n, _)| *n == number).map(|(_, nodes)| nodes.clone()).unwrap_or_default()
+}
+
+fn join(prefix: &str, segment: String) -> String {
+    if prefix.is_empty() { segment } else { format!("{}.{}", prefix, segment) }
+}
+
+/// Compares two messages at `path`, adding their differences to `changes`. With `ignore_order`,
+/// fields may come in any order and so may the values of repeated fields: equal values are paired
+/// up wherever they are, and only those left over are compared.
+fn compare(a: &[(u64, Node)], b: &[(u64, Node)], path: &str, ignore_order: bool, changes: &mut Vec<Change>) {
+    let (a_numbers, b_numbers) = (by_number(a), by_number(b));
+    let mut numbers: Vec<u64> = a_numbers.iter().chain(b_numbers.iter()).map(|&(number, _)| number).collect();
+    numbers.sort_unstable();
+    numbers.dedup();
+    let before = changes.len();
+    for number in numbers {
+        let (a_values, b_values) = (values(&a_numbers, number), values(&b_numbers, number));
+        let indexed = a_values.len() > 1 || b_values.len() > 1;
+        let segment = |i: usize| if indexed { format!("{}[{}]", number, i) } else { number.to_string() };
+        // Indexes into each side of the values still to be compared.
+        let mut a_left: Vec<usize> = (0..a_values.len()).collect();
+        let mut b_left: Vec<usize> = (0..b_values.len()).collect();
+        if ignore_order {
+            let b_canonical: Vec<String> = b_values.iter().map(|node| canonical(node)).collect();
+            a_left.retain(|&i| {
+                let form = canonical(a_values[i]);
+                match b_left.iter().position(|&j| b_canonical[j] == form) {
+                    Some(position) => {
+                        b_left.remove(position);
+                        false
+                    }
+                    None => true,
+                }
+            });
+        }
+        for k in 0..a_left.len().max(b_left.len()) {
+            match (a_left.get(k), b_left.get(k)) {
+                (Some(&i), Some(&j)) => match (a_values[i], b_values[j]) {
+                    (Node::Message(a), Node::Message(b)) => compare(a, b, &join(path, segment(i)), ignore_order, changes),
+                    (a, b) if a != b => changes.push(Change::Changed(join(path, segment(i)), render(a), render(b))),
+                    _ => {}
+                },
+                (Some(&i), None) => changes.push(Change::Removed(join(path, segment(i)), render(a_values[i]))),
+                (None, Some(&j)) => changes.push(Change::Added(join(path, segment(j)), render(b_values[j]))),
+                (None, None) => unreachable!("k is within one of the sides"),
+            }
+        }
+    }
+    // Fields that match up but were written in another order are only worth a note when nothing
+    // else differs here, since added and removed fields reorder the rest anyway.
+    let order = |fields: &[(u64, Node)]| fields.iter().map(|&(number, _)| number).collect::<Vec<_>>();
+    if !ignore_order && changes.len() == before && order(a) != order(b) {
+        changes.push(Change::Reordered(if path.is_empty() { ".".to_string() } else { path.to_string() }));
+    }
+}
+
+/// Compares two messages, each given with the name of its input for warnings, returning their
+/// differences in field order.
+pub fn run(a: (&str, Vec<ProtoField<Span>>), b: (&str, Vec<ProtoField<Span>>), ignore_order: bool, options: &Options, warnings: &mut Warnings) -> Vec<Change> {
+    warnings.set_input(a.0);
+    let a = decode(Cursor::message(a.1), &mut Vec::new(), options, warnings);
+    warnings.set_input(b.0);
+    let b = decode(Cursor::message(b.1), &mut Vec::new(), options, warnings);
+    let mut changes = Vec::new();
+    compare(&a, &b, "", ignore_order, &mut changes);
+    changes
+}
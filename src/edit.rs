@@ -0,0 +1,174 @@
+//! `edit` and `strip`: setting, removing and redacting fields in a message and writing it back out,
+//! such as to tweak a captured request for a test or to share one without its secrets.
+//!
+//! Only the fields being changed are re-encoded, along with the lengths of the submessages they are
+//! in; every other byte is copied over as it was, padded varints and all.
+
+use nom::combinator::all_consuming;
+use nom::AsBytes;
+
+use crate::assemble::{self, Scalar};
+use crate::encode::{write_tag, write_varint};
+use crate::path::parse_path;
+use crate::{encoded_bytes, protobuf, Cursor, Error, ProtoField, Span, WireType, WireValue};
+
+/// What to do to the fields at a path.
+#[derive(Clone, Debug)]
+pub enum Action {
+    /// Give them a value, written as `encode` reads it.
+    Set(String),
+    Delete,
+    /// Replace their data with a placeholder.
+    Redact(Vec<u8>),
+}
+
+/// A change to the fields at a path.
+#[derive(Clone, Debug)]
+pub struct Edit {
+    path: Vec<u64>,
+    action: Action,
+}
+
+impl Edit {
+    pub fn new(path: Vec<u64>, action: Action) -> Self {
+        Edit{path, action}
+    }
+
+    /// Parses `--set PATH=VALUE`.
+    pub fn parse_set(text: &str) -> Result<Self, String> {
+        let (path, value) = text.split_once('=').ok_or_else(|| format!("expected PATH=VALUE, got '{}'", text))?;
+        let path = parse_path(path)?;
+        assemble::value(value)?;
+        Ok(Edit{path, action: Action::Set(value.to_string())})
+    }
+
+    /// The value encoded for a field that has `wire_type` now. A plain number keeps a fixed-width
+    /// field fixed-width, as if it had the `i32` or `i64` suffix.
+    fn encode(value: &str, wire_type: Option<WireType>) -> Scalar {
+        let suffix = match wire_type {
+            Some(WireType::Int32) => "i32",
+            Some(WireType::Int64) => "i64",
+            _ => "",
+        };
+        // Anything but a plain number doesn't parse with a suffix added.
+        let suffixed = (!suffix.is_empty()).then(|| assemble::value(&format!("{}{}", value.trim(), suffix)).ok()).flatten();
+        suffixed.unwrap_or_else(|| assemble::value(value).expect("values are checked when parsed"))
+    }
+}
+
+fn wire_type<I>(value: &WireValue<I>) -> WireType {
+    match value {
+        WireValue::VarInt(_) => WireType::VarInt,
+        WireValue::Int64(_) => WireType::Int64,
+        WireValue::LengthPrefixed(_) => WireType::LengthPrefixed,
+        WireValue::StartGroup => WireType::StartGroup,
+        WireValue::EndGroup => WireType::EndGroup,
+        WireValue::Int32(_) => WireType::Int32,
+    }
+}
+
+/// Writes a field with a new value, keeping its tag's bytes if its wire type doesn't change.
+fn write_field(out: &mut Vec<u8>, field: &ProtoField<Span>, (wire_type, value): Scalar) {
+    if self::wire_type(&field.value) == wire_type {
+        out.extend_from_slice(tag_bytes(field));
+    } else {
+        write_tag(out, field.tag_number, wire_type);
+    }
+    out.extend(value);
+}
+
+/// The bytes of a field's tag, as they were written.
+fn tag_bytes<'a>(field: &ProtoField<Span<'a>>) -> &'a [u8] {
+    let raw: &'a [u8] = field.raw.fragment();
+    &raw[..raw.iter().position(|b| b & 0x80 == 0).map_or(raw.len(), |i| i + 1)]
+}
+
+/// Applies the edits to a message, each with its path relative to the message, returning the new
+/// message. Every field at an edit's path is changed. A field that isn't there is added to the end
+/// of its message by a set, along with any submessages on the way to it that aren't there either,
+/// and is left alone by the others.
+pub fn apply(fields: Vec<ProtoField<Span>>, edits: &[&Edit], depth: usize) -> Result<Vec<u8>, String> {
+    apply_to(Cursor::message(fields), edits, depth)
+}
+
+/// Applies the edits to one message level, a message or a group.
+fn apply_to(mut level: Cursor, edits: &[&Edit], depth: usize) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    let mut found = vec![false; edits.len()];
+    while let Some(index) = level.next() {
+        let field = level.list.fields[index];
+        let matching: Vec<usize> = (0..edits.len()).filter(|&i| edits[i].path[depth] == field.tag_number).collect();
+        for &i in &matching {
+            found[i] = true;
+        }
+        // The last edit of the field itself wins, as with repeated flags elsewhere.
+        let own = matching.iter().rev().map(|&i| edits[i]).find(|edit| edit.path.len() == depth + 1);
+        let nested: Vec<&Edit> = matching.iter().map(|&i| edits[i]).filter(|edit| edit.path.len() > depth + 1).collect();
+        // A group's fields go with it.
+        let group = matches!(field.value, WireValue::StartGroup).then(|| level.list.group(index));
+        match (own.map(|edit| &edit.action), field.value, group) {
+            (Some(Action::Set(value)), _, Some(_)) => write_field(&mut out, &field, Edit::encode(value, None)),
+            (Some(Action::Set(value)), value_now, None) => write_field(&mut out, &field, Edit::encode(value, Some(wire_type(&value_now)))),
+            (Some(Action::Delete), _, _) => {}
+            (Some(Action::Redact(placeholder)), WireValue::LengthPrefixed(_), _) => {
+                out.extend_from_slice(tag_bytes(&field));
+                write_varint(&mut out, placeholder.len() as u64);
+                out.extend_from_slice(placeholder);
+            }
+            (Some(Action::Redact(_)), _, _) => {
+                return Err(format!("{} can't be redacted, since it isn't length-delimited", path_text(&own.expect("matched").path)));
+            }
+            (None, _, _) if nested.is_empty() => out.extend(encoded_bytes(&level.list.fields[level.list.extent(index)])),
+            (None, WireValue::LengthPrefixed(d), _) => {
+                let (_, inner) = all_consuming(protobuf::<_, Error<_>>(false))(Span::new(d.as_bytes()))
+                    .map_err(|_| format!("{} isn't a submessage", path_text(&nested[0].path[..=depth])))?;
+                let content = apply(inner, &nested, depth + 1)?;
+                out.extend_from_slice(tag_bytes(&field));
+                write_varint(&mut out, content.len() as u64);
+                out.extend(content);
+            }
+            (None, _, Some((content, end))) => {
+                out.extend_from_slice(field.raw.as_bytes());
+                out.extend(apply_to(Cursor::new(level.list.clone(), content), &nested, depth + 1)?);
+                if let Some(end) = end {
+                    out.extend_from_slice(end.raw.as_bytes());
+                }
+            }
+            (None, _, None) => return Err(format!("{} isn't a submessage", path_text(&nested[0].path[..=depth]))),
+        }
+    }
+    let mut added = Vec::new();
+    for (i, edit) in edits.iter().enumerate() {
+        let value = match &edit.action {
+            Action::Set(value) => value,
+            _ => continue,
+        };
+        let number = edit.path[depth];
+        if edit.path.len() > depth + 1 {
+            // A missing submessage is added where the first set in it is, with all of them applied
+            // to it at once.
+            if found[i] || added.contains(&number) {
+                continue;
+            }
+            added.push(number);
+            let nested: Vec<&Edit> = edits.iter().copied().filter(|other| other.path[depth] == number && other.path.len() > depth + 1).collect();
+            let content = apply(Vec::new(), &nested, depth + 1)?;
+            write_tag(&mut out, number, WireType::LengthPrefixed);
+            write_varint(&mut out, content.len() as u64);
+            out.extend(content);
+            continue;
+        }
+        // Only the last set of a missing field adds it.
+        if found[i] || edits[i + 1..].iter().any(|other| other.path == edit.path) {
+            continue;
+        }
+        let (wire_type, value) = Edit::encode(value, None);
+        write_tag(&mut out, number, wire_type);
+        out.extend(value);
+    }
+    Ok(out)
+}
+
+fn path_text(path: &[u64]) -> String {
+    path.iter().map(u64::to_string).collect::<Vec<_>>().join(".")
+}
@@ -0,0 +1,51 @@
+//! Serialization back to the wire format.
+
+use nom::AsBytes;
+
+use crate::{ProtoField, WireType, WireValue};
+
+pub use protocat::write_varint;
+
+pub fn write_tag(out: &mut Vec<u8>, tag_number: u64, wire_type: WireType) {
+    let wire_type = match wire_type {
+        WireType::VarInt => 0,
+        WireType::Int64 => 1,
+        WireType::LengthPrefixed => 2,
+        WireType::StartGroup => 3,
+        WireType::EndGroup => 4,
+        WireType::Int32 => 5,
+    };
+    write_varint(out, tag_number << 3 | wire_type);
+}
+
+pub fn write_bytes(out: &mut Vec<u8>, tag_number: u64, data: &[u8]) {
+    write_tag(out, tag_number, WireType::LengthPrefixed);
+    write_varint(out, data.len() as u64);
+    out.extend_from_slice(data);
+}
+
+/// Re-encodes parsed fields. Since fields keep their raw wire values, this reproduces the input
+/// exactly, except that non-canonical varints come out in their shortest form.
+pub fn encode_message<I: AsBytes>(fields: &[ProtoField<I>]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for field in fields {
+        match &field.value {
+            WireValue::VarInt(v) => {
+                write_tag(&mut out, field.tag_number, WireType::VarInt);
+                write_varint(&mut out, *v);
+            }
+            WireValue::Int64(v) => {
+                write_tag(&mut out, field.tag_number, WireType::Int64);
+                out.extend_from_slice(&v.to_le_bytes());
+            }
+            WireValue::LengthPrefixed(d) => write_bytes(&mut out, field.tag_number, d.as_bytes()),
+            WireValue::StartGroup => write_tag(&mut out, field.tag_number, WireType::StartGroup),
+            WireValue::EndGroup => write_tag(&mut out, field.tag_number, WireType::EndGroup),
+            WireValue::Int32(v) => {
+                write_tag(&mut out, field.tag_number, WireType::Int32);
+                out.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+    }
+    out
+}
@@ -0,0 +1,54 @@
+//! Errors that end a run, each kind with an exit code of its own so that scripts can tell them
+//! apart.
+
+use std::fmt;
+use std::process;
+
+/// The exit code of `diff` when the messages differ, which isn't an error but has to be told apart
+/// from every one of them.
+pub const DIFFERS: i32 = 6;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProtocatError {
+    /// The input isn't valid: it doesn't parse, is cut off, or doesn't match what was asked of it.
+    Parse(String),
+    /// The command line, a config file, a manifest, a template, a script or a plugin is wrong.
+    Usage(String),
+    /// A file or stdin couldn't be read.
+    Io(String),
+    /// A schema couldn't be loaded, or doesn't have what the options ask of it.
+    Schema(String),
+    /// The input went over a limit it was checked against.
+    Limit(String),
+}
+
+impl ProtocatError {
+    /// The process exit code for the error. Usage errors share 2 with clap's own.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ProtocatError::Parse(_) => 1,
+            ProtocatError::Usage(_) => 2,
+            ProtocatError::Io(_) => 3,
+            ProtocatError::Schema(_) => 4,
+            ProtocatError::Limit(_) => 5,
+        }
+    }
+
+    /// Prints the error to stderr and exits with its code.
+    pub fn exit(&self) -> ! {
+        eprintln!("protocat: {}", self);
+        process::exit(self.exit_code());
+    }
+}
+
+impl fmt::Display for ProtocatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProtocatError::Parse(message)
+            | ProtocatError::Usage(message)
+            | ProtocatError::Io(message)
+            | ProtocatError::Schema(message)
+            | ProtocatError::Limit(message) => f.write_str(message),
+        }
+    }
+}
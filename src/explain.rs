@@ -0,0 +1,164 @@
+//! `protocat explain`: a byte-by-byte account of a message, giving the range of every tag, length
+//! and value and what it was read as. `--format hexdump` prints the same account beside the bytes
+//! themselves, xxd-style.
+
+use std::io::Write;
+
+use nom::{AsBytes, InputLength};
+
+use crate::encode::write_varint;
+use crate::text::StringEncoding;
+use crate::warning::Warnings;
+use crate::{alternates, base128_vlq, format_packed, interpret, plural, Error, Interpretation, Options, PrintStats, ProtoField, Span, WireValue};
+
+/// How each tag, length and value is shown.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Style {
+    /// As the range of offsets it takes, e.g. `bytes 3-4: varint 150`.
+    Ranges,
+    /// As its bytes in hex, sixteen to a row, with the explanation beside the first row.
+    Hexdump,
+}
+
+/// The width of a hexdump row's bytes, each written as two digits and a space.
+const ROW: usize = 16;
+
+/// Prints one part of a field. With the hexdump style, a submessage's bytes aren't dumped along with
+/// its line, since its fields' lines dump them.
+fn print_part(out: &mut dyn Write, style: Style, indent: &str, start: usize, bytes: &[u8], what: &str, dump: bool) {
+    match style {
+        Style::Ranges => outln!(out, "{}{}: {}", indent, range(start, bytes.len()), what),
+        Style::Hexdump if !dump => outln!(out, "{:08x}: {:width$} {}{}", start, "", indent, what, width = ROW * 3),
+        Style::Hexdump => {
+            for (i, row) in bytes.chunks(ROW).enumerate() {
+                let hex: String = row.iter().map(|b| format!("{:02x} ", b)).collect();
+                if i == 0 {
+                    outln!(out, "{:08x}: {:width$} {}{}", start, hex, indent, what, width = ROW * 3);
+                } else {
+                    outln!(out, "{:08x}: {}", start + i * ROW, hex.trim_end());
+                }
+            }
+        }
+    }
+}
+
+/// Dumps bytes that `--lenient` skipped.
+pub fn print_skipped(out: &mut dyn Write, skipped: Span) {
+    print_part(out, Style::Hexdump, "", skipped.location_offset(), skipped.as_bytes(), "unparseable", true);
+}
+
+/// Prints the explanation of a parsed message, one line per tag, length or value, with the contents
+/// of submessages and groups indented beneath them.
+pub fn run(out: &mut dyn Write, fields: Vec<ProtoField<Span>>, style: Style, options: &Options, warnings: &mut Warnings) -> PrintStats {
+    // Walked with an explicit stack, as print_message does, so deep nesting can't overflow the call
+    // stack. Each level also counts the groups open in it, which indent their contents.
+    let mut stack = vec![(fields.into_iter(), 0usize)];
+    let mut path = Vec::new();
+    let mut stats = PrintStats{fields: 0, max_depth: 0};
+    while !stack.is_empty() {
+        let depth = stack.len() - 1;
+        let (level, groups) = &mut stack[depth];
+        let field = match level.next() {
+            Some(field) => field,
+            None => {
+                stack.pop();
+                path.pop();
+                continue;
+            }
+        };
+        if let WireValue::EndGroup = field.value {
+            *groups = groups.saturating_sub(1);
+        }
+        stats.fields += 1;
+        stats.max_depth = stats.max_depth.max(depth);
+        let indent = "  ".repeat(depth + *groups);
+        let raw = field.raw.as_bytes();
+        let offset = field.raw.location_offset();
+        let mut line = |start: usize, len: usize, what: String| {
+            print_part(out, style, &indent, start, &raw[start - offset..start - offset + len], &what, true);
+        };
+
+        let tag_len = varint_len(raw);
+        let wire_type = raw[0] & 7;
+        line(offset, tag_len, format!(
+            "tag (field {}, wire type {}: {}){}",
+            field.tag_number, wire_type, WIRE_TYPE_NAMES[wire_type as usize], padding(raw, tag_len),
+        ));
+        let value_offset = offset + tag_len;
+        match field.value {
+            WireValue::VarInt(v) => {
+                let len = raw.len() - tag_len;
+                let zigzag = alternates::zigzag(v);
+                let mut note = if (v as i64) < 0 { format!(", or {} as an int64", v as i64) } else { String::new() };
+                if zigzag < 0 {
+                    note += &format!(", or {} zigzag-decoded", zigzag);
+                }
+                line(value_offset, len, format!("varint {}{}{}", v, note, padding(&raw[tag_len..], len)));
+            }
+            WireValue::Int64(v) => line(value_offset, 8, format!("fixed64 {}, or {} as a double", v, f64::from_bits(v))),
+            WireValue::Int32(v) => line(value_offset, 4, format!("fixed32 {}, or {} as a float", v, f32::from_bits(v))),
+            WireValue::StartGroup => {
+                *groups += 1;
+            }
+            WireValue::EndGroup => {}
+            WireValue::LengthPrefixed(d) => {
+                let len_len = d.location_offset() - value_offset;
+                let size = d.input_len();
+                // An empty value has no bytes of its own to explain.
+                let empty = if size == 0 { ", an empty string, bytes or submessage" } else { "" };
+                line(value_offset, len_len, format!("length {}{}{}", size, empty, padding(&raw[tag_len..], len_len)));
+                if size == 0 {
+                    continue;
+                }
+                path.push(field.tag_number);
+                let what = match interpret(d, options, &path, warnings) {
+                    Interpretation::Message(fields) => {
+                        let what = format!("submessage of {} field{}", fields.len(), plural(fields.len()));
+                        print_part(out, style, &indent, d.location_offset(), d.as_bytes(), &what, false);
+                        stack.push((fields.into_iter(), 0));
+                        continue;
+                    }
+                    Interpretation::String(str, StringEncoding::Utf8) => format!("UTF-8 string {:?}", str),
+                    Interpretation::String(str, encoding) => format!("{} string {:?}", encoding.name(), str),
+                    Interpretation::LossyString(str, invalid) => {
+                        format!("mostly UTF-8 string {:?}, with {} invalid sequence{}", str, invalid, plural(invalid))
+                    }
+                    Interpretation::Packed(values, wire_type) => format!("packed values {}", format_packed(&values, wire_type)),
+                    Interpretation::Bytes => format!("raw bytes {}", options.bytes_format.format(d.as_bytes())),
+                };
+                path.pop();
+                line(d.location_offset(), size, what);
+            }
+        }
+    }
+    stats
+}
+
+const WIRE_TYPE_NAMES: [&str; 8] = ["varint", "fixed64", "length-delimited", "start group", "end group", "fixed32", "invalid", "invalid"];
+
+fn range(start: usize, len: usize) -> String {
+    if len == 1 {
+        format!("byte {}", start)
+    } else {
+        format!("bytes {}-{}", start, start + len - 1)
+    }
+}
+
+/// The length of the varint at the start of data.
+fn varint_len(data: &[u8]) -> usize {
+    let (rest, _) = base128_vlq::<_, Error<_>>(data).expect("the field was parsed");
+    data.len() - rest.len()
+}
+
+/// Notes a varint that takes more bytes than it needs to, which some encoders emit on purpose to
+/// patch lengths in later, and others by mistake.
+fn padding(data: &[u8], len: usize) -> String {
+    let (_, value) = base128_vlq::<_, Error<_>>(data).expect("the field was parsed");
+    let mut minimal = Vec::new();
+    write_varint(&mut minimal, value);
+    if minimal.len() < len {
+        format!(" (padded; {} byte{} would do)", minimal.len(), plural(minimal.len()))
+    } else {
+        String::new()
+    }
+}
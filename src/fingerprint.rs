@@ -0,0 +1,77 @@
+//! Suggestions of well-known message types for payloads decoded without a schema, by how well their
+//! fields fit: the bundled schemas make up the dictionary, extended by any `.proto` files the config
+//! file lists.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::schema::Schema;
+use crate::{builtin, proto, ProtoField, Span};
+
+/// How many suggestions are made for an input.
+const MAX_SUGGESTIONS: usize = 3;
+
+pub struct Dictionary {
+    /// Each schema, with how to decode with it: `--builtin NAME`, or the `.proto` file it came from.
+    schemas: Vec<(String, Schema)>,
+}
+
+pub struct Suggestion<'d> {
+    pub type_name: &'d str,
+    pub source: &'d str,
+    /// The names of the input's top-level fields under the type.
+    pub fields: Vec<(u64, &'d str)>,
+}
+
+impl Dictionary {
+    pub fn load(extra: &[String]) -> Result<Self, String> {
+        let mut schemas = Vec::new();
+        for name in builtin::names() {
+            let (_, schema) = builtin::load(name)?;
+            schemas.push((format!("--builtin {}", name), schema));
+        }
+        for path in extra {
+            // Imports are looked for next to the file, then among the bundled schemas.
+            let include = Path::new(path).parent().map(|dir| dir.to_string_lossy().into_owned()).filter(|dir| !dir.is_empty());
+            let mut schema = Schema::default();
+            for file in proto::load(std::slice::from_ref(path), include.as_slice())? {
+                schema.add_file(file);
+            }
+            schemas.push((format!("from {}", path), schema));
+        }
+        Ok(Dictionary{schemas})
+    }
+
+    /// The types that fit the fields best, best first. A type has to fit at least two fields and
+    /// fail to fit no more than one in ten; among equally good fits, types that declare fewer fields
+    /// are more specific and come first.
+    pub fn suggest(&self, fields: &[ProtoField<Span>]) -> Vec<Suggestion<'_>> {
+        let mut candidates = Vec::new();
+        let mut seen = HashSet::new();
+        for (source, schema) in self.schemas.iter() {
+            for name in schema.message_names() {
+                // Map entries are synthetic, and types shared between schemas are only scored once.
+                if name.ends_with("Entry") || !seen.insert(name) {
+                    continue;
+                }
+                let (fits, misfits) = schema.fit(fields, name).expect("the name came from the schema");
+                if fits >= 2 && misfits * 10 <= fits {
+                    let declared = schema.message(name).map_or(0, |message| message.fields.len());
+                    candidates.push((fits as i64 - misfits as i64, declared, name, source.as_str(), schema));
+                }
+            }
+        }
+        candidates.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)).then(a.2.cmp(b.2)));
+
+        let mut numbers: Vec<u64> = fields.iter().map(|field| field.tag_number).collect();
+        numbers.sort_unstable();
+        numbers.dedup();
+        candidates.into_iter().take(MAX_SUGGESTIONS).map(|(_, _, type_name, source, schema)| {
+            let message = schema.message(type_name).expect("the name came from the schema");
+            let fields = numbers.iter()
+                .filter_map(|&number| message.fields.iter().find(|f| f.number == number).map(|f| (number, f.name.as_str())))
+                .collect();
+            Suggestion{type_name, source, fields}
+        }).collect()
+    }
+}
@@ -0,0 +1,122 @@
+//! `--follow`: decoding a log of framed messages as it is written, like `tail -f` does for text.
+//!
+//! The file is read to its end and then polled for more, and a message that is only partly written
+//! is held back until the rest of it arrives. A file that shrinks was truncated, and is read again
+//! from the start. A file that another takes the name of, as when a log is rotated, is read to its
+//! end first, and then the new one is read from its start.
+
+use std::fs::{self, File, Metadata};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::thread;
+use std::time::Duration;
+
+use crate::framing::{self, Frame, Framing};
+use crate::schema::{print_as, Schema};
+use crate::warning::Warnings;
+use crate::{parse_message, plural, print_message_indented, Options};
+
+/// How long to wait before looking for more of the file once its end is reached.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// What tells one file from another that took its name, where the platform has it.
+#[cfg(unix)]
+fn identity(metadata: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn identity(_metadata: &Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Prints a message to `out` as a block, numbered across the whole run and placed by the frame's
+/// offset in the stream, with the schema's type if there is one. With `named`, the block's header gives the input the warnings are about as where the
+/// message came from, like `message 3 from 10.0.0.2:4312`, for messages from several places printed
+/// together. A message that doesn't parse is reported and shown as bytes, so that one bad record
+/// doesn't end the run.
+pub fn print_frame(out: &mut dyn Write, index: usize, frame: &Frame, schema: Option<(&Schema, &str)>, named: bool, options: &Options, warnings: &mut Warnings) {
+    let (offset, size) = (frame.offset, frame.data.len());
+    let from = if named { format!(" from {}", warnings.input()) } else { String::new() };
+    if frame.trailers {
+        outln!(out, "trailers{} (offset {}): {}", from, offset, framing::trailers(frame.data));
+        return;
+    }
+    if frame.compressed {
+        outln!(out, "message {}{} (offset {}, {} byte{}, compressed): {}", index, from, offset, size, plural(size), options.bytes_format.format(frame.data));
+        return;
+    }
+    match parse_message(frame.data, options.lenient) {
+        Ok(fields) => {
+            outln!(out, "message {}{} (offset {}, {} byte{}): {{", index, from, offset, size, plural(size));
+            match schema {
+                Some((schema, type_name)) => print_as(out, frame.data, schema, type_name, 1, options, warnings),
+                None => {
+                    print_message_indented(out, fields, 1, options, &[], warnings);
+                }
+            }
+            outln!(out, "}}");
+        }
+        Err(err) => {
+            eprintln!("protocat: {}: message {}: {}", warnings.input(), index, err);
+            outln!(out, "message {}{} (offset {}, {} byte{}): {}", index, from, offset, size, plural(size), options.bytes_format.format(frame.data));
+        }
+    }
+}
+
+/// Prints the messages in the file at `path` to `out` as they are written, until reading it fails.
+pub fn run(out: &mut dyn Write, path: &str, framing: Framing, schema: Option<(&Schema, &str)>, options: &Options, warnings: &mut Warnings) -> Result<(), String> {
+    let io_error = |err: std::io::Error| format!("{}: {}", path, err);
+    let mut file = File::open(path).map_err(io_error)?;
+    let mut current = identity(&file.metadata().map_err(io_error)?);
+    // What has been read but not printed yet, and where in the file it starts.
+    let mut pending = Vec::new();
+    let mut offset = 0;
+    let mut count = 0;
+    let mut chunk = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut chunk).map_err(io_error)?;
+        if read > 0 {
+            pending.extend_from_slice(&chunk[..read]);
+            let len = framing::complete_len(&pending, framing).expect("only streams are followed");
+            let mut frames = framing::split(&pending[..len], framing).map_err(|err| format!("{}: {}", path, err))?;
+            for frame in &mut frames {
+                frame.offset += offset;
+                print_frame(out, count, frame, schema, false, options, warnings);
+                count += 1;
+            }
+            pending.drain(..len);
+            offset += len;
+            continue;
+        }
+
+        thread::sleep(POLL_INTERVAL);
+        let position = (offset + pending.len()) as u64;
+        if file.metadata().map_err(io_error)?.len() < position {
+            eprintln!("protocat: {}: file truncated, reading it from the start", path);
+            file.seek(SeekFrom::Start(0)).map_err(io_error)?;
+        } else {
+            // A file that is gone for now may come back under the same name.
+            let replaced = match fs::metadata(path) {
+                Ok(metadata) => current.is_some() && identity(&metadata) != current,
+                Err(_) => false,
+            };
+            if !replaced {
+                continue;
+            }
+            // The new file may not be readable yet, while it is being created.
+            let new = match File::open(path) {
+                Ok(new) => new,
+                Err(_) => continue,
+            };
+            eprintln!("protocat: {}: file replaced, reading the new one", path);
+            current = identity(&new.metadata().map_err(io_error)?);
+            file = new;
+        }
+        if !pending.is_empty() {
+            eprintln!("protocat: {}: dropping {} byte{} of an unfinished message", path, pending.len(), plural(pending.len()));
+        }
+        pending.clear();
+        offset = 0;
+    }
+}
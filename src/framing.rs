@@ -0,0 +1,361 @@
+//! Splitting inputs that hold more than a bare message: streams of length-delimited messages and
+//! the common container framings around them.
+
+use std::convert::{TryFrom, TryInto};
+
+use clap::ValueEnum;
+use nom::combinator::all_consuming;
+
+use crate::{base128_vlq, base64, protobuf, Error, Span};
+
+#[derive(Copy, Clone, Debug, PartialEq, ValueEnum)]
+pub enum Framing {
+    /// The input is a single message.
+    None,
+    /// Messages each preceded by their length as a varint, as written by writeDelimitedTo.
+    Delimited,
+    /// gRPC length-prefixed messages: a compressed flag byte and a big-endian u32 length.
+    Grpc,
+    /// gRPC-Web frames: gRPC's, plus a frame of trailers at the end.
+    GrpcWeb,
+    /// gRPC-Web frames in base64, as in an application/grpc-web-text body.
+    GrpcWebText,
+    /// A Confluent Schema Registry record: a zero byte, a schema ID and message indexes.
+    Confluent,
+    /// TensorFlow TFRecord records, with their CRCs checked.
+    Tfrecord,
+    /// Pick whichever of the others fits the whole input.
+    Auto,
+}
+
+impl Framing {
+    pub fn name(self) -> &'static str {
+        match self {
+            Framing::None => "a single message",
+            Framing::Delimited => "varint-delimited messages",
+            Framing::Grpc => "gRPC frames",
+            Framing::GrpcWeb => "gRPC-Web frames",
+            Framing::GrpcWebText => "base64 gRPC-Web frames",
+            Framing::Confluent => "a Confluent-framed record",
+            Framing::Tfrecord => "TFRecord records",
+            Framing::Auto => "auto",
+        }
+    }
+
+    /// Whether the framing holds any number of messages one after another, so that it can be read
+    /// as it is written.
+    pub fn is_stream(self) -> bool {
+        matches!(self, Framing::Delimited | Framing::Grpc | Framing::GrpcWeb | Framing::Tfrecord)
+    }
+}
+
+/// One message cut out of the input.
+pub struct Frame<'a> {
+    pub offset: usize,
+    pub data: &'a [u8],
+    /// Framing metadata worth showing, e.g. the schema ID of a Confluent record.
+    pub note: Option<String>,
+    /// Whether the message is compressed, which only gRPC frames flag.
+    pub compressed: bool,
+    /// Whether the frame holds gRPC-Web trailers rather than a message.
+    pub trailers: bool,
+}
+
+impl<'a> Frame<'a> {
+    fn new(data: &'a [u8], start: usize, len: usize) -> Self {
+        Frame{offset: start, data: &data[start..start + len], note: None, compressed: false, trailers: false}
+    }
+}
+
+/// Splits the input into its messages. `Framing::None` and `Framing::Auto` should be resolved
+/// first; see `detect`. `Framing::GrpcWebText` input should be decoded first; see `decode_text`.
+pub fn split(data: &[u8], framing: Framing) -> Result<Vec<Frame<'_>>, String> {
+    match framing {
+        Framing::None | Framing::Auto => Ok(vec![Frame::new(data, 0, data.len())]),
+        Framing::Delimited => delimited(data),
+        Framing::Grpc => grpc(data, false),
+        Framing::GrpcWeb | Framing::GrpcWebText => grpc(data, true),
+        Framing::Confluent => confluent(data).map(|frame| vec![frame]),
+        Framing::Tfrecord => tfrecord(data),
+    }
+}
+
+/// The length of the messages at the start of a stream that is still being written, up to the
+/// first that hasn't been written in full, so that it can be left for when the rest of it comes.
+/// Malformed headers aren't checked here; splitting the messages finds them. `None` is returned for
+/// framings that aren't streams.
+pub fn complete_len(data: &[u8], framing: Framing) -> Option<usize> {
+    // The length of a message's header and the length of its data, if the header is all there.
+    let header: fn(&[u8]) -> Option<(usize, u64)> = match framing {
+        Framing::Delimited => |data| match base128_vlq::<_, Error<_>>(data) {
+            Ok((rest, len)) => Some((data.len() - rest.len(), len)),
+            // A varint that is too long is all there, and fails to split.
+            Err(nom::Err::Failure(_)) => Some((data.len(), 0)),
+            Err(_) => None,
+        },
+        Framing::Grpc | Framing::GrpcWeb => |data| {
+            let header = data.get(..5)?;
+            Some((5, u32::from_be_bytes(header[1..5].try_into().expect("slice is 4 bytes")) as u64))
+        },
+        // The data has a CRC after it. A length too big to add it to is all there, and fails to split.
+        Framing::Tfrecord => |data| {
+            let len = u64::from_le_bytes(data.get(..8)?.try_into().expect("slice is 8 bytes"));
+            Some(len.checked_add(4).map_or((data.len(), 0), |len| (12, len)))
+        },
+        _ => return None,
+    };
+    let mut pos = 0;
+    while let Some((header_len, len)) = header(&data[pos..]) {
+        match usize::try_from(len).ok().and_then(|len| (pos + header_len).checked_add(len)) {
+            Some(end) if end <= data.len() => pos = end,
+            _ => break,
+        }
+    }
+    Some(pos)
+}
+
+/// Picks the framing that accounts for the whole input with every message parsing, from the
+/// strongest evidence to the weakest: TFRecord's CRCs, then gRPC, gRPC-Web and Confluent headers
+/// (which a bare message can't start with, field number 0 being invalid), then varint lengths.
+/// Base64 gRPC-Web isn't binary, so it is never picked.
+pub fn detect(data: &[u8]) -> Framing {
+    let framing = detect_framing(data);
+    trace_event!(framing = framing.name(), "detected framing");
+    framing
+}
+
+fn detect_framing(data: &[u8]) -> Framing {
+    if !data.is_empty() && tfrecord(data).is_ok() {
+        return Framing::Tfrecord;
+    }
+    // Compressed frames can't be checked without decompressing them.
+    let all_messages = |frames: Vec<Frame>| frames.iter().all(|frame| frame.compressed || frame.trailers || is_message(frame.data));
+    if grpc(data, false).is_ok_and(all_messages) {
+        return Framing::Grpc;
+    }
+    if grpc(data, true).is_ok_and(all_messages) {
+        return Framing::GrpcWeb;
+    }
+    if confluent(data).is_ok_and(|frame| is_message(frame.data)) {
+        return Framing::Confluent;
+    }
+    // Short single messages can often be read as a delimited stream of one message too, so a
+    // stream has to have at least two messages to win over a message that parses.
+    if let Ok(frames) = delimited(data) {
+        if frames.iter().all(|frame| is_message(frame.data)) && (frames.len() >= 2 || !is_message(data)) {
+            return Framing::Delimited;
+        }
+    }
+    Framing::None
+}
+
+fn is_message(data: &[u8]) -> bool {
+    all_consuming(protobuf::<_, Error<_>>(false))(Span::new(data)).is_ok()
+}
+
+fn delimited(data: &[u8]) -> Result<Vec<Frame<'_>>, String> {
+    let mut frames = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let (rest, len) = base128_vlq::<_, Error<_>>(&data[pos..])
+            .map_err(|_| format!("truncated length at offset {}", pos))?;
+        let start = data.len() - rest.len();
+        let len = usize::try_from(len).ok().filter(|&len| len <= rest.len())
+            .ok_or_else(|| format!("message at offset {} runs past the end of the input", pos))?;
+        frames.push(Frame::new(data, start, len));
+        pos = start + len;
+    }
+    Ok(frames)
+}
+
+/// Splits gRPC frames, or with `web` gRPC-Web frames, whose flags can also mark trailers.
+fn grpc(data: &[u8], web: bool) -> Result<Vec<Frame<'_>>, String> {
+    if data.is_empty() {
+        return Err("no gRPC frames".to_string());
+    }
+    let mut frames = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let header = data.get(pos..pos + 5).ok_or_else(|| format!("truncated gRPC frame header at offset {}", pos))?;
+        let flag = header[0];
+        let known = if web { 0x81 } else { 0x01 };
+        if flag & !known != 0 {
+            return Err(format!("gRPC frame at offset {} has unknown flags {:#04x}", pos, flag));
+        }
+        let len = u32::from_be_bytes(header[1..5].try_into().expect("slice is 4 bytes")) as usize;
+        if data.len() - pos - 5 < len {
+            return Err(format!("gRPC frame at offset {} runs past the end of the input", pos));
+        }
+        let mut frame = Frame::new(data, pos + 5, len);
+        frame.compressed = flag & 0x01 != 0;
+        frame.trailers = flag & 0x80 != 0;
+        frames.push(frame);
+        pos += 5 + len;
+    }
+    Ok(frames)
+}
+
+/// Decodes an application/grpc-web-text body. Each chunk a server flushes is base64 of its own,
+/// padded, so the body is decoded a chunk at a time, ending each at its padding.
+pub fn decode_text(data: &[u8]) -> Result<Vec<u8>, String> {
+    let text = std::str::from_utf8(data).map_err(|_| "not valid base64".to_string())?;
+    let mut out = Vec::new();
+    let mut rest = text.trim();
+    while !rest.is_empty() {
+        let end = match rest.find('=') {
+            Some(i) => i + rest[i..].find(|c| c != '=').unwrap_or(rest.len() - i),
+            None => rest.len(),
+        };
+        out.extend(base64::decode(&rest[..end]).ok_or_else(|| "not valid base64".to_string())?);
+        rest = &rest[end..];
+    }
+    Ok(out)
+}
+
+/// Formats the header lines of a gRPC-Web trailers frame as `name: value` pairs on one line.
+pub fn trailers(data: &[u8]) -> String {
+    let text = String::from_utf8_lossy(data);
+    let lines: Vec<&str> = text.lines().map(str::trim).filter(|line| !line.is_empty()).collect();
+    lines.join(", ")
+}
+
+fn confluent(data: &[u8]) -> Result<Frame<'_>, String> {
+    if data.len() < 5 || data[0] != 0 {
+        return Err("no Confluent magic byte".to_string());
+    }
+    let schema_id = u32::from_be_bytes(data[1..5].try_into().expect("slice is 4 bytes"));
+    // Message indexes: a zigzag varint count and that many zigzag varint indexes into the schema's
+    // nested messages, with a lone 0 standing for the first message.
+    let mut rest = &data[5..];
+    let next = |rest: &mut &[u8]| -> Result<u64, String> {
+        let (tail, value) = base128_vlq::<_, Error<_>>(*rest).map_err(|_| "truncated message indexes".to_string())?;
+        *rest = tail;
+        Ok((value >> 1) ^ (value & 1).wrapping_neg())
+    };
+    let count = next(&mut rest)?;
+    if count > rest.len() as u64 {
+        return Err("implausible message index count".to_string());
+    }
+    let mut indexes = Vec::new();
+    for _ in 0..count {
+        indexes.push(next(&mut rest)?.to_string());
+    }
+    if indexes.is_empty() {
+        indexes.push("0".to_string());
+    }
+    let start = data.len() - rest.len();
+    let mut frame = Frame::new(data, start, rest.len());
+    frame.note = Some(format!("schema {}, message {}", schema_id, indexes.join(".")));
+    Ok(frame)
+}
+
+fn tfrecord(data: &[u8]) -> Result<Vec<Frame<'_>>, String> {
+    let mut frames = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let header = data.get(pos..pos + 12).ok_or_else(|| format!("truncated TFRecord header at offset {}", pos))?;
+        if masked_crc32c(&header[..8]) != u32::from_le_bytes(header[8..12].try_into().expect("slice is 4 bytes")) {
+            return Err(format!("TFRecord length at offset {} fails its CRC", pos));
+        }
+        let len = u64::from_le_bytes(header[..8].try_into().expect("slice is 8 bytes"));
+        let len = usize::try_from(len).ok().filter(|&len| len <= data.len() - pos - 12)
+            .ok_or_else(|| format!("TFRecord at offset {} runs past the end of the input", pos))?;
+        let start = pos + 12;
+        let crc = data.get(start + len..start + len + 4)
+            .ok_or_else(|| format!("TFRecord at offset {} is missing its data CRC", pos))?;
+        if masked_crc32c(&data[start..start + len]) != u32::from_le_bytes(crc.try_into().expect("slice is 4 bytes")) {
+            return Err(format!("TFRecord data at offset {} fails its CRC", pos));
+        }
+        frames.push(Frame::new(data, start, len));
+        pos = start + len + 4;
+    }
+    Ok(frames)
+}
+
+/// CRC-32C, masked the way TFRecord (after LevelDB) stores it.
+fn masked_crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0x82f6_3b78 } else { crc >> 1 };
+        }
+    }
+    let crc = !crc;
+    crc.rotate_right(15).wrapping_add(0xa282_ead8)
+}
+
+/// A record container described in the config file, for proprietary formats that wrap each message
+/// in a header of their own.
+pub struct Container {
+    pub name: String,
+    /// Bytes skipped once, at the start of the input.
+    pub header: usize,
+    /// Bytes skipped at the start of each record, before its length.
+    pub skip: usize,
+    pub length: LengthField,
+    /// Bytes skipped between the length and the message, such as a header checksum.
+    pub after_length: usize,
+    /// Bytes skipped after each message, such as a checksum.
+    pub trailer: usize,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LengthField {
+    Varint,
+    Fixed{size: usize, big_endian: bool},
+}
+
+impl LengthField {
+    pub fn parse(text: &str) -> Option<Self> {
+        if text == "varint" {
+            return Some(LengthField::Varint);
+        }
+        let (size, big_endian) = match text {
+            "u8" => (1, false),
+            "u16le" => (2, false),
+            "u16be" => (2, true),
+            "u32le" => (4, false),
+            "u32be" => (4, true),
+            "u64le" => (8, false),
+            "u64be" => (8, true),
+            _ => return None,
+        };
+        Some(LengthField::Fixed{size, big_endian})
+    }
+}
+
+/// Splits the input into the messages of a custom container.
+pub fn split_container<'a>(data: &'a [u8], container: &Container) -> Result<Vec<Frame<'a>>, String> {
+    if data.len() < container.header {
+        return Err(format!("shorter than the {}-byte {} header", container.header, container.name));
+    }
+    let mut frames = Vec::new();
+    let mut pos = container.header;
+    while pos < data.len() {
+        let truncated = || format!("truncated {} record at offset {}", container.name, pos);
+        let mut at = pos + container.skip;
+        let len = match container.length {
+            LengthField::Varint => {
+                let (rest, len) = base128_vlq::<_, Error<_>>(data.get(at..).ok_or_else(truncated)?).map_err(|_| truncated())?;
+                at = data.len() - rest.len();
+                len
+            }
+            LengthField::Fixed{size, big_endian} => {
+                let bytes = data.get(at..at + size).ok_or_else(truncated)?;
+                at += size;
+                let fold = |len: u64, &byte: &u8| len << 8 | byte as u64;
+                if big_endian { bytes.iter().fold(0, fold) } else { bytes.iter().rev().fold(0, fold) }
+            }
+        };
+        let start = at + container.after_length;
+        let len = usize::try_from(len).ok().filter(|&len| start <= data.len() && len <= data.len() - start)
+            .ok_or_else(|| format!("{} record at offset {} runs past the end of the input", container.name, pos))?;
+        if data.len() - start - len < container.trailer {
+            return Err(truncated());
+        }
+        frames.push(Frame::new(data, start, len));
+        pos = start + len + container.trailer;
+    }
+    Ok(frames)
+}
@@ -0,0 +1,258 @@
+//! Decoding of rich gRPC errors: the `google.rpc.Status` carried base64-encoded in the
+//! `grpc-status-details-bin` trailer, with its `Any` details expanded against bundled copies of the
+//! `google/rpc/error_details.proto` messages.
+
+use std::io::Write;
+
+use nom::AsBytes;
+
+use crate::units::format_duration;
+use crate::warning::Warnings;
+use crate::{base64, parse_message, text, print_indent, print_message_indented, Options, ProtoField, Span, WireValue};
+
+/// How a known field is displayed.
+enum Kind {
+    String,
+    Int,
+    /// A `google.rpc.Code` value.
+    Code,
+    /// A `google.protobuf.Duration`.
+    Duration,
+    /// A `map<string, string>` entry.
+    StringMap,
+    /// A `google.protobuf.Any`, expanded if its type is bundled.
+    Any,
+    Message(&'static Schema),
+}
+
+struct Schema {
+    name: &'static str,
+    fields: &'static [(u64, &'static str, Kind)],
+}
+
+static STATUS: Schema = Schema{name: "google.rpc.Status", fields: &[
+    (1, "code", Kind::Code),
+    (2, "message", Kind::String),
+    (3, "details", Kind::Any),
+]};
+
+static ERROR_INFO: Schema = Schema{name: "google.rpc.ErrorInfo", fields: &[
+    (1, "reason", Kind::String),
+    (2, "domain", Kind::String),
+    (3, "metadata", Kind::StringMap),
+]};
+
+static RETRY_INFO: Schema = Schema{name: "google.rpc.RetryInfo", fields: &[
+    (1, "retry_delay", Kind::Duration),
+]};
+
+static DEBUG_INFO: Schema = Schema{name: "google.rpc.DebugInfo", fields: &[
+    (1, "stack_entries", Kind::String),
+    (2, "detail", Kind::String),
+]};
+
+static QUOTA_FAILURE_VIOLATION: Schema = Schema{name: "google.rpc.QuotaFailure.Violation", fields: &[
+    (1, "subject", Kind::String),
+    (2, "description", Kind::String),
+    (3, "api_service", Kind::String),
+    (4, "quota_metric", Kind::String),
+    (5, "quota_id", Kind::String),
+    (6, "quota_dimensions", Kind::StringMap),
+    (7, "quota_value", Kind::Int),
+    (8, "future_quota_value", Kind::Int),
+]};
+
+static QUOTA_FAILURE: Schema = Schema{name: "google.rpc.QuotaFailure", fields: &[
+    (1, "violations", Kind::Message(&QUOTA_FAILURE_VIOLATION)),
+]};
+
+static PRECONDITION_FAILURE_VIOLATION: Schema = Schema{name: "google.rpc.PreconditionFailure.Violation", fields: &[
+    (1, "type", Kind::String),
+    (2, "subject", Kind::String),
+    (3, "description", Kind::String),
+]};
+
+static PRECONDITION_FAILURE: Schema = Schema{name: "google.rpc.PreconditionFailure", fields: &[
+    (1, "violations", Kind::Message(&PRECONDITION_FAILURE_VIOLATION)),
+]};
+
+static LOCALIZED_MESSAGE: Schema = Schema{name: "google.rpc.LocalizedMessage", fields: &[
+    (1, "locale", Kind::String),
+    (2, "message", Kind::String),
+]};
+
+static BAD_REQUEST_FIELD_VIOLATION: Schema = Schema{name: "google.rpc.BadRequest.FieldViolation", fields: &[
+    (1, "field", Kind::String),
+    (2, "description", Kind::String),
+    (3, "reason", Kind::String),
+    (4, "localized_message", Kind::Message(&LOCALIZED_MESSAGE)),
+]};
+
+static BAD_REQUEST: Schema = Schema{name: "google.rpc.BadRequest", fields: &[
+    (1, "field_violations", Kind::Message(&BAD_REQUEST_FIELD_VIOLATION)),
+]};
+
+static REQUEST_INFO: Schema = Schema{name: "google.rpc.RequestInfo", fields: &[
+    (1, "request_id", Kind::String),
+    (2, "serving_data", Kind::String),
+]};
+
+static RESOURCE_INFO: Schema = Schema{name: "google.rpc.ResourceInfo", fields: &[
+    (1, "resource_type", Kind::String),
+    (2, "resource_name", Kind::String),
+    (3, "owner", Kind::String),
+    (4, "description", Kind::String),
+]};
+
+static HELP_LINK: Schema = Schema{name: "google.rpc.Help.Link", fields: &[
+    (1, "description", Kind::String),
+    (2, "url", Kind::String),
+]};
+
+static HELP: Schema = Schema{name: "google.rpc.Help", fields: &[
+    (1, "links", Kind::Message(&HELP_LINK)),
+]};
+
+/// The detail messages that may appear in a `Status`.
+static DETAILS: [&Schema; 10] = [
+    &ERROR_INFO, &RETRY_INFO, &DEBUG_INFO, &QUOTA_FAILURE, &PRECONDITION_FAILURE, &BAD_REQUEST,
+    &REQUEST_INFO, &RESOURCE_INFO, &HELP, &LOCALIZED_MESSAGE,
+];
+
+const CODE_NAMES: [&str; 17] = [
+    "OK", "CANCELLED", "UNKNOWN", "INVALID_ARGUMENT", "DEADLINE_EXCEEDED", "NOT_FOUND", "ALREADY_EXISTS",
+    "PERMISSION_DENIED", "RESOURCE_EXHAUSTED", "FAILED_PRECONDITION", "ABORTED", "OUT_OF_RANGE",
+    "UNIMPLEMENTED", "INTERNAL", "UNAVAILABLE", "DATA_LOSS", "UNAUTHENTICATED",
+];
+
+const TRAILER: &str = "grpc-status-details-bin:";
+
+/// Turns a trailer value, optionally with the trailer name in front, into the serialized `Status`.
+/// Anything that isn't base64 is assumed to be the binary message itself.
+pub fn status_bytes(input: Vec<u8>) -> Vec<u8> {
+    let decoded = std::str::from_utf8(&input).ok().and_then(|text| {
+        let text = text.trim();
+        let text = match text.get(..TRAILER.len()) {
+            Some(name) if name.eq_ignore_ascii_case(TRAILER) => &text[TRAILER.len()..],
+            _ => text,
+        };
+        base64::decode(text)
+    });
+    decoded.unwrap_or(input)
+}
+
+/// Prints a serialized `google.rpc.Status`.
+pub fn print_status(out: &mut dyn Write, data: &[u8], options: &Options, warnings: &mut Warnings) -> Result<(), String> {
+    let fields = parse_message(data, options.lenient)?;
+    print_fields(out, fields, &STATUS, 0, options, warnings);
+    Ok(())
+}
+
+fn print_fields(out: &mut dyn Write, fields: Vec<ProtoField<Span>>, schema: &Schema, indent: usize, options: &Options, warnings: &mut Warnings) {
+    for field in fields {
+        let known = schema.fields.iter().find(|(tag, _, _)| *tag == field.tag_number);
+        match known {
+            Some((_, name, kind)) if print_field(out, name, kind, &field, indent, options, warnings) => {}
+            // Unknown fields and ones with an unexpected wire type get the usual raw decode.
+            _ => {
+                print_message_indented(out, vec![field], indent, options, &[], warnings);
+            }
+        }
+    }
+}
+
+/// Prints a known field, returning false if its value doesn't fit the schema.
+fn print_field(out: &mut dyn Write, name: &str, kind: &Kind, field: &ProtoField<Span>, indent: usize, options: &Options, warnings: &mut Warnings) -> bool {
+    match (kind, field.value) {
+        (Kind::String, WireValue::LengthPrefixed(d)) => match std::str::from_utf8(d.as_bytes()) {
+            Ok(str) => {
+                print_indent(out, indent);
+                outln!(out, "{}: {}", name, text::quote(str));
+            }
+            Err(_) => return false,
+        },
+        (Kind::Int, WireValue::VarInt(v)) => {
+            print_indent(out, indent);
+            outln!(out, "{}: {}", name, v as i64);
+        }
+        (Kind::Code, WireValue::VarInt(v)) => {
+            print_indent(out, indent);
+            match CODE_NAMES.get(v as usize) {
+                Some(code) => outln!(out, "{}: {} ({})", name, v, code),
+                None => outln!(out, "{}: {}", name, v as i32),
+            }
+        }
+        (Kind::Duration, WireValue::LengthPrefixed(d)) => {
+            let (mut seconds, mut nanos) = (0i64, 0i32);
+            for field in parse_message(d.as_bytes(), false).unwrap_or_default() {
+                match (field.tag_number, field.value) {
+                    (1, WireValue::VarInt(v)) => seconds = v as i64,
+                    (2, WireValue::VarInt(v)) => nanos = v as i32,
+                    _ => return false,
+                }
+            }
+            let sign = if seconds < 0 || nanos < 0 { "-" } else { "" };
+            print_indent(out, indent);
+            outln!(out, "{}: {}{}", name, sign, format_duration(seconds.unsigned_abs(), nanos.unsigned_abs()));
+        }
+        (Kind::StringMap, WireValue::LengthPrefixed(d)) => {
+            let (mut key, mut value) = (String::new(), String::new());
+            for field in parse_message(d.as_bytes(), false).unwrap_or_default() {
+                match (field.tag_number, field.value) {
+                    (1, WireValue::LengthPrefixed(d)) => key = String::from_utf8_lossy(d.as_bytes()).into_owned(),
+                    (2, WireValue::LengthPrefixed(d)) => value = String::from_utf8_lossy(d.as_bytes()).into_owned(),
+                    _ => return false,
+                }
+            }
+            print_indent(out, indent);
+            outln!(out, "{}[{}]: {}", name, text::quote(&key), text::quote(&value));
+        }
+        (Kind::Any, WireValue::LengthPrefixed(d)) => {
+            let (mut type_url, mut value) = (String::new(), Span::new(b""));
+            let fields = match parse_message(d.as_bytes(), false) {
+                Ok(fields) => fields,
+                Err(_) => return false,
+            };
+            for field in fields {
+                match (field.tag_number, field.value) {
+                    (1, WireValue::LengthPrefixed(d)) => type_url = String::from_utf8_lossy(d.as_bytes()).into_owned(),
+                    (2, WireValue::LengthPrefixed(d)) => value = d,
+                    _ => return false,
+                }
+            }
+            // The type name is whatever follows the last slash of the URL.
+            let type_name = type_url.rsplit('/').next().unwrap_or_default();
+            let fields = match parse_message(value.fragment(), false) {
+                Ok(fields) => fields,
+                Err(_) => {
+                    print_indent(out, indent);
+                    outln!(out, "{}: {} {}", name, type_name, options.bytes_format.format(value.fragment()));
+                    return true;
+                }
+            };
+            print_indent(out, indent);
+            outln!(out, "{}: {} {{", name, type_name);
+            match DETAILS.iter().find(|schema| schema.name == type_name) {
+                Some(schema) => print_fields(out, fields, schema, indent + 1, options, warnings),
+                None => {
+                    print_message_indented(out, fields, indent + 1, options, &[], warnings);
+                }
+            }
+            print_indent(out, indent);
+            outln!(out, "}}");
+        }
+        (Kind::Message(schema), WireValue::LengthPrefixed(d)) => {
+            let fields = match parse_message(d.as_bytes(), false) {
+                Ok(fields) => fields,
+                Err(_) => return false,
+            };
+            print_indent(out, indent);
+            outln!(out, "{}: {{", name);
+            print_fields(out, fields, schema, indent + 1, options, warnings);
+            print_indent(out, indent);
+            outln!(out, "}}");
+        }
+        _ => return false,
+    }
+    true
+}
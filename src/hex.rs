@@ -0,0 +1,18 @@
+//! Hex decoding for `--input-encoding hex`, lenient about the layout bytes copied from logs and hex
+//! dumps come in.
+
+/// Decodes hex digits, ignoring whitespace, a leading `0x` and `:` or `,` separators. Returns `None`
+/// if anything else is in the way or a digit is left over.
+pub fn decode(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim();
+    let text = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).unwrap_or(text);
+    let digits: Vec<u8> = text.bytes().filter(|c| !c.is_ascii_whitespace() && *c != b':' && *c != b',').collect();
+    if !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    digits.chunks(2).map(|pair| Some((digit(pair[0])? << 4) | digit(pair[1])?)).collect()
+}
+
+fn digit(c: u8) -> Option<u8> {
+    (c as char).to_digit(16).map(|d| d as u8)
+}
@@ -0,0 +1,24 @@
+//! Extension point for user-supplied field decoders, called by `print_message` for every field.
+
+use crate::{ProtoField, Span};
+
+#[cfg_attr(not(any(feature = "plugins", feature = "scripting")), allow(dead_code))]
+pub enum HookAction {
+    /// Print the field as protocat normally would.
+    Default,
+    /// Print this rendering of the value instead, without descending into it.
+    Replace(String),
+    /// Leave the field out of the output.
+    #[cfg_attr(not(feature = "scripting"), allow(dead_code))]
+    Skip,
+}
+
+pub trait FieldHook {
+    /// Decides how to print the field at `path`, given as tag numbers from the top-level message.
+    fn on_field(&self, path: &[u64], field: &ProtoField<Span>) -> Result<HookAction, String>;
+
+    /// Called once each input has been printed.
+    fn on_input_end(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
@@ -0,0 +1,370 @@
+//! Decoding of protobuf bodies in HTTP/1.x dumps: raw requests and responses as saved by proxies,
+//! `curl --trace` and `--trace-ascii` logs, and Burp Suite's saved items.
+
+use std::convert::TryInto;
+use std::io::{Read, Write};
+
+use crate::warning::Warnings;
+use crate::{base64, framing, parse_message, print_indent, print_message_indented, Options};
+
+struct Message {
+    start_line: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Message {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    fn is_request(&self) -> bool {
+        !self.start_line.starts_with("HTTP/")
+    }
+}
+
+/// Splits an input into the HTTP byte streams it contains, in order.
+fn streams(data: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    let text = String::from_utf8_lossy(&data[..data.len().min(4096)]);
+    if text.trim_start().starts_with("<?xml") && text.contains("<items") {
+        burp_items(data)
+    } else if text.starts_with("== Info:") || text.starts_with("=> Send") || text.starts_with("<= Recv") {
+        Ok(curl_trace(data))
+    } else {
+        Ok(vec![data.to_vec()])
+    }
+}
+
+/// Extracts requests and responses from a Burp Suite "Save items" XML file.
+fn burp_items(data: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    let text = String::from_utf8_lossy(data);
+    let mut streams = Vec::new();
+    let mut rest = text.as_ref();
+    while let Some(start) = rest.find("<request").into_iter().chain(rest.find("<response")).min() {
+        rest = &rest[start..];
+        let tag_end = rest.find('>').ok_or("malformed Burp item")?;
+        let base64 = rest[..tag_end].contains("base64=\"true\"");
+        rest = &rest[tag_end + 1..];
+        let content = match rest.strip_prefix("<![CDATA[") {
+            Some(cdata) => &cdata[..cdata.find("]]>").ok_or("malformed Burp item")?],
+            // Empty elements, e.g. a request that got no response.
+            None => "",
+        };
+        rest = &rest[content.len()..];
+        if content.is_empty() {
+            continue;
+        }
+        streams.push(if base64 {
+            base64::decode(content).ok_or("malformed base64 in Burp item")?
+        } else {
+            content.as_bytes().to_vec()
+        });
+    }
+    Ok(streams)
+}
+
+/// Reassembles the sent and received data in a curl trace, giving a stream each time the direction
+/// changes. Both the hex `--trace` format and `--trace-ascii` are understood, but the latter
+/// replaces unprintable bytes with dots, which leaves binary bodies beyond repair.
+fn curl_trace(data: &[u8]) -> Vec<Vec<u8>> {
+    let text = String::from_utf8_lossy(data);
+    let mut streams: Vec<Vec<u8>> = Vec::new();
+    let mut sending = None;
+    let mut lines = text.lines().peekable();
+    while let Some(line) = lines.next() {
+        let send = if line.starts_with("=> Send header") || line.starts_with("=> Send data") {
+            true
+        } else if line.starts_with("<= Recv header") || line.starts_with("<= Recv data") {
+            false
+        } else {
+            continue;
+        };
+        let total = line.split(", ").nth(1).and_then(|size| size.split(' ').next()).and_then(|n| n.parse::<usize>().ok()).unwrap_or(0);
+
+        let mut block: Vec<(usize, &str)> = Vec::new();
+        while let Some(line) = lines.peek() {
+            let (offset, content) = match line.split_once(": ") {
+                Some((offset, content)) if offset.len() == 4 => match usize::from_str_radix(offset, 16) {
+                    Ok(offset) => (offset, content),
+                    Err(_) => break,
+                },
+                _ => break,
+            };
+            block.push((offset, content));
+            lines.next();
+        }
+
+        if sending != Some(send) {
+            streams.push(Vec::new());
+            sending = Some(send);
+        }
+        let stream = streams.last_mut().unwrap();
+        for (i, &(offset, content)) in block.iter().enumerate() {
+            let end = block.get(i + 1).map_or(total, |&(next, _)| next);
+            let count = end.saturating_sub(offset).min(16);
+            let hex: Option<Vec<u8>> = content.split(' ').take(count)
+                .map(|byte| if byte.len() == 2 { u8::from_str_radix(byte, 16).ok() } else { None })
+                .collect();
+            match hex.filter(|bytes| bytes.len() == count && count > 0) {
+                Some(bytes) => stream.extend_from_slice(&bytes),
+                None => {
+                    // In --trace-ascii, line breaks are dropped and show only in the offsets.
+                    stream.extend_from_slice(content.as_bytes());
+                    match end.saturating_sub(offset + content.len()) {
+                        0 => {}
+                        1 => stream.push(b'\n'),
+                        _ => stream.extend_from_slice(b"\r\n"),
+                    }
+                }
+            }
+        }
+    }
+    streams
+}
+
+/// Finds the end of the line starting at `pos`, returning it and the start of the next line.
+fn line_end(data: &[u8], pos: usize) -> Option<(usize, usize)> {
+    let newline = pos + data[pos..].iter().position(|&b| b == b'\n')?;
+    let end = if newline > pos && data[newline - 1] == b'\r' { newline - 1 } else { newline };
+    Some((end, newline + 1))
+}
+
+/// Parses the HTTP messages in a stream, one after another.
+fn parse_messages(data: &[u8]) -> Result<Vec<Message>, String> {
+    let mut messages = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        // Tolerate blank lines between messages.
+        if data[pos] == b'\r' || data[pos] == b'\n' {
+            pos += 1;
+            continue;
+        }
+        let (end, next) = line_end(data, pos).ok_or("truncated start line")?;
+        let start_line = String::from_utf8_lossy(&data[pos..end]).into_owned();
+        if start_line.split(' ').count() < 2 {
+            return Err(format!("not an HTTP message: {}", start_line));
+        }
+        pos = next;
+
+        let mut headers = Vec::new();
+        loop {
+            let (end, next) = line_end(data, pos).ok_or("truncated headers")?;
+            let line = String::from_utf8_lossy(&data[pos..end]).into_owned();
+            pos = next;
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                headers.push((name.trim().to_string(), value.trim().to_string()));
+            }
+        }
+        let mut message = Message{start_line, headers, body: Vec::new()};
+
+        let chunked = message.header("transfer-encoding").is_some_and(|te| te.to_ascii_lowercase().contains("chunked"));
+        if chunked {
+            loop {
+                let (end, next) = line_end(data, pos).ok_or("truncated chunk")?;
+                let size = String::from_utf8_lossy(&data[pos..end]);
+                let size = usize::from_str_radix(size.split(';').next().unwrap_or_default().trim(), 16)
+                    .map_err(|_| format!("bad chunk size {}", size))?;
+                pos = next;
+                if size == 0 {
+                    // Skip trailers, up to the blank line ending the message.
+                    while let Some((end, next)) = line_end(data, pos) {
+                        let blank = end == pos;
+                        pos = next;
+                        if blank {
+                            break;
+                        }
+                    }
+                    break;
+                }
+                let chunk = data.get(pos..pos + size).ok_or("truncated chunk")?;
+                message.body.extend_from_slice(chunk);
+                pos += size;
+                if let Some((_, next)) = line_end(data, pos) {
+                    pos = next;
+                }
+            }
+        } else if let Some(length) = message.header("content-length") {
+            let length: usize = length.parse().map_err(|_| format!("bad content-length {}", length))?;
+            let end = (pos + length).min(data.len());
+            message.body = data[pos..end].to_vec();
+            pos = end;
+        } else if !message.is_request() {
+            // Responses without a length run to the end of the connection.
+            message.body = data[pos..].to_vec();
+            pos = data.len();
+        }
+        messages.push(message);
+    }
+    Ok(messages)
+}
+
+/// Undoes a Content-Encoding or grpc-encoding.
+fn decode_content(encoding: &str, data: Vec<u8>) -> Result<Vec<u8>, String> {
+    let mut data = data;
+    // Encodings are listed in the order they were applied.
+    for encoding in encoding.split(',').map(str::trim).rev() {
+        let mut out = Vec::new();
+        let result = match encoding.to_ascii_lowercase().as_str() {
+            "" | "identity" => continue,
+            "gzip" | "x-gzip" => flate2::read::GzDecoder::new(&data[..]).read_to_end(&mut out),
+            // Meant to be zlib-wrapped, but raw deflate is common enough to be worth trying.
+            "deflate" => flate2::read::ZlibDecoder::new(&data[..]).read_to_end(&mut out).or_else(|_| {
+                out.clear();
+                flate2::read::DeflateDecoder::new(&data[..]).read_to_end(&mut out)
+            }),
+            "zstd" => ruzstd::decoding::StreamingDecoder::new(&data[..])
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err.to_string()))
+                .and_then(|mut decoder| decoder.read_to_end(&mut out)),
+            other => return Err(format!("unsupported encoding {}", other)),
+        };
+        result.map_err(|err| format!("bad {} data: {}", encoding, err))?;
+        data = out;
+    }
+    Ok(data)
+}
+
+enum BodyKind {
+    Protobuf,
+    /// gRPC or gRPC-Web length-prefixed messages.
+    Grpc,
+    /// gRPC-Web messages in base64.
+    GrpcWebText,
+    Other,
+}
+
+fn body_kind(content_type: Option<&str>, body: &[u8]) -> BodyKind {
+    let content_type = content_type.unwrap_or_default().to_ascii_lowercase();
+    let media_type = content_type.split(';').next().unwrap_or_default().trim();
+    match media_type {
+        t if t.starts_with("application/grpc-web-text") => BodyKind::GrpcWebText,
+        "application/grpc" | "application/grpc+proto" | "application/grpc-web" | "application/grpc-web+proto" => BodyKind::Grpc,
+        t if t.contains("protobuf") || t.ends_with("+proto") => BodyKind::Protobuf,
+        // Without a useful content type, sniff.
+        "" | "application/octet-stream" if !body.is_empty() && parse_message(body, false).is_ok() => BodyKind::Protobuf,
+        _ => BodyKind::Other,
+    }
+}
+
+fn print_protobuf(out: &mut dyn Write, label: &str, data: &[u8], options: &Options, warnings: &mut Warnings) {
+    print_indent(out, 1);
+    match parse_message(data, options.lenient) {
+        Ok(fields) => {
+            outln!(out, "{}: {{", label);
+            print_message_indented(out, fields, 2, options, &[], warnings);
+            print_indent(out, 1);
+            outln!(out, "}}");
+        }
+        Err(err) => outln!(out, "{}: not protobuf: {}", label, err),
+    }
+}
+
+/// Prints each length-prefixed gRPC message in a body, and gRPC-Web trailers.
+fn print_grpc(out: &mut dyn Write, message: &Message, body: &[u8], options: &Options, warnings: &mut Warnings) -> Result<(), String> {
+    let mut rest = body;
+    while !rest.is_empty() {
+        let header = rest.get(..5).ok_or("truncated gRPC frame")?;
+        let flags = header[0];
+        let len = u32::from_be_bytes(header[1..5].try_into().unwrap()) as usize;
+        let data = rest.get(5..5 + len).ok_or("truncated gRPC frame")?;
+        rest = &rest[5 + len..];
+
+        if flags & 0x80 != 0 {
+            print_indent(out, 1);
+            outln!(out, "trailers: {}", framing::trailers(data));
+            continue;
+        }
+        if flags & 0x01 != 0 {
+            let encoding = message.header("grpc-encoding").unwrap_or("gzip");
+            let data = decode_content(encoding, data.to_vec())?;
+            print_protobuf(out, "message", &data, options, warnings);
+        } else {
+            print_protobuf(out, "message", data, options, warnings);
+        }
+    }
+    Ok(())
+}
+
+/// Prints the protobuf bodies in an HTTP dump, under the start line of their message.
+pub fn run(out: &mut dyn Write, data: &[u8], options: &Options, warnings: &mut Warnings) -> Result<(), String> {
+    let mut last_request = None;
+    for stream in streams(data)? {
+        for message in parse_messages(&stream)? {
+            if message.is_request() {
+                outln!(out, "{}", message.start_line);
+                last_request = Some(message.start_line.rsplit_once(' ').map_or(message.start_line.clone(), |(line, _)| line.to_string()));
+            } else {
+                match &last_request {
+                    Some(request) => outln!(out, "{} ({})", message.start_line, request),
+                    None => outln!(out, "{}", message.start_line),
+                }
+            }
+            if message.body.is_empty() {
+                continue;
+            }
+
+            let body = match decode_content(message.header("content-encoding").unwrap_or_default(), message.body.clone()) {
+                Ok(body) => body,
+                Err(err) => {
+                    print_indent(out, 1);
+                    outln!(out, "body: {}", err);
+                    continue;
+                }
+            };
+            let content_type = message.header("content-type");
+            let result = match body_kind(content_type, &body) {
+                BodyKind::Protobuf => {
+                    print_protobuf(out, "body", &body, options, warnings);
+                    Ok(())
+                }
+                BodyKind::Grpc => print_grpc(out, &message, &body, options, warnings),
+                BodyKind::GrpcWebText => framing::decode_text(&body).and_then(|body| print_grpc(out, &message, &body, options, warnings)),
+                BodyKind::Other => {
+                    print_indent(out, 1);
+                    outln!(out, "body: {} bytes of {}, not decoded", body.len(), content_type.unwrap_or("unknown type"));
+                    Ok(())
+                }
+            };
+            if let Err(err) = result {
+                print_indent(out, 1);
+                outln!(out, "body: {}", err);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Whether the data starts like an HTTP/1.x request or response.
+pub fn is_http(data: &[u8]) -> bool {
+    let line = match line_end(data, 0) {
+        Some((end, _)) => String::from_utf8_lossy(&data[..end]),
+        None => return false,
+    };
+    let words: Vec<&str> = line.split(' ').collect();
+    // A status line, or a request line.
+    match words.as_slice() {
+        [version, status, ..] if version.starts_with("HTTP/1.") => status.len() == 3 && status.bytes().all(|b| b.is_ascii_digit()),
+        [method, _, version] => !method.is_empty() && method.bytes().all(|b| b.is_ascii_uppercase()) && version.starts_with("HTTP/1."),
+        _ => false,
+    }
+}
+
+/// The body of a saved HTTP request or response, the first message with one if there are more,
+/// with its content encoding undone, and the framing its content type gives: gRPC's for gRPC and
+/// gRPC-Web, and none for a single protobuf message.
+pub fn body(data: &[u8]) -> Result<(Vec<u8>, framing::Framing), String> {
+    let messages = parse_messages(data)?;
+    let message = messages.iter().find(|message| !message.body.is_empty()).ok_or("no HTTP message with a body")?;
+    let body = decode_content(message.header("content-encoding").unwrap_or_default(), message.body.clone())?;
+    let content_type = message.header("content-type");
+    let web = content_type.is_some_and(|content_type| content_type.to_ascii_lowercase().contains("grpc-web"));
+    match body_kind(content_type, &body) {
+        BodyKind::Protobuf => Ok((body, framing::Framing::None)),
+        BodyKind::Grpc if web => Ok((body, framing::Framing::GrpcWeb)),
+        BodyKind::Grpc => Ok((body, framing::Framing::Grpc)),
+        BodyKind::GrpcWebText => Ok((body, framing::Framing::GrpcWebText)),
+        BodyKind::Other => Err(format!("the body is {} bytes of {}, not protobuf", body.len(), content_type.unwrap_or("unknown type"))),
+    }
+}
@@ -0,0 +1,417 @@
+//! `infer-schema`: a best guess at the `.proto` definition of the messages in some samples, as a
+//! skeleton to refine by hand.
+//!
+//! Every occurrence of a field in every sample is looked at, and a field's type is the one all of
+//! them fit: varints are int32 unless a value doesn't fit in one, or bool if they are all 0 or 1,
+//! fixed-width values are floats and doubles if they mostly look like plausible ones, and
+//! length-delimited values are submessages, strings or packed lists only if every one of them
+//! reads as the same, and bytes otherwise. A field is repeated if a message has it more than once
+//! or it is packed. Submessages have their shapes merged across every occurrence, and become nested
+//! message types. The guesses are only as good as the samples, so every field is commented with
+//! how sure the guess is, and with where its occurrences disagree.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use nom::AsBytes;
+
+use crate::alternates::{self, FloatDisplay};
+use crate::text;
+use crate::warning::{WarningKind, Warnings};
+use crate::{interpret, plural, Cursor, Interpretation, Options, ProtoField, Span, WireType, WireValue};
+
+/// The varints seen in a field, or any one of the fields packed in it.
+#[derive(Default)]
+struct Varints {
+    count: usize,
+    max: u64,
+    /// Whether any were negative int32s, which are sign-extended to ten bytes.
+    negative32: bool,
+    /// Whether any were negative int64s, or too large for an int64 otherwise.
+    negative64: bool,
+}
+
+impl Varints {
+    fn add(&mut self, v: u64) {
+        self.count += 1;
+        if v >= 0xffff_ffff_8000_0000 {
+            self.negative32 = true;
+        } else if v > i64::MAX as u64 {
+            self.negative64 = true;
+        } else {
+            self.max = self.max.max(v);
+        }
+    }
+
+    fn type_name(&self) -> &'static str {
+        if self.negative64 || self.max > i32::MAX as u64 {
+            "int64"
+        } else if self.max <= 1 && !self.negative32 {
+            "bool"
+        } else {
+            "int32"
+        }
+    }
+}
+
+/// The fixed-width values seen in a field, and how many of them were plausible floating point
+/// numbers.
+#[derive(Default)]
+struct Fixed {
+    count: usize,
+    plausible: usize,
+}
+
+impl Fixed {
+    fn add(&mut self, plausible: bool) {
+        self.count += 1;
+        self.plausible += plausible as usize;
+    }
+
+    /// The type the values are, floating point if at least half of them are plausible as that, and
+    /// the share of them that fit it.
+    fn declare(&self, float: &'static str, integer: &'static str) -> (&'static str, f64) {
+        let share = self.plausible as f64 / self.count.max(1) as f64;
+        if share >= 0.5 { (float, share) } else { (integer, 1.0 - share) }
+    }
+}
+
+/// What the occurrences of a field were seen as.
+#[derive(Default)]
+struct FieldShape {
+    /// How many instances of the message had the field, and the most times one did.
+    present: usize,
+    max_count: usize,
+    varints: Varints,
+    fixed64: Fixed,
+    fixed32: Fixed,
+    /// How many occurrences had each wire type, packed values aside.
+    wire_varints: usize,
+    wire_fixed64: usize,
+    wire_fixed32: usize,
+    groups: usize,
+    /// How many occurrences had the default value, zero or empty, which fields without explicit
+    /// presence are never written with in proto3.
+    defaults: usize,
+    /// The length-delimited occurrences, by what they read as; submessages that are also clean text
+    /// are counted again in `text_messages`, and of those that aren't empty or packed, the ones that
+    /// are valid UTF-8 in `utf8`.
+    empty: usize,
+    messages: usize,
+    text_messages: usize,
+    strings: usize,
+    bytes: usize,
+    utf8: usize,
+    packed: [usize; 3],
+    /// The merged shape of the submessages and groups in the field.
+    child: Option<Box<MessageShape>>,
+}
+
+impl FieldShape {
+    fn child(&mut self) -> &mut MessageShape {
+        self.child.get_or_insert_with(Default::default)
+    }
+}
+
+/// The fields seen in the instances of a message.
+#[derive(Default)]
+struct MessageShape {
+    instances: usize,
+    fields: BTreeMap<u64, FieldShape>,
+}
+
+impl MessageShape {
+    /// Adds an instance of the message, whose fields are those of `level`, at `path`.
+    fn add(&mut self, mut level: Cursor, path: &mut Vec<u64>, options: &Options, warnings: &mut Warnings) {
+        self.instances += 1;
+        let mut counts = BTreeMap::new();
+        while let Some(index) = level.next() {
+            let field = level.list.fields[index];
+            if let WireValue::EndGroup = field.value {
+                continue;
+            }
+            *counts.entry(field.tag_number).or_insert(0) += 1;
+            path.push(field.tag_number);
+            let shape = self.fields.entry(field.tag_number).or_default();
+            match field.value {
+                WireValue::VarInt(0) | WireValue::Int64(0) | WireValue::Int32(0) => shape.defaults += 1,
+                WireValue::LengthPrefixed(d) if d.as_bytes().is_empty() => shape.defaults += 1,
+                _ => {}
+            }
+            match field.value {
+                WireValue::VarInt(v) => {
+                    shape.wire_varints += 1;
+                    shape.varints.add(v);
+                }
+                WireValue::Int64(v) => {
+                    shape.wire_fixed64 += 1;
+                    shape.fixed64.add(alternates::double(v, Some(FloatDisplay::Plausible)).is_some());
+                }
+                WireValue::Int32(v) => {
+                    shape.wire_fixed32 += 1;
+                    shape.fixed32.add(alternates::float(v, Some(FloatDisplay::Plausible)).is_some());
+                }
+                WireValue::StartGroup => {
+                    shape.groups += 1;
+                    // Groups count towards --max-depth as submessages do.
+                    if path.len() > options.max_depth {
+                        let message = format!("nested deeper than --max-depth {}, left out", options.max_depth);
+                        warnings.emit(WarningKind::DepthLimit, path, &message);
+                    } else {
+                        let (content, _) = level.list.group(index);
+                        shape.child().add(Cursor::new(level.list.clone(), content), path, options, warnings);
+                    }
+                }
+                WireValue::LengthPrefixed(d) if d.as_bytes().is_empty() => shape.empty += 1,
+                WireValue::LengthPrefixed(d) => {
+                    let utf8 = std::str::from_utf8(d.as_bytes()).is_ok() as usize;
+                    match interpret(d, options, path, warnings) {
+                        Interpretation::Message(fields) => {
+                            shape.messages += 1;
+                            shape.utf8 += utf8;
+                            shape.text_messages += text::is_clean(d.as_bytes(), options.string_encoding) as usize;
+                            shape.child().add(Cursor::message(fields), path, options, warnings);
+                        }
+                        Interpretation::String(..) | Interpretation::LossyString(..) => {
+                            shape.strings += 1;
+                            shape.utf8 += utf8;
+                        }
+                        Interpretation::Packed(values, WireType::Int64) => {
+                            shape.packed[1] += 1;
+                            for v in values {
+                                shape.fixed64.add(alternates::double(v, Some(FloatDisplay::Plausible)).is_some());
+                            }
+                        }
+                        Interpretation::Packed(values, WireType::Int32) => {
+                            shape.packed[2] += 1;
+                            for v in values {
+                                shape.fixed32.add(alternates::float(v as u32, Some(FloatDisplay::Plausible)).is_some());
+                            }
+                        }
+                        Interpretation::Packed(values, _) => {
+                            shape.packed[0] += 1;
+                            for v in values {
+                                shape.varints.add(v);
+                            }
+                        }
+                        Interpretation::Bytes => {
+                            shape.bytes += 1;
+                            shape.utf8 += utf8;
+                        }
+                    }
+                }
+                WireValue::EndGroup => unreachable!("ends of groups are skipped"),
+            }
+            path.pop();
+        }
+        for (tag_number, count) in counts {
+            let shape = self.fields.get_mut(&tag_number).expect("every field counted was added");
+            shape.present += 1;
+            shape.max_count = shape.max_count.max(count);
+        }
+    }
+
+    fn has_groups(&self) -> bool {
+        self.fields.values().any(|field| field.groups > 0 || field.child.as_ref().is_some_and(|child| child.has_groups()))
+    }
+}
+
+/// How a field is declared: its type, whether it is repeated, whether it is a group, whether it is
+/// seen to have explicit presence, how sure the guess is, as a fraction, and what is worth noting
+/// about it.
+struct Declaration {
+    type_name: String,
+    repeated: bool,
+    group: bool,
+    presence: bool,
+    confidence: f64,
+    notes: Vec<String>,
+}
+
+/// Declares a field from its occurrences. Packed lists count towards their values' wire type, and
+/// when occurrences disagree, the field is widened to what fits them all, or as many as it can: a
+/// submessage or a string to bytes, a varint to an int64. The confidence is the share of the
+/// occurrences that fit the declaration, discounted while there are few of them, so that a single
+/// occurrence is a coin toss.
+fn declare(tag_number: u64, shape: &FieldShape) -> Declaration {
+    let nested = format!("Field{}", tag_number);
+    let mut declaration = Declaration{
+        type_name: "bytes".to_string(),
+        repeated: shape.max_count > 1,
+        group: false,
+        presence: false,
+        confidence: 0.0,
+        notes: Vec::new(),
+    };
+    let packed = shape.packed.iter().sum::<usize>();
+    let delimited = shape.empty + shape.messages + shape.strings + shape.bytes;
+    // The wire type most occurrences had wins, and the others are noted.
+    let wire_types = [
+        ("varint", shape.wire_varints + shape.packed[0]),
+        ("fixed64", shape.wire_fixed64 + shape.packed[1]),
+        ("fixed32", shape.wire_fixed32 + shape.packed[2]),
+        ("length-delimited value", delimited),
+        ("group", shape.groups),
+    ];
+    let total: usize = wire_types.iter().map(|(_, count)| count).sum();
+    let (winner, _) = wire_types.iter().enumerate().max_by_key(|(i, (_, count))| (*count, std::cmp::Reverse(*i))).expect("there are wire types");
+    for (i, (name, count)) in wire_types.iter().enumerate() {
+        if i != winner && *count > 0 {
+            declaration.notes.push(format!("also seen as {} {}{}", count, name, plural(*count)));
+        }
+    }
+    // How many of the occurrences fit, and how well.
+    let (fits, quality) = match winner {
+        0..=2 => {
+            let packed = shape.packed[winner];
+            // Empty data is an empty packed list as well as anything else.
+            let fits = wire_types[winner].1 + if packed > 0 { shape.empty } else { 0 };
+            declaration.repeated |= packed > 0;
+            declaration.presence = shape.defaults > 0;
+            let (type_name, quality) = match winner {
+                0 => (shape.varints.type_name(), 1.0),
+                1 => shape.fixed64.declare("double", "fixed64"),
+                _ => shape.fixed32.declare("float", "fixed32"),
+            };
+            declaration.type_name = type_name.to_string();
+            (fits, quality)
+        }
+        4 => {
+            declaration.type_name = nested;
+            declaration.group = true;
+            (shape.groups, 1.0)
+        }
+        _ => {
+            let filled = delimited - shape.empty;
+            declaration.presence = shape.empty > 0;
+            if filled == 0 {
+                declaration.notes.push("always empty".to_string());
+                (shape.empty, 0.5)
+            } else if shape.strings + shape.text_messages > 0 && shape.utf8 == filled {
+                // Text is text even when it parses as a message by accident, or is too full of
+                // control characters for the string heuristic.
+                declaration.type_name = "string".to_string();
+                (delimited, (shape.strings + shape.text_messages) as f64 / filled as f64)
+            } else if shape.messages == filled {
+                declaration.type_name = nested;
+                declaration.presence = false;
+                (delimited, 1.0)
+            } else {
+                if packed > 0 || shape.messages + shape.bytes < filled {
+                    let kinds = [
+                        (shape.messages, "submessage"),
+                        (shape.strings, "string"),
+                        (packed, "packed list"),
+                        (shape.bytes, "raw value"),
+                    ];
+                    let seen: Vec<_> = kinds.iter().filter(|(count, _)| *count > 0).map(|(count, kind)| format!("{} {}{}", count, kind, plural(*count))).collect();
+                    declaration.notes.push(format!("read as {}", seen.join(", ")));
+                }
+                // Bytes fit anything, but say nothing of what is in them.
+                (delimited, if shape.bytes == filled { 1.0 } else { 0.5 })
+            }
+        }
+    };
+    declaration.confidence = fits as f64 * quality / (total + 1) as f64;
+    declaration
+}
+
+/// Writes the definition of a message type named `name`, indented by `indent` levels, with its
+/// fields in number order followed by the types nested in it.
+fn write_message(out: &mut String, name: &str, shape: &MessageShape, proto2: bool, indent: usize) {
+    let pad = "  ".repeat(indent);
+    let _ = writeln!(out, "{}message {} {{", pad, name);
+    write_fields(out, shape, proto2, indent + 1);
+    let _ = writeln!(out, "{}}}", pad);
+}
+
+fn write_fields(out: &mut String, shape: &MessageShape, proto2: bool, indent: usize) {
+    let pad = "  ".repeat(indent);
+    let mut nested = Vec::new();
+    for (&tag_number, field) in &shape.fields {
+        let mut declaration = declare(tag_number, field);
+        if field.present < shape.instances {
+            declaration.notes.insert(0, format!("in {} of {} message{}", field.present, shape.instances, plural(shape.instances)));
+        }
+        // A proto3 field without explicit presence is never written with its default value, so one
+        // that is must be optional.
+        let label = match (declaration.repeated, proto2) {
+            (true, _) => "repeated ",
+            (false, true) => "optional ",
+            (false, false) if declaration.presence => "optional ",
+            (false, false) => "",
+        };
+        declaration.notes.insert(0, format!("confidence {:.0}%", declaration.confidence * 100.0));
+        let comment = format!(" // {}", declaration.notes.join("; "));
+        if declaration.group {
+            let _ = writeln!(out, "{}{}group {} = {} {{{}", pad, label, declaration.type_name, tag_number, comment);
+            if let Some(child) = &field.child {
+                write_fields(out, child, proto2, indent + 1);
+            }
+            let _ = writeln!(out, "{}}}", pad);
+            continue;
+        }
+        let _ = writeln!(out, "{}{}{} field_{} = {};{}", pad, label, declaration.type_name, tag_number, tag_number, comment);
+        if let Some(child) = field.child.as_ref().filter(|_| declaration.type_name.starts_with("Field")) {
+            nested.push((declaration.type_name, child));
+        }
+    }
+    for (name, child) in nested {
+        out.push('\n');
+        write_message(out, &name, child, proto2, indent);
+    }
+}
+
+/// Finds the sample files among the paths given: the files themselves, and every file in the
+/// directories, and the directories in them, in name order.
+pub fn samples(paths: &[String]) -> Result<Vec<String>, String> {
+    let mut files = Vec::new();
+    let mut directories: Vec<String> = Vec::new();
+    for path in paths {
+        if path != "-" && Path::new(path).is_dir() {
+            directories.push(path.clone());
+        } else {
+            files.push(path.clone());
+        }
+        while let Some(directory) = directories.pop() {
+            let mut entries = Vec::new();
+            for entry in fs::read_dir(&directory).map_err(|err| format!("{}: {}", directory, err))? {
+                entries.push(entry.map_err(|err| format!("{}: {}", directory, err))?.path());
+            }
+            entries.sort();
+            // Directories are pushed in reverse so that the first is walked first.
+            for entry in entries.iter().rev().filter(|entry| entry.is_dir()) {
+                directories.push(entry.to_string_lossy().into_owned());
+            }
+            files.extend(entries.iter().filter(|entry| !entry.is_dir()).map(|entry| entry.to_string_lossy().into_owned()));
+        }
+    }
+    Ok(files)
+}
+
+/// What has been inferred from the samples so far.
+#[derive(Default)]
+pub struct Inference {
+    samples: usize,
+    shape: MessageShape,
+}
+
+impl Inference {
+    /// Adds a sample's fields.
+    pub fn add(&mut self, fields: Vec<ProtoField<Span>>, options: &Options, warnings: &mut Warnings) {
+        self.samples += 1;
+        self.shape.add(Cursor::message(fields), &mut Vec::new(), options, warnings);
+    }
+
+    /// The `.proto` definition inferred, with the top-level message type named `name`. Groups are
+    /// only in proto2, so the file is proto2 if there are any and proto3 otherwise.
+    pub fn to_proto(&self, name: &str) -> String {
+        let proto2 = self.shape.has_groups();
+        let mut out = format!("// Inferred by protocat infer-schema from {} sample{}.\n", self.samples, plural(self.samples));
+        let _ = writeln!(out, "syntax = \"{}\";\n", if proto2 { "proto2" } else { "proto3" });
+        write_message(&mut out, name, &self.shape, proto2, 0);
+        out
+    }
+}
@@ -0,0 +1,202 @@
+//! A small JSON parser, for proto3 JSON input, and the string quoting that JSON output needs.
+
+use std::convert::TryFrom;
+use std::fmt::Write;
+
+use crate::payload::{Value, MAX_NESTING};
+
+/// Parses a JSON document; the top level must be an object.
+pub fn parse(source: &str) -> Result<Vec<(String, Value)>, String> {
+    let mut parser = Parser{source: source.as_bytes(), pos: 0};
+    parser.whitespace();
+    if parser.peek() != Some(b'{') {
+        return parser.error("expected an object");
+    }
+    let value = parser.value(0)?;
+    parser.whitespace();
+    if parser.pos < parser.source.len() {
+        return parser.error("trailing data after the object");
+    }
+    match value {
+        Value::Message(fields) => Ok(fields),
+        _ => unreachable!("the top level was checked to be an object"),
+    }
+}
+
+struct Parser<'a> {
+    source: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error<T>(&self, message: &str) -> Result<T, String> {
+        Err(format!("JSON error at offset {}: {}", self.pos, message))
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.source.get(self.pos).copied()
+    }
+
+    fn whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        self.whitespace();
+        if self.peek() != Some(byte) {
+            return self.error(&format!("expected '{}'", byte as char));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, String> {
+        if depth > MAX_NESTING {
+            return self.error("nested too deeply");
+        }
+        self.whitespace();
+        match self.peek() {
+            Some(b'{') => {
+                self.pos += 1;
+                let mut fields = Vec::new();
+                self.whitespace();
+                if self.peek() == Some(b'}') {
+                    self.pos += 1;
+                    return Ok(Value::Message(fields));
+                }
+                loop {
+                    self.whitespace();
+                    let key = match self.peek() {
+                        Some(b'"') => String::from_utf8_lossy(&self.string()?).into_owned(),
+                        _ => return self.error("expected a key"),
+                    };
+                    self.expect(b':')?;
+                    fields.push((key, self.value(depth + 1)?));
+                    self.whitespace();
+                    match self.peek() {
+                        Some(b',') => self.pos += 1,
+                        Some(b'}') => { self.pos += 1; return Ok(Value::Message(fields)) }
+                        _ => return self.error("expected ',' or '}'"),
+                    }
+                }
+            }
+            Some(b'[') => {
+                self.pos += 1;
+                let mut values = Vec::new();
+                self.whitespace();
+                if self.peek() == Some(b']') {
+                    self.pos += 1;
+                    return Ok(Value::List(values));
+                }
+                loop {
+                    values.push(self.value(depth + 1)?);
+                    self.whitespace();
+                    match self.peek() {
+                        Some(b',') => self.pos += 1,
+                        Some(b']') => { self.pos += 1; return Ok(Value::List(values)) }
+                        _ => return self.error("expected ',' or ']'"),
+                    }
+                }
+            }
+            Some(b'"') => Ok(Value::String(self.string()?)),
+            Some(b'-' | b'0'..=b'9') => {
+                let start = self.pos;
+                while matches!(self.peek(), Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')) {
+                    self.pos += 1;
+                }
+                let number = std::str::from_utf8(&self.source[start..self.pos]).expect("number characters are ASCII");
+                if number.parse::<f64>().is_err() {
+                    self.pos = start;
+                    return self.error("malformed number");
+                }
+                Ok(Value::Number(number.to_string()))
+            }
+            _ => {
+                for (word, value) in [("true", Value::Ident("true".to_string())), ("false", Value::Ident("false".to_string())), ("null", Value::Null)] {
+                    if self.source[self.pos..].starts_with(word.as_bytes()) {
+                        self.pos += word.len();
+                        return Ok(value);
+                    }
+                }
+                self.error("expected a value")
+            }
+        }
+    }
+
+    /// Parses a string, returning its UTF-8 bytes.
+    fn string(&mut self) -> Result<Vec<u8>, String> {
+        self.pos += 1;
+        let mut out = Vec::new();
+        loop {
+            match self.peek() {
+                None => return self.error("unterminated string"),
+                Some(b'"') => { self.pos += 1; return Ok(out) }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    let escaped = match self.peek() {
+                        Some(b'"') => b'"',
+                        Some(b'\\') => b'\\',
+                        Some(b'/') => b'/',
+                        Some(b'b') => 0x08,
+                        Some(b'f') => 0x0c,
+                        Some(b'n') => b'\n',
+                        Some(b'r') => b'\r',
+                        Some(b't') => b'\t',
+                        Some(b'u') => {
+                            self.pos += 1;
+                            let mut code = self.hex4()?;
+                            // A high surrogate has to be followed by the low one.
+                            if (0xd800..0xdc00).contains(&code) && self.source[self.pos..].starts_with(b"\\u") {
+                                self.pos += 2;
+                                let low = self.hex4()?;
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff);
+                            }
+                            let c = char::try_from(code).unwrap_or(char::REPLACEMENT_CHARACTER);
+                            out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                            continue;
+                        }
+                        _ => return self.error("invalid escape"),
+                    };
+                    out.push(escaped);
+                    self.pos += 1;
+                }
+                Some(byte) => {
+                    out.push(byte);
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits = self.source.get(self.pos..self.pos + 4).and_then(|digits| std::str::from_utf8(digits).ok());
+        match digits.and_then(|digits| u32::from_str_radix(digits, 16).ok()) {
+            Some(code) => {
+                self.pos += 4;
+                Ok(code)
+            }
+            None => self.error("invalid \\u escape"),
+        }
+    }
+}
+
+/// Quotes and escapes a string for JSON output.
+pub fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => { let _ = write!(out, "\\u{:04x}", c as u32); }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
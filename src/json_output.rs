@@ -0,0 +1,198 @@
+//! `--format json`: the decoded tree as JSON, for post-processing with jq and scripts.
+//!
+//! Keys are field numbers, and a field that occurs more than once in a message becomes an array of
+//! its values in wire order. Submessages and groups are objects, strings are strings and other length-delimited
+//! data is base64, as in proto3 JSON. Integers above 2^53, which most JSON tools can't hold exactly,
+//! are written as strings.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::ops::Range;
+use std::rc::Rc;
+
+use nom::AsBytes;
+
+use crate::hook::{FieldHook, HookAction};
+use crate::warning::{WarningKind, Warnings};
+use crate::{check_field_number, check_overlong, check_group_end, encoded_bytes, interpret, json, print_indent, run_hooks, Cursor, FieldList, Interpretation, Options, PrintStats, ProtoField, Span, WireValue};
+
+/// A value to print: a field, the range of a group's fields in its level's list, or what a hook
+/// replaced a field with.
+enum Entry<'a> {
+    Field(ProtoField<Span<'a>>),
+    Group(Range<usize>),
+    Replaced(String),
+}
+
+/// One message being printed.
+struct Level<'a> {
+    /// The list the message's fields are in, which its groups share.
+    list: Rc<FieldList<'a>>,
+    /// The fields not yet started, grouped by number in order of first occurrence.
+    keys: std::vec::IntoIter<(u64, Vec<Entry<'a>>)>,
+    /// The remaining values of the field being printed.
+    values: std::vec::IntoIter<Entry<'a>>,
+    /// Whether the field being printed is an array, and whether its first value is still to come.
+    array: bool,
+    first_value: bool,
+    /// Whether a field is being printed, and whether one was before it.
+    in_key: bool,
+    started: bool,
+    /// The indent of the message's keys.
+    indent: usize,
+}
+
+impl<'a> Level<'a> {
+    /// Groups a message's fields by number, running hooks and the field number checks on each. A
+    /// group's fields are an entry of their own, to be printed as an object.
+    fn new(mut fields: Cursor<'a>, indent: usize, path: &mut Vec<u64>, options: &Options, hooks: &[Box<dyn FieldHook>], warnings: &mut Warnings) -> Self {
+        let mut keys: Vec<(u64, Vec<Entry>)> = Vec::new();
+        let mut index = HashMap::new();
+        while let Some(i) = fields.next() {
+            let field = fields.list.fields[i];
+            let tag_number = field.tag_number;
+            path.push(tag_number);
+            check_field_number(tag_number, path, warnings);
+            check_overlong(&field, path, options, warnings);
+            let entry = match (field.value, run_hooks(hooks, path, &field)) {
+                (WireValue::StartGroup, action) => {
+                    let (content, end) = fields.list.group(i);
+                    check_group_end(&end, path, warnings);
+                    match action {
+                        HookAction::Default => Some(Entry::Group(content)),
+                        HookAction::Replace(value) => Some(Entry::Replaced(value)),
+                        HookAction::Skip => None,
+                    }
+                }
+                (WireValue::EndGroup, _) => {
+                    warnings.emit(WarningKind::UnbalancedGroup, path, "end of a group that wasn't started");
+                    None
+                }
+                (_, HookAction::Default) => Some(Entry::Field(field)),
+                (_, HookAction::Replace(value)) => Some(Entry::Replaced(value)),
+                (_, HookAction::Skip) => None,
+            };
+            if let Some(entry) = entry {
+                let i = *index.entry(tag_number).or_insert_with(|| {
+                    keys.push((tag_number, Vec::new()));
+                    keys.len() - 1
+                });
+                keys[i].1.push(entry);
+            }
+            path.pop();
+        }
+        Level{list: fields.list, keys: keys.into_iter(), values: Vec::new().into_iter(), array: false, first_value: false, in_key: false, started: false, indent}
+    }
+}
+
+pub fn number(value: u64) -> String {
+    if value <= 1 << 53 { value.to_string() } else { format!("\"{}\"", value) }
+}
+
+/// Prints a message as a JSON object. Nested messages are walked with an explicit stack like
+/// `print_message` does.
+pub fn print(out: &mut dyn Write, fields: Vec<ProtoField<Span>>, options: &Options, hooks: &[Box<dyn FieldHook>], warnings: &mut Warnings) -> PrintStats {
+    let mut stats = PrintStats{fields: 0, max_depth: 0};
+    let mut path = Vec::new();
+    let mut stack = vec![Level::new(Cursor::message(fields), 1, &mut path, options, hooks, warnings)];
+    out!(out, "{{");
+
+    while !stack.is_empty() {
+        let depth = stack.len() - 1;
+        let level = &mut stack[depth];
+        if let Some(entry) = level.values.next() {
+            if level.array {
+                if !level.first_value {
+                    out!(out, ",");
+                }
+                outln!(out);
+                print_indent(out, level.indent + 1);
+            }
+            level.first_value = false;
+            stats.fields += 1;
+            stats.max_depth = stats.max_depth.max(depth);
+            let field = match entry {
+                Entry::Field(field) => field,
+                Entry::Group(fields) if fields.start >= fields.end => {
+                    out!(out, "{{}}");
+                    continue;
+                }
+                // Groups count towards --max-depth as submessages do, and past it are raw data too.
+                Entry::Group(fields) if path.len() > options.max_depth => {
+                    let message = format!("nested deeper than --max-depth {}, shown as raw data", options.max_depth);
+                    warnings.emit(WarningKind::DepthLimit, &path, &message);
+                    out!(out, "{}", options.bytes_format.json(&encoded_bytes(&level.list.fields[fields])));
+                    continue;
+                }
+                Entry::Group(fields) => {
+                    out!(out, "{{");
+                    let indent = level.indent + if level.array { 2 } else { 1 };
+                    let level = Level::new(Cursor::new(level.list.clone(), fields), indent, &mut path, options, hooks, warnings);
+                    stack.push(level);
+                    continue;
+                }
+                Entry::Replaced(value) => {
+                    out!(out, "{}", json::quote(&value));
+                    continue;
+                }
+            };
+            match field.value {
+                WireValue::VarInt(v) | WireValue::Int64(v) => out!(out, "{}", number(v)),
+                WireValue::Int32(v) => out!(out, "{}", v),
+                WireValue::StartGroup | WireValue::EndGroup => unreachable!("groups were gathered into entries"),
+                WireValue::LengthPrefixed(d) => match interpret(d, options, &path, warnings) {
+                    Interpretation::Message(fields) if fields.is_empty() => out!(out, "{{}}"),
+                    Interpretation::Message(fields) => {
+                        out!(out, "{{");
+                        let indent = level.indent + if level.array { 2 } else { 1 };
+                        let level = Level::new(Cursor::message(fields), indent, &mut path, options, hooks, warnings);
+                        stack.push(level);
+                    }
+                    Interpretation::String(str, _) | Interpretation::LossyString(str, _) => out!(out, "{}", json::quote(&str)),
+                    Interpretation::Packed(values, _) => out!(out, "[{}]", values.iter().map(|&v| number(v)).collect::<Vec<_>>().join(", ")),
+                    Interpretation::Bytes => out!(out, "{}", options.bytes_format.json(d.as_bytes())),
+                },
+            }
+            continue;
+        }
+
+        if level.in_key {
+            if level.array {
+                outln!(out);
+                print_indent(out, level.indent);
+                out!(out, "]");
+            }
+            level.in_key = false;
+            path.pop();
+        }
+        match level.keys.next() {
+            Some((tag_number, entries)) => {
+                if level.started {
+                    out!(out, ",");
+                }
+                outln!(out);
+                print_indent(out, level.indent);
+                out!(out, "\"{}\": ", tag_number);
+                level.array = entries.len() > 1;
+                if level.array {
+                    out!(out, "[");
+                }
+                level.first_value = true;
+                level.values = entries.into_iter();
+                level.in_key = true;
+                level.started = true;
+                path.push(tag_number);
+            }
+            None => {
+                if level.started {
+                    outln!(out);
+                    print_indent(out, level.indent - 1);
+                }
+                out!(out, "}}");
+                stack.pop();
+            }
+        }
+    }
+    outln!(out);
+    stats
+}
@@ -0,0 +1,624 @@
+//! A decoder for the protobuf wire format that needs no schema, as used by the `protocat` tool.
+//!
+//! `parse` splits a message into its fields, leaving it to the caller to decide what
+//! length-delimited values hold; they can be passed to `parse` again to try them as submessages:
+//!
+//! ```
+//! let fields = protocat::parse(&[0x08, 0x96, 0x01, 0x12, 0x02, 0x08, 0x01]).unwrap();
+//! assert_eq!(fields[0].number, 1);
+//! assert_eq!(fields[0].value, protocat::Value::Varint(150));
+//! if let protocat::Value::Bytes(data) = fields[1].value {
+//!     assert_eq!(protocat::parse(data).unwrap()[0].value, protocat::Value::Varint(1));
+//! }
+//! ```
+//!
+//! `FieldIter` reads fields one at a time from a `Read` source instead, for inputs too big to hold in
+//! memory.
+//!
+//! The nom parsers that `parse` is built on are public too, for callers that want to parse
+//! incrementally or keep spans into the input rather than plain slices.
+//!
+//! With the `serde` feature, `Tree` serializes the fields `parse` returns as a tree, with
+//! submessages and groups nested in the fields they're in, and `to_json_value` converts them to a
+//! `serde_json::Value`, for feeding decoded messages into serde-based pipelines.
+//!
+//! With the `wasm` feature, the library built for `wasm32-unknown-unknown` exports a `decode`
+//! function to JavaScript through wasm-bindgen, giving a message's fields as that JSON. Nothing in
+//! the library touches the file system, so it builds for the browser as it is.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, Read};
+use std::ops::{RangeFrom, RangeInclusive};
+
+use nom::*;
+use nom::bytes::complete::*;
+use nom::combinator::*;
+use nom::error::*;
+use nom::multi::*;
+use nom::number::complete::*;
+use nom_locate::*;
+
+#[cfg(feature = "serde")]
+mod tree;
+#[cfg(feature = "wasm")]
+mod wasm;
+
+#[cfg(feature = "serde")]
+pub use tree::{to_json_value, Tree};
+
+/// A field of a message, as returned by `parse`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Field<'a> {
+    pub number: u64,
+    pub value: Value<'a>,
+    /// Where the field's tag starts in the parsed data.
+    pub offset: usize,
+    /// The whole encoded field, tag included.
+    pub raw: &'a [u8],
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Value<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    /// A length-delimited value: a string, bytes, a submessage or a packed repeated field.
+    Bytes(&'a [u8]),
+    StartGroup,
+    EndGroup,
+    Fixed32(u32),
+}
+
+impl<'a> From<ProtoField<Span<'a>>> for Field<'a> {
+    fn from(field: ProtoField<Span<'a>>) -> Self {
+        let value = match field.value {
+            WireValue::VarInt(v) => Value::Varint(v),
+            WireValue::Int64(v) => Value::Fixed64(v),
+            WireValue::LengthPrefixed(d) => Value::Bytes(d.fragment()),
+            WireValue::StartGroup => Value::StartGroup,
+            WireValue::EndGroup => Value::EndGroup,
+            WireValue::Int32(v) => Value::Fixed32(v),
+        };
+        Field{number: field.tag_number, value, offset: field.raw.location_offset(), raw: field.raw.fragment()}
+    }
+}
+
+/// Why data isn't a message.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Error {
+    /// Where parsing failed.
+    pub offset: usize,
+    message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Parses a whole message into its fields, in wire order. A group's fields come between its start
+/// and end, which `parse` checks are balanced.
+pub fn parse(data: &[u8]) -> Result<Vec<Field<'_>>, Error> {
+    parse_with(data, false)
+}
+
+/// Like `parse`, but accepts field numbers outside of the range the spec allows and unbalanced
+/// groups, which `parse` rejects as the surest signs that data isn't a message.
+pub fn parse_lenient(data: &[u8]) -> Result<Vec<Field<'_>>, Error> {
+    parse_with(data, true)
+}
+
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(bytes = data.len())))]
+fn parse_with(data: &[u8], lenient: bool) -> Result<Vec<Field<'_>>, Error> {
+    match all_consuming(protobuf::<_, VerboseError<_>>(lenient))(Span::new(data)) {
+        Ok((_, fields)) => {
+            if !lenient {
+                check_groups(&fields)?;
+            }
+            Ok(fields.into_iter().map(Field::from).collect())
+        }
+        Err(nom::Err::Error(err)) | Err(nom::Err::Failure(err)) => {
+            let offset = err.errors.first().map_or(0, |(span, _)| span.location_offset());
+            Err(Error{offset, message: describe_error(&err)})
+        }
+        Err(nom::Err::Incomplete(_)) => unreachable!("complete parsers never return Incomplete"),
+    }
+}
+
+/// Checks that every group in a message is ended, by an end tag with the same field number, and that
+/// nothing ends a group that wasn't started.
+pub fn check_groups(fields: &[ProtoField<Span>]) -> Result<(), Error> {
+    let mut open: Vec<&ProtoField<Span>> = Vec::new();
+    for field in fields {
+        let offset = field.raw.location_offset();
+        match (field.value, open.last()) {
+            (WireValue::StartGroup, _) => open.push(field),
+            (WireValue::EndGroup, Some(start)) if start.tag_number == field.tag_number => {
+                open.pop();
+            }
+            (WireValue::EndGroup, Some(start)) => {
+                let message = format!("end of group {} at offset {} inside group {}", field.tag_number, offset, start.tag_number);
+                return Err(Error{offset, message});
+            }
+            (WireValue::EndGroup, None) => {
+                let message = format!("end of group {} at offset {} without a start", field.tag_number, offset);
+                return Err(Error{offset, message});
+            }
+            _ => {}
+        }
+    }
+    match open.last() {
+        Some(start) => {
+            let offset = start.raw.location_offset();
+            Err(Error{offset, message: format!("group {} at offset {} is never ended", start.tag_number, offset)})
+        }
+        None => Ok(()),
+    }
+}
+
+/// A field read by `FieldIter`, which owns its encoded bytes since the input isn't kept around.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OwnedField {
+    /// Where the field's tag starts in the input.
+    pub offset: usize,
+    /// The whole encoded field, tag included.
+    pub raw: Vec<u8>,
+}
+
+impl OwnedField {
+    /// The field, borrowing from the encoded bytes; its offset is still the one in the input.
+    pub fn field(&self) -> Field<'_> {
+        let (_, field) = ProtoField::parse::<VerboseError<_>>(true)(Span::new(&self.raw)).expect("the field was read whole");
+        Field{offset: self.offset, ..Field::from(field)}
+    }
+}
+
+/// Reads the fields of a message one at a time from a `Read` source, so that inputs too big to hold
+/// in memory can be gone through field by field; only one field's bytes are held at a time.
+/// Submessages are length-delimited values like any other, for `parse` to split up.
+///
+/// Since fields are yielded as they are read, groups aren't checked for balance, and a truncated last
+/// field is only found once the fields before it have been yielded. Iteration ends at the first error.
+///
+/// Tags and lengths are read a byte at a time, so a file or socket should be wrapped in a
+/// `BufReader` rather than passed in as it is.
+pub struct FieldIter<R> {
+    reader: R,
+    offset: usize,
+    lenient: bool,
+    done: bool,
+}
+
+impl<R: Read> FieldIter<R> {
+    pub fn new(reader: R) -> Self {
+        FieldIter{reader, offset: 0, lenient: false, done: false}
+    }
+
+    /// Like `new`, but accepts field numbers outside of the range the spec allows, as `parse_lenient` does.
+    pub fn lenient(reader: R) -> Self {
+        FieldIter{reader, offset: 0, lenient: true, done: false}
+    }
+
+    fn error(&self, offset: usize, message: String) -> Error {
+        Error{offset, message: format!("parse error at offset {}: {}", offset, message)}
+    }
+
+    /// Reads a varint onto the end of `raw`, returning its value, or `None` if the input ended before it
+    /// started.
+    fn read_varint(&mut self, raw: &mut Vec<u8>, start: usize) -> Result<Option<u64>, Error> {
+        let mut value = 0u64;
+        for i in 0..MAX_VARINT_LEN {
+            let mut byte = [0u8];
+            // An interrupted read is retried for the same byte.
+            loop {
+                match self.reader.read(&mut byte) {
+                    Ok(0) if i == 0 => return Ok(None),
+                    Ok(0) => return Err(self.error(start, "input ends inside a varint".to_string())),
+                    Ok(_) => break,
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(err) => return Err(self.error(start, err.to_string())),
+                }
+            }
+            raw.push(byte[0]);
+            if i == MAX_VARINT_LEN - 1 && byte[0] > 1 {
+                break;
+            }
+            value |= ((byte[0] & 0x7f) as u64) << (7 * i);
+            if byte[0] & 0x80 == 0 {
+                return Ok(Some(value));
+            }
+        }
+        Err(self.error(start, "malformed varint, longer than 10 bytes or over 64 bits".to_string()))
+    }
+
+    /// Reads exactly `len` bytes onto the end of `raw`, without allocating for a length that the
+    /// input doesn't actually have.
+    fn read_exact(&mut self, raw: &mut Vec<u8>, len: u64, start: usize) -> Result<(), Error> {
+        let read = (&mut self.reader).take(len).read_to_end(raw).map_err(|err| self.error(start, err.to_string()))?;
+        if (read as u64) < len {
+            return Err(self.error(start, format!("input ends {} bytes into a value of {} bytes", read, len)));
+        }
+        Ok(())
+    }
+
+    fn read_field(&mut self) -> Result<Option<OwnedField>, Error> {
+        let start = self.offset;
+        let mut raw = Vec::new();
+        let tag = match self.read_varint(&mut raw, start)? {
+            Some(tag) => tag,
+            None => return Ok(None),
+        };
+        let tag_number = tag >> 3;
+        if !self.lenient && tag_number == 0 {
+            return Err(self.error(start, "field number 0 is not allowed".to_string()));
+        }
+        if !self.lenient && tag_number > MAX_FIELD_NUMBER {
+            return Err(self.error(start, "field number exceeds the maximum of 536870911".to_string()));
+        }
+        let value_start = start + raw.len();
+        match tag & 7 {
+            0 => {
+                if self.read_varint(&mut raw, value_start)?.is_none() {
+                    return Err(self.error(value_start, "input ends before a varint".to_string()));
+                }
+            }
+            1 => self.read_exact(&mut raw, 8, value_start)?,
+            2 => match self.read_varint(&mut raw, value_start)? {
+                Some(len) => self.read_exact(&mut raw, len, value_start)?,
+                None => return Err(self.error(value_start, "input ends before a length".to_string())),
+            },
+            3 | 4 => {}
+            5 => self.read_exact(&mut raw, 4, value_start)?,
+            wire_type => return Err(self.error(start, format!("invalid wire type {}", wire_type))),
+        }
+        self.offset += raw.len();
+        Ok(Some(OwnedField{offset: start, raw}))
+    }
+}
+
+impl<R: Read> Iterator for FieldIter<R> {
+    type Item = Result<OwnedField, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let result = self.read_field().transpose();
+        self.done = !matches!(result, Some(Ok(_)));
+        result
+    }
+}
+
+pub fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Input to the low-level parsers: the bytes being parsed, located within the whole input so that
+/// fields and errors know their offsets.
+pub type Span<'a> = LocatedSpan<&'a [u8]>;
+
+/// The wire type in the low three bits of a tag.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum WireType {
+    VarInt,
+    Int64,
+    LengthPrefixed,
+    StartGroup,
+    EndGroup,
+    Int32
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ProtoTag {
+    pub wire_type: WireType,
+    pub tag_number: u64,
+}
+
+/// A field's value as it is on the wire. Groups aren't nested: their start and end are fields of
+/// their own, with the group's fields in between.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum WireValue<I> {
+    VarInt(u64),
+    Int64(u64),
+    LengthPrefixed(I),
+    StartGroup,
+    EndGroup,
+    Int32(u32),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ProtoField<I> {
+    pub tag_number: u64,
+    pub value: WireValue<I>,
+    /// The whole encoded field, tag included.
+    pub raw: I,
+}
+
+/// Parses a length with `f` and takes that many bytes. A length too big for a `usize`, which a
+/// 32-bit target can be given, runs past the end of any input there, and fails as one that does.
+fn length_take<I, E, F>(mut f: F) -> impl FnMut(I) -> IResult<I, I, E>
+where
+    I: InputIter + InputTake + InputLength,
+    F: Parser<I, u64, E>,
+    E: ParseError<I>,
+{
+    move |i: I| {
+        let (i, len) = f.parse(i)?;
+        match usize::try_from(len) {
+            Ok(count) => take(count)(i),
+            Err(_) => Err(nom::Err::Error(E::from_error_kind(i, ErrorKind::Eof))),
+        }
+    }
+}
+
+/// The most bytes a varint can take: ten, for 64 bits at 7 bits a byte.
+pub const MAX_VARINT_LEN: usize = 10;
+
+/// Parses a base 128 varint. A varint longer than `MAX_VARINT_LEN` bytes, or whose last byte has
+/// bits beyond the 64th set, fails with `ErrorKind::TooLarge`, which `describe_error` reports as
+/// a malformed varint.
+pub fn base128_vlq<I, E>(input: I) -> IResult<I, u64, E>
+where
+    I: Clone + PartialEq + Slice<RangeFrom<usize>> + InputIter<Item = u8> + InputLength,
+    E: ParseError<I>,
+{
+    let start = input.clone();
+    let mut input = input;
+    let mut result = 0u64;
+    for i in 0..MAX_VARINT_LEN {
+        let (rest, byte) = le_u8(input)?;
+        input = rest;
+        // The tenth byte only has room for the 64th bit.
+        if i == MAX_VARINT_LEN - 1 && byte > 1 {
+            break;
+        }
+        result |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok((input, result));
+        }
+    }
+    Err(nom::Err::Failure(E::from_error_kind(start, ErrorKind::TooLarge)))
+}
+
+/// Whether a varint at the start of `data` is overlong: encoded in more bytes than its value needs,
+/// with trailing zero groups that a canonical encoder never writes.
+fn is_overlong(data: &[u8]) -> bool {
+    let len = data.iter().position(|b| b & 0x80 == 0).map_or(data.len(), |i| i + 1);
+    len > 1 && data[len - 1] == 0
+}
+
+/// The largest field number permitted by the protobuf spec, 2^29 - 1.
+pub const MAX_FIELD_NUMBER: u64 = 536_870_911;
+
+/// Field numbers reserved for the protobuf implementation; they are legal on the wire, but a real
+/// schema can't declare them, so seeing one is suspicious.
+pub const RESERVED_FIELD_NUMBERS: RangeInclusive<u64> = 19_000..=19_999;
+
+impl ProtoTag {
+    /// Parses a tag. Unless `lenient` is set, field numbers outside of the range permitted by the spec
+    /// are rejected, since they are the best early signal that data isn't actually protobuf.
+    pub fn parse<I, E>(lenient: bool) -> impl FnMut(I) -> IResult<I, Self, E>
+    where
+        I: Copy + Clone + PartialEq + Slice<RangeFrom<usize>> + InputIter<Item = u8> + InputLength,
+        E: ParseError<I> + ContextError<I>,
+    {
+        context("ProtoTag", move |start: I| -> IResult<I, Self, E> {
+            let (input, tag) = base128_vlq(start)?;
+            let tag_number = tag >> 3;
+            if !lenient {
+                if tag_number == 0 {
+                    let err = E::from_error_kind(start, ErrorKind::Verify);
+                    return Err(nom::Err::Failure(E::add_context(start, "field number 0 is not allowed", err)));
+                }
+                if tag_number > MAX_FIELD_NUMBER {
+                    let err = E::from_error_kind(start, ErrorKind::Verify);
+                    return Err(nom::Err::Failure(E::add_context(start, "field number exceeds the maximum of 536870911", err)));
+                }
+            }
+            Ok((input, Self{
+                wire_type: match tag & 0x7 {
+                    0 => WireType::VarInt,
+                    1 => WireType::Int64,
+                    2 => WireType::LengthPrefixed,
+                    3 => WireType::StartGroup,
+                    4 => WireType::EndGroup,
+                    5 => WireType::Int32,
+                    wire_type => {
+                        let err = E::from_error_kind(start, ErrorKind::Verify);
+                        let message = if wire_type == 6 { "invalid wire type 6" } else { "invalid wire type 7" };
+                        return Err(nom::Err::Failure(E::add_context(start, message, err)));
+                    }
+                },
+                tag_number,
+            }))
+        })
+    }
+}
+
+impl<I> ProtoField<I>
+where
+    I: Copy + PartialEq + Slice<RangeFrom<usize>> + InputIter<Item = u8> + InputLength + InputTake
+{
+    /// Parses one field; see `ProtoTag::parse` for what `lenient` allows.
+    pub fn parse<E>(lenient: bool) -> impl FnMut(I) -> IResult<I, Self, E>
+    where
+        E: ParseError<I> + ContextError<I>
+    {
+        context("ProtoField", move |start: I| -> IResult<I, Self, E> {
+            let (input, ProtoTag{wire_type, tag_number}) = ProtoTag::parse(lenient)(start)?;
+            let (input, value) = match wire_type {
+                WireType::VarInt => map(base128_vlq, WireValue::VarInt)(input)?,
+                WireType::Int64 => map(le_u64, WireValue::Int64)(input)?,
+                WireType::LengthPrefixed => map(length_take(base128_vlq), WireValue::LengthPrefixed)(input)?,
+                WireType::StartGroup => (input, WireValue::StartGroup),
+                WireType::EndGroup => (input, WireValue::EndGroup),
+                WireType::Int32 => map(le_u32, WireValue::Int32)(input)?,
+            };
+            let raw = start.take(start.input_len() - input.input_len());
+            Ok((input, ProtoField{tag_number, value, raw}))
+        })
+    }
+}
+
+impl<'a> ProtoField<Span<'a>> {
+    /// Whether the field's tag, or its varint value or length, is overlong. Decoders accept such
+    /// varints, but an encoder that writes them is either unusual or broken, and a message with
+    /// them doesn't re-encode to the same bytes.
+    pub fn is_overlong(&self) -> bool {
+        let raw = self.raw.as_bytes();
+        let tag_len = raw.iter().position(|b| b & 0x80 == 0).map_or(raw.len(), |i| i + 1);
+        is_overlong(raw) || (matches!(self.value, WireValue::VarInt(_) | WireValue::LengthPrefixed(_)) && is_overlong(&raw[tag_len..]))
+    }
+
+    /// The bytes of the field's value, along with their offset in the input if known. Only
+    /// length-prefixed values keep their location; scalars are re-encoded.
+    pub fn value_bytes(&self) -> (Vec<u8>, Option<usize>) {
+        match self.value {
+            WireValue::VarInt(v) => {
+                let mut out = Vec::new();
+                write_varint(&mut out, v);
+                (out, None)
+            }
+            WireValue::Int64(v) => (v.to_le_bytes().to_vec(), None),
+            WireValue::Int32(v) => (v.to_le_bytes().to_vec(), None),
+            WireValue::LengthPrefixed(d) => (d.as_bytes().to_vec(), Some(d.location_offset())),
+            WireValue::StartGroup | WireValue::EndGroup => (Vec::new(), None),
+        }
+    }
+}
+
+/// Parses the fields of a message until the input runs out or a field fails to parse. Wrap it in
+/// `all_consuming` to require the whole input to be a message.
+pub fn protobuf<I, E>(lenient: bool) -> impl FnMut(I) -> IResult<I, Vec<ProtoField<I>>, E>
+where
+    I: Copy + PartialEq + Slice<RangeFrom<usize>> + InputIter<Item = u8> + InputLength + InputTake,
+    E: ParseError<I> + ContextError<I>
+{
+    many0(complete(ProtoField::parse(lenient)))
+}
+
+/// Describes a parse failure for humans, as the innermost offset followed by the contexts it occurred in.
+pub fn describe_error(err: &VerboseError<Span>) -> String {
+    let offset = err.errors.first().map_or(0, |(span, _)| span.location_offset());
+    let mut message = format!("parse error at offset {}", offset);
+    for (_, kind) in err.errors.iter() {
+        match kind {
+            VerboseErrorKind::Context(ctx) => message += &format!(": {}", ctx),
+            VerboseErrorKind::Nom(ErrorKind::Eof) => message += ": unexpected trailing data",
+            VerboseErrorKind::Nom(ErrorKind::TooLarge) => message += ": malformed varint, longer than 10 bytes or over 64 bits",
+            _ => {}
+        }
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The message of the error `parse` fails with, and where it says parsing failed.
+    fn parse_error(data: &[u8]) -> (String, usize) {
+        let err = parse(data).expect_err("the data isn't a message");
+        (err.to_string(), err.offset)
+    }
+
+    /// A varint for `value` that takes `len` bytes, padded with trailing zero groups as needed.
+    fn padded_varint(value: u64, len: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_varint(&mut out, value);
+        let last = out.len() - 1;
+        out[last] |= 0x80;
+        out.resize(len, 0x80);
+        out[len - 1] = 0;
+        out
+    }
+
+    #[test]
+    fn varint_length_bounds() {
+        // u64::MAX takes all ten bytes, with just the 64th bit in the last.
+        let mut max = vec![0x08];
+        write_varint(&mut max, u64::MAX);
+        assert_eq!(max.len(), 1 + MAX_VARINT_LEN);
+        assert_eq!(parse(&max).unwrap()[0].value, Value::Varint(u64::MAX));
+        // Padding is allowed up to ten bytes and no further.
+        let mut padded = vec![0x08];
+        padded.extend(padded_varint(1, MAX_VARINT_LEN));
+        assert_eq!(parse(&padded).unwrap()[0].value, Value::Varint(1));
+        let mut long = vec![0x08];
+        long.extend(padded_varint(1, MAX_VARINT_LEN + 1));
+        assert_eq!(parse_error(&long), ("parse error at offset 1: malformed varint, longer than 10 bytes or over 64 bits: ProtoField".to_string(), 1));
+        // A tenth byte with bits beyond the 64th.
+        let mut over = vec![0x08];
+        over.extend([0xff; 9]);
+        over.push(0x02);
+        assert_eq!(parse_error(&over).1, 1);
+    }
+
+    #[test]
+    fn field_numbers() {
+        assert_eq!(parse_error(b"\x00\x01"), ("parse error at offset 0: field number 0 is not allowed: ProtoTag: ProtoField".to_string(), 0));
+        let mut max = Vec::new();
+        write_varint(&mut max, MAX_FIELD_NUMBER << 3);
+        max.push(0x01);
+        assert_eq!(parse(&max).unwrap()[0].number, MAX_FIELD_NUMBER);
+        let mut over = Vec::new();
+        write_varint(&mut over, (MAX_FIELD_NUMBER + 1) << 3);
+        over.push(0x01);
+        assert!(parse_error(&over).0.contains("field number exceeds the maximum of 536870911"));
+        // Reserved numbers are legal on the wire.
+        for number in [*RESERVED_FIELD_NUMBERS.start(), *RESERVED_FIELD_NUMBERS.end()] {
+            let mut reserved = Vec::new();
+            write_varint(&mut reserved, number << 3);
+            reserved.push(0x01);
+            assert_eq!(parse(&reserved).unwrap()[0].number, number);
+        }
+    }
+
+    #[test]
+    fn invalid_wire_type() {
+        // `1: 1`, then a tag with wire type 6.
+        assert_eq!(parse_error(b"\x08\x01\x0e\x01"), ("parse error at offset 2: invalid wire type 6: ProtoTag: ProtoField".to_string(), 2));
+        let err = FieldIter::new(&b"\x08\x01\x0f"[..]).nth(1).unwrap().unwrap_err();
+        assert_eq!(err.to_string(), "parse error at offset 2: invalid wire type 7");
+    }
+
+    #[test]
+    fn length_past_the_end() {
+        let mut huge = vec![0x0a];
+        write_varint(&mut huge, u64::MAX);
+        assert_eq!(parse_error(&huge), ("parse error at offset 0: unexpected trailing data".to_string(), 0));
+        assert!(parse(b"\x0a\x02\x08").is_err());
+    }
+
+    #[test]
+    fn unbalanced_groups() {
+        assert_eq!(parse(b"\x0b\x08\x01\x0c").unwrap().len(), 3);
+        assert_eq!(parse_error(b"\x0b\x08\x01"), ("group 1 at offset 0 is never ended".to_string(), 0));
+        assert_eq!(parse_error(b"\x08\x01\x0c"), ("end of group 1 at offset 2 without a start".to_string(), 2));
+        assert_eq!(parse_error(b"\x0b\x14\x0c"), ("end of group 2 at offset 1 inside group 1".to_string(), 1));
+    }
+
+    #[test]
+    fn lenient() {
+        // What `parse` rejects as not a message, `parse_lenient` reads on through.
+        let fields = parse_lenient(b"\x00\x05\x0b\x14\x08\x01").unwrap();
+        let values: Vec<(u64, Value)> = fields.iter().map(|field| (field.number, field.value)).collect();
+        assert_eq!(values, [(0, Value::Varint(5)), (1, Value::StartGroup), (2, Value::EndGroup), (1, Value::Varint(1))]);
+        assert_eq!(fields[3].offset, 4);
+        let mut over = Vec::new();
+        write_varint(&mut over, (MAX_FIELD_NUMBER + 1) << 3);
+        over.push(0x01);
+        assert_eq!(parse_lenient(&over).unwrap()[0].number, MAX_FIELD_NUMBER + 1);
+        // Malformed data is still an error.
+        assert!(parse_lenient(b"\x0e").is_err());
+        let fields: Result<Vec<OwnedField>, Error> = FieldIter::lenient(&b"\x00\x05\x08\x01"[..]).collect();
+        assert_eq!(fields.unwrap().iter().map(|field| field.field().number).collect::<Vec<_>>(), [0, 1]);
+    }
+}
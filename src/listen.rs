@@ -0,0 +1,117 @@
+//! `listen`: a debugging sink that decodes the messages services send it over TCP or UDP, printing
+//! them as they arrive.
+//!
+//! Each TCP connection is read on a thread of its own, as a stream in the `--framing` given, or as a
+//! single message ending when the connection closes if there is none. Each UDP datagram is a
+//! message, or a batch of them in the framing. Every message is printed in a block with where it
+//! came from, and a block is printed whole before another starts.
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::follow::print_frame;
+use crate::framing::{self, Framing};
+use crate::schema::Schema;
+use crate::warning::Warnings;
+use crate::{plural, Options};
+
+/// The largest UDP datagram.
+const MAX_DATAGRAM: usize = 65_535;
+
+/// How to decode what arrives, shared by the connections.
+struct Sink<'a> {
+    framing: Framing,
+    schema: Option<(&'a Schema, &'a str)>,
+    options: &'a Options,
+    /// Where the messages are printed, held for a block at a time so that each is printed whole.
+    out: Mutex<&'a mut (dyn Write + Send)>,
+    /// Messages are numbered across connections, in the order they are printed.
+    count: AtomicUsize,
+}
+
+impl Sink<'_> {
+    /// Prints the messages in `data`, a whole datagram or what has been read from a connection,
+    /// returning how many bytes they take up. Without `complete`, a message that hasn't all arrived
+    /// is left for later. The first message starts at `offset` in the stream.
+    fn print(&self, data: &[u8], offset: usize, complete: bool, warnings: &mut Warnings) -> Result<usize, String> {
+        let len = match self.framing {
+            // An empty connection has no message in it.
+            Framing::None if !complete || data.is_empty() => return Ok(0),
+            Framing::None => data.len(),
+            framing => framing::complete_len(data, framing).expect("only streams are listened for"),
+        };
+        let mut frames = framing::split(&data[..len], self.framing)?;
+        let mut out = self.out.lock().unwrap_or_else(|err| err.into_inner());
+        for frame in &mut frames {
+            let index = self.count.fetch_add(1, Ordering::Relaxed);
+            frame.offset += offset;
+            print_frame(*out, index, frame, self.schema, true, self.options, warnings);
+        }
+        Ok(len)
+    }
+
+    fn serve(&self, mut stream: TcpStream, peer: SocketAddr) -> Result<(), String> {
+        let mut warnings = Warnings::new(self.options.warnings_json);
+        warnings.set_input(&peer.to_string());
+        eprintln!("protocat: {}: connected", peer);
+        let mut pending = Vec::new();
+        let mut offset = 0;
+        let mut chunk = vec![0; 64 * 1024];
+        loop {
+            let read = stream.read(&mut chunk).map_err(|err| format!("{}: {}", peer, err))?;
+            pending.extend_from_slice(&chunk[..read]);
+            let len = self.print(&pending, offset, read == 0, &mut warnings).map_err(|err| format!("{}: {}", peer, err))?;
+            pending.drain(..len);
+            offset += len;
+            if read == 0 {
+                break;
+            }
+        }
+        if !pending.is_empty() {
+            eprintln!("protocat: {}: connection closed {} byte{} into a message", peer, pending.len(), plural(pending.len()));
+        }
+        eprintln!("protocat: {}: disconnected", peer);
+        Ok(())
+    }
+}
+
+/// Accepts TCP connections on `address`, printing the messages on each to `out`, until accepting
+/// fails. A connection that goes wrong is reported and closed, and the others carry on.
+pub fn tcp(out: &mut (dyn Write + Send), address: &str, framing: Framing, schema: Option<(&Schema, &str)>, options: &Options) -> Result<(), String> {
+    let listener = TcpListener::bind(address).map_err(|err| format!("{}: {}", address, err))?;
+    let local = listener.local_addr().map_err(|err| format!("{}: {}", address, err))?;
+    eprintln!("protocat: listening on tcp {}", local);
+    let sink = Sink{framing, schema, options, out: Mutex::new(out), count: AtomicUsize::new(0)};
+    let sink = &sink;
+    thread::scope(|scope| loop {
+        let (stream, peer) = listener.accept().map_err(|err| format!("{}: {}", local, err))?;
+        scope.spawn(move || {
+            if let Err(err) = sink.serve(stream, peer) {
+                eprintln!("protocat: {}", err);
+            }
+        });
+    })
+}
+
+/// Receives UDP datagrams on `address`, printing the messages in each to `out`, until receiving fails.
+pub fn udp(out: &mut (dyn Write + Send), address: &str, framing: Framing, schema: Option<(&Schema, &str)>, options: &Options) -> Result<(), String> {
+    let socket = UdpSocket::bind(address).map_err(|err| format!("{}: {}", address, err))?;
+    let local = socket.local_addr().map_err(|err| format!("{}: {}", address, err))?;
+    eprintln!("protocat: listening on udp {}", local);
+    let sink = Sink{framing, schema, options, out: Mutex::new(out), count: AtomicUsize::new(0)};
+    let mut warnings = Warnings::new(options.warnings_json);
+    let mut datagram = vec![0; MAX_DATAGRAM];
+    loop {
+        let (len, peer) = socket.recv_from(&mut datagram).map_err(|err| format!("{}: {}", local, err))?;
+        warnings.set_input(&peer.to_string());
+        // A datagram is all there is of its messages, so one cut short is an error in it alone.
+        match sink.print(&datagram[..len], 0, true, &mut warnings) {
+            Ok(used) if used < len => eprintln!("protocat: {}: datagram ends {} byte{} into a message", peer, len - used, plural(len - used)),
+            Ok(_) => {}
+            Err(err) => eprintln!("protocat: {}: {}", peer, err),
+        }
+    }
+}
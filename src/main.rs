@@ -1,216 +1,2249 @@
-extern crate nom;
-extern crate nom_locate;
-
-use std::env;
-use std::fs::File;
-use std::io::Read;
-use std::ops::RangeFrom;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::process;
+use std::rc::Rc;
+use std::time::Instant;
 
 use nom::*;
 use nom::combinator::*;
 use nom::error::*;
-use nom::number::complete::*;
-use nom::bytes::complete::*;
-use nom::multi::*;
-use nom_locate::*;
+use clap::{Args, CommandFactory, Parser as _, Subcommand};
+use clap_complete::Shell;
+use protocat::{
+    base128_vlq, check_groups, describe_error, protobuf, ProtoField, ProtoTag, Span, WireType, WireValue, MAX_FIELD_NUMBER,
+    RESERVED_FIELD_NUMBERS,
+};
+
+/// Emits a `tracing` debug event when the tracing feature is enabled, and nothing otherwise, so the
+/// decoder can be observed when embedded without costing anything when it isn't.
+macro_rules! trace_event {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::debug!($($arg)*);
+    };
+}
+
+/// `print!` and `println!` for the printers, to the `out` they're given. A decode that can't be
+/// written is of no use, so a write error ends the run, as it would with `print!`.
+macro_rules! out {
+    ($out:expr, $($arg:tt)*) => {
+        write!($out, $($arg)*).unwrap_or_else(|err| crate::output::write_failed(err))
+    };
+}
+
+macro_rules! outln {
+    ($out:expr) => {
+        writeln!($out).unwrap_or_else(|err| crate::output::write_failed(err))
+    };
+    ($out:expr, $($arg:tt)*) => {
+        writeln!($out, $($arg)*).unwrap_or_else(|err| crate::output::write_failed(err))
+    };
+}
+
+mod alternates;
+mod assemble;
+mod base64;
+mod bbolt;
+mod budget;
+mod builtin;
+mod color;
+mod compat;
+mod compressed;
+mod config;
+mod conformance;
+mod descriptor;
+mod diff;
+mod edit;
+mod encode;
+mod error;
+mod explain;
+mod fingerprint;
+mod follow;
+mod framing;
+mod grpc_status;
+mod hex;
+mod hook;
+mod http;
+mod infer;
+mod json;
+mod json_output;
+mod listen;
+mod manifest;
+mod maps;
+mod mcap;
+mod mmap;
+mod open;
+mod output;
+mod path;
+mod payload;
+mod pcap;
+#[cfg(feature = "plugins")]
+mod plugin;
+mod proto;
+mod proto_json;
+mod protoc_cli;
+mod protoc_plugin;
+mod protoscope;
+mod query;
+mod repl;
+mod schema;
+#[cfg(feature = "scripting")]
+mod script;
+mod sha256;
+mod snappy;
+mod stats;
+mod store;
+mod template;
+mod text;
+mod textformat;
+mod units;
+mod warning;
+mod websocket;
+mod wkt;
+mod yaml;
+
+use alternates::FloatDisplay;
+use color::{ColorChoice, Style};
+use error::ProtocatError;
+use framing::Framing;
+use hook::{FieldHook, HookAction};
+use mmap::{Input, MmapChoice};
+use output::Output;
+use payload::InputFormat;
+use template::Template;
+use text::StringEncoding;
+use warning::{WarningKind, Warnings};
 
-type Span<'a> = LocatedSpan<&'a [u8]>;
 type Error<I> = VerboseError<I>;
 
-#[derive(Copy, Clone, Debug, PartialEq)]
-enum WireType {
-    VarInt,
-    Int64,
-    LengthPrefixed,
-    StartGroup,
-    EndGroup,
-    Int32
-}
-
-#[derive(Copy, Clone, Debug, PartialEq)]
-struct ProtoTag {
-    wire_type: WireType,
-    tag_number: u64,
-}
-
-#[derive(Copy, Clone, Debug, PartialEq)]
-enum WireValue<I> {
-    VarInt(u64),
-    Int64(u64),
-    LengthPrefixed(I),
-    StartGroup,
-    EndGroup,
-    Int32(u32),
-}
-
-#[derive(Copy, Clone, Debug, PartialEq)]
-struct ProtoField<I> {
-    tag_number: u64,
-    value: WireValue<I>,
-}
-
-type ProtoMessage<'a, I> = &'a [ProtoField<I>];
-
-fn length_take<I, N, E, F>(mut f: F) -> impl FnMut(I) -> IResult<I, I, E>
-where
-    I: InputIter + InputTake + InputLength,
-    N: ToUsize,
-    F: Parser<I, N, E>,
-    E: ParseError<I>,
-{
-    move |i: I| {
-        let (i, count) = f.parse(i)?;
-        take(count)(i)
-    }
-}
-
-fn base128_vlq<'a, I, E>(input: I) -> IResult<I, u64, E>
-where
-    I: Clone + PartialEq + Slice<RangeFrom<usize>> + InputIter<Item = u8> + InputLength,
-    E: ParseError<I>,
-{
-    let mut result = 0u64;
-    let (input, (lead, tail)) = many_till(
-        verify(le_u8, |c| c & 0x80 != 0),
-        verify(le_u8, |c| c & 0x80 == 0),
-    )(input)?;
-    result |= (tail & 0x7f) as u64;
-    for b in lead.as_bytes().iter().rev() {
-        result <<= 7;
-        result |= (*b & 0x7f) as u64;
-    }
-    Ok((input, result))
-}
-
-impl ProtoTag {
-    fn parse<'a, I, E>(input: I) -> IResult<I, Self, E>
-    where
-        I: Copy + Clone + PartialEq + Slice<RangeFrom<usize>> + InputIter<Item = u8> + InputLength,
-        E: ParseError<I> + ContextError<I>,
-    {
-        context("ProtoTag", move |input: I| -> IResult<I, Self, E> {
-            let (input, tag) = base128_vlq(input)?;
-            Ok((input, Self{
-                wire_type: match tag & 0x7 {
-                    0 => WireType::VarInt,
-                    1 => WireType::Int64,
-                    2 => WireType::LengthPrefixed,
-                    3 => WireType::StartGroup,
-                    4 => WireType::EndGroup,
-                    5 => WireType::Int32,
-                    _ => return Err(nom::Err::Failure(E::from_error_kind(input, ErrorKind::Verify)))
-                },
-                tag_number: tag >> 3,
-            }))
-        })(input)
+fn plural(count: usize) -> &'static str {
+    if count == 1 { "" } else { "s" }
+}
+
+/// Formats an annotation for the end of a line, if `--annotate-units` is on and there is one.
+fn annotation<F: FnOnce() -> Option<String>>(options: &Options, annotate: F) -> String {
+    match options.annotate_units.then(annotate).flatten() {
+        Some(note) => format!(" ({})", note),
+        None => String::new(),
+    }
+}
+
+/// Formats a field's encoded size for the end of its line, if `--show-sizes` is on.
+fn size_note(options: &Options, len: usize) -> String {
+    if options.show_sizes { format!(" // {}", units::format_size(len as u64)) } else { String::new() }
+}
+
+/// Stands in for a string or bytes value longer than `--max-value-bytes`, giving its size and
+/// SHA-256, unless `--full-values` is on.
+fn elided(kind: &str, data: &[u8], options: &Options) -> Option<String> {
+    (!options.full_values && data.len() as u64 > options.max_value_bytes)
+        .then(|| format!("<{}, {}, sha256={}>", kind, units::format_size(data.len() as u64), sha256::hex_digest(data)))
+}
+
+fn print_indent(out: &mut dyn Write, indent: usize) {
+    for _ in 0..indent {
+        out!(out, "  ")
+    }
+}
+
+fn check_field_number(tag_number: u64, path: &[u64], warnings: &mut Warnings) {
+    if tag_number == 0 || tag_number > MAX_FIELD_NUMBER {
+        let message = format!("field number {} is outside of the range allowed by the spec", tag_number);
+        warnings.emit(WarningKind::InvalidFieldNumber, path, &message);
+    } else if RESERVED_FIELD_NUMBERS.contains(&tag_number) {
+        let message = format!("field number {} is in the reserved range 19000-19999", tag_number);
+        warnings.emit(WarningKind::ReservedFieldNumber, path, &message);
+    }
+}
+
+/// Warns about a field with an overlong varint, with `--warn-overlong`.
+fn check_overlong(field: &ProtoField<Span>, path: &[u64], options: &Options, warnings: &mut Warnings) {
+    if options.warn_overlong && field.is_overlong() {
+        warnings.emit(WarningKind::OverlongVarint, path, "varint encoded in more bytes than it needs");
+    }
+}
+
+/// How length-prefixed data is displayed.
+enum Interpretation<'a> {
+    Message(Vec<ProtoField<Span<'a>>>),
+    /// Text, along with the encoding that decoded it.
+    String(String, StringEncoding),
+    /// Almost-text with replacement characters, along with the number of invalid sequences.
+    LossyString(String, usize),
+    /// A packed repeated field, along with the wire type of its values.
+    Packed(Vec<u64>, WireType),
+    Bytes,
+}
+
+/// Unpacks data as a packed repeated field of the given wire type, if `--packed` is on and it is one.
+fn packed_values(data: Span, wire_type: WireType, options: &Options) -> Option<Vec<u64>> {
+    let values = schema::unpack(data, wire_type).filter(|_| options.packed)?;
+    Some(values.into_iter().map(|value| match value {
+        WireValue::VarInt(v) | WireValue::Int64(v) => v,
+        WireValue::Int32(v) => v as u64,
+        _ => unreachable!("only scalars are unpacked"),
+    }).collect())
+}
+
+/// Formats packed values as a list, noting the wire type if it isn't the usual varints.
+fn format_packed(values: &[u64], wire_type: WireType) -> String {
+    let list = values.iter().map(u64::to_string).collect::<Vec<_>>().join(", ");
+    match wire_type {
+        WireType::Int64 => format!("[{}] (fixed64)", list),
+        WireType::Int32 => format!("[{}] (fixed32)", list),
+        _ => format!("[{}]", list),
     }
 }
 
-impl<I> ProtoField<I>
-where
-    I: Copy + PartialEq + Slice<RangeFrom<usize>> + InputIter<Item = u8> + InputLength + InputTake
-{
-    fn parse<E>(input: I) -> IResult<I, Self, E>
-    where
-        E: ParseError<I> + ContextError<I>
+/// Applies heuristics to length-prefixed data to attempt to drill deeper, going in order of most strict
+/// to least strict, and warns about the field at `path` if the guess is shaky. The heuristics turned
+/// off by `--no-submessage-heuristic`, `--no-string-heuristic` and `--bytes-only` are skipped.
+fn interpret<'a>(data: Span<'a>, options: &Options, path: &[u64], warnings: &mut Warnings) -> Interpretation<'a> {
+    if options.bytes_only {
+        return Interpretation::Bytes;
+    }
+    // TODO: should probably handle certain cases (like all zeros should probably be raw data.)
+    // Submessages are always parsed strictly, even in lenient mode: out of range field numbers are a
+    // strong hint that the data is something else.
+    let message = (!options.no_submessage_heuristic)
+        .then(|| all_consuming(protobuf::<_, Error<_>>(false))(data).ok())
+        .flatten()
+        .filter(|(_, fields)| check_groups(fields).is_ok());
+    let strings = !options.no_string_heuristic;
+    // Data that nests deeper than anyone would write is more likely to be crafted to exhaust
+    // resources than real, so it's left as raw data.
+    if message.is_some() && path.len() > options.max_depth {
+        trace_event!(?path, bytes = data.input_len(), "too deep to read as a submessage");
+        let message = format!("nested deeper than --max-depth {}, shown as raw data", options.max_depth);
+        warnings.emit(WarningKind::DepthLimit, path, &message);
+        Interpretation::Bytes
+    } else if let Some((_, fields)) = message {
+        trace_event!(?path, bytes = data.input_len(), fields = fields.len(), "read as a submessage");
+        Interpretation::Message(fields)
+    } else if let Some(values) = packed_values(data, WireType::VarInt, options).filter(|_| !text::is_clean(data.as_bytes(), options.string_encoding)) {
+        // Any ASCII is also a list of varints, so only text with control characters is taken for one.
+        trace_event!(?path, bytes = data.input_len(), values = values.len(), "read as packed varints");
+        Interpretation::Packed(values, WireType::VarInt)
+    } else if let Some((str, encoding)) = text::decode(data.as_bytes(), options.string_encoding, options.printable_threshold).filter(|_| strings) {
+        trace_event!(?path, bytes = data.input_len(), encoding = encoding.name(), "read as a string");
+        Interpretation::String(str, encoding)
+    } else if let Some((str, invalid)) = text::lossy_utf8(data.as_bytes()).filter(|(str, _)| strings && options.lossy_utf8 && text::mostly_printable(str, options.printable_threshold)) {
+        trace_event!(?path, bytes = data.input_len(), invalid, "fell back to a lossy string");
+        let message = format!("shown as a string despite {} invalid UTF-8 sequence{}", invalid, plural(invalid));
+        warnings.emit(WarningKind::LossyString, path, &message);
+        Interpretation::LossyString(str, invalid)
+    } else if let Some((values, wire_type)) = [WireType::Int64, WireType::Int32].iter()
+        .find_map(|&wire_type| packed_values(data, wire_type, options).map(|values| (values, wire_type)))
     {
-        context("ProtoField", move |input: I| -> IResult<I, Self, E> {
-            let (input, tag) = ProtoTag::parse(input)?;
-            match tag {
-                ProtoTag{wire_type: WireType::VarInt, tag_number} =>
-                    map(base128_vlq, |value| ProtoField{tag_number, value: WireValue::VarInt(value)})(input),
+        trace_event!(?path, bytes = data.input_len(), values = values.len(), "read as packed fixed-width values");
+        Interpretation::Packed(values, wire_type)
+    } else {
+        trace_event!(?path, bytes = data.input_len(), "fell back to raw bytes");
+        // Raw data is only a failed guess if it was allowed to be a guess.
+        if strings && !options.no_submessage_heuristic {
+            warnings.emit(WarningKind::StringGuessFailed, path, "not a submessage or text, shown as raw data");
+        }
+        Interpretation::Bytes
+    }
+}
 
-                ProtoTag{wire_type: WireType::Int64, tag_number} =>
-                    map(le_u64, |value| ProtoField{tag_number, value: WireValue::Int64(value)})(input),
+/// A message's fields, shared by everything that walks them, with where each of its groups ends, so
+/// that a group can be walked as a range of them rather than copied out.
+#[derive(Debug)]
+struct FieldList<'a> {
+    fields: Vec<ProtoField<Span<'a>>>,
+    /// For each field that starts a group, the index of the field that ends it, or the number of
+    /// fields if nothing does. A group ends at the first end tag that no group inside it takes.
+    ends: Vec<usize>,
+}
 
-                ProtoTag{wire_type: WireType::LengthPrefixed, tag_number} =>
-                    map(length_take(base128_vlq), |value| ProtoField{tag_number, value: WireValue::LengthPrefixed(value)})(input),
+impl<'a> FieldList<'a> {
+    fn new(fields: Vec<ProtoField<Span<'a>>>) -> Rc<Self> {
+        let mut ends = vec![fields.len(); fields.len()];
+        let mut open = Vec::new();
+        for (i, field) in fields.iter().enumerate() {
+            match field.value {
+                WireValue::StartGroup => open.push(i),
+                WireValue::EndGroup => {
+                    if let Some(start) = open.pop() {
+                        ends[start] = i;
+                    }
+                }
+                _ => {}
+            }
+        }
+        Rc::new(FieldList{fields, ends})
+    }
 
-                ProtoTag{wire_type: WireType::StartGroup, tag_number} =>
-                    Ok((input, ProtoField{tag_number, value: WireValue::StartGroup})),
+    /// The fields that the one at `i` takes up: a group's run through its end.
+    fn extent(&self, i: usize) -> Range<usize> {
+        match self.fields[i].value {
+            WireValue::StartGroup => i..(self.ends[i] + 1).min(self.fields.len()),
+            _ => i..i + 1,
+        }
+    }
+
+    /// The fields of the group that starts at `i`, and the field that ends it if anything does.
+    fn group(&self, i: usize) -> (Range<usize>, Option<ProtoField<Span<'a>>>) {
+        let end = self.ends[i];
+        (i + 1..end, self.fields.get(end).copied())
+    }
+}
+
+/// The fields of one message level still to be walked, as ranges of a `FieldList` in the order
+/// they're walked in. Every range is made of whole groups, so a group's fields are skipped over by
+/// `next` to be walked as a level of their own.
+struct Cursor<'a> {
+    list: Rc<FieldList<'a>>,
+    ranges: VecDeque<Range<usize>>,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(list: Rc<FieldList<'a>>, range: Range<usize>) -> Self {
+        Cursor{list, ranges: VecDeque::from([range])}
+    }
+
+    /// A cursor over all of a message's fields.
+    fn message(fields: Vec<ProtoField<Span<'a>>>) -> Self {
+        let list = FieldList::new(fields);
+        let len = list.fields.len();
+        Cursor::new(list, 0..len)
+    }
+
+    /// The index of the next field, past the fields of any group before it.
+    fn next(&mut self) -> Option<usize> {
+        while let Some(range) = self.ranges.front_mut() {
+            if range.start >= range.end {
+                self.ranges.pop_front();
+                continue;
+            }
+            let i = range.start;
+            range.start = self.list.extent(i).end;
+            return Some(i);
+        }
+        None
+    }
 
-                ProtoTag{wire_type: WireType::EndGroup, tag_number} =>
-                    Ok((input, ProtoField{tag_number, value: WireValue::EndGroup})),
+    /// The indices of the fields still to come, leaving out the fields of groups.
+    fn rest(&self) -> impl Iterator<Item = usize> + '_ {
+        self.ranges.iter().flat_map(move |range| {
+            let mut i = range.start;
+            std::iter::from_fn(move || {
+                (i < range.end).then(|| {
+                    let field = i;
+                    i = self.list.extent(i).end;
+                    field
+                })
+            })
+        })
+    }
 
-                ProtoTag{wire_type: WireType::Int32, tag_number} =>
-                    map(le_u32, |value| ProtoField{tag_number, value: WireValue::Int32(value)})(input)
+    /// Takes the fields at `indices`, which are among those still to come and in the order they
+    /// come in, out of the cursor, each with its group's fields, returning them as ranges.
+    fn take(&mut self, indices: &[usize]) -> Vec<Range<usize>> {
+        let mut taken = Vec::new();
+        let mut remaining = VecDeque::new();
+        let mut indices = indices.iter().peekable();
+        for mut range in self.ranges.drain(..) {
+            while let Some(&&i) = indices.peek().filter(|&&&i| range.contains(&i)) {
+                let extent = self.list.extent(i);
+                remaining.push_back(range.start..i);
+                range.start = extent.end;
+                taken.push(extent);
+                indices.next();
             }
-        })(input)
+            remaining.push_back(range);
+        }
+        remaining.retain(|range| range.start < range.end);
+        self.ranges = remaining;
+        taken
     }
 }
 
-fn protobuf<'a, I, E>(input: I) -> IResult<I, Vec<ProtoField<I>>, E>
-where
-    I: Copy + PartialEq + Slice<RangeFrom<usize>> + InputIter<Item = u8> + InputLength + InputTake,
-    E: ParseError<I> + ContextError<I>
-{
-    many0(complete(ProtoField::parse))(input)
+/// The bytes `fields` were read from, such as a group's fields to show it as raw data when it's
+/// nested too deep.
+fn encoded_bytes(fields: &[ProtoField<Span>]) -> Vec<u8> {
+    fields.iter().flat_map(|field| field.raw.as_bytes().iter().copied()).collect()
 }
 
-fn print_indent(indent: usize) {
-    for _ in 0..indent {
-        print!("  ")
+/// Warns about a group at `path` that isn't properly ended, which only lenient parsing lets through.
+fn check_group_end(end: &Option<ProtoField<Span>>, path: &[u64], warnings: &mut Warnings) {
+    let tag_number = path[path.len() - 1];
+    match end {
+        None => warnings.emit(WarningKind::UnbalancedGroup, path, "group is never ended"),
+        Some(end) if end.tag_number != tag_number => {
+            let message = format!("group is ended by the end of group {}", end.tag_number);
+            warnings.emit(WarningKind::UnbalancedGroup, path, &message);
+        }
+        Some(_) => {}
     }
 }
 
-fn print_message(indent: usize, fields: ProtoMessage<LocatedSpan<&[u8]>>) {
-    for field in fields.iter() {
+/// What `print_message` saw while printing, for the summary line.
+struct PrintStats {
+    fields: usize,
+    max_depth: usize,
+}
+
+/// Asks each hook in turn how to print a field; the first one with an opinion wins.
+fn run_hooks(hooks: &[Box<dyn FieldHook>], path: &[u64], field: &ProtoField<Span>) -> HookAction {
+    for hook in hooks {
+        match hook.on_field(path, field) {
+            Ok(HookAction::Default) => {}
+            Ok(action) => return action,
+            Err(err) => ProtocatError::Usage(err).exit(),
+        }
+    }
+    HookAction::Default
+}
+
+/// Starts `field`'s line with `print_extent_start`. If `element` is set, the field is a value in a
+/// list from --group-repeated, which already has the tag, so the tag is left out.
+fn print_field_start(out: &mut dyn Write, indent: usize, field: &ProtoField<Span>, element: bool, options: &Options) {
+    let tag_number = (!element).then_some(field.tag_number);
+    print_extent_start(out, indent, field.raw.location_offset(), field.raw.input_len(), tag_number, options);
+}
+
+/// Starts a field's line: the indent, with `--show-offsets` where the field is in the input and how
+/// many bytes it takes, tag included, e.g. `[12+3] `, and the tag number if there is one.
+fn print_extent_start(out: &mut dyn Write, indent: usize, offset: usize, len: usize, tag_number: Option<u64>, options: &Options) {
+    print_indent(out, indent);
+    if options.show_offsets {
+        out!(out, "[{}+{}] ", offset, len);
+    }
+    if let Some(tag_number) = tag_number {
+        out!(out, "{}: ", color::paint(options, Style::Tag, tag_number));
+    }
+}
+
+/// What `print_message_indented` keeps about a message level besides its fields.
+struct LevelState {
+    /// The field numbers that were checked for being a map.
+    checked: HashSet<u64>,
+    /// With --group-repeated, how many times each field number occurs, not counting fields in groups.
+    counts: HashMap<u64, usize>,
+    /// The number of values still to come of the list being printed, for --group-repeated.
+    list: Option<usize>,
+}
+
+impl LevelState {
+    fn new(level: &Cursor, options: &Options) -> Self {
+        let mut counts = HashMap::new();
+        if options.group_repeated {
+            for field in level.rest().map(|i| level.list.fields[i]).filter(|field| field.value != WireValue::EndGroup) {
+                *counts.entry(field.tag_number).or_insert(0) += 1;
+            }
+        }
+        LevelState{checked: HashSet::new(), counts, list: None}
+    }
+}
+
+/// How many of `levels` are in the middle of a list, each of which indents its values one more
+/// level.
+fn open_lists(levels: &[LevelState]) -> usize {
+    levels.iter().filter(|level| level.list.is_some()).count()
+}
+
+/// The indices of the later occurrences of a field numbered `tag_number` among the fields still to
+/// come in `level`, leaving out fields inside groups.
+fn later_occurrences(level: &Cursor, tag_number: u64) -> Vec<usize> {
+    level.rest().filter(|&i| level.list.fields[i].tag_number == tag_number && level.list.fields[i].value != WireValue::EndGroup).collect()
+}
+
+fn print_message(out: &mut dyn Write, fields: Vec<ProtoField<Span>>, options: &Options, hooks: &[Box<dyn FieldHook>], warnings: &mut Warnings) -> PrintStats {
+    print_message_indented(out, fields, 0, options, hooks, warnings)
+}
+
+/// Like `print_message`, but with every line indented by `base` more levels, for printing a decode
+/// inside other output.
+fn print_message_indented(out: &mut dyn Write, fields: Vec<ProtoField<Span>>, base: usize, options: &Options, hooks: &[Box<dyn FieldHook>], warnings: &mut Warnings) -> PrintStats {
+    print_message_at(out, fields, base, &[], options, hooks, warnings)
+}
+
+/// Like `print_message_indented`, for a message that is itself at `prefix`, such as a compressed
+/// one, so that its fields' paths and --max-depth carry on from there.
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(fields = fields.len())))]
+fn print_message_at(
+    out: &mut dyn Write, fields: Vec<ProtoField<Span>>, base: usize, prefix: &[u64], options: &Options, hooks: &[Box<dyn FieldHook>],
+    warnings: &mut Warnings,
+) -> PrintStats {
+    // Nested messages are walked with an explicit stack rather than recursion, so that crafted inputs
+    // with thousands of nesting levels can't overflow the call stack. Each entry holds the remaining
+    // fields of one message level; the indent is the depth of the entry in the stack. `path` holds the
+    // tag numbers of the submessages entered so far.
+    let cursor = Cursor::message(fields);
+    let mut levels = vec![LevelState::new(&cursor, options)];
+    let mut stack = vec![cursor];
+    let mut path = prefix.to_vec();
+    let mut stats = PrintStats{fields: 0, max_depth: 0};
+    // A span for each submessage being printed, entered while it is.
+    #[cfg(feature = "tracing")]
+    let mut spans: Vec<tracing::span::EnteredSpan> = Vec::new();
+
+    while !stack.is_empty() {
+        let depth = stack.len() - 1;
+        let level = &mut stack[depth];
+        // A list ends once its last value has been printed, with everything nested in it.
+        if levels[depth].list == Some(0) {
+            print_indent(out, base + depth + open_lists(&levels) - 1);
+            outln!(out, "{}", color::paint(options, Style::Brace, "]"));
+            levels[depth].list = None;
+        }
+        let (index, field) = match level.next() {
+            Some(i) => (i, level.list.fields[i]),
+            None => {
+                stack.pop();
+                levels.pop();
+                path.pop();
+                #[cfg(feature = "tracing")]
+                spans.pop();
+                if !stack.is_empty() {
+                    print_indent(out, base + stack.len() - 1 + open_lists(&levels));
+                    outln!(out, "{}", color::paint(options, Style::Brace, "}"));
+                }
+                continue;
+            }
+        };
+        // With --group-repeated, a field's later occurrences are moved up to follow its first, which
+        // starts a list of them all. Maps are left to the map heuristic.
+        let map_heuristic = !options.no_map_heuristic && !options.no_submessage_heuristic && !options.bytes_only && hooks.is_empty();
+        if levels[depth].list.is_none() && levels[depth].counts.remove(&field.tag_number).is_some_and(|count| count > 1) {
+            let later = later_occurrences(level, field.tag_number);
+            let occurrences: Vec<ProtoField<Span>> = std::iter::once(field).chain(later.iter().map(|&i| level.list.fields[i])).collect();
+            let is_map = map_heuristic && maps::looks_like_map(&occurrences);
+            if !later.is_empty() && !is_map {
+                let taken = level.take(&later);
+                // The list takes up everything from its first value through its last, groups included.
+                let offset = field.raw.location_offset();
+                let last = taken.last().map_or(field, |extent| level.list.fields[extent.end - 1]);
+                let len = last.raw.location_offset() + last.raw.input_len() - offset;
+                for range in taken.into_iter().rev() {
+                    level.ranges.push_front(range);
+                }
+                print_extent_start(out, base + depth + open_lists(&levels), offset, len, Some(field.tag_number), options);
+                outln!(out, "{}", color::paint(options, Style::Brace, "["));
+                levels[depth].list = Some(later.len() + 1);
+            }
+        }
+        if let Some(remaining) = &mut levels[depth].list {
+            *remaining -= 1;
+        }
+        let element = levels[depth].list.is_some();
+        let indent = base + depth + open_lists(&levels);
+        stats.fields += 1;
+        stats.max_depth = stats.max_depth.max(depth);
+        path.push(field.tag_number);
+        check_field_number(field.tag_number, &path, warnings);
+        check_overlong(&field, &path, options, warnings);
+
+        match run_hooks(hooks, &path, &field) {
+            HookAction::Default => {}
+            HookAction::Replace(value) => {
+                print_field_start(out, indent, &field, element, options);
+                outln!(out, "{}{}", value, size_note(options, field.raw.input_len()));
+                path.pop();
+                continue;
+            }
+            HookAction::Skip => {
+                path.pop();
+                continue;
+            }
+        }
+
         match field.value {
             WireValue::VarInt(v) => {
-                print_indent(indent);
-                println!("{}: {}", field.tag_number, v);
+                print_field_start(out, indent, &field, element, options);
+                let alternates: String = alternates::varint(v, options.show_alternates).iter().map(|note| format!(" ({})", note)).collect();
+                outln!(out, "{}{}{}{}", color::paint(options, Style::Varint, v), alternates, annotation(options, || units::annotate_varint(v)), size_note(options, field.raw.input_len()));
             }
 
             WireValue::Int64(v) => {
-                print_indent(indent);
-                println!("{}: {}", field.tag_number, v);
+                print_field_start(out, indent, &field, element, options);
+                let double = alternates::double(v, options.floats).map(|d| format!(" (double: {})", d)).unwrap_or_default();
+                outln!(out, "{}{}{}{}", color::paint(options, Style::Fixed, v), double, annotation(options, || units::annotate_integer(v)), size_note(options, field.raw.input_len()));
             }
 
             WireValue::LengthPrefixed(d) => {
-                // Apply heuristics to attempt to drill deeper, going in order of most strict to least strict.
-                // TODO: should probably handle certain cases (like all zeros should probably be raw data.)
-                if let Ok((_, fields)) = all_consuming(protobuf::<_, Error<_>>)(d) {
-                    // Treat as submessage.
-                    print_indent(indent);
-                    println!("{}: {{", field.tag_number);
-
-                    print_message(indent + 1, &fields);
-
-                    print_indent(indent);
-                    println!("}}")
-                } else if let Ok(str) = String::from_utf8(d.as_bytes().to_vec()) {
-                    // Treat as string.
-                    println!("{}: {}", field.tag_number, str);
-                } else {
-                    // Treat as raw data.
-                    let data = d.as_bytes().to_vec();
-                    println!("{}: {:x?}", field.tag_number, data);
+                // A field is checked for being a map at its first occurrence, which stands for them all,
+                // and the map is printed there. Hooks see every occurrence, so they get no maps.
+                if map_heuristic && !element && levels[depth].checked.insert(field.tag_number) {
+                    let later = later_occurrences(level, field.tag_number);
+                    let occurrences: Vec<ProtoField<Span>> = std::iter::once(field).chain(later.iter().map(|&i| level.list.fields[i])).collect();
+                    if let Some(entries) = maps::entries(&occurrences, &mut path, options, warnings) {
+                        level.take(&later);
+                        print_field_start(out, indent, &field, element, options);
+                        let size = occurrences.iter().map(|field| field.raw.input_len()).sum();
+                        let inner = maps::print(out, entries, indent, size, &mut path, options, warnings);
+                        stats.fields += inner.fields;
+                        stats.max_depth = stats.max_depth.max(depth + 1 + inner.max_depth);
+                        path.pop();
+                        continue;
+                    }
+                }
+                // A compressed message is printed in a block of its own, since its fields aren't in the
+                // input to be walked like a submessage's, at the path it's at so that it counts towards
+                // --max-depth as a submessage does.
+                let decompressed = (options.decompress && path.len() <= options.max_depth).then(|| compressed::message(d.as_bytes())).flatten();
+                if let Some((compression, inflated)) = decompressed {
+                    let fields = parse_message(&inflated, false).expect("compressed::message parsed it");
+                    print_field_start(out, indent, &field, element, options);
+                    let note = format!(" ({}, {} decompressed)", compression.name(), units::format_size(inflated.len() as u64));
+                    outln!(out, "{}{}{}", color::paint(options, Style::Brace, "{"), note, size_note(options, field.raw.input_len()));
+                    let inner = print_message_at(out, fields, indent + 1, &path, options, hooks, warnings);
+                    stats.fields += inner.fields;
+                    stats.max_depth = stats.max_depth.max(depth + 1 + inner.max_depth);
+                    print_indent(out, indent);
+                    outln!(out, "{}", color::paint(options, Style::Brace, "}"));
+                    path.pop();
+                    continue;
+                }
+                let interpretation = interpret(d, options, &path, warnings);
+                let alternates = alternates::length_prefixed(d, &interpretation, options).map(|note| format!(" ({})", note)).unwrap_or_default();
+                match interpretation {
+                    Interpretation::Message(fields) => {
+                        // Treat as submessage; its fields are printed on the following iterations.
+                        print_field_start(out, indent, &field, element, options);
+                        let note = match options.wkt_heuristics.then(|| wkt::recognize(&fields)).flatten() {
+                            Some(note) => format!(" ({})", note),
+                            None => annotation(options, || units::annotate_message(&fields)),
+                        };
+                        outln!(out, "{}{}{}{}", color::paint(options, Style::Brace, "{"), alternates, note, size_note(options, field.raw.input_len()));
+
+                        #[cfg(feature = "tracing")]
+                        spans.push(tracing::debug_span!("submessage", tag = field.tag_number, depth = depth + 1).entered());
+                        let cursor = Cursor::message(fields);
+                        levels.push(LevelState::new(&cursor, options));
+                        stack.push(cursor);
+                        continue;
+                    }
+                    Interpretation::String(str, encoding) => {
+                        // Treat as string, noting the encoding if it isn't the usual one.
+                        print_field_start(out, indent, &field, element, options);
+                        let size = annotation(options, || units::annotate_length(d.input_len())) + size_note(options, field.raw.input_len()).as_str();
+                        let str = elided("string", d.as_bytes(), options).unwrap_or_else(|| text::quote(&str));
+                        if encoding == StringEncoding::Utf8 {
+                            outln!(out, "{}{}{}", color::paint(options, Style::String, str), alternates, size);
+                        } else {
+                            outln!(out, "{} ({}){}{}", color::paint(options, Style::String, str), encoding.name(), alternates, size);
+                        }
+                    }
+                    Interpretation::LossyString(str, invalid) => {
+                        // Treat as string with some binary garbage in it.
+                        print_field_start(out, indent, &field, element, options);
+                        let size = annotation(options, || units::annotate_length(d.input_len())) + size_note(options, field.raw.input_len()).as_str();
+                        let str = elided("string", d.as_bytes(), options).unwrap_or_else(|| text::quote(&str));
+                        outln!(out, "{} ({} invalid UTF-8 sequence{}){}{}", color::paint(options, Style::String, str), invalid, plural(invalid), alternates, size);
+                    }
+                    Interpretation::Packed(values, wire_type) => {
+                        print_field_start(out, indent, &field, element, options);
+                        outln!(out, "{}{}{}", color::paint(options, Style::of(wire_type), format_packed(&values, wire_type)), alternates, size_note(options, field.raw.input_len()));
+                    }
+                    Interpretation::Bytes => {
+                        // Treat as raw data.
+                        let data = d.as_bytes();
+                        print_field_start(out, indent, &field, element, options);
+                        let bytes = elided("bytes", data, options).unwrap_or_else(|| options.bytes_format.format(data));
+                        outln!(out, "{}{}{}", color::paint(options, Style::Bytes, bytes), annotation(options, || units::annotate_length(data.len())), size_note(options, field.raw.input_len()));
+                    }
                 }
             }
 
-            WireValue::StartGroup => {}
-            WireValue::EndGroup => {}
+            // A group's fields are printed beneath it like a submessage's.
+            WireValue::StartGroup => {
+                let (content, end) = level.list.group(index);
+                check_group_end(&end, &path, warnings);
+                // A group takes up everything through its end tag, or its last field if it has none.
+                let offset = field.raw.location_offset();
+                let last = end.or_else(|| content.clone().last().map(|i| level.list.fields[i])).unwrap_or(field);
+                let len = last.raw.location_offset() + last.raw.input_len() - offset;
+                print_extent_start(out, indent, offset, len, (!element).then_some(field.tag_number), options);
+                // Groups count towards --max-depth as submessages do, and past it are raw data too.
+                if path.len() > options.max_depth {
+                    let message = format!("nested deeper than --max-depth {}, shown as raw data", options.max_depth);
+                    warnings.emit(WarningKind::DepthLimit, &path, &message);
+                    let data = encoded_bytes(&level.list.fields[content]);
+                    let bytes = elided("bytes", &data, options).unwrap_or_else(|| options.bytes_format.format(&data));
+                    outln!(out, "{}{}{}", color::paint(options, Style::Bytes, bytes), annotation(options, || units::annotate_length(data.len())), size_note(options, len));
+                    path.pop();
+                    continue;
+                }
+                outln!(out, "{}{}", color::paint(options, Style::Brace, "{"), size_note(options, len));
+
+                #[cfg(feature = "tracing")]
+                spans.push(tracing::debug_span!("group", tag = field.tag_number, depth = depth + 1).entered());
+                let cursor = Cursor::new(level.list.clone(), content);
+                levels.push(LevelState::new(&cursor, options));
+                stack.push(cursor);
+                continue;
+            }
+            WireValue::EndGroup => warnings.emit(WarningKind::UnbalancedGroup, &path, "end of a group that wasn't started"),
 
             WireValue::Int32(v) => {
-                print_indent(indent); println!("{}: {}", field.tag_number, v);
+                print_field_start(out, indent, &field, element, options);
+                let float = alternates::float(v, options.floats).map(|f| format!(" (float: {})", f)).unwrap_or_default();
+                outln!(out, "{}{}{}{}", color::paint(options, Style::Fixed, v), float, annotation(options, || units::annotate_integer(v as u64)), size_note(options, field.raw.input_len()));
+            }
+        }
+        path.pop();
+    }
+    stats
+}
+
+/// Dump raw protocol buffers data without a schema.
+#[derive(clap::Parser)]
+#[command(version, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    options: Options,
+
+    #[command(flatten)]
+    protoc: ProtocArgs,
+
+    /// Files to decode, where - is stdin, or with the protoc flags, .proto files to load. Stdin is
+    /// decoded if there are none.
+    files: Vec<String>,
+
+    /// Decode this input given on the command line, usually hex or base64 with --input-encoding;
+    /// may be repeated.
+    #[arg(long, value_name = "BYTES")]
+    data: Vec<String>,
+
+    /// Also decode the inputs listed in this manifest: a file with one path per line, or a YAML list
+    /// of entries with per-file framing, input-format, builtin and type settings.
+    #[arg(long, value_name = "MANIFEST")]
+    files_from: Option<String>,
+
+    /// Write the decode to this file rather than stdout.
+    #[arg(short, long, value_name = "FILE", conflicts_with_all = ["output_dir", "out"])]
+    output: Option<String>,
+
+    /// Write the decode of each input to a file of its own in this directory, named after the input
+    /// with an extension for the --format, e.g. foo.bin to foo.txt or foo.json.
+    #[arg(long, value_name = "DIR", conflicts_with = "out")]
+    output_dir: Option<String>,
+}
+
+/// protoc's own decoding flags, for use as a drop-in replacement in scripts. Input is read from stdin
+/// and output written to stdout, as protoc does.
+#[derive(Args)]
+struct ProtocArgs {
+    /// Decode a message from stdin without a schema, printing protoc's text format.
+    #[arg(long = "decode_raw", conflicts_with_all = ["decode", "encode"])]
+    decode_raw: bool,
+
+    /// Decode a message of the given type from stdin, printing protoc's text format.
+    #[arg(long, value_name = "TYPE", conflicts_with = "encode")]
+    decode: Option<String>,
+
+    /// Encode a text format message of the given type from stdin, writing binary to stdout.
+    #[arg(long, value_name = "TYPE")]
+    encode: Option<String>,
+
+    /// Directory to search for .proto imports, as with protoc (repeatable).
+    #[arg(short = 'I', long = "proto_path", value_name = "PATH")]
+    proto_path: Vec<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Decode files, as protocat does when given files without a subcommand.
+    Decode {
+        /// Files to decode, where - is stdin; stdin is decoded if there are none.
+        files: Vec<String>,
+    },
+    /// Parse files without printing a decode, reporting OK or FAIL for each.
+    Check {
+        /// Files to check.
+        files: Vec<String>,
+    },
+    /// Check files against size budgets, reporting every field over them; the exit code is 5 if any
+    /// file is over budget.
+    Lint {
+        /// Files to lint.
+        files: Vec<String>,
+        /// A budget, as [PATH:]KIND=LIMIT: message=SIZE for whole inputs, field=SIZE for
+        /// length-delimited values and repeated=COUNT for occurrences in one message, e.g.
+        /// message=1MiB, 2.1:field=64KiB or 3:repeated=1000. May be repeated.
+        #[arg(long = "budget", value_name = "RULE", value_parser = budget::Rule::parse, required = true)]
+        budgets: Vec<budget::Rule>,
+    },
+    /// Report what messages are made of: how many fields are at each path, how many bytes they take
+    /// and with which wire types, over all the files together.
+    Stats {
+        /// Files to count, where - is stdin; stdin is counted if there are none.
+        files: Vec<String>,
+        /// List the paths largest first, rather than in path order.
+        #[arg(long)]
+        by_size: bool,
+    },
+    /// Print a best guess at the .proto definition of the messages in the files, from the fields
+    /// they have and the values in them, to refine by hand.
+    InferSchema {
+        /// Sample messages, or directories of them, where - is stdin; stdin is read if there are
+        /// none.
+        files: Vec<String>,
+        /// The name to give the top-level message type.
+        #[arg(long, default_value = "Message")]
+        name: String,
+    },
+    /// Compare the decode of each file with protoc --decode_raw's, reporting where they diverge.
+    CompatCheck {
+        /// Files to compare.
+        files: Vec<String>,
+    },
+    /// Compare two messages field by field, printing each field added, removed or changed, by path;
+    /// the exit code is 6 if they differ.
+    Diff {
+        /// The old message's file.
+        old: String,
+        /// The new message's file.
+        new: String,
+        /// Let fields, and the values of repeated fields, come in any order.
+        #[arg(long)]
+        ignore_order: bool,
+    },
+    /// Encode a message written as protocat prints it, or in protoscope, text format or JSON, writing
+    /// the wire format to stdout.
+    Encode {
+        /// File to encode; read from stdin if left out.
+        file: Option<String>,
+        /// The syntax of the input.
+        #[arg(long, value_enum, default_value_t = assemble::Syntax::Auto)]
+        from: assemble::Syntax,
+    },
+    /// Set the values of fields in a message, re-encoding only what changes, and write it back out.
+    Edit {
+        /// File to edit.
+        file: String,
+        /// A value to set, as PATH=VALUE with the value written as `encode` reads it, e.g. 3.1=42
+        /// or 2="hello". Every field at the path is set, and a missing one is added, along with any
+        /// missing submessages on the way to it. May be repeated.
+        #[arg(long = "set", value_name = "PATH=VALUE", value_parser = edit::Edit::parse_set, required = true)]
+        sets: Vec<edit::Edit>,
+        /// File to write the edited message to, rather than stdout.
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<String>,
+        /// Read the file as text in this syntax, as encode does, rather than as the wire format, to
+        /// edit a text format or JSON fixture; the edited message is written in the wire format.
+        #[arg(long, value_enum)]
+        from: Option<assemble::Syntax>,
+    },
+    /// Remove fields from a message, or redact their contents, and write it back out.
+    Strip {
+        /// File to strip.
+        file: String,
+        /// Dotted tag numbers of fields to remove, e.g. 2.7. May be repeated.
+        #[arg(long = "field", value_name = "PATH", value_parser = path::parse_path)]
+        fields: Vec<Vec<u64>>,
+        /// Dotted tag numbers of strings, bytes or submessages whose contents are replaced with the
+        /// placeholder. May be repeated.
+        #[arg(long = "redact", value_name = "PATH", value_parser = path::parse_path)]
+        redact: Vec<Vec<u64>>,
+        /// What redacted contents are replaced with.
+        #[arg(long, default_value = "REDACTED")]
+        placeholder: String,
+        /// File to write the stripped message to, rather than stdout.
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<String>,
+    },
+    /// Account for every byte of a message: the range of each tag, length and value, and what it
+    /// was read as.
+    Explain {
+        /// File to explain.
+        file: String,
+    },
+    /// Load a message and query it interactively.
+    Repl {
+        /// File to load.
+        file: String,
+    },
+    /// Write the bytes of the field(s) at a path to a temporary file and open it with --open-with.
+    Open {
+        /// File to load.
+        file: String,
+        /// Dotted tag numbers of the field, e.g. 2.3.
+        path: String,
+    },
+    /// Decode a rich gRPC error: a google.rpc.Status, as given in the grpc-status-details-bin trailer.
+    GrpcStatus {
+        /// The trailer value, in base64 with or without the trailer name in front. Read from stdin,
+        /// where it may also be the binary Status, if left out.
+        value: Option<String>,
+    },
+    /// List the key/value pairs in a bbolt database, decoding values as protobuf.
+    Bbolt {
+        /// Database file to read.
+        file: String,
+        /// Only list this bucket, given as a slash-separated path for nested buckets.
+        #[arg(long)]
+        bucket: Option<String>,
+        /// Only list keys starting with this prefix.
+        #[arg(long)]
+        prefix: Option<String>,
+    },
+    /// List the keys in an etcd snapshot or data file, decoding values as protobuf.
+    Etcd {
+        /// Snapshot or member/snap/db file to read.
+        file: String,
+        /// Only list keys starting with this prefix, e.g. /registry/pods/.
+        #[arg(long)]
+        prefix: Option<String>,
+        /// List every stored revision of each key, including deletions, rather than the latest.
+        #[arg(long)]
+        all_revisions: bool,
+    },
+    /// Print the protobuf messages in an MCAP file or rosbag2 bag, decoded with their embedded
+    /// schemas.
+    Mcap {
+        /// MCAP file, or rosbag2 bag directory, to read.
+        file: String,
+        /// Only print messages on this topic; may be repeated.
+        #[arg(long = "topic")]
+        topics: Vec<String>,
+        /// List the channels and their message counts instead of printing messages.
+        #[arg(long)]
+        channels: bool,
+    },
+    /// Decode the protobuf in a pcap or pcapng capture: reassembled TCP streams, with gRPC over
+    /// HTTP/2 split into its messages, and UDP datagrams.
+    Pcap {
+        /// Capture file to read.
+        file: String,
+        /// Only decode flows with this port on either end; may be repeated.
+        #[arg(long = "port")]
+        ports: Vec<u16>,
+    },
+    /// Decode a Prometheus remote write request body, Snappy-compressed or not.
+    RemoteWrite {
+        /// File holding the request body; read from stdin if left out.
+        file: Option<String>,
+    },
+    /// Decode the protobuf and gRPC bodies in an HTTP/1.x dump: raw requests and responses, a curl
+    /// --trace log or Burp Suite saved items.
+    Http {
+        /// Dump file to read.
+        file: String,
+    },
+    /// Decode the messages in a captured WebSocket byte stream, with or without the upgrade
+    /// handshake in front.
+    Websocket {
+        /// Capture file to read.
+        file: String,
+    },
+    /// Listen on a TCP or UDP address and print the messages sent to it as they arrive, each TCP
+    /// connection read in the --framing given, or as one message if there is none.
+    Listen {
+        /// Address to accept TCP connections on, e.g. 0.0.0.0:9000.
+        #[arg(long, value_name = "ADDRESS", required_unless_present = "udp", conflicts_with = "udp")]
+        tcp: Option<String>,
+        /// Address to receive UDP datagrams on, each a message, or some in the --framing given.
+        #[arg(long, value_name = "ADDRESS")]
+        udp: Option<String>,
+    },
+    /// Act as a testee for the protobuf conformance test runner.
+    Conformance,
+    /// Act as a protoc plugin, writing a report of every descriptor for --protocat_out. This is also
+    /// what runs when protocat is invoked as protoc-gen-protocat.
+    ProtocPlugin,
+    /// Print a shell completion script.
+    Completions {
+        /// Shell to generate completions for.
+        shell: Shell,
+    },
+}
+
+#[derive(Args)]
+struct Options {
+    /// Downgrade spec violations that would otherwise abort parsing into warnings.
+    #[arg(long, global = true)]
+    lenient: bool,
+    /// Warn about varints encoded in more bytes than they need, which decoders accept but no
+    /// canonical encoder writes.
+    #[arg(long, global = true)]
+    warn_overlong: bool,
+    /// How deep submessages and groups are decoded; data nested deeper is shown as raw data.
+    #[arg(long, global = true, default_value_t = 100)]
+    max_depth: usize,
+    /// Show strings and bytes longer than this, such as embedded blobs, as their size and SHA-256
+    /// rather than in full; a byte count with an optional unit like KiB or MB.
+    #[arg(long, global = true, value_name = "SIZE", default_value = "64KiB", value_parser = parse_byte_count)]
+    max_value_bytes: u64,
+    /// Show every string and bytes value in full, however long.
+    #[arg(long, global = true)]
+    full_values: bool,
+    /// How to show data that isn't a submessage, string or packed values; JSON output keeps base64
+    /// unless another format is given.
+    #[arg(long, global = true, value_enum, default_value_t = BytesFormat::List)]
+    bytes_format: BytesFormat,
+    /// Decompress length-delimited values that start with a gzip, zlib or zstd header, and decode
+    /// what they hold if it's a message.
+    #[arg(long, global = true)]
+    decompress: bool,
+    /// Display almost-text data as strings with replacement characters rather than as raw data.
+    #[arg(long, global = true)]
+    lossy_utf8: bool,
+    /// Emit warnings as JSON lines rather than plain text.
+    #[arg(long, global = true)]
+    warnings_json: bool,
+    /// Annotate values with derived units: timestamps as dates, sizes in KiB/MiB, duration-shaped
+    /// submessages as durations and small integers as likely enums.
+    #[arg(long, global = true)]
+    annotate_units: bool,
+    /// Show submessages shaped like a google.protobuf.Timestamp or Duration as one, with the time
+    /// they hold.
+    #[arg(long, global = true)]
+    wkt_heuristics: bool,
+    /// Show length-prefixed data that is a whole number of varints, fixed64s or fixed32s as a packed
+    /// repeated field, e.g. 4: [1, 2, 3], unless it is clean text.
+    #[arg(long, global = true)]
+    packed: bool,
+    /// Never show length-prefixed data as a submessage, for formats whose strings or bytes keep
+    /// parsing as one by accident.
+    #[arg(long, global = true)]
+    no_submessage_heuristic: bool,
+    /// Never show length-prefixed data as a string, for formats whose binary data keeps decoding as
+    /// text by accident.
+    #[arg(long, global = true)]
+    no_string_heuristic: bool,
+    /// Show all the values of a repeated field in one list where it first occurs, a line each in the
+    /// order they came in, rather than each with its tag where it is.
+    #[arg(long, global = true)]
+    group_repeated: bool,
+    /// Never show a field repeated as submessages of a key and a value as a map, e.g.
+    /// 5: {"alice": 3, "bob": 7}.
+    #[arg(long, global = true)]
+    no_map_heuristic: bool,
+    /// Show all length-prefixed data as raw data, trying none of the heuristics.
+    #[arg(long, global = true)]
+    bytes_only: bool,
+    /// Also show fixed32 and fixed64 values as floats and doubles, e.g. 5: 1069547520 (float: 1.5);
+    /// plausible, the default, only does so when the bits look like a floating point number, and
+    /// --floats=always always does.
+    #[arg(long, global = true, value_enum, num_args = 0..=1, require_equals = true, default_missing_value = "plausible")]
+    floats: Option<FloatDisplay>,
+    /// Show other readings of varints next to them: the zigzag decoding of sint32 and sint64 fields,
+    /// e.g. 3: 3 (sint: -2), and the two's complement of negative int32 and int64 fields. Data
+    /// that could be more than one of a submessage, a string and a packed list is noted with every
+    /// reading and a rough confidence in each.
+    #[arg(long, global = true)]
+    show_alternates: bool,
+    /// Start each field's line with its byte offset in the input and its encoded length, tag
+    /// included, e.g. [12+3] 1: 150.
+    #[arg(long, global = true)]
+    show_offsets: bool,
+    /// End each field's line with its encoded size, tag included, e.g. 3: { // 1.2 KiB, for finding
+    /// the fields that make a message large.
+    #[arg(long, global = true)]
+    show_sizes: bool,
+    /// When to color the output: auto colors it when stdout is a terminal and NO_COLOR isn't set.
+    #[arg(long, global = true, value_enum, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+    /// The text encoding to try for length-prefixed data that isn't a submessage.
+    #[arg(long, global = true, value_enum, default_value_t = StringEncoding::Utf8)]
+    string_encoding: StringEncoding,
+    /// The fraction of the characters of UTF-8 data that have to be printable for it to be shown as
+    /// a string; the rest are escaped.
+    #[arg(long, global = true, value_name = "FRACTION", default_value_t = 0.9, value_parser = parse_fraction)]
+    printable_threshold: f64,
+    /// Guarantee byte-identical output for the same input and options, leaving out timings.
+    #[arg(long, global = true)]
+    deterministic: bool,
+    /// How the bytes of each input are written: raw, or as hex or base64 text copied from a log.
+    #[arg(long, global = true, value_enum, default_value_t = InputEncoding::Raw)]
+    input_encoding: InputEncoding,
+    /// When to map input files into memory rather than reading them, which spares huge files a copy;
+    /// auto maps files of 64 MiB or more.
+    #[arg(long, global = true, value_enum, default_value_t = MmapChoice::Auto)]
+    mmap: MmapChoice,
+    /// The encoding of each input: binary wire format, text format, proto3 JSON, or a saved HTTP
+    /// request or response whose body is decoded. Text format and JSON need field numbers in place
+    /// of names unless --builtin gives a schema.
+    #[arg(long, global = true, value_enum, default_value_t = InputFormat::Auto)]
+    input_format: InputFormat,
+    /// How messages are packed into each input; auto picks the framing that fits the whole input.
+    #[arg(long, global = true, value_enum, default_value_t = Framing::None)]
+    framing: Framing,
+    /// Read each input as varint-delimited messages, as written by writeDelimitedTo; short for
+    /// --framing delimited.
+    #[arg(long, global = true, conflicts_with_all = ["framing", "container"])]
+    delimited: bool,
+    /// Read each input as gRPC length-prefixed messages, as in a captured request or response
+    /// body; short for --framing grpc.
+    #[arg(long, global = true, conflicts_with_all = ["framing", "container", "delimited"])]
+    grpc: bool,
+    /// Unpack each input as a custom record container defined in the config file, instead of a
+    /// --framing.
+    #[arg(long, global = true, conflicts_with = "framing")]
+    container: Option<String>,
+    /// Keep reading the input as it grows, like tail -f, printing each message once it has been
+    /// written in full; for a log of delimited messages, unless --framing gives another stream.
+    #[arg(long, global = true, conflicts_with = "container")]
+    follow: bool,
+    /// The config file, where custom containers are defined; defaults to protocat/config.yaml in
+    /// the user's config directory.
+    #[arg(long, global = true, env = "PROTOCAT_CONFIG")]
+    config: Option<String>,
+    /// Decode with a bundled schema: otlp (OpenTelemetry traces, metrics and logs), prometheus
+    /// (remote write), descriptor (descriptor.proto and plugin.proto), wkt (the well-known types),
+    /// grpc-health, grpc-reflection or google-rpc (Status and error details). The message type is
+    /// picked per input by how well it fits.
+    #[arg(long, global = true, conflicts_with = "template")]
+    builtin: Option<String>,
+    /// Decode with the schema in this .proto file, found along with its imports on the -I paths; may
+    /// be repeated. The message type is picked by how well it fits unless --type is given.
+    #[arg(long = "proto", value_name = "FILE", global = true, conflicts_with_all = ["builtin", "template"])]
+    protos: Vec<String>,
+    /// Decode with the schema in this FileDescriptorSet, as written by protoc --descriptor_set_out;
+    /// may be repeated, and combined with --proto.
+    #[arg(long = "descriptor-set", value_name = "FILE", global = true, conflicts_with_all = ["builtin", "template"])]
+    descriptor_sets: Vec<String>,
+    /// The fully qualified message type to decode as, from --proto, --descriptor-set or --builtin.
+    #[arg(long = "type", value_name = "TYPE", global = true)]
+    type_name: Option<String>,
+    /// Suggest likely well-known message types and field names for each input decoded without a
+    /// schema, from the bundled schemas and any dictionary files listed in the config file.
+    #[arg(long, global = true)]
+    suggest_types: bool,
+    /// How to print decoded messages: the usual tree, JSON with field numbers as keys, protoscope, or
+    /// exactly what protoc --decode_raw prints.
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+    /// Print one line per field in this format instead of the usual tree, using the placeholders
+    /// {path}, {tag}, {type}, {value}, {offset} and {length}.
+    #[arg(long, global = true, conflicts_with = "format")]
+    template: Option<String>,
+    /// Run field hooks from this Rhai script, for custom decoders, filters and derived output.
+    #[cfg(feature = "scripting")]
+    #[arg(long, global = true)]
+    script: Option<String>,
+    /// Load a WebAssembly decoder plugin for length-prefixed fields; may be repeated.
+    #[cfg(feature = "plugins")]
+    #[arg(long = "plugin", global = true)]
+    plugins: Vec<String>,
+    /// External viewer to open field bytes with, e.g. 'imhex {file}', for the open subcommand and the
+    /// repl's open command.
+    #[arg(long, global = true, env = "PROTOCAT_OPEN_WITH")]
+    open_with: Option<String>,
+    /// Print only the fields at this path of dotted tag numbers, e.g. 2.3.1, descending into
+    /// submessages; every repeated occurrence along the way is followed.
+    #[arg(long, global = true, value_name = "PATH")]
+    path: Option<String>,
+    /// With --path, write the bytes of the values found to stdout as they are, rather than decoding
+    /// them.
+    #[arg(long, global = true, requires = "path")]
+    raw: bool,
+    /// Write the bytes of the values at a path, such as an embedded image or serialized message, to
+    /// stdout or the file given with --out; short for --path PATH --raw.
+    #[arg(long, global = true, value_name = "PATH", conflicts_with_all = ["path", "query", "template"])]
+    extract: Option<String>,
+    /// With --extract or --raw, the file to write the bytes to, rather than stdout.
+    #[arg(long, global = true, value_name = "FILE")]
+    out: Option<String>,
+    /// Print what a jq-like query picks out of each message, e.g. '.2[] | select(.1 == 5) | .3', as
+    /// text or, with --format json, as JSON.
+    #[arg(long, global = true, value_name = "QUERY", conflicts_with_all = ["path", "template"])]
+    query: Option<String>,
+    /// Print a one-line summary of each input to stderr after its decode.
+    #[arg(long, global = true)]
+    summary: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, clap::ValueEnum)]
+enum OutputFormat {
+    Text,
+    /// One JSON object per message; repeated fields are arrays and bytes are base64.
+    Json,
+    /// The proto3 JSON mapping of messages decoded with a schema, keyed by fields' JSON names.
+    ProtoJson,
+    /// The protoscope language, which protoscope can assemble back into the same bytes.
+    Protoscope,
+    /// Exactly what protoc --decode_raw prints, without protocat's heuristics, hooks or warnings.
+    DecodeRaw,
+    /// The bytes in hex, beside what each tag, length and value in them was read as.
+    Hexdump,
+}
+
+/// How raw bytes are written, for `--bytes-format`.
+#[derive(Copy, Clone, Debug, PartialEq, clap::ValueEnum)]
+enum BytesFormat {
+    /// A list of hex bytes, e.g. [de, ad, 1].
+    List,
+    /// Hex digits, e.g. dead01.
+    Hex,
+    /// Standard base64, e.g. 3q0B.
+    Base64,
+    /// A quoted string, with printable ASCII as it is and other bytes escaped, e.g. "\xde\xad\x01".
+    Escape,
+    /// A C array initializer, e.g. {0xde, 0xad, 0x01}.
+    CArray,
+}
+
+impl BytesFormat {
+    fn format(self, data: &[u8]) -> String {
+        match self {
+            BytesFormat::List => format!("{:x?}", data),
+            BytesFormat::Hex => data.iter().map(|b| format!("{:02x}", b)).collect(),
+            BytesFormat::Base64 => base64::encode(data),
+            BytesFormat::Escape => {
+                let mut out = String::with_capacity(data.len() + 2);
+                out.push('"');
+                for &b in data {
+                    match b {
+                        b'"' => out.push_str("\\\""),
+                        b'\\' => out.push_str("\\\\"),
+                        b'\n' => out.push_str("\\n"),
+                        b'\r' => out.push_str("\\r"),
+                        b'\t' => out.push_str("\\t"),
+                        b' '..=b'~' => out.push(b as char),
+                        b => out.push_str(&format!("\\x{:02x}", b)),
+                    }
+                }
+                out.push('"');
+                out
+            }
+            BytesFormat::CArray => {
+                let bytes: Vec<String> = data.iter().map(|b| format!("0x{:02x}", b)).collect();
+                format!("{{{}}}", bytes.join(", "))
+            }
+        }
+    }
+
+    /// Bytes as a JSON string: base64, as in proto3 JSON, unless another format was asked for.
+    fn json(self, data: &[u8]) -> String {
+        match self {
+            BytesFormat::List | BytesFormat::Base64 => format!("\"{}\"", base64::encode(data)),
+            format => json::quote(&format.format(data)),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, clap::ValueEnum)]
+enum InputEncoding {
+    Raw,
+    /// Hex digits, with any whitespace, colons or commas between them.
+    Hex,
+    /// Standard or URL-safe base64, padded or not.
+    Base64,
+}
+
+impl InputEncoding {
+    fn decode(self, input: Input) -> Result<Input, String> {
+        let text = |input: &[u8]| String::from_utf8(input.to_vec()).ok();
+        match self {
+            InputEncoding::Raw => Ok(input),
+            InputEncoding::Hex => text(&input).and_then(|text| hex::decode(&text)).map(Input::Read).ok_or_else(|| "not valid hex".to_string()),
+            InputEncoding::Base64 => {
+                text(&input).and_then(|text| base64::decode(&text)).map(Input::Read).ok_or_else(|| "not valid base64".to_string())
             }
         }
     }
 }
 
+/// Parses a fraction from 0 to 1, for `--printable-threshold`.
+fn parse_fraction(text: &str) -> Result<f64, String> {
+    text.parse().ok().filter(|fraction| (0.0..=1.0).contains(fraction)).ok_or_else(|| "must be a number from 0 to 1".to_string())
+}
+
+/// Parses a byte count, for `--max-value-bytes`.
+fn parse_byte_count(text: &str) -> Result<u64, String> {
+    budget::parse_size(text).ok_or_else(|| "must be a byte count, like 65536 or 64KiB".to_string())
+}
+
+/// Reads a whole input file, or stdin if the name is `-`.
+fn read_input(name: &str) -> io::Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    if name == "-" {
+        io::stdin().lock().read_to_end(&mut buffer)?;
+    } else {
+        File::open(name)?.read_to_end(&mut buffer)?;
+    }
+    Ok(buffer)
+}
+
+/// Reads an input with `read_input`, ending the run with an I/O error if it can't be read.
+fn read_or_exit(name: &str) -> Vec<u8> {
+    read_input(name).unwrap_or_else(|err| ProtocatError::Io(format!("{}: {}", input_name(name), err)).exit())
+}
+
+/// The name to give an input in messages.
+fn input_name(name: &str) -> &str {
+    if name == "-" { "<stdin>" } else { name }
+}
+
+/// Parses a top-level message, ensuring that we consume all bytes.
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(bytes = buffer.len())))]
+fn parse_message(buffer: &[u8], lenient: bool) -> Result<Vec<ProtoField<Span<'_>>>, String> {
+    match all_consuming(protobuf::<_, Error<_>>(lenient))(Span::new(buffer)) {
+        Ok((_, fields)) if lenient => Ok(fields),
+        Ok((_, fields)) => match check_groups(&fields) {
+            Ok(()) => Ok(fields),
+            Err(err) => {
+                trace_event!(error = %err, "parse failed");
+                Err(err.to_string())
+            }
+        },
+        Err(nom::Err::Error(err)) | Err(nom::Err::Failure(err)) => {
+            let message = describe_error(&err);
+            trace_event!(error = %message, "parse failed");
+            Err(message)
+        }
+        Err(nom::Err::Incomplete(_)) => unreachable!("complete parsers never return Incomplete"),
+    }
+}
+
+/// Where a message that was cut off ends, and what it was in the middle of.
+struct Truncation {
+    offset: usize,
+    /// The field whose value was cut off, if its tag was read.
+    tag_number: Option<u64>,
+    /// How many more bytes the value needed, or `None` if a varint was cut off, whose length can't
+    /// be known.
+    missing: Option<usize>,
+}
+
+impl std::fmt::Display for Truncation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "truncated at offset {}", self.offset)?;
+        match (self.tag_number, self.missing) {
+            (None, _) => write!(f, ", inside a tag"),
+            (Some(tag_number), None) => write!(f, ", inside a varint of field {}", tag_number),
+            (Some(tag_number), Some(missing)) => write!(f, ", expected {} more byte{} for field {}", missing, plural(missing), tag_number),
+        }
+    }
+}
+
+/// Checks whether a message that doesn't parse is just cut off in its last field, as truncated logs
+/// are, and if so returns the fields before it and what was cut off.
+fn truncation(buffer: &[u8], lenient: bool) -> Option<(Vec<ProtoField<Span<'_>>>, Truncation)> {
+    let mut fields = Vec::new();
+    let mut input = Span::new(buffer);
+    while let Ok((rest, field)) = ProtoField::parse::<Error<_>>(lenient)(input) {
+        fields.push(field);
+        input = rest;
+    }
+    // A varint is only cut off if every byte left says another one follows.
+    let runs_out = |data: Span| data.as_bytes().iter().all(|b| b & 0x80 != 0);
+    let offset = buffer.len();
+    let (value, tag) = match ProtoTag::parse::<_, Error<_>>(lenient)(input) {
+        Ok(parsed) => parsed,
+        Err(_) if input.input_len() > 0 && runs_out(input) => return Some((fields, Truncation{offset, tag_number: None, missing: None})),
+        Err(_) => return None,
+    };
+    // How many bytes short the rest of the input is of a value that needs `needed`, if it is.
+    let short = |needed: u64, rest: Span| needed.checked_sub(rest.input_len() as u64).filter(|&missing| missing > 0).map(|missing| missing as usize);
+    let missing = match tag.wire_type {
+        WireType::VarInt if runs_out(value) => None,
+        WireType::Int64 => Some(short(8, value)?),
+        WireType::Int32 => Some(short(4, value)?),
+        WireType::LengthPrefixed => match base128_vlq::<_, Error<_>>(value) {
+            Ok((data, len)) => Some(short(len, data)?),
+            Err(_) if runs_out(value) => None,
+            Err(_) => return None,
+        },
+        _ => return None,
+    };
+    Some((fields, Truncation{offset, tag_number: Some(tag.tag_number), missing}))
+}
+
+/// Fields of a top-level message decoded by `resync`, followed by the bytes skipped after them, if any.
+type Segment<'a> = (Vec<ProtoField<Span<'a>>>, Option<Span<'a>>);
+
+/// The number of fields that have to parse strictly after a point for `resync` to pick back up there.
+const RESYNC_FIELDS: usize = 3;
+
+/// Decodes a top-level message that doesn't parse as well as it can, for `--lenient`: fields are
+/// taken up to where parsing fails, then bytes are skipped until the next offset where a few fields
+/// in a row parse strictly, or the fields that do run to the end of the input.
+fn resync(buffer: &[u8]) -> Vec<Segment<'_>> {
+    let plausible = |mut input: Span| {
+        for _ in 0..RESYNC_FIELDS {
+            if input.input_len() == 0 {
+                break;
+            }
+            match ProtoField::parse::<Error<_>>(false)(input) {
+                Ok((rest, _)) => input = rest,
+                Err(_) => return false,
+            }
+        }
+        true
+    };
+
+    let mut segments = Vec::new();
+    let mut fields = Vec::new();
+    let mut input = Span::new(buffer);
+    while input.input_len() > 0 {
+        match ProtoField::parse::<Error<_>>(true)(input) {
+            Ok((rest, field)) => {
+                fields.push(field);
+                input = rest;
+            }
+            Err(_) => {
+                let skip = (1..input.input_len()).find(|&skip| plausible(input.slice(skip..))).unwrap_or(input.input_len());
+                trace_event!(offset = input.location_offset(), bytes = skip, "skipped unparseable bytes");
+                segments.push((std::mem::take(&mut fields), Some(input.take(skip))));
+                input = input.slice(skip..);
+            }
+        }
+    }
+    if !fields.is_empty() {
+        segments.push((fields, None));
+    }
+    segments
+}
+
+/// Prints bytes `resync` skipped, in the text output.
+fn print_skipped(out: &mut dyn Write, skipped: Span, base: usize, options: &Options) {
+    print_indent(out, base);
+    let bytes = color::paint(options, Style::Bytes, options.bytes_format.format(skipped.as_bytes()));
+    outln!(out, "unparseable at offset {}: {}", skipped.location_offset(), bytes);
+}
+
+/// Validates each input without printing a decode, returning the first failure if any didn't parse.
+/// Failures are reported on stdout along with the inputs that passed.
+fn check(names: &[String], options: &Options) -> Result<(), ProtocatError> {
+    let mut result = Ok(());
+    for path in names {
+        let name = input_name(path);
+        let checked = read_input(path)
+            .map_err(|err| ProtocatError::Io(err.to_string()))
+            .and_then(|buffer| parse_message(&buffer, options.lenient).map(|_| ()).map_err(ProtocatError::Parse));
+        match checked {
+            Ok(()) => println!("{}: OK", name),
+            Err(err) => {
+                println!("{}: FAIL: {}", name, err);
+                result = result.and(Err(err));
+            }
+        }
+    }
+    result
+}
+
+/// Checks each input against size budgets, returning the first failure if any didn't parse or
+/// went over a budget.
+fn lint(names: &[String], budgets: &[budget::Rule], options: &Options) -> Result<(), ProtocatError> {
+    let mut result = Ok(());
+    for path in names {
+        let name = input_name(path);
+        let checked = read_input(path).map_err(|err| ProtocatError::Io(err.to_string())).and_then(|buffer| {
+            let fields = parse_message(&buffer, options.lenient).map_err(ProtocatError::Parse)?;
+            Ok(budget::check(buffer.len(), fields, budgets))
+        });
+        match checked {
+            Ok(violations) if violations.is_empty() => println!("{}: OK", name),
+            Ok(violations) => {
+                println!("{}: OVER BUDGET", name);
+                for violation in violations.iter() {
+                    println!("  {}", violation.describe());
+                }
+                result = result.and(Err(ProtocatError::Limit(format!("{}: over budget", name))));
+            }
+            Err(err) => {
+                println!("{}: FAIL: {}", name, err);
+                result = result.and(Err(err));
+            }
+        }
+    }
+    result
+}
+
+fn compat_check(names: &[String], options: &Options) -> Result<(), ProtocatError> {
+    if !compat::protoc_available() {
+        eprintln!("protocat: protoc isn't on PATH, so files are only checked to parse");
+        return check(names, options);
+    }
+    let mut result = Ok(());
+    for path in names {
+        let name = input_name(path);
+        let compared = read_input(path).and_then(|buffer| compat::compare(&buffer));
+        match compared {
+            Ok(compat::Outcome::Match(lines)) => println!("{}: OK ({} line{})", name, lines, plural(lines)),
+            Ok(compat::Outcome::NeitherParsed) => println!("{}: OK (neither parses it)", name),
+            Ok(compat::Outcome::Diverged{line, ours, protoc}) => {
+                println!("{}: DIFF at line {}", name, line);
+                println!("  protocat: {}", ours.as_deref().unwrap_or("(end of output)"));
+                println!("  protoc:   {}", protoc.as_deref().unwrap_or("(end of output)"));
+                result = result.and(Err(ProtocatError::Parse(format!("{}: protocat and protoc differ", name))));
+            }
+            Ok(compat::Outcome::OnlyParsedBy(parser, detail)) => {
+                println!("{}: DIFF: only {} parses it; {}", name, parser, detail);
+                result = result.and(Err(ProtocatError::Parse(format!("{}: protocat and protoc differ", name))));
+            }
+            Err(err) => {
+                println!("{}: FAIL: {}", name, err);
+                result = result.and(Err(ProtocatError::Io(err.to_string())));
+            }
+        }
+    }
+    result
+}
+
+/// Applies edits to the message read from a file for `edit` and `strip`, writing the result to
+/// `output` or stdout.
+fn edit_file(file: &str, buffer: &[u8], edits: &[edit::Edit], output: Option<&str>) {
+    let fields = parse_message(buffer, false)
+        .unwrap_or_else(|err| ProtocatError::Parse(format!("{}: {}", input_name(file), err)).exit());
+    let edited = edit::apply(fields, &edits.iter().collect::<Vec<_>>(), 0)
+        .unwrap_or_else(|err| ProtocatError::Parse(format!("{}: {}", input_name(file), err)).exit());
+    let written = match output {
+        Some(path) => std::fs::write(path, &edited).map_err(|err| format!("{}: {}", path, err)),
+        None => io::stdout().write_all(&edited).map_err(|err| format!("<stdout>: {}", err)),
+    };
+    written.unwrap_or_else(|err| ProtocatError::Io(err).exit());
+}
+
+/// Loads the schema given with --proto and --descriptor-set, if any.
+fn load_user_schema(options: &Options, include: &[String]) -> Option<schema::Schema> {
+    let user_schema = (!options.protos.is_empty() || !options.descriptor_sets.is_empty()).then(|| {
+        let mut schema = schema::Schema::from_proto_files(&options.protos, include)?;
+        for path in options.descriptor_sets.iter() {
+            let data = read_input(path).map_err(|err| format!("{}: {}", path, err))?;
+            schema.add_descriptor_set(&data).map_err(|err| format!("{}: {}", path, err))?;
+        }
+        Ok::<_, String>(schema)
+    });
+    user_schema.transpose().unwrap_or_else(|err| ProtocatError::Schema(err).exit())
+}
+
+/// Encodes text to the wire format for `encode`. Text format and JSON can name fields with the
+/// schema given by --builtin or --proto, as when they're decoded.
+fn encode_text(buffer: &[u8], name: &str, from: assemble::Syntax, options: &Options, include: &[String]) -> Result<Vec<u8>, String> {
+    let input_format = match from.resolve(buffer) {
+        assemble::Syntax::Text => InputFormat::Text,
+        assemble::Syntax::Json => InputFormat::Json,
+        _ => {
+            let text = std::str::from_utf8(buffer).map_err(|_| format!("{}: input isn't valid UTF-8", name))?;
+            return assemble::assemble(text).map_err(|err| format!("{}: {}", name, err));
+        }
+    };
+    let builtin = options.builtin.as_deref().map(builtin::load).transpose().unwrap_or_else(|err| ProtocatError::Schema(err).exit());
+    let user_schema = load_user_schema(options, include);
+    let schema = builtin.as_ref().map(|(_, schema)| schema).or(user_schema.as_ref());
+    let roots = match (options.type_name.as_deref(), &builtin) {
+        (Some(type_name), _) => vec![type_name],
+        (None, Some((builtin, _))) => builtin.roots.to_vec(),
+        (None, None) => schema.map_or(Vec::new(), |schema| schema.message_names().collect()),
+    };
+    if let (Some(type_name), Some(schema)) = (options.type_name.as_deref(), schema) {
+        if schema.message(type_name).is_none() {
+            ProtocatError::Schema(format!("the schema has no message type {}", type_name)).exit();
+        }
+    }
+    payload::to_wire(buffer, input_format, schema.map(|schema| (schema, roots.as_slice())))
+        .map(|(encoded, _)| encoded)
+        .map_err(|err| format!("{}: {}", name, err))
+}
+
+/// The schema and message type to decode messages with, from --type and --builtin or --proto, for
+/// the inputs that aren't read in the usual way: followed files, sockets and captures. A bundled
+/// schema's first message type is the default.
+fn message_schema(options: &Options, include: &[String]) -> Option<(schema::Schema, String)> {
+    let builtin = options.builtin.as_deref().map(builtin::load).transpose().unwrap_or_else(|err| ProtocatError::Schema(err).exit());
+    let (schema, type_name) = match builtin {
+        Some((builtin, schema)) => (schema, options.type_name.clone().unwrap_or_else(|| builtin.roots[0].to_string())),
+        None => {
+            let schema = load_user_schema(options, include)?;
+            let type_name = options.type_name.clone()
+                .unwrap_or_else(|| ProtocatError::Usage("decoding these messages with a schema needs a --type".to_string()).exit());
+            (schema, type_name)
+        }
+    };
+    if schema.message(&type_name).is_none() {
+        ProtocatError::Schema(format!("the schema has no message type {}", type_name)).exit();
+    }
+    Some((schema, type_name))
+}
+
+/// Follows the one input named for --follow until reading it fails.
+fn follow_file(names: &[String], output: Option<&str>, options: &Options, include: &[String]) -> ! {
+    let path = match names {
+        [path] if path != "-" => path,
+        _ => ProtocatError::Usage("--follow reads one file, named on the command line".to_string()).exit(),
+    };
+    let framing = match options.framing {
+        Framing::None | Framing::Auto => Framing::Delimited,
+        framing if framing.is_stream() => framing,
+        framing => ProtocatError::Usage(format!("--follow can't read {}", framing.name())).exit(),
+    };
+    let schema = message_schema(options, include);
+    let mut output = match output {
+        Some(path) => Output::create(Path::new(path)).unwrap_or_else(|err| ProtocatError::Io(format!("{}: {}", path, err)).exit()),
+        None => Output::stdout(),
+    };
+    let mut warnings = Warnings::new(options.warnings_json);
+    warnings.set_input(path);
+    let schema = schema.as_ref().map(|(schema, type_name)| (schema, type_name.as_str()));
+    match follow::run(&mut output, path, framing, schema, options, &mut warnings) {
+        Ok(()) => process::exit(0),
+        Err(err) => ProtocatError::Parse(err).exit(),
+    }
+}
+
+fn run_protoc_plugin() -> ! {
+    if let Err(err) = protoc_plugin::run() {
+        ProtocatError::Io(err.to_string()).exit();
+    }
+    process::exit(0);
+}
+
 fn main() {
-    for name in env::args().skip(1) {
-        // Read file.
-        let mut f = File::open(name).expect("opening file failed");
-        let mut buffer = Vec::new();
-        f.read_to_end(&mut buffer).expect("reading file failed");
-
-        // Parse, ensuring that we consume all bytes.
-        let (_, fields) = all_consuming(protobuf::<_, Error<_>>)(
-            Span::new(&buffer)
-        ).expect("parse error");
-
-        // Print message to stdout.
-        print_message(0, &fields);
+    // protoc runs plugins by name without arguments, so a protoc-gen-protocat link needs no wrapper.
+    let invoked_as = std::env::args_os().next().map(std::path::PathBuf::from);
+    if invoked_as.as_deref().and_then(|path| path.file_stem()).is_some_and(|stem| stem == "protoc-gen-protocat") {
+        run_protoc_plugin();
+    }
+
+    let cli = Cli::parse();
+    let mut options = cli.options;
+    // Output to files is never colored unless asked for, as if stdout were redirected to them.
+    options.color = match options.color {
+        ColorChoice::Auto if cli.output.is_some() || cli.output_dir.is_some() => ColorChoice::Never,
+        color => color.resolve(),
+    };
+    if options.delimited {
+        options.framing = Framing::Delimited;
+    }
+    if options.grpc {
+        options.framing = Framing::Grpc;
+    }
+    if let Some(path) = options.extract.clone() {
+        options.path = Some(path);
+        options.raw = true;
+    }
+    if options.out.is_some() && !options.raw {
+        ProtocatError::Usage("--out is for the bytes of --extract or --raw".to_string()).exit();
+    }
+
+    let protoc = &cli.protoc;
+    let result = if protoc.decode_raw {
+        Some(protoc_cli::decode_raw())
+    } else if let Some(type_name) = &protoc.decode {
+        Some(protoc_cli::decode(type_name, &cli.files, &protoc.proto_path))
+    } else {
+        let files = &cli.files;
+        protoc.encode.as_ref().map(|type_name| protoc_cli::encode(type_name, files, &protoc.proto_path))
+    };
+    if let Some(result) = result {
+        if let Err(err) = result {
+            ProtocatError::Parse(err).exit();
+        }
+        return;
+    }
+
+    let names = match cli.command {
+        None => cli.files,
+        Some(Command::Decode{files}) => files,
+        // These report every failure on stdout already, so only the exit code is left to give.
+        Some(Command::Check{files}) => process::exit(check(&files, &options).map_or_else(|err| err.exit_code(), |()| 0)),
+        Some(Command::Lint{files, budgets}) => process::exit(lint(&files, &budgets, &options).map_or_else(|err| err.exit_code(), |()| 0)),
+        Some(Command::CompatCheck{files}) => process::exit(compat_check(&files, &options).map_or_else(|err| err.exit_code(), |()| 0)),
+        Some(Command::Stats{files, by_size}) => {
+            let files = if files.is_empty() { vec!["-".to_string()] } else { files };
+            let mut stats = stats::Stats::default();
+            let mut warnings = Warnings::new(options.warnings_json);
+            for file in &files {
+                warnings.set_input(input_name(file));
+                let buffer = read_or_exit(file);
+                let fields = parse_message(&buffer, options.lenient)
+                    .unwrap_or_else(|err| ProtocatError::Parse(format!("{}: {}", input_name(file), err)).exit());
+                stats.add(buffer.len(), fields, &options, &mut warnings);
+            }
+            stats.print(&mut io::stdout(), by_size);
+            return;
+        }
+        Some(Command::InferSchema{files, name}) => {
+            let identifier = name.starts_with(|c: char| c.is_ascii_alphabetic()) && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !identifier {
+                ProtocatError::Usage(format!("--name {:?} is not a message name", name)).exit();
+            }
+            let files = if files.is_empty() { vec!["-".to_string()] } else { files };
+            let files = infer::samples(&files).unwrap_or_else(|err| ProtocatError::Io(err).exit());
+            let mut inference = infer::Inference::default();
+            let mut warnings = Warnings::new(options.warnings_json);
+            for file in &files {
+                let buffer = read_or_exit(file);
+                let fields = parse_message(&buffer, options.lenient)
+                    .unwrap_or_else(|err| ProtocatError::Parse(format!("{}: {}", input_name(file), err)).exit());
+                warnings.set_input(input_name(file));
+                inference.add(fields, &options, &mut warnings);
+            }
+            print!("{}", inference.to_proto(&name));
+            return;
+        }
+        Some(Command::Diff{old, new, ignore_order}) => {
+            let (old_buffer, new_buffer) = (read_or_exit(&old), read_or_exit(&new));
+            let parse = |buffer, name| parse_message(buffer, options.lenient)
+                .unwrap_or_else(|err| ProtocatError::Parse(format!("{}: {}", input_name(name), err)).exit());
+            let (old_fields, new_fields) = (parse(&old_buffer, &old), parse(&new_buffer, &new));
+            let mut warnings = Warnings::new(options.warnings_json);
+            let changes = diff::run((input_name(&old), old_fields), (input_name(&new), new_fields), ignore_order, &options, &mut warnings);
+            for change in &changes {
+                println!("{}", change);
+            }
+            process::exit(if changes.is_empty() { 0 } else { error::DIFFERS });
+        }
+        Some(Command::Encode{file, from}) => {
+            let path = file.as_deref().unwrap_or("-");
+            let buffer = read_or_exit(path);
+            let encoded = encode_text(&buffer, input_name(path), from, &options, &cli.protoc.proto_path)
+                .unwrap_or_else(|err| ProtocatError::Parse(err).exit());
+            io::stdout().write_all(&encoded).unwrap_or_else(|err| ProtocatError::Io(format!("<stdout>: {}", err)).exit());
+            return;
+        }
+        Some(Command::Edit{file, sets, output, from}) => {
+            let buffer = read_or_exit(&file);
+            let buffer = match from {
+                Some(from) => encode_text(&buffer, input_name(&file), from, &options, &cli.protoc.proto_path)
+                    .unwrap_or_else(|err| ProtocatError::Parse(err).exit()),
+                None => buffer,
+            };
+            edit_file(&file, &buffer, &sets, output.as_deref());
+            return;
+        }
+        Some(Command::Strip{file, fields, redact, placeholder, output}) => {
+            if fields.is_empty() && redact.is_empty() {
+                ProtocatError::Usage("strip needs a --field or --redact".to_string()).exit();
+            }
+            let edits: Vec<edit::Edit> = fields.into_iter().map(|path| edit::Edit::new(path, edit::Action::Delete))
+                .chain(redact.into_iter().map(|path| edit::Edit::new(path, edit::Action::Redact(placeholder.clone().into_bytes()))))
+                .collect();
+            edit_file(&file, &read_or_exit(&file), &edits, output.as_deref());
+            return;
+        }
+        Some(Command::Explain{file}) => {
+            let buffer = read_or_exit(&file);
+            let fields = parse_message(&buffer, options.lenient)
+                .unwrap_or_else(|err| ProtocatError::Parse(format!("{}: {}", file, err)).exit());
+            let mut warnings = Warnings::new(options.warnings_json);
+            warnings.set_input(&file);
+            explain::run(&mut io::stdout(), fields, explain::Style::Ranges, &options, &mut warnings);
+            return;
+        }
+        Some(Command::Repl{file}) => {
+            let buffer = read_or_exit(&file);
+            let fields = parse_message(&buffer, options.lenient)
+                .unwrap_or_else(|err| ProtocatError::Parse(format!("{}: {}", file, err)).exit());
+            repl::run(&file, &buffer, fields, &options).unwrap_or_else(|err| ProtocatError::Io(format!("reading commands: {}", err)).exit());
+            return;
+        }
+        Some(Command::Open{file, path}) => {
+            let buffer = read_or_exit(&file);
+            let fields = parse_message(&buffer, options.lenient)
+                .unwrap_or_else(|err| ProtocatError::Parse(format!("{}: {}", file, err)).exit());
+            let result = path::parse_path(&path).and_then(|tags| {
+                let matches = path::select(&fields, &tags);
+                if matches.is_empty() {
+                    return Err(format!("no field at {}", path));
+                }
+                let command = options.open_with.as_deref().ok_or("no viewer given, pass --open-with or set PROTOCAT_OPEN_WITH")?;
+                open::open_with(command, &file, &path, &matches)
+            });
+            if let Err(err) = result {
+                ProtocatError::Parse(format!("{}: {}", file, err)).exit();
+            }
+            return;
+        }
+        Some(Command::GrpcStatus{value}) => {
+            let input = match value {
+                Some(value) => value.into_bytes(),
+                None => {
+                    let mut input = Vec::new();
+                    io::stdin().lock().read_to_end(&mut input).unwrap_or_else(|err| ProtocatError::Io(format!("<stdin>: {}", err)).exit());
+                    input
+                }
+            };
+            let mut warnings = Warnings::new(options.warnings_json);
+            warnings.set_input("grpc-status-details-bin");
+            if let Err(err) = grpc_status::print_status(&mut io::stdout(), &grpc_status::status_bytes(input), &options, &mut warnings) {
+                ProtocatError::Parse(format!("grpc-status-details-bin: {}", err)).exit();
+            }
+            return;
+        }
+        Some(Command::Bbolt{file, bucket, prefix}) => {
+            let buffer = read_or_exit(&file);
+            let mut warnings = Warnings::new(options.warnings_json);
+            warnings.set_input(&file);
+            if let Err(err) = store::print_bbolt(&mut io::stdout(), &buffer, bucket.as_deref(), prefix.as_deref(), &options, &mut warnings) {
+                ProtocatError::Parse(format!("{}: {}", file, err)).exit();
+            }
+            return;
+        }
+        Some(Command::Etcd{file, prefix, all_revisions}) => {
+            let buffer = read_or_exit(&file);
+            let mut warnings = Warnings::new(options.warnings_json);
+            warnings.set_input(&file);
+            if let Err(err) = store::print_etcd(&mut io::stdout(), &buffer, prefix.as_deref(), all_revisions, &options, &mut warnings) {
+                ProtocatError::Parse(format!("{}: {}", file, err)).exit();
+            }
+            return;
+        }
+        Some(Command::Mcap{file, topics, channels}) => {
+            let mut warnings = Warnings::new(options.warnings_json);
+            warnings.set_input(&file);
+            if let Err(err) = mcap::run(&mut io::stdout(), &file, &topics, channels, &options, &mut warnings) {
+                ProtocatError::Parse(err).exit();
+            }
+            return;
+        }
+        Some(Command::Pcap{file, ports}) => {
+            let buffer = read_or_exit(&file);
+            let schema = message_schema(&options, &cli.protoc.proto_path);
+            let schema = schema.as_ref().map(|(schema, type_name)| (schema, type_name.as_str()));
+            let mut warnings = Warnings::new(options.warnings_json);
+            if let Err(err) = pcap::run(&mut io::stdout(), &buffer, &ports, schema, &options, &mut warnings) {
+                ProtocatError::Parse(format!("{}: {}", file, err)).exit();
+            }
+            return;
+        }
+        Some(Command::RemoteWrite{file}) => {
+            let name = file.as_deref().unwrap_or("<stdin>");
+            let buffer = match &file {
+                Some(file) => read_input(file),
+                None => {
+                    let mut input = Vec::new();
+                    io::stdin().lock().read_to_end(&mut input).map(|_| input)
+                }
+            }.unwrap_or_else(|err| ProtocatError::Io(format!("{}: {}", name, err)).exit());
+            // Bodies are Snappy-compressed on the wire, but captures are often already decompressed.
+            let body = snappy::decompress(&buffer).unwrap_or(buffer);
+            let (_, schema) = builtin::load("prometheus").expect("bundled schema is valid");
+            let mut warnings = Warnings::new(options.warnings_json);
+            warnings.set_input(name);
+            if let Err(err) = parse_message(&body, options.lenient) {
+                ProtocatError::Parse(format!("{}: {}", name, err)).exit();
+            }
+            schema::print_as(&mut io::stdout(), &body, &schema, "prometheus.WriteRequest", 0, &options, &mut warnings);
+            return;
+        }
+        Some(Command::Http{file}) => {
+            let buffer = read_or_exit(&file);
+            let mut warnings = Warnings::new(options.warnings_json);
+            warnings.set_input(&file);
+            if let Err(err) = http::run(&mut io::stdout(), &buffer, &options, &mut warnings) {
+                ProtocatError::Parse(format!("{}: {}", file, err)).exit();
+            }
+            return;
+        }
+        Some(Command::Websocket{file}) => {
+            let buffer = read_or_exit(&file);
+            let mut warnings = Warnings::new(options.warnings_json);
+            warnings.set_input(&file);
+            if let Err(err) = websocket::run(&mut io::stdout(), &buffer, &options, &mut warnings) {
+                ProtocatError::Parse(format!("{}: {}", file, err)).exit();
+            }
+            return;
+        }
+        Some(Command::Listen{tcp, udp}) => {
+            if options.framing == Framing::Auto || !(options.framing == Framing::None || options.framing.is_stream()) {
+                ProtocatError::Usage(format!("listen can't read {}", options.framing.name())).exit();
+            }
+            let schema = message_schema(&options, &cli.protoc.proto_path);
+            let schema = schema.as_ref().map(|(schema, type_name)| (schema, type_name.as_str()));
+            let result = match (tcp, udp) {
+                (Some(address), _) => listen::tcp(&mut io::stdout(), &address, options.framing, schema, &options),
+                (None, Some(address)) => listen::udp(&mut io::stdout(), &address, options.framing, schema, &options),
+                (None, None) => unreachable!("clap requires one of them"),
+            };
+            if let Err(err) = result {
+                ProtocatError::Io(err).exit();
+            }
+            return;
+        }
+        Some(Command::Conformance) => {
+            if let Err(err) = conformance::run() {
+                ProtocatError::Io(err.to_string()).exit();
+            }
+            return;
+        }
+        Some(Command::ProtocPlugin) => run_protoc_plugin(),
+        Some(Command::Completions{shell}) => {
+            clap_complete::generate(shell, &mut Cli::command(), "protocat", &mut io::stdout());
+            return;
+        }
+    };
+
+    if options.follow {
+        if cli.output_dir.is_some() {
+            ProtocatError::Usage("--follow writes to stdout or --output, not --output-dir".to_string()).exit();
+        }
+        follow_file(&names, cli.output.as_deref(), &options, &cli.protoc.proto_path);
+    }
+
+    let template = options.template.as_deref().map(Template::parse).transpose().unwrap_or_else(|err| ProtocatError::Usage(err).exit());
+    let selected_path = options.path.as_deref().map(path::parse_path).transpose().unwrap_or_else(|err| ProtocatError::Usage(err).exit());
+    // With --out, raw bytes all go to the one file, however many inputs and frames they come from;
+    // otherwise they go where the decode would.
+    let mut raw_out = options.out.as_ref().map(|path| File::create(path).unwrap_or_else(|err| ProtocatError::Io(format!("{}: {}", path, err)).exit()));
+    let mut extracted = 0;
+    let query = options.query.as_deref().map(query::Query::parse).transpose()
+        .unwrap_or_else(|err| ProtocatError::Usage(format!("--query: {}", err)).exit());
+
+    // Hooks only come from optional features.
+    #[allow(unused_mut)]
+    let mut hooks: Vec<Box<dyn FieldHook>> = Vec::new();
+    #[cfg(feature = "plugins")]
+    for path in options.plugins.iter() {
+        hooks.push(Box::new(plugin::Plugin::load(path).unwrap_or_else(|err| ProtocatError::Usage(err).exit())));
+    }
+    #[cfg(feature = "scripting")]
+    if let Some(path) = &options.script {
+        hooks.push(Box::new(script::Script::load(path).unwrap_or_else(|err| ProtocatError::Usage(err).exit())));
+    }
+
+    let mut entries: Vec<manifest::Entry> = names.into_iter().map(manifest::Entry::new).collect();
+    entries.extend(cli.data.into_iter().map(|data| manifest::Entry{data: Some(data), ..manifest::Entry::new("<data>".to_string())}));
+    if let Some(path) = &cli.files_from {
+        entries.extend(manifest::load(path).unwrap_or_else(|err| ProtocatError::Usage(err).exit()));
+    }
+
+    if entries.is_empty() {
+        entries.push(manifest::Entry::new("-".to_string()));
+    }
+
+    let config = config::load(options.config.as_deref()).unwrap_or_else(|err| ProtocatError::Usage(err).exit());
+
+    let user_schema = load_user_schema(&options, &cli.protoc.proto_path);
+
+    // Every schema and container the inputs need is loaded and checked up front, before any output.
+    let mut builtins = HashMap::new();
+    for entry in entries.iter() {
+        if let Some(Err(err)) = entry.container.as_deref().or(options.container.as_deref()).map(|name| config.container(name)) {
+            ProtocatError::Usage(format!("{}: {}", entry.path, err)).exit();
+        }
+        let schema = match entry.builtin.as_deref().or(options.builtin.as_deref()) {
+            Some(name) => {
+                if !builtins.contains_key(name) {
+                    let loaded = builtin::load(name).unwrap_or_else(|err| ProtocatError::Schema(err).exit());
+                    builtins.insert(name.to_string(), loaded);
+                }
+                Some((&builtins[name].1, format!("builtin schema {}", name)))
+            }
+            None => user_schema.as_ref().map(|schema| (schema, "the schema".to_string())),
+        };
+        if options.format == OutputFormat::ProtoJson && schema.is_none() {
+            let message = format!("{}: --format proto-json needs a schema, given with --proto, --descriptor-set or --builtin", entry.path);
+            ProtocatError::Usage(message).exit();
+        }
+        match (entry.type_name.as_deref().or(options.type_name.as_deref()), schema) {
+            (Some(_), None) => {
+                let message = format!("{}: a type needs a schema to come from, given with --proto, --descriptor-set or --builtin", entry.path);
+                ProtocatError::Usage(message).exit();
+            }
+            (Some(type_name), Some((schema, what))) if schema.message(type_name).is_none() => {
+                ProtocatError::Schema(format!("{}: {} has no message type {}", entry.path, what, type_name)).exit();
+            }
+            _ => {}
+        }
+    }
+
+    // Each input's decode has a file of its own, so no two may have the same name.
+    let extension = match options.format {
+        _ if options.raw => "bin",
+        OutputFormat::Json | OutputFormat::ProtoJson => "json",
+        OutputFormat::Protoscope => "protoscope",
+        _ => "txt",
+    };
+    let output_dir = cli.output_dir.as_deref().map(Path::new);
+    if let Some(dir) = output_dir {
+        let mut paths = HashMap::new();
+        for entry in entries.iter() {
+            let path = match entry.data {
+                Some(_) => Err("an input given with --data has no name to write its decode under with --output-dir".to_string()),
+                None => output::path_in(dir, &entry.path, extension),
+            };
+            let path = path.unwrap_or_else(|err| ProtocatError::Usage(err).exit());
+            if let Some(other) = paths.insert(path.clone(), &entry.path) {
+                ProtocatError::Usage(format!("{} and {} would both be decoded to {}", other, entry.path, path.display())).exit();
+            }
+        }
+        fs::create_dir_all(dir).unwrap_or_else(|err| ProtocatError::Io(format!("{}: {}", dir.display(), err)).exit());
+    }
+    let mut output = match cli.output.as_deref() {
+        Some(path) => Output::create(Path::new(path)).unwrap_or_else(|err| ProtocatError::Io(format!("{}: {}", path, err)).exit()),
+        None => Output::stdout(),
+    };
+
+    let dictionary = options.suggest_types.then(|| fingerprint::Dictionary::load(&config.dictionary)).transpose().unwrap_or_else(|err| ProtocatError::Usage(err).exit());
+
+    let mut warnings = Warnings::new(options.warnings_json);
+    let mut truncated = false;
+    for entry in entries.iter() {
+        let name = input_name(&entry.path);
+        warnings.set_input(name);
+        let start = Instant::now();
+        let warnings_before = warnings.count();
+        let mut entry_output = output_dir.map(|dir| {
+            let path = output::path_in(dir, &entry.path, extension).expect("output paths were checked");
+            Output::create(&path).unwrap_or_else(|err| ProtocatError::Io(format!("{}: {}", path.display(), err)).exit())
+        });
+        let out: &mut dyn Write = match &mut entry_output {
+            Some(entry_output) => entry_output,
+            None => &mut output,
+        };
+        let builtin = entry.builtin.as_deref().or(options.builtin.as_deref()).map(|name| &builtins[name]);
+        let schema = builtin.map(|(_, schema)| schema).or(user_schema.as_ref());
+        let type_name = entry.type_name.as_deref().or(options.type_name.as_deref());
+        // A container given for the entry overrides a framing given on the command line, and the
+        // other way around.
+        let container = match (&entry.container, entry.framing) {
+            (Some(name), _) => Some(name),
+            (None, Some(_)) => None,
+            (None, None) => options.container.as_ref(),
+        }.map(|name| config.container(name).expect("containers were checked"));
+        let framing = if container.is_some() { Framing::None } else { entry.framing.unwrap_or(options.framing) };
+        // Without a type, any message of the user's own schema may be the one.
+        let roots = match (type_name, builtin) {
+            (Some(type_name), _) => vec![type_name],
+            (None, Some((builtin, _))) => builtin.roots.to_vec(),
+            (None, None) => schema.map_or(Vec::new(), |schema| schema.message_names().collect()),
+        };
+
+        let buffer = match &entry.data {
+            Some(data) => Input::Read(data.as_bytes().to_vec()),
+            None if entry.path == "-" => Input::Read(read_or_exit(&entry.path)),
+            None => mmap::open(&entry.path, options.mmap).unwrap_or_else(|err| ProtocatError::Io(format!("{}: {}", name, err)).exit()),
+        };
+        let buffer = options.input_encoding.decode(buffer).unwrap_or_else(|err| ProtocatError::Parse(format!("{}: {}", name, err)).exit());
+        // Framed input is always binary, so only a bare message is checked for other encodings.
+        let input_format = match entry.input_format.unwrap_or(options.input_format) {
+            InputFormat::Auto if framing == Framing::None && container.is_none() => {
+                let input_format = payload::detect(&buffer);
+                if input_format != InputFormat::Binary {
+                    eprintln!("protocat: {}: reading as {}", name, input_format.name());
+                }
+                input_format
+            }
+            input_format => input_format,
+        };
+        // An HTTP message's body is what gets decoded, in the framing its content type gives unless
+        // another is given.
+        let (buffer, framing) = match input_format {
+            InputFormat::Http => {
+                let (body, body_framing) = http::body(&buffer).unwrap_or_else(|err| ProtocatError::Parse(format!("{}: {}", name, err)).exit());
+                (Input::Read(body), if framing == Framing::None { body_framing } else { framing })
+            }
+            _ => (buffer, framing),
+        };
+        let (buffer, encoded_root) = match input_format {
+            InputFormat::Auto | InputFormat::Binary | InputFormat::Http => (buffer, None),
+            input_format => {
+                let schema = schema.map(|schema| (schema, roots.as_slice()));
+                let (encoded, root) = payload::to_wire(&buffer, input_format, schema)
+                    .unwrap_or_else(|err| ProtocatError::Parse(format!("{}: {}", name, err)).exit());
+                (Input::Read(encoded), root)
+            }
+        };
+        let framing = match framing {
+            Framing::Auto => {
+                let framing = framing::detect(&buffer);
+                eprintln!("protocat: {}: reading as {}", name, framing.name());
+                framing
+            }
+            framing => framing,
+        };
+        let buffer = match framing {
+            Framing::GrpcWebText => framing::decode_text(&buffer).map(Input::Read)
+                .unwrap_or_else(|err| ProtocatError::Parse(format!("{}: {}", name, err)).exit()),
+            _ => buffer,
+        };
+        let frames = match container {
+            Some(container) => framing::split_container(&buffer, container),
+            None => framing::split(&buffer, framing),
+        };
+        let frames = frames.unwrap_or_else(|err| ProtocatError::Parse(format!("{}: {}", name, err)).exit());
+
+        // Print messages, each framed one in a block of its own.
+        let mut stats = PrintStats{fields: 0, max_depth: 0};
+        for (i, frame) in frames.iter().enumerate() {
+            let framed = (framing != Framing::None || container.is_some()) && template.is_none() && options.format == OutputFormat::Text
+                && !options.raw && query.is_none();
+            // Protoscope has no block syntax that isn't also a length prefix, so its headers are comments,
+            // and hexdumps have no blocks at all.
+            let commented = (framing != Framing::None || container.is_some()) && !options.raw && query.is_none()
+                && matches!(options.format, OutputFormat::Protoscope | OutputFormat::Hexdump);
+            // gRPC-Web trailers go in a line of their own, or to stderr for formats with no room for them.
+            if frame.trailers {
+                let trailers = framing::trailers(frame.data);
+                match (framed, commented) {
+                    (_, true) => outln!(out, "# trailers (offset {}): {}", frame.offset, trailers),
+                    (true, _) => outln!(out, "trailers (offset {}): {}", frame.offset, trailers),
+                    _ => eprintln!("protocat: {}: trailers: {}", name, trailers),
+                }
+                continue;
+            }
+            if framed || commented {
+                let note = frame.note.as_ref().map_or(String::new(), |note| format!(", {}", note));
+                let size = frame.data.len();
+                if frame.compressed && commented {
+                    outln!(out, "# message {} (offset {}, {} byte{}, compressed, not decoded)", i, frame.offset, size, plural(size));
+                    continue;
+                }
+                if frame.compressed {
+                    outln!(out, "message {} (offset {}, {} byte{}, compressed): {}", i, frame.offset, size, plural(size), options.bytes_format.format(frame.data));
+                    continue;
+                }
+                if commented {
+                    outln!(out, "# message {} (offset {}, {} byte{}{})", i, frame.offset, size, plural(size), note);
+                } else {
+                    outln!(out, "message {} (offset {}, {} byte{}{}): {{", i, frame.offset, size, plural(size), note);
+                }
+            }
+            // Nor is a compressed message decoded in the other modes. JSON has a value for every message,
+            // so there it's a string of the bytes; the rest only say it was passed over.
+            if frame.compressed {
+                if matches!(options.format, OutputFormat::Json | OutputFormat::ProtoJson) && template.is_none() && query.is_none() && !options.raw {
+                    outln!(out, "{}", options.bytes_format.json(frame.data));
+                } else {
+                    eprintln!("protocat: {}: message {} is compressed, not decoded", name, i);
+                }
+                continue;
+            }
+            let segments = match parse_message(frame.data, options.lenient) {
+                Ok(fields) => vec![(fields, None)],
+                Err(err) => match truncation(frame.data, options.lenient) {
+                    // A message that was cut off still has its fields up to there printed, though it
+                    // fails the run.
+                    Some((fields, truncation)) => {
+                        if framed {
+                            eprintln!("protocat: {}: message {}: {}", name, i, truncation);
+                        } else {
+                            eprintln!("protocat: {}: {}", name, truncation);
+                        }
+                        truncated = true;
+                        vec![(fields, None)]
+                    }
+                    // Lenient decodes go around what doesn't parse, rather than giving up.
+                    None if options.lenient => resync(frame.data),
+                    None if framed => ProtocatError::Parse(format!("{}: message {}: {}", name, i, err)).exit(),
+                    None => ProtocatError::Parse(format!("{}: {}", name, err)).exit(),
+                },
+            };
+            for skipped in segments.iter().filter_map(|(_, skipped)| skipped.as_ref()) {
+                let len = skipped.input_len();
+                let message = format!("skipped {} unparseable byte{} at offset {}", len, plural(len), skipped.location_offset());
+                warnings.emit(WarningKind::Resynced, &[], &message);
+            }
+            let fields: Vec<ProtoField<Span>> = segments.iter().flat_map(|(fields, _)| fields.iter().copied()).collect();
+            // A type given in the manifest, or the one text and JSON input was encoded as, needs no
+            // guessing.
+            let root = schema.and_then(|schema| {
+                type_name.or(encoded_root).or_else(|| schema.best_match(&fields, &roots))
+                    .and_then(|root| schema.message(root)).map(|root| (schema, root))
+            });
+            if schema.is_some() && root.is_none() {
+                eprintln!("protocat: {}: doesn't fit any message type of the schema, decoding without it", name);
+            }
+            // Suggestions are made from the first message only, since a stream is usually all of one type.
+            if let (Some(dictionary), None, 0) = (&dictionary, root, i) {
+                let suggestions = dictionary.suggest(&fields);
+                if suggestions.is_empty() {
+                    eprintln!("protocat: {}: no well-known message type fits", name);
+                }
+                for suggestion in suggestions {
+                    let fields: Vec<String> = suggestion.fields.iter().map(|(number, name)| format!("{} {}", number, name)).collect();
+                    eprintln!("protocat: {}: looks like {} ({}): {}", name, suggestion.type_name, suggestion.source, fields.join(", "));
+                }
+            }
+            // With a path, the fields at it are printed as if they were all there was, with the type
+            // they have in the schema.
+            let (segments, fields, root) = match &selected_path {
+                Some(tags) => {
+                    let selected = path::select(&fields, tags);
+                    let root = root.and_then(|(schema, root)| Some((schema, schema.descend(root, &tags[..tags.len() - 1])?)));
+                    (vec![(selected.clone(), None)], selected, root)
+                }
+                None => (segments, fields, root),
+            };
+            if options.raw {
+                let raw: &mut dyn Write = match &mut raw_out {
+                    Some(file) => file,
+                    None => &mut *out,
+                };
+                let out_name = options.out.as_deref().unwrap_or("<stdout>");
+                for field in &fields {
+                    raw.write_all(&field.value_bytes().0).unwrap_or_else(|err| ProtocatError::Io(format!("{}: {}", out_name, err)).exit());
+                }
+                extracted += fields.len();
+                continue;
+            }
+            if let Some(query) = &query {
+                query.run(out, fields, &options, &mut warnings);
+                continue;
+            }
+            let base = if framed { 1 } else { 0 };
+            let frame_stats = match (&template, root) {
+                (Some(template), _) => template.render(out, fields, &options, &mut warnings),
+                (None, _) if options.format == OutputFormat::Json => json_output::print(out, fields, &options, &hooks, &mut warnings),
+                (None, Some((schema, root))) if options.format == OutputFormat::ProtoJson => proto_json::print(out, fields, schema, root, &options, &mut warnings),
+                // A message that doesn't fit the schema is as JSON as it can be without it.
+                (None, None) if options.format == OutputFormat::ProtoJson => json_output::print(out, fields, &options, &hooks, &mut warnings),
+                // With a path, the fields at it are rendered as a message of their own.
+                (None, _) if options.format == OutputFormat::DecodeRaw => {
+                    let selected = selected_path.as_ref().map(|_| encoded_bytes(&fields));
+                    protoc_cli::print_raw(out, selected.as_deref().unwrap_or(frame.data))
+                        .unwrap_or_else(|err| ProtocatError::Parse(format!("{}: {}", name, err)).exit())
+                }
+                // The other formats print the fields around skipped bytes in pieces, with the bytes between.
+                (None, root) => {
+                    let mut frame_stats = PrintStats{fields: 0, max_depth: 0};
+                    for (fields, skipped) in segments {
+                        let segment_stats = match (options.format, root) {
+                            (OutputFormat::Protoscope, _) => protoscope::print(out, fields, &options, &hooks, &mut warnings),
+                            (OutputFormat::Hexdump, _) => explain::run(out, fields, explain::Style::Hexdump, &options, &mut warnings),
+                            (_, Some(root)) => schema::print_typed(out, fields, root, base, &options, &hooks, &mut warnings),
+                            (_, None) => print_message_indented(out, fields, base, &options, &hooks, &mut warnings),
+                        };
+                        frame_stats.fields += segment_stats.fields;
+                        frame_stats.max_depth = frame_stats.max_depth.max(segment_stats.max_depth);
+                        match (skipped, options.format) {
+                            (None, _) => {}
+                            (Some(skipped), OutputFormat::Protoscope) => protoscope::print_skipped(out, skipped),
+                            (Some(skipped), OutputFormat::Hexdump) => explain::print_skipped(out, skipped),
+                            (Some(skipped), _) => print_skipped(out, skipped, base, &options),
+                        }
+                    }
+                    frame_stats
+                }
+            };
+            if framed {
+                outln!(out, "}}");
+            }
+            stats.fields += frame_stats.fields;
+            stats.max_depth = stats.max_depth.max(frame_stats.max_depth);
+        }
+        for hook in hooks.iter() {
+            if let Err(err) = hook.on_input_end() {
+                ProtocatError::Usage(err).exit();
+            }
+        }
+
+        if options.summary {
+            let warning_count = warnings.count() - warnings_before;
+            let mut summary = format!(
+                "protocat: {}: {} field{}, max depth {}, {} byte{}, {} warning{}",
+                name, stats.fields, plural(stats.fields), stats.max_depth,
+                buffer.len(), plural(buffer.len()), warning_count, plural(warning_count)
+            );
+            if !options.deterministic {
+                summary += &format!(", {:.2?}", start.elapsed());
+            }
+            eprintln!("{}", summary);
+        }
+        if let Some(entry_output) = entry_output {
+            entry_output.finish();
+        }
+    }
+    output.finish();
+    if let Some(path) = options.extract.as_deref().filter(|_| extracted == 0) {
+        ProtocatError::Parse(format!("no field at {}", path)).exit();
+    }
+    // The truncation was reported when it was found, so only the exit code is left to give.
+    if truncated {
+        process::exit(ProtocatError::Parse(String::new()).exit_code());
     }
 }
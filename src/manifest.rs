@@ -0,0 +1,115 @@
+//! Batch manifests for `--files-from`: either a plain list of inputs, one per line, or YAML giving
+//! each input its own framing, input format and message type.
+
+use std::fs;
+use std::path::Path;
+
+use clap::ValueEnum;
+
+use crate::framing::Framing;
+use crate::payload::InputFormat;
+use crate::yaml::{self, Item};
+
+/// One input to decode, with the options set for it alone.
+pub struct Entry {
+    pub path: String,
+    pub framing: Option<Framing>,
+    /// A custom container format from the config file, used in place of a framing.
+    pub container: Option<String>,
+    pub input_format: Option<InputFormat>,
+    pub builtin: Option<String>,
+    /// The message type to decode as, which the builtin schema has to define.
+    pub type_name: Option<String>,
+    /// The input itself, given with --data in place of a file to read.
+    pub data: Option<String>,
+}
+
+impl Entry {
+    pub fn new(path: String) -> Self {
+        Entry{path, framing: None, container: None, input_format: None, builtin: None, type_name: None, data: None}
+    }
+}
+
+/// Reads a manifest. Relative paths in it are taken relative to the manifest's own directory, so a
+/// manifest can be kept next to the data it describes and run from anywhere.
+pub fn load(path: &str) -> Result<Vec<Entry>, String> {
+    let text = fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+    let mut entries = if is_yaml(path, &text) {
+        yaml(&text).map_err(|err| format!("{}: {}", path, err))?
+    } else {
+        text.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| Entry::new(line.to_string()))
+            .collect()
+    };
+    let base = Path::new(path).parent().filter(|base| !base.as_os_str().is_empty());
+    if let Some(base) = base {
+        for entry in entries.iter_mut().filter(|entry| Path::new(&entry.path).is_relative()) {
+            entry.path = base.join(&entry.path).to_string_lossy().into_owned();
+        }
+    }
+    Ok(entries)
+}
+
+/// YAML manifests are told apart from plain lists by their extension or by starting with a list
+/// item or the `files:` key.
+fn is_yaml(path: &str, text: &str) -> bool {
+    if path.ends_with(".yaml") || path.ends_with(".yml") {
+        return true;
+    }
+    let first = text.lines().map(str::trim).find(|line| !line.is_empty() && !line.starts_with('#'));
+    first.is_some_and(|line| line == "files:" || line == "-" || line.starts_with("- "))
+}
+
+/// Parses a YAML list of entries, optionally under a top-level `files:` key. Each item is a path or
+/// a mapping with `path` and any of `framing`, `container`, `input-format`, `builtin` and `type`:
+///
+/// ```yaml
+/// files:
+///   - a.bin
+///   - path: traces.bin
+///     builtin: otlp
+///     type: opentelemetry.proto.trace.v1.TracesData
+/// ```
+fn yaml(text: &str) -> Result<Vec<Entry>, String> {
+    let mut entries = Vec::new();
+    for (key, items) in yaml::lists(text)? {
+        if !key.is_empty() && key != "files" {
+            return Err(format!("unknown key {}", key));
+        }
+        for item in items {
+            let pairs = match item {
+                Item::Scalar(path) => {
+                    entries.push(Entry::new(path));
+                    continue;
+                }
+                Item::Mapping(pairs) => pairs,
+            };
+            let mut entry = Entry::new(String::new());
+            for pair in pairs {
+                set(&mut entry, &pair.key, &pair.value).map_err(|err| format!("line {}: {}", pair.line, err))?;
+            }
+            if entry.path.is_empty() {
+                return Err("every entry needs a path".to_string());
+            }
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+fn set(entry: &mut Entry, key: &str, value: &str) -> Result<(), String> {
+    match key {
+        "path" => entry.path = value.to_string(),
+        "framing" => entry.framing = Some(Framing::from_str(value, true).map_err(|_| format!("unknown framing {}", value))?),
+        "container" => entry.container = Some(value.to_string()),
+        "input-format" | "input_format" => {
+            entry.input_format = Some(InputFormat::from_str(value, true).map_err(|_| format!("unknown input format {}", value))?);
+        }
+        "builtin" => entry.builtin = Some(value.to_string()),
+        "type" => entry.type_name = Some(value.to_string()),
+        _ => return Err(format!("unknown key {}", key)),
+    }
+    Ok(())
+}
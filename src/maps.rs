@@ -0,0 +1,130 @@
+//! The map heuristic: a field repeated as submessages that each hold just a key in field 1 and a
+//! value in field 2 is how every protobuf map is encoded, so without a schema it's shown as one,
+//! e.g. `5: {"alice": 3, "bob": 7}`.
+
+use std::io::Write;
+
+use nom::AsBytes;
+
+use crate::color::{self, Style};
+use crate::text::{self, StringEncoding};
+use crate::warning::Warnings;
+use crate::{
+    elided, format_packed, interpret, parse_message, print_indent, print_message_at, size_note, Interpretation, Options, PrintStats, ProtoField, Span,
+    WireValue,
+};
+
+/// A map's value: a scalar as it's written in a line, or a submessage's fields.
+pub enum Value<'a> {
+    Inline(String),
+    Message(Vec<ProtoField<Span<'a>>>),
+}
+
+/// The entries of the map that `occurrences`, all of one field in wire order, encode, or `None` if
+/// they don't look like one. Every entry has to be a submessage of a key and a value, once each,
+/// since encoders always write both, and the keys have to be integers or text and all different.
+/// It takes two entries to tell a map from a submessage that happens to have fields 1 and 2.
+pub fn entries<'a>(occurrences: &[ProtoField<Span<'a>>], path: &mut Vec<u64>, options: &Options, warnings: &mut Warnings) -> Option<Vec<(String, Value<'a>)>> {
+    let (keys, pairs) = keys(occurrences)?;
+    path.push(2);
+    let entries = keys.into_iter().zip(pairs).map(|(key, (_, value))| {
+        let value = match value {
+            WireValue::VarInt(v) | WireValue::Int64(v) => Value::Inline(v.to_string()),
+            WireValue::Int32(v) => Value::Inline(v.to_string()),
+            WireValue::LengthPrefixed(d) => match interpret(d, options, path, warnings) {
+                Interpretation::Message(fields) => Value::Message(fields),
+                Interpretation::String(str, _) | Interpretation::LossyString(str, _) => {
+                    Value::Inline(elided("string", d.as_bytes(), options).unwrap_or_else(|| text::quote(&str)))
+                }
+                Interpretation::Packed(values, wire_type) => Value::Inline(format_packed(&values, wire_type)),
+                Interpretation::Bytes => Value::Inline(elided("bytes", d.as_bytes(), options).unwrap_or_else(|| options.bytes_format.format(d.as_bytes()))),
+            },
+            WireValue::StartGroup | WireValue::EndGroup => unreachable!("group values were ruled out"),
+        };
+        (key, value)
+    }).collect();
+    path.pop();
+    Some(entries)
+}
+
+/// Whether `occurrences`, all of one field in wire order, look like the entries of a map, as
+/// `entries` decides before looking at any values.
+pub fn looks_like_map(occurrences: &[ProtoField<Span>]) -> bool {
+    keys(occurrences).is_some()
+}
+
+/// An entry's key and value, as they're encoded.
+type Pair<'a> = (WireValue<Span<'a>>, WireValue<Span<'a>>);
+
+/// The keys of the map that `occurrences` encode, formatted, with each entry's key and value.
+fn keys<'a>(occurrences: &[ProtoField<Span<'a>>]) -> Option<(Vec<String>, Vec<Pair<'a>>)> {
+    if occurrences.len() < 2 {
+        return None;
+    }
+    let mut pairs = Vec::new();
+    for occurrence in occurrences {
+        let data = match occurrence.value {
+            WireValue::LengthPrefixed(data) => data,
+            _ => return None,
+        };
+        let fields = parse_message(data.fragment(), false).ok()?;
+        match fields.as_slice() {
+            [key, value] if key.tag_number == 1 && value.tag_number == 2 && !matches!(value.value, WireValue::StartGroup | WireValue::EndGroup) => {
+                pairs.push((key.value, value.value))
+            }
+            _ => return None,
+        }
+    }
+    let mut keys = Vec::new();
+    for (key, _) in &pairs {
+        let key = match key {
+            WireValue::VarInt(v) | WireValue::Int64(v) => v.to_string(),
+            WireValue::Int32(v) => v.to_string(),
+            WireValue::LengthPrefixed(key) if text::is_clean(key.as_bytes(), StringEncoding::Utf8) => {
+                text::quote(&String::from_utf8_lossy(key.as_bytes()))
+            }
+            _ => return None,
+        };
+        if keys.contains(&key) {
+            return None;
+        }
+        keys.push(key);
+    }
+    Some((keys, pairs))
+}
+
+/// Prints a map found by `entries` as the value of a field whose tag is already printed, on one line
+/// if its values are all scalars, returning how many fields were in it. `size` is the encoded size
+/// of all its entries, and `path` the map field's.
+pub fn print(out: &mut dyn Write, entries: Vec<(String, Value)>, indent: usize, size: usize, path: &mut Vec<u64>, options: &Options, warnings: &mut Warnings) -> PrintStats {
+    let mut stats = PrintStats{fields: entries.len(), max_depth: 0};
+    let brace = |brace| color::paint(options, Style::Brace, brace);
+    if entries.iter().all(|(_, value)| matches!(value, Value::Inline(_))) {
+        let entries: Vec<String> = entries.into_iter().map(|(key, value)| match value {
+            Value::Inline(value) => format!("{}: {}", key, value),
+            Value::Message(_) => unreachable!("checked above"),
+        }).collect();
+        outln!(out, "{}{}{}{}", brace("{"), entries.join(", "), brace("}"), size_note(options, size));
+        return stats;
+    }
+    outln!(out, "{}{}", brace("{"), size_note(options, size));
+    path.push(2);
+    for (key, value) in entries {
+        print_indent(out, indent + 1);
+        match value {
+            Value::Inline(value) => outln!(out, "{}: {}", key, value),
+            Value::Message(fields) => {
+                outln!(out, "{}: {}", key, brace("{"));
+                let inner = print_message_at(out, fields, indent + 2, path, options, &[], warnings);
+                stats.fields += inner.fields;
+                stats.max_depth = stats.max_depth.max(inner.max_depth + 1);
+                print_indent(out, indent + 1);
+                outln!(out, "{}", brace("}"));
+            }
+        }
+    }
+    path.pop();
+    print_indent(out, indent);
+    outln!(out, "{}", brace("}"));
+    stats
+}
@@ -0,0 +1,244 @@
+//! Reading of MCAP files, the log container of Foxglove and of rosbag2's default storage.
+//!
+//! An MCAP file is a series of records: schemas, channels referring to a schema, and messages on a
+//! channel, optionally grouped into compressed chunks. Protobuf channels carry a serialized
+//! `FileDescriptorSet` as their schema, which is used to decode their messages.
+
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use crate::schema::{print_as, Schema};
+use crate::units::format_timestamp;
+use crate::warning::Warnings;
+use crate::{read_input, Options};
+
+const MAGIC: &[u8] = b"\x89MCAP0\r\n";
+
+const OP_SCHEMA: u8 = 0x03;
+const OP_CHANNEL: u8 = 0x04;
+const OP_MESSAGE: u8 = 0x05;
+const OP_CHUNK: u8 = 0x06;
+const OP_DATA_END: u8 = 0x0F;
+
+/// Reads the fields of a record in order.
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Fields<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if len > self.0.len() {
+            return Err("truncated record".to_string());
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.u32()? as usize;
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], String> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+}
+
+struct SchemaRecord {
+    name: String,
+    /// The decoded descriptors, for protobuf schemas that could be read.
+    protobuf: Option<Schema>,
+}
+
+struct Channel {
+    topic: String,
+    message_encoding: String,
+    schema_id: u16,
+    messages: usize,
+}
+
+/// What has been read so far, across chunks and files.
+struct Reader<'o> {
+    schemas: HashMap<u16, SchemaRecord>,
+    channels: HashMap<u16, Channel>,
+    topics: &'o [String],
+    /// Whether to print messages, rather than only counting them for the channel list.
+    print: bool,
+    options: &'o Options,
+    out: &'o mut dyn Write,
+}
+
+impl Reader<'_> {
+    fn records(&mut self, mut data: &[u8], in_chunk: bool, warnings: &mut Warnings) -> Result<(), String> {
+        while !data.is_empty() {
+            let mut header = Fields(data);
+            let opcode = header.take(1)?[0];
+            let len = header.u64()?;
+            let body = header.take(usize::try_from(len).map_err(|_| "truncated record")?)?;
+            data = header.0;
+
+            let mut fields = Fields(body);
+            match opcode {
+                OP_SCHEMA => {
+                    let id = fields.u16()?;
+                    let name = fields.string()?;
+                    let encoding = fields.string()?;
+                    let data = fields.bytes()?;
+                    let protobuf = if encoding == "protobuf" {
+                        Schema::from_descriptor_set(data)
+                            .map_err(|err| eprintln!("protocat: schema {}: {}; decoding without it", name, err))
+                            .ok()
+                    } else {
+                        None
+                    };
+                    self.schemas.insert(id, SchemaRecord{name, protobuf});
+                }
+                OP_CHANNEL => {
+                    let id = fields.u16()?;
+                    let schema_id = fields.u16()?;
+                    let topic = fields.string()?;
+                    let message_encoding = fields.string()?;
+                    self.channels.entry(id).or_insert(Channel{topic, message_encoding, schema_id, messages: 0});
+                }
+                OP_MESSAGE => {
+                    let channel_id = fields.u16()?;
+                    let _sequence = fields.u32()?;
+                    let log_time = fields.u64()?;
+                    let _publish_time = fields.u64()?;
+                    self.message(channel_id, log_time, fields.0, warnings)?;
+                }
+                OP_CHUNK if !in_chunk => {
+                    let _start_time = fields.u64()?;
+                    let _end_time = fields.u64()?;
+                    let uncompressed_size = fields.u64()?;
+                    let _crc = fields.u32()?;
+                    let compression = fields.string()?;
+                    let len = fields.u64()?;
+                    let records = fields.take(usize::try_from(len).map_err(|_| "truncated record")?)?;
+                    let records = decompress(&compression, records, uncompressed_size)?;
+                    self.records(&records, true, warnings)?;
+                }
+                // Everything after this is the summary, which repeats what came before.
+                OP_DATA_END => return Ok(()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn message(&mut self, channel_id: u16, log_time: u64, data: &[u8], warnings: &mut Warnings) -> Result<(), String> {
+        let channel = self.channels.get_mut(&channel_id).ok_or_else(|| format!("message on unknown channel {}", channel_id))?;
+        channel.messages += 1;
+        if !self.print || channel.message_encoding != "protobuf" {
+            return Ok(());
+        }
+        if !self.topics.is_empty() && !self.topics.contains(&channel.topic) {
+            return Ok(());
+        }
+
+        let time = format_timestamp((log_time / 1_000_000_000) as i64, (log_time % 1_000_000_000) as u32);
+        let empty = Schema::default();
+        let (schema, type_name) = match self.schemas.get(&channel.schema_id) {
+            Some(record) => (record.protobuf.as_ref().unwrap_or(&empty), record.name.as_str()),
+            None => (&empty, ""),
+        };
+        if type_name.is_empty() {
+            outln!(self.out, "{} @ {}: {{", channel.topic, time);
+        } else {
+            outln!(self.out, "{} @ {} ({}): {{", channel.topic, time, type_name);
+        }
+        print_as(self.out, data, schema, type_name, 1, self.options, warnings);
+        outln!(self.out, "}}");
+        Ok(())
+    }
+}
+
+fn decompress(compression: &str, data: &[u8], uncompressed_size: u64) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(uncompressed_size.min(1 << 30) as usize);
+    match compression {
+        "" => return Ok(data.to_vec()),
+        "zstd" => ruzstd::decoding::StreamingDecoder::new(data)
+            .map_err(|err| format!("bad zstd chunk: {}", err))?
+            .read_to_end(&mut out)
+            .map_err(|err| format!("bad zstd chunk: {}", err))?,
+        "lz4" => lz4_flex::frame::FrameDecoder::new(data)
+            .read_to_end(&mut out)
+            .map_err(|err| format!("bad lz4 chunk: {}", err))?,
+        other => return Err(format!("unsupported chunk compression {}", other)),
+    };
+    Ok(out)
+}
+
+/// Finds the MCAP files making up an input: the file itself, or for a rosbag2 bag directory, the
+/// MCAP files inside in name order.
+fn mcap_files(path: &str) -> Result<Vec<String>, String> {
+    if !Path::new(path).is_dir() {
+        return Ok(vec![path.to_string()]);
+    }
+    let mut files = Vec::new();
+    let mut sqlite = false;
+    for entry in fs::read_dir(path).map_err(|err| err.to_string())? {
+        let entry = entry.map_err(|err| err.to_string())?.path();
+        match entry.extension().and_then(|ext| ext.to_str()) {
+            Some("mcap") => files.push(entry.to_string_lossy().into_owned()),
+            Some("db3") => sqlite = true,
+            _ => {}
+        }
+    }
+    if files.is_empty() {
+        return Err(if sqlite {
+            "rosbag2 sqlite3 storage isn't supported; convert the bag to MCAP with ros2 bag convert".to_string()
+        } else {
+            "no MCAP files in directory".to_string()
+        });
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Prints the protobuf messages in an MCAP file or rosbag2 bag, optionally only those on the given
+/// topics, or with `list_channels`, a list of channels instead.
+pub fn run(out: &mut dyn Write, path: &str, topics: &[String], list_channels: bool, options: &Options, warnings: &mut Warnings) -> Result<(), String> {
+    let mut reader = Reader{schemas: HashMap::new(), channels: HashMap::new(), topics, print: !list_channels, options, out};
+    for file in mcap_files(path)? {
+        let data = read_input(&file).map_err(|err| format!("{}: {}", file, err))?;
+        let records = data.strip_prefix(MAGIC).ok_or_else(|| format!("{}: not an MCAP file", file))?;
+        reader.records(records, false, warnings).map_err(|err| format!("{}: {}", file, err))?;
+    }
+
+    if list_channels {
+        let mut ids: Vec<&u16> = reader.channels.keys().collect();
+        ids.sort();
+        for id in ids {
+            let channel = &reader.channels[id];
+            let schema = reader.schemas.get(&channel.schema_id).map_or("no schema", |schema| schema.name.as_str());
+            outln!(
+                reader.out,
+                "{}: {} ({}, {}, {} message{})",
+                id, channel.topic, channel.message_encoding, schema, channel.messages, crate::plural(channel.messages)
+            );
+        }
+        return Ok(());
+    }
+
+    let skipped: usize = reader.channels.values().filter(|c| c.message_encoding != "protobuf").map(|c| c.messages).sum();
+    if skipped > 0 {
+        eprintln!("protocat: {}: skipped {} message{} on channels that aren't protobuf", path, skipped, crate::plural(skipped));
+    }
+    Ok(())
+}
@@ -0,0 +1,101 @@
+//! Memory-mapped input files, so that decoding a huge capture doesn't first copy all of it into
+//! memory. Only Unix has mappings; elsewhere inputs are always read.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::ops::Deref;
+
+/// The size from which `--mmap auto` maps a file rather than reading it.
+const AUTO_THRESHOLD: u64 = 64 << 20;
+
+/// When to map input files into memory, for `--mmap`.
+#[derive(Copy, Clone, Debug, PartialEq, clap::ValueEnum)]
+pub enum MmapChoice {
+    /// For files of 64 MiB or more.
+    Auto,
+    Always,
+    /// Never, for files that may be truncated while they are decoded, which a mapping can't survive.
+    Never,
+}
+
+/// The bytes of an input, either read into memory or mapped.
+pub enum Input {
+    Read(Vec<u8>),
+    #[cfg(unix)]
+    Mapped(Mapping),
+}
+
+impl Deref for Input {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Input::Read(data) => data,
+            #[cfg(unix)]
+            Input::Mapped(mapping) => mapping,
+        }
+    }
+}
+
+/// Opens a file as an input, mapping it into memory if `choice` says to and the platform can. Empty
+/// files, which can't be mapped, and anything that isn't a regular file are read.
+pub fn open(path: &str, choice: MmapChoice) -> io::Result<Input> {
+    let mut file = File::open(path)?;
+    let metadata = file.metadata()?;
+    let map = match choice {
+        MmapChoice::Auto => metadata.len() >= AUTO_THRESHOLD,
+        MmapChoice::Always => true,
+        MmapChoice::Never => false,
+    };
+    #[cfg(unix)]
+    if map && metadata.is_file() && metadata.len() > 0 {
+        return Mapping::new(&file, metadata.len() as usize).map(Input::Mapped);
+    }
+    #[cfg(not(unix))]
+    let _ = map;
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer)?;
+    Ok(Input::Read(buffer))
+}
+
+/// A read-only private mapping of a whole file.
+#[cfg(unix)]
+pub struct Mapping {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+#[cfg(unix)]
+impl Mapping {
+    fn new(file: &File, len: usize) -> io::Result<Self> {
+        use std::os::unix::io::AsRawFd;
+
+        // SAFETY: the arguments describe a fresh read-only mapping of the open file, which doesn't
+        // alias any memory of ours. The mapping outlives the file descriptor, which it doesn't need.
+        let ptr = unsafe { libc::mmap(std::ptr::null_mut(), len, libc::PROT_READ, libc::MAP_PRIVATE, file.as_raw_fd(), 0) };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mapping{ptr, len})
+    }
+}
+
+#[cfg(unix)]
+impl Deref for Mapping {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: the mapping is `len` readable bytes until it is dropped.
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: the mapping was made by `Mapping::new` and nothing borrows from it any more.
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+        }
+    }
+}
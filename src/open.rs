@@ -0,0 +1,69 @@
+//! Handing field bytes to an external viewer for `--open-with`.
+
+use std::collections::hash_map::RandomState;
+use std::env;
+use std::fs::{DirBuilder, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::{ProtoField, Span};
+
+/// Writes the bytes of each field to a temporary file and runs `command` on it, waiting for it to exit.
+///
+/// The command is split on whitespace, without going through a shell, and `{file}` in any argument is
+/// replaced with the temporary file's path; if there is no placeholder, the path is appended. Files
+/// are left behind afterwards, since viewers may still be reading them or the user may want to keep
+/// them. They're written to a directory of their own that only the user can get into, so that
+/// other users of the machine can't read them or plant links where they'll be written.
+pub fn open_with(command: &str, input: &str, path: &str, fields: &[ProtoField<Span>]) -> Result<(), String> {
+    let mut words = command.split_whitespace();
+    let program = words.next().ok_or("the --open-with command is empty")?;
+    let args: Vec<&str> = words.collect();
+
+    let stem = Path::new(input).file_stem().map_or("input".into(), |s| s.to_string_lossy());
+    let dir = private_dir().map_err(|err| format!("creating a temporary directory: {}", err))?;
+    for (i, field) in fields.iter().enumerate() {
+        let suffix = if fields.len() > 1 { format!("-{}", i) } else { String::new() };
+        let file = dir.join(format!("{}-{}{}.bin", stem, path, suffix));
+        OpenOptions::new().write(true).create_new(true).open(&file)
+            .and_then(|mut out| out.write_all(&field.value_bytes().0))
+            .map_err(|err| format!("{}: {}", file.display(), err))?;
+
+        let file = file.to_string_lossy();
+        let mut substituted = false;
+        let mut command = Command::new(program);
+        for arg in args.iter() {
+            substituted |= arg.contains("{file}");
+            command.arg(arg.replace("{file}", &file));
+        }
+        if !substituted {
+            command.arg(&*file);
+        }
+
+        let status = command.status().map_err(|err| format!("running {}: {}", program, err))?;
+        if !status.success() {
+            return Err(format!("{} exited with {}", program, status));
+        }
+    }
+    Ok(())
+}
+
+/// Creates a directory with a random name in the temporary directory, readable only by the user on
+/// Unix. Creating it fails rather than following a link if the name is taken, so a new one is tried.
+fn private_dir() -> io::Result<PathBuf> {
+    let mut builder = DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    loop {
+        // RandomState hashes with random keys, which is all the randomness the name needs.
+        let name = format!("protocat-{:016x}", RandomState::new().build_hasher().finish());
+        let dir = env::temp_dir().join(name);
+        match builder.create(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+}
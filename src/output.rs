@@ -0,0 +1,72 @@
+//! `--output` and `--output-dir`: decodes written to files rather than stdout.
+//!
+//! The printers write to whatever `out` they're handed with the `out!` and `outln!` macros, so a
+//! decode goes to a file by handing them an `Output` for it, and nothing else written to stdout on
+//! the way ends up there.
+
+use std::fs::File;
+use std::io::{self, LineWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::error::ProtocatError;
+
+/// Where a decode is written: stdout, or a file, which is line-buffered as stdout is so that what was
+/// decoded before an error ended the run is kept. Its write errors are given its name.
+pub struct Output {
+    name: String,
+    writer: Box<dyn Write>,
+}
+
+impl Output {
+    pub fn stdout() -> Self {
+        Output{name: "<stdout>".to_string(), writer: Box::new(io::stdout())}
+    }
+
+    /// Creates the file at `path`, or truncates it.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let file = File::create(path)?;
+        Ok(Output{name: path.display().to_string(), writer: Box::new(LineWriter::new(file))})
+    }
+
+    /// Writes out what's left for the file, ending the run if it can't be.
+    pub fn finish(mut self) {
+        self.flush().unwrap_or_else(|err| write_failed(err));
+    }
+
+    fn named(&self, err: io::Error) -> io::Error {
+        io::Error::new(err.kind(), format!("{}: {}", self.name, err))
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf).map_err(|err| self.named(err))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush().map_err(|err| self.named(err))
+    }
+}
+
+/// Ends the run when a decode can't be written, which leaves nothing to do. Output piped into
+/// `head` and the like stops quietly when the reader goes away.
+pub fn write_failed(err: io::Error) -> ! {
+    if err.kind() == io::ErrorKind::BrokenPipe {
+        process::exit(0);
+    }
+    ProtocatError::Io(err.to_string()).exit()
+}
+
+/// Where `--output-dir` writes the decode of the input at `path`: its name in `dir`, with the
+/// extension swapped for `extension`.
+pub fn path_in(dir: &Path, path: &str, extension: &str) -> Result<PathBuf, String> {
+    if path == "-" {
+        return Err("stdin has no name to write its decode under with --output-dir".to_string());
+    }
+    let stem = Path::new(path).file_stem().ok_or_else(|| format!("{}: no file name to write its decode under", path))?;
+    let mut name = stem.to_os_string();
+    name.push(".");
+    name.push(extension);
+    Ok(dir.join(name))
+}
@@ -0,0 +1,43 @@
+//! Field paths, written as dotted tag numbers like `2.3.1`.
+
+use nom::combinator::all_consuming;
+
+use crate::{protobuf, Cursor, Error, ProtoField, Span, WireValue};
+
+pub fn parse_path(path: &str) -> Result<Vec<u64>, String> {
+    path.split('.')
+        .map(|segment| segment.parse::<u64>().map_err(|_| format!("invalid path segment '{}' in '{}'", segment, path)))
+        .collect()
+}
+
+/// Finds every field at `path`, descending into each group and each length-prefixed field on the way
+/// that parses as a submessage. Repeated fields at any level are all followed, in wire order. A
+/// group found at the end of the path comes with its fields and end.
+pub fn select<'a>(fields: &[ProtoField<Span<'a>>], path: &[u64]) -> Vec<ProtoField<Span<'a>>> {
+    let mut current = fields.to_vec();
+    for (i, &tag_number) in path.iter().enumerate() {
+        let last = i == path.len() - 1;
+        let mut matching = Vec::new();
+        let mut level = Cursor::message(current);
+        while let Some(index) = level.next() {
+            // A group's fields belong to it rather than to the message around it, and are skipped
+            // over by the cursor.
+            let field = level.list.fields[index];
+            if field.tag_number != tag_number {
+                continue;
+            }
+            let group = matches!(field.value, WireValue::StartGroup).then(|| level.list.group(index).0);
+            match (group, field.value) {
+                (Some(_), _) if last => matching.extend_from_slice(&level.list.fields[level.list.extent(index)]),
+                (Some(content), _) => matching.extend_from_slice(&level.list.fields[content]),
+                (None, _) if last => matching.push(field),
+                (None, WireValue::LengthPrefixed(d)) => {
+                    matching.extend(all_consuming(protobuf::<_, Error<_>>(false))(d).map_or(Vec::new(), |(_, fields)| fields));
+                }
+                (None, _) => {}
+            }
+        }
+        current = matching;
+    }
+    current
+}
@@ -0,0 +1,389 @@
+//! Input that isn't binary wire format. Text format and proto3 JSON are parsed into a common tree
+//! and encoded back to the wire format, so the rest of protocat only ever sees binary messages.
+
+use clap::ValueEnum;
+
+use crate::descriptor::*;
+use crate::encode::{write_bytes, write_tag, write_varint};
+use crate::schema::Schema;
+use crate::{base64, http, json, parse_message, textformat, WireType, MAX_FIELD_NUMBER};
+
+/// How deeply text format and JSON input may nest, keeping the recursive parsers and the encoder
+/// well within the stack.
+pub const MAX_NESTING: usize = 100;
+
+#[derive(Copy, Clone, Debug, PartialEq, ValueEnum)]
+pub enum InputFormat {
+    /// An HTTP message if the input starts with its start line, binary wire format if it parses as
+    /// it, otherwise text format or JSON by its shape.
+    Auto,
+    Binary,
+    /// Protobuf text format, such as protoc --decode_raw output.
+    Text,
+    /// proto3 JSON.
+    Json,
+    /// A saved HTTP/1.x request or response, whose body is decoded in the framing its content type
+    /// gives.
+    Http,
+}
+
+impl InputFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            InputFormat::Auto => "auto",
+            InputFormat::Binary => "binary wire format",
+            InputFormat::Text => "text format",
+            InputFormat::Json => "JSON",
+            InputFormat::Http => "an HTTP message body",
+        }
+    }
+}
+
+/// A parsed text format or JSON value, before its field's type is known.
+pub enum Value {
+    /// A number as written, since whether it's an integer, a float or out of range depends on the
+    /// field.
+    Number(String),
+    String(Vec<u8>),
+    /// A bare identifier: an enum value name, true or false.
+    Ident(String),
+    Null,
+    List(Vec<Value>),
+    Message(Vec<(String, Value)>),
+}
+
+/// Picks the format of the data. A saved HTTP message is told by its start line, which may well
+/// parse as binary too. Otherwise, anything that parses as binary is binary, text that starts like
+/// a JSON object is JSON, and other text is text format.
+pub fn detect(data: &[u8]) -> InputFormat {
+    if http::is_http(data) {
+        return InputFormat::Http;
+    }
+    if parse_message(data, true).is_ok() {
+        return InputFormat::Binary;
+    }
+    match std::str::from_utf8(data) {
+        Ok(text) if !text.chars().any(|c| c.is_control() && !c.is_whitespace()) => {
+            if text.trim_start().starts_with('{') { InputFormat::Json } else { InputFormat::Text }
+        }
+        _ => InputFormat::Binary,
+    }
+}
+
+/// Encodes text format or JSON input to the wire format. With a schema, field names are resolved
+/// against the first of `roots` they all belong to and values are encoded by their declared types,
+/// and that root is returned too; without one, fields have to be given by number.
+pub fn to_wire<'r>(
+    data: &[u8], format: InputFormat, schema: Option<(&Schema, &[&'r str])>,
+) -> Result<(Vec<u8>, Option<&'r str>), String> {
+    let text = std::str::from_utf8(data).map_err(|_| format!("{} input isn't valid UTF-8", format.name()))?;
+    let fields = match format {
+        InputFormat::Text => textformat::parse(text)?,
+        InputFormat::Json => json::parse(text)?,
+        InputFormat::Auto | InputFormat::Binary | InputFormat::Http => unreachable!("only text format and JSON are converted"),
+    };
+    let json = format == InputFormat::Json;
+    match schema {
+        None => Encoder{schema: None, json}.encode(&fields, None).map(|encoded| (encoded, None)),
+        Some((schema, roots)) => {
+            let encoder = Encoder{schema: Some(schema), json};
+            let mut first_err = None;
+            for (name, root) in roots.iter().filter_map(|&name| schema.message(name).map(|root| (name, root))) {
+                match encoder.encode(&fields, Some(root)) {
+                    Ok(encoded) => return Ok((encoded, Some(name))),
+                    Err(err) => { first_err.get_or_insert(err); }
+                }
+            }
+            Err(first_err.unwrap_or_else(|| "the schema has no message types to encode into".to_string()))
+        }
+    }
+}
+
+struct Encoder<'s> {
+    schema: Option<&'s Schema>,
+    /// Whether the input was JSON, where bytes are base64 and maps are objects.
+    json: bool,
+}
+
+impl<'s> Encoder<'s> {
+    fn encode(&self, fields: &[(String, Value)], message: Option<&MessageDescriptor>) -> Result<Vec<u8>, String> {
+        let mut out = Vec::new();
+        self.message(fields, message, &mut out)?;
+        Ok(out)
+    }
+
+    fn message(&self, fields: &[(String, Value)], message: Option<&MessageDescriptor>, out: &mut Vec<u8>) -> Result<(), String> {
+        // All the values of a packed field go in one record, where the field first appears, as
+        // protoc writes them.
+        let mut packed: Vec<(u64, usize, Vec<u8>)> = Vec::new();
+        for (name, value) in fields {
+            if let Some(url) = name.strip_prefix('[').and_then(|name| name.strip_suffix(']')) {
+                self.any(url, value, message, out)?;
+                continue;
+            }
+            let declared = match message {
+                Some(message) => Some(message.fields.iter()
+                    .find(|f| f.name == *name || f.json_name() == *name || f.number.to_string() == *name)
+                    .ok_or_else(|| format!("{} has no field {}", message.name, name))?),
+                None => None,
+            };
+            let number = match declared {
+                Some(declared) => declared.number,
+                None => name.parse().ok().filter(|number| (1..=MAX_FIELD_NUMBER).contains(number))
+                    .ok_or_else(|| format!("field {} can't be encoded without a schema; give it by number", name))?,
+            };
+            if let Some(declared) = declared.filter(|declared| declared.packed == Some(true)) {
+                let index = match packed.iter().position(|(n, _, _)| *n == number) {
+                    Some(index) => index,
+                    None => {
+                        packed.push((number, out.len(), Vec::new()));
+                        packed.len() - 1
+                    }
+                };
+                let values = match value {
+                    Value::List(values) => values.as_slice(),
+                    value => std::slice::from_ref(value),
+                };
+                for value in values {
+                    // Field 1's tag is a single byte, leaving just the value after it.
+                    let mut tagged = Vec::new();
+                    self.value(1, Some(declared), value, &mut tagged)?;
+                    packed[index].2.extend_from_slice(tagged.get(1..).unwrap_or(&[]));
+                }
+                continue;
+            }
+            let map = if self.json { declared.and_then(|declared| self.map_entry(declared)) } else { None };
+            match (value, map) {
+                (Value::Message(entries), Some((key, value))) => {
+                    for (k, v) in entries {
+                        let k = match TYPE_NAMES.get(key.ty as usize) {
+                            Some(&"string") => Value::String(k.as_bytes().to_vec()),
+                            Some(&"bool") => Value::Ident(k.clone()),
+                            _ => Value::Number(k.clone()),
+                        };
+                        let mut entry = Vec::new();
+                        self.value(1, Some(key), &k, &mut entry)?;
+                        self.value(2, Some(value), v, &mut entry)?;
+                        write_bytes(out, number, &entry);
+                    }
+                }
+                (Value::List(values), _) => {
+                    for value in values {
+                        self.value(number, declared, value, out)?;
+                    }
+                }
+                (value, _) => self.value(number, declared, value, out)?,
+            }
+        }
+        for (number, offset, values) in packed.into_iter().rev().filter(|(_, _, values)| !values.is_empty()) {
+            let mut record = Vec::new();
+            write_bytes(&mut record, number, &values);
+            out.splice(offset..offset, record);
+        }
+        Ok(())
+    }
+
+    /// Encodes text format's expanded `google.protobuf.Any`, `[type.googleapis.com/pkg.Type] { ... }`,
+    /// as the Any's type URL and the message encoded as the type the URL ends with: by the schema's
+    /// declaration of it if there is a schema, and by field number otherwise. Extensions, bracketed
+    /// names without a `/`, can't be encoded.
+    fn any(&self, url: &str, value: &Value, message: Option<&MessageDescriptor>, out: &mut Vec<u8>) -> Result<(), String> {
+        let (_, type_name) = url.rsplit_once('/').ok_or_else(|| format!("[{}] is an extension, which can't be encoded", url))?;
+        if let Some(message) = message {
+            let declares = |number, name| message.fields.iter().any(|f| f.number == number && f.name == name);
+            if !declares(1, "type_url") || !declares(2, "value") {
+                return Err(format!("[{}] can only be in a google.protobuf.Any, not in {}", url, message.name));
+            }
+        }
+        let fields = match value {
+            Value::Message(fields) => fields,
+            _ => return Err(format!("expected a message after [{}]", url)),
+        };
+        let inner = match self.schema {
+            Some(schema) => Some(schema.message(type_name).ok_or_else(|| format!("the schema has no message type {} for [{}]", type_name, url))?),
+            None => None,
+        };
+        write_bytes(out, 1, url.as_bytes());
+        write_bytes(out, 2, &self.encode(fields, inner)?);
+        Ok(())
+    }
+
+    /// The key and value fields of a map field's entry type, if it is a map field.
+    fn map_entry(&self, field: &FieldDescriptor) -> Option<(&'s FieldDescriptor, &'s FieldDescriptor)> {
+        if field.label != LABEL_REPEATED || field.ty != TYPE_MESSAGE || !field.type_name.ends_with("Entry") {
+            return None;
+        }
+        let entry = self.schema?.message(&field.type_name)?;
+        let key = entry.fields.iter().find(|f| f.number == 1 && f.name == "key")?;
+        let value = entry.fields.iter().find(|f| f.number == 2 && f.name == "value")?;
+        Some((key, value))
+    }
+
+    fn value(&self, number: u64, declared: Option<&FieldDescriptor>, value: &Value, out: &mut Vec<u8>) -> Result<(), String> {
+        let declared = match declared {
+            Some(declared) => declared,
+            None => return self.untyped(number, value, out),
+        };
+        let mismatch = || format!("{} can't hold that value", declared.name);
+        match (TYPE_NAMES.get(declared.ty as usize).copied().unwrap_or(""), value) {
+            (_, Value::Null) => {}
+            ("double", value) => fixed64(out, number, float(value).ok_or_else(mismatch)?.to_bits()),
+            ("float", value) => fixed32(out, number, (float(value).ok_or_else(mismatch)? as f32).to_bits()),
+            ("int64", value) => varint(out, number, integer(value, i64::MIN as i128, i64::MAX as i128).ok_or_else(mismatch)? as u64),
+            ("uint64", value) => varint(out, number, integer(value, 0, u64::MAX as i128).ok_or_else(mismatch)? as u64),
+            // Negative int32s are sign-extended to ten bytes, as the spec requires.
+            ("int32", value) => varint(out, number, integer(value, i32::MIN as i128, i32::MAX as i128).ok_or_else(mismatch)? as i64 as u64),
+            ("uint32", value) => varint(out, number, integer(value, 0, u32::MAX as i128).ok_or_else(mismatch)? as u64),
+            ("sint32", value) => {
+                let v = integer(value, i32::MIN as i128, i32::MAX as i128).ok_or_else(mismatch)? as i64;
+                varint(out, number, ((v << 1) ^ (v >> 63)) as u64);
+            }
+            ("sint64", value) => {
+                let v = integer(value, i64::MIN as i128, i64::MAX as i128).ok_or_else(mismatch)? as i64;
+                varint(out, number, ((v << 1) ^ (v >> 63)) as u64);
+            }
+            ("fixed64", value) => fixed64(out, number, integer(value, 0, u64::MAX as i128).ok_or_else(mismatch)? as u64),
+            ("sfixed64", value) => fixed64(out, number, integer(value, i64::MIN as i128, i64::MAX as i128).ok_or_else(mismatch)? as u64),
+            ("fixed32", value) => fixed32(out, number, integer(value, 0, u32::MAX as i128).ok_or_else(mismatch)? as u32),
+            ("sfixed32", value) => fixed32(out, number, integer(value, i32::MIN as i128, i32::MAX as i128).ok_or_else(mismatch)? as u32),
+            ("bool", value) => varint(out, number, boolean(value).ok_or_else(mismatch)? as u64),
+            ("enum", value) => {
+                let name = match value {
+                    Value::Ident(name) => Some(name.clone()),
+                    Value::String(bytes) => Some(String::from_utf8_lossy(bytes).into_owned()),
+                    _ => None,
+                };
+                let v = match name {
+                    Some(name) => {
+                        let descriptor = self.schema.and_then(|schema| schema.enumeration(&declared.type_name));
+                        descriptor.and_then(|descriptor| descriptor.values.iter().find(|(n, _)| *n == name)).map(|(_, v)| *v)
+                            .ok_or_else(|| format!("{} has no value {}", declared.type_name.trim_start_matches('.'), name))?
+                    }
+                    None => integer(value, i32::MIN as i128, i32::MAX as i128).ok_or_else(mismatch)? as i32,
+                };
+                varint(out, number, v as i64 as u64);
+            }
+            ("string", Value::String(bytes)) => write_bytes(out, number, bytes),
+            ("bytes", Value::String(bytes)) if self.json => {
+                let text = std::str::from_utf8(bytes).map_err(|_| mismatch())?;
+                write_bytes(out, number, &base64::decode(text).ok_or_else(|| format!("{} isn't valid base64", declared.name))?);
+            }
+            ("bytes", Value::String(bytes)) => write_bytes(out, number, bytes),
+            ("message", Value::Message(fields)) => {
+                let nested = self.schema.and_then(|schema| schema.message(&declared.type_name));
+                write_bytes(out, number, &self.encode(fields, nested)?);
+            }
+            ("group", Value::Message(fields)) => {
+                let nested = self.schema.and_then(|schema| schema.message(&declared.type_name));
+                write_tag(out, number, WireType::StartGroup);
+                self.message(fields, nested, out)?;
+                write_tag(out, number, WireType::EndGroup);
+            }
+            _ => return Err(mismatch()),
+        }
+        Ok(())
+    }
+
+    /// Encodes a value with no declared type, picking the wire type from how it's written.
+    fn untyped(&self, number: u64, value: &Value, out: &mut Vec<u8>) -> Result<(), String> {
+        match value {
+            Value::Null => {}
+            Value::Number(text) => {
+                // protoc --decode_raw writes fixed-width values as zero-padded hex.
+                let hex = text.strip_prefix("0x").filter(|hex| hex.len() == 8 || hex.len() == 16);
+                match hex.and_then(|hex| u64::from_str_radix(hex, 16).ok().map(|v| (v, hex.len()))) {
+                    Some((v, 16)) => fixed64(out, number, v),
+                    Some((v, _)) => fixed32(out, number, v as u32),
+                    None => match integer(value, i64::MIN as i128, u64::MAX as i128) {
+                        Some(v) => varint(out, number, v as u64),
+                        None => fixed64(out, number, float(value).ok_or_else(|| format!("{} isn't a number", text))?.to_bits()),
+                    },
+                }
+            }
+            Value::String(bytes) => write_bytes(out, number, bytes),
+            Value::Ident(name) => match boolean(value) {
+                Some(v) => varint(out, number, v as u64),
+                None => return Err(format!("{} can't be encoded without a schema", name)),
+            },
+            Value::Message(fields) => write_bytes(out, number, &self.encode(fields, None)?),
+            Value::List(_) => return Err(format!("field {} has a list nested in a list", number)),
+        }
+        Ok(())
+    }
+}
+
+fn varint(out: &mut Vec<u8>, number: u64, value: u64) {
+    write_tag(out, number, WireType::VarInt);
+    write_varint(out, value);
+}
+
+fn fixed64(out: &mut Vec<u8>, number: u64, value: u64) {
+    write_tag(out, number, WireType::Int64);
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn fixed32(out: &mut Vec<u8>, number: u64, value: u32) {
+    write_tag(out, number, WireType::Int32);
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+/// The text of a number, which JSON may also give as a string.
+fn number_text(value: &Value) -> Option<&str> {
+    match value {
+        Value::Number(text) => Some(text),
+        Value::String(bytes) => std::str::from_utf8(bytes).ok(),
+        _ => None,
+    }
+}
+
+/// Parses an integer in decimal, hex or octal, or written as an integral float, within a range.
+fn integer(value: &Value, min: i128, max: i128) -> Option<i128> {
+    let text = number_text(value)?;
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let magnitude = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i128::from_str_radix(hex, 16).ok()
+    } else if digits.len() > 1 && digits.starts_with('0') && digits.bytes().all(|b| b.is_ascii_digit()) {
+        i128::from_str_radix(&digits[1..], 8).ok()
+    } else {
+        digits.parse::<i128>().ok().or_else(|| {
+            digits.parse::<f64>().ok().filter(|f| f.fract() == 0.0 && f.abs() < 1e38).map(|f| f as i128)
+        })
+    }?;
+    let v = if negative { -magnitude } else { magnitude };
+    Some(v).filter(|v| (min..=max).contains(v))
+}
+
+fn float(value: &Value) -> Option<f64> {
+    let text = number_text(value)?;
+    let (negative, word) = match text.strip_prefix('-') {
+        Some(word) => (true, word),
+        None => (false, text),
+    };
+    let v = match word.to_ascii_lowercase().as_str() {
+        "inf" | "infinity" => f64::INFINITY,
+        "nan" => f64::NAN,
+        // Text format allows an f suffix on floats.
+        lower => lower.strip_suffix('f').unwrap_or(lower).parse().ok()?,
+    };
+    Some(if negative { -v } else { v })
+}
+
+fn boolean(value: &Value) -> Option<bool> {
+    match value {
+        Value::Ident(word) => match word.as_str() {
+            "true" | "True" | "t" => Some(true),
+            "false" | "False" | "f" => Some(false),
+            _ => None,
+        },
+        Value::Number(text) => match text.as_str() {
+            "1" => Some(true),
+            "0" => Some(false),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
@@ -0,0 +1,481 @@
+//! `pcap`: decoding the protobuf in packet captures, in pcap or pcapng files, such as tcpdump and
+//! Wireshark save.
+//!
+//! TCP streams are reassembled from their segments in sequence order, dropping retransmissions,
+//! and a stream stops where a segment is missing from the capture. HTTP/2 streams, recognized by
+//! the client's connection preface or the server's first SETTINGS frame, are split into their
+//! streams' DATA, and a body that is gRPC messages is split into them. Any other TCP stream is
+//! split as --framing auto would, and each UDP datagram that parses is a message. Headers aren't
+//! decoded, so gRPC's compressed messages are shown as bytes.
+//!
+//! Messages from every flow are printed in the order of the packets they start in, each with its
+//! flow and the time of that packet.
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use crate::framing::{self, Framing};
+use crate::schema::{print_as, Schema};
+use crate::warning::Warnings;
+use crate::{parse_message, plural, print_message_indented, units, Options};
+
+/// Seconds and nanoseconds since the Unix epoch.
+type Time = (i64, u32);
+
+struct Packet<'a> {
+    time: Time,
+    link_type: u32,
+    data: &'a [u8],
+}
+
+fn u16_at(data: &[u8], pos: usize, big: bool) -> Option<u16> {
+    let bytes = data.get(pos..pos + 2)?.try_into().expect("slice is 2 bytes");
+    Some(if big { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+}
+
+fn u32_at(data: &[u8], pos: usize, big: bool) -> Option<u32> {
+    let bytes = data.get(pos..pos + 4)?.try_into().expect("slice is 4 bytes");
+    Some(if big { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+}
+
+/// Reads the packets of a pcap or pcapng file.
+fn packets(data: &[u8]) -> Result<Vec<Packet<'_>>, String> {
+    match data.get(..4) {
+        Some([0x0a, 0x0d, 0x0d, 0x0a]) => pcapng(data),
+        Some(_) => pcap(data),
+        None => Err("not a pcap or pcapng file".to_string()),
+    }
+}
+
+fn pcap(data: &[u8]) -> Result<Vec<Packet<'_>>, String> {
+    // The magic number is written in the byte order of the rest, and tells microseconds from
+    // nanoseconds.
+    let (big, nanos) = match data.get(..4) {
+        Some([0xd4, 0xc3, 0xb2, 0xa1]) => (false, false),
+        Some([0x4d, 0x3c, 0xb2, 0xa1]) => (false, true),
+        Some([0xa1, 0xb2, 0xc3, 0xd4]) => (true, false),
+        Some([0xa1, 0xb2, 0x3c, 0x4d]) => (true, true),
+        _ => return Err("not a pcap or pcapng file".to_string()),
+    };
+    let link_type = u32_at(data, 20, big).ok_or("truncated pcap header")?;
+    let mut packets = Vec::new();
+    let mut pos = 24;
+    while pos < data.len() {
+        let header = data.get(pos..pos + 16).ok_or_else(|| format!("truncated packet header at offset {}", pos))?;
+        let seconds = u32_at(header, 0, big).expect("header is 16 bytes");
+        let fraction = u32_at(header, 4, big).expect("header is 16 bytes");
+        let len = u32_at(header, 8, big).expect("header is 16 bytes") as usize;
+        let packet = data.get(pos + 16..pos + 16 + len).ok_or_else(|| format!("packet at offset {} runs past the end of the file", pos))?;
+        let nanos = if nanos { fraction } else { fraction.saturating_mul(1000) };
+        packets.push(Packet{time: (seconds as i64, nanos), link_type, data: packet});
+        pos += 16 + len;
+    }
+    Ok(packets)
+}
+
+fn pcapng(data: &[u8]) -> Result<Vec<Packet<'_>>, String> {
+    const SECTION_HEADER: u32 = 0x0a0d_0d0a;
+    const INTERFACE_DESCRIPTION: u32 = 1;
+    const SIMPLE_PACKET: u32 = 3;
+    const ENHANCED_PACKET: u32 = 6;
+
+    let mut packets = Vec::new();
+    let mut big = false;
+    // Each interface's link type and timestamp units per second.
+    let mut interfaces: Vec<(u32, u64)> = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let truncated = || format!("truncated block at offset {}", pos);
+        // A section header gives the byte order of itself and everything after it.
+        if data.get(pos..pos + 4) == Some(&[0x0a, 0x0d, 0x0d, 0x0a]) {
+            big = match data.get(pos + 8..pos + 12) {
+                Some([0x1a, 0x2b, 0x3c, 0x4d]) => true,
+                Some([0x4d, 0x3c, 0x2b, 0x1a]) => false,
+                _ => return Err(format!("bad byte-order magic at offset {}", pos + 8)),
+            };
+            interfaces.clear();
+        }
+        let block_type = u32_at(data, pos, big).ok_or_else(truncated)?;
+        let len = u32_at(data, pos + 4, big).ok_or_else(truncated)? as usize;
+        if len < 12 || !len.is_multiple_of(4) {
+            return Err(format!("bad block length {} at offset {}", len, pos));
+        }
+        let body = data.get(pos + 8..pos + len - 4).ok_or_else(truncated)?;
+        match block_type {
+            SECTION_HEADER => {}
+            INTERFACE_DESCRIPTION => {
+                let link_type = u16_at(body, 0, big).ok_or_else(truncated)? as u32;
+                interfaces.push((link_type, timestamp_units(&body[8.min(body.len())..], big)));
+            }
+            ENHANCED_PACKET => {
+                let interface = u32_at(body, 0, big).ok_or_else(truncated)? as usize;
+                let &(link_type, per_second) = interfaces.get(interface).ok_or_else(|| format!("packet at offset {} has no interface", pos))?;
+                let high = u32_at(body, 4, big).ok_or_else(truncated)? as u64;
+                let low = u32_at(body, 8, big).ok_or_else(truncated)? as u64;
+                let captured = u32_at(body, 12, big).ok_or_else(truncated)? as usize;
+                let packet = body.get(20..20 + captured).ok_or_else(truncated)?;
+                let ticks = high << 32 | low;
+                let nanos = (ticks % per_second) as u128 * 1_000_000_000 / per_second as u128;
+                packets.push(Packet{time: ((ticks / per_second) as i64, nanos as u32), link_type, data: packet});
+            }
+            // Simple packets carry no timestamp, and their length is only bounded by the block's.
+            SIMPLE_PACKET => {
+                let &(link_type, _) = interfaces.first().ok_or_else(|| format!("packet at offset {} has no interface", pos))?;
+                let original = u32_at(body, 0, big).ok_or_else(truncated)? as usize;
+                let packet = &body[4..];
+                packets.push(Packet{time: (0, 0), link_type, data: &packet[..original.min(packet.len())]});
+            }
+            _ => {}
+        }
+        pos += len;
+    }
+    Ok(packets)
+}
+
+/// The timestamp units per second that an interface's options give, microseconds if they don't.
+fn timestamp_units(options: &[u8], big: bool) -> u64 {
+    const IF_TSRESOL: u16 = 9;
+    let mut pos = 0;
+    while let (Some(code), Some(len)) = (u16_at(options, pos, big), u16_at(options, pos + 2, big)) {
+        if code == IF_TSRESOL && len >= 1 {
+            return match options.get(pos + 4) {
+                // A power of two, or of ten.
+                Some(&resolution) if resolution & 0x80 != 0 => 1u64.checked_shl((resolution & 0x7f) as u32).unwrap_or(1_000_000),
+                Some(&resolution) => 10u64.checked_pow(resolution as u32).unwrap_or(1_000_000),
+                None => 1_000_000,
+            };
+        }
+        pos += 4 + (len as usize).div_ceil(4) * 4;
+    }
+    1_000_000
+}
+
+/// The IP packet in a frame of the given link type, if it has one.
+fn ip_packet(link_type: u32, data: &[u8]) -> Option<&[u8]> {
+    const NULL: u32 = 0;
+    const ETHERNET: u32 = 1;
+    const RAW: [u32; 3] = [12, 14, 101];
+    const LINUX_SLL: u32 = 113;
+    const LINUX_SLL2: u32 = 276;
+
+    let (ethertype, payload) = match link_type {
+        ETHERNET => {
+            let mut pos = 12;
+            // Skip VLAN tags.
+            while matches!(u16_at(data, pos, true)?, 0x8100 | 0x88a8) {
+                pos += 4;
+            }
+            (u16_at(data, pos, true)?, data.get(pos + 2..)?)
+        }
+        LINUX_SLL => (u16_at(data, 14, true)?, data.get(16..)?),
+        LINUX_SLL2 => (u16_at(data, 0, true)?, data.get(20..)?),
+        // The address family, in the byte order of the machine that captured it.
+        NULL => {
+            let family = u32_at(data, 0, false)?.min(u32_at(data, 0, true)?);
+            (if family == 2 { 0x0800 } else { 0x86dd }, data.get(4..)?)
+        }
+        link_type if RAW.contains(&link_type) => (0, data),
+        _ => return None,
+    };
+    match ethertype {
+        0 | 0x0800 | 0x86dd => Some(payload),
+        _ => None,
+    }
+}
+
+/// A flow's source and destination.
+type Flow = (SocketAddr, SocketAddr);
+
+/// A TCP segment's sequence number and flags.
+struct Tcp {
+    seq: u32,
+    syn: bool,
+}
+
+/// A TCP or UDP packet's endpoints and payload.
+struct Segment<'a> {
+    src: SocketAddr,
+    dst: SocketAddr,
+    /// Only TCP segments have it.
+    tcp: Option<Tcp>,
+    payload: &'a [u8],
+}
+
+const TCP: u8 = 6;
+const UDP: u8 = 17;
+
+/// The TCP or UDP segment in an IP packet. Fragments are left out, since this only looks at whole
+/// packets.
+fn segment(ip: &[u8]) -> Option<Segment<'_>> {
+    let (src, dst, protocol, payload): (IpAddr, IpAddr, u8, &[u8]) = match ip.first()? >> 4 {
+        4 => {
+            let header_len = (ip[0] & 0x0f) as usize * 4;
+            let total = (u16_at(ip, 2, true)? as usize).clamp(header_len, ip.len());
+            let fragment = u16_at(ip, 6, true)?;
+            if fragment & 0x3fff != 0 {
+                return None;
+            }
+            let src: [u8; 4] = ip.get(12..16)?.try_into().expect("slice is 4 bytes");
+            let dst: [u8; 4] = ip.get(16..20)?.try_into().expect("slice is 4 bytes");
+            (Ipv4Addr::from(src).into(), Ipv4Addr::from(dst).into(), *ip.get(9)?, ip.get(header_len..total)?)
+        }
+        6 => {
+            let total = (40 + u16_at(ip, 4, true)? as usize).min(ip.len());
+            let src: [u8; 16] = ip.get(8..24)?.try_into().expect("slice is 16 bytes");
+            let dst: [u8; 16] = ip.get(24..40)?.try_into().expect("slice is 16 bytes");
+            let mut next = *ip.get(6)?;
+            let mut pos = 40;
+            // Skip extension headers: hop-by-hop, routing and destination options.
+            while matches!(next, 0 | 43 | 60) {
+                next = *ip.get(pos)?;
+                pos += (*ip.get(pos + 1)? as usize + 1) * 8;
+            }
+            (Ipv6Addr::from(src).into(), Ipv6Addr::from(dst).into(), next, ip.get(pos..total)?)
+        }
+        _ => return None,
+    };
+    let ports = (u16_at(payload, 0, true)?, u16_at(payload, 2, true)?);
+    let (tcp, payload) = match protocol {
+        TCP => {
+            let header_len = (payload.get(12)? >> 4) as usize * 4;
+            let flags = *payload.get(13)?;
+            (Some(Tcp{seq: u32_at(payload, 4, true)?, syn: flags & 0x02 != 0}), payload.get(header_len..)?)
+        }
+        UDP => {
+            let len = (u16_at(payload, 4, true)? as usize).clamp(8, payload.len());
+            (None, &payload[8..len])
+        }
+        _ => return None,
+    };
+    Some(Segment{src: SocketAddr::new(src, ports.0), dst: SocketAddr::new(dst, ports.1), tcp, payload})
+}
+
+/// When each part of a byte stream arrived: the offsets at which packets start, with their times.
+struct Timeline(Vec<(usize, Time)>);
+
+impl Timeline {
+    /// The time of the packet the byte at `offset` came in.
+    fn at(&self, offset: usize) -> Time {
+        let i = self.0.partition_point(|&(start, _)| start <= offset);
+        self.0[i.saturating_sub(1)].1
+    }
+}
+
+/// One direction of a TCP connection, as captured.
+#[derive(Default)]
+struct Direction {
+    /// The sequence number of the first byte of data.
+    start: Option<u32>,
+    /// Each segment's data, with its offset in the stream and its time.
+    segments: Vec<(usize, Time, Vec<u8>)>,
+}
+
+impl Direction {
+    fn add(&mut self, tcp: &Tcp, time: Time, payload: &[u8]) {
+        // A SYN takes up a sequence number before the data.
+        let seq = if tcp.syn { tcp.seq.wrapping_add(1) } else { tcp.seq };
+        let start = *self.start.get_or_insert(seq);
+        let offset = seq.wrapping_sub(start);
+        // Segments from before the first one seen, when the capture started mid-connection.
+        if payload.is_empty() || offset >= 1 << 31 {
+            return;
+        }
+        self.segments.push((offset as usize, time, payload.to_vec()));
+    }
+
+    /// Puts the stream together, dropping what was sent again, up to the first segment missing from
+    /// the capture.
+    fn reassemble(mut self) -> Stream {
+        self.segments.sort_by_key(|&(offset, _, _)| offset);
+        let mut data = Vec::new();
+        let mut timeline = Vec::new();
+        for (offset, time, payload) in &self.segments {
+            if *offset > data.len() {
+                break;
+            }
+            if offset + payload.len() > data.len() {
+                timeline.push((data.len(), *time));
+                data.extend_from_slice(&payload[data.len() - offset..]);
+            }
+        }
+        let end = self.segments.iter().map(|(offset, _, payload)| offset + payload.len()).max().unwrap_or(0);
+        let lost = end - data.len();
+        Stream{data, timeline: Timeline(timeline), lost}
+    }
+}
+
+/// A reassembled TCP stream.
+struct Stream {
+    data: Vec<u8>,
+    timeline: Timeline,
+    /// How far the stream runs on past `data`, which ends where a segment is missing.
+    lost: usize,
+}
+
+/// A message found in the capture.
+struct Found {
+    time: Time,
+    flow: String,
+    data: Vec<u8>,
+    compressed: bool,
+}
+
+const HTTP2_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// Whether a stream starts like the server's side of an HTTP/2 connection: a SETTINGS frame on
+/// stream 0.
+fn starts_like_http2_server(data: &[u8]) -> bool {
+    data.len() >= 9 && u32::from_be_bytes([0, data[0], data[1], data[2]]).is_multiple_of(6) && data[3] == 4
+        && u32_at(data, 5, true) == Some(0)
+}
+
+/// Splits an HTTP/2 connection's data, less the client's preface, into each stream's body, with
+/// when each part of it arrived. A frame cut off at the end of the capture is left out.
+fn http2_bodies(data: &[u8], base: usize, timeline: &Timeline) -> Vec<(u32, Vec<u8>, Timeline)> {
+    const DATA: u8 = 0;
+    const PADDED: u8 = 0x08;
+    let mut bodies: Vec<(u32, Vec<u8>, Timeline)> = Vec::new();
+    let mut pos = 0;
+    while let Some(header) = data.get(pos..pos + 9) {
+        let len = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+        let (kind, flags) = (header[3], header[4]);
+        let stream = u32_at(header, 5, true).expect("header is 9 bytes") & 0x7fff_ffff;
+        let payload = match data.get(pos + 9..pos + 9 + len) {
+            Some(payload) => payload,
+            None => break,
+        };
+        if kind == DATA {
+            let (skip, padding) = if flags & PADDED != 0 { (1, payload.first().copied().unwrap_or(0) as usize) } else { (0, 0) };
+            let content = payload.get(skip..len.saturating_sub(padding)).unwrap_or_default();
+            let i = match bodies.iter().position(|(id, _, _)| *id == stream) {
+                Some(i) => i,
+                None => {
+                    bodies.push((stream, Vec::new(), Timeline(Vec::new())));
+                    bodies.len() - 1
+                }
+            };
+            let (_, body, body_timeline) = &mut bodies[i];
+            body_timeline.0.push((body.len(), timeline.at(base + pos)));
+            body.extend_from_slice(content);
+        }
+        pos += 9 + len;
+    }
+    bodies
+}
+
+/// The messages in a body or stream: gRPC messages if it is made of them, or the messages of the
+/// framing that fits it.
+fn messages_in(data: &[u8], timeline: &Timeline, flow: &str, found: &mut Vec<Found>) -> bool {
+    let framing = match framing::detect(data) {
+        Framing::None if parse_message(data, false).is_err() => return false,
+        framing => framing,
+    };
+    let frames = framing::split(data, framing).expect("detected framings split");
+    for frame in frames.iter().filter(|frame| !frame.trailers) {
+        found.push(Found{time: timeline.at(frame.offset), flow: flow.to_string(), data: frame.data.to_vec(), compressed: frame.compressed});
+    }
+    true
+}
+
+/// Prints the protobuf messages in a capture, only looking at flows with one of `ports` on either
+/// end if there are any.
+pub fn run(out: &mut dyn Write, data: &[u8], ports: &[u16], schema: Option<(&Schema, &str)>, options: &Options, warnings: &mut Warnings) -> Result<(), String> {
+    let packets = packets(data)?;
+    let mut found = Vec::new();
+    // TCP flows, one for each direction, in the order they were first seen.
+    let mut flows: Vec<Flow> = Vec::new();
+    let mut directions: HashMap<Flow, Direction> = HashMap::new();
+    let mut unknown = 0;
+    for packet in &packets {
+        let segment = match ip_packet(packet.link_type, packet.data).and_then(segment) {
+            Some(segment) => segment,
+            None => {
+                unknown += 1;
+                continue;
+            }
+        };
+        if !ports.is_empty() && !ports.contains(&segment.src.port()) && !ports.contains(&segment.dst.port()) {
+            continue;
+        }
+        let flow = format!("{} -> {}", segment.src, segment.dst);
+        match segment.tcp {
+            Some(tcp) => {
+                let key = (segment.src, segment.dst);
+                let direction = directions.entry(key).or_insert_with(|| {
+                    flows.push(key);
+                    Direction::default()
+                });
+                direction.add(&tcp, packet.time, segment.payload);
+            }
+            None if !segment.payload.is_empty() && parse_message(segment.payload, false).is_ok() => {
+                found.push(Found{time: packet.time, flow, data: segment.payload.to_vec(), compressed: false});
+            }
+            None => {}
+        }
+    }
+    if unknown > 0 {
+        eprintln!("protocat: skipped {} packet{} that aren't whole TCP or UDP packets", unknown, plural(unknown));
+    }
+
+    // A server's side is HTTP/2 when its client's is, as it starts with the connection preface.
+    let streams: Vec<(Flow, Stream)> = flows.iter()
+        .map(|key| (*key, directions.remove(key).expect("every flow has a direction").reassemble()))
+        .collect();
+    for ((src, dst), Stream{data, timeline, lost}) in &streams {
+        let flow = format!("{} -> {}", src, dst);
+        if data.is_empty() {
+            continue;
+        }
+        if *lost > 0 {
+            eprintln!("protocat: {}: a segment is missing from the capture, leaving {} byte{} after it undecoded", flow, lost, plural(*lost));
+        }
+        let client_http2 = streams.iter().any(|((s, d), stream)| s == dst && d == src && stream.data.starts_with(HTTP2_PREFACE));
+        let http2 = if data.starts_with(HTTP2_PREFACE) {
+            Some(HTTP2_PREFACE.len())
+        } else if client_http2 || starts_like_http2_server(data) {
+            Some(0)
+        } else {
+            None
+        };
+        match http2 {
+            Some(base) => {
+                for (stream, body, body_timeline) in http2_bodies(&data[base..], base, timeline) {
+                    let flow = format!("{}, stream {}", flow, stream);
+                    if !body.is_empty() && !messages_in(&body, &body_timeline, &flow, &mut found) {
+                        eprintln!("protocat: {}: {} byte{} of data, not protobuf", flow, body.len(), plural(body.len()));
+                    }
+                }
+            }
+            None => {
+                if !messages_in(data, timeline, &flow, &mut found) {
+                    eprintln!("protocat: {}: {} byte{} of data, not protobuf", flow, data.len(), plural(data.len()));
+                }
+            }
+        }
+    }
+
+    found.sort_by_key(|found| found.time);
+    for (i, found) in found.iter().enumerate() {
+        let size = found.data.len();
+        let time = units::format_timestamp(found.time.0, found.time.1);
+        let header = format!("message {} ({}, {}, {} byte{}", i, found.flow, time, size, plural(size));
+        if found.compressed {
+            outln!(out, "{}, compressed): {}", header, options.bytes_format.format(&found.data));
+            continue;
+        }
+        // Only messages that parse are found.
+        let fields = parse_message(&found.data, false).expect("found messages parse");
+        warnings.set_input(&found.flow);
+        outln!(out, "{}): {{", header);
+        match schema {
+            Some((schema, type_name)) => print_as(out, &found.data, schema, type_name, 1, options, warnings),
+            None => {
+                print_message_indented(out, fields, 1, options, &[], warnings);
+            }
+        }
+        outln!(out, "}}");
+    }
+    Ok(())
+}
@@ -0,0 +1,79 @@
+//! WebAssembly field decoder plugins for `--plugin`.
+//!
+//! A plugin is a WebAssembly module (binary or text format) with no imports that exports:
+//!
+//! - `memory`, its linear memory;
+//! - `alloc(len: i32) -> i32`, returning a pointer to `len` writable bytes;
+//! - `decode(data: i32, data_len: i32, path: i32, path_len: i32) -> i64`, called for every
+//!   length-prefixed field with its bytes and its dotted path, both copied into memory from `alloc`.
+//!   It returns 0 to decline the field, or a pointer in the high 32 bits and a length in the low 32
+//!   bits of a UTF-8 rendering of the value, which is printed in place of protocat's own.
+//!
+//! The plugin instance lives for the whole run, so it may keep state and manage its own memory.
+
+use std::cell::RefCell;
+use std::fs;
+
+use nom::AsBytes;
+use wasmi::{Engine, Linker, Memory, Module, Store, TypedFunc};
+
+use crate::hook::{FieldHook, HookAction};
+use crate::{ProtoField, Span, WireValue};
+
+pub struct Plugin {
+    name: String,
+    store: RefCell<Store<()>>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    decode: TypedFunc<(i32, i32, i32, i32), i64>,
+}
+
+impl Plugin {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let error = |err: &dyn std::fmt::Display| format!("{}: {}", path, err);
+        let wasm = fs::read(path).map_err(|err| error(&err))?;
+        let engine = Engine::default();
+        let module = Module::new(&engine, wasm).map_err(|err| error(&err))?;
+        let mut store = Store::new(&engine, ());
+        let instance = Linker::new(&engine).instantiate_and_start(&mut store, &module).map_err(|err| error(&err))?;
+        let memory = instance.get_memory(&store, "memory").ok_or_else(|| error(&"missing memory export"))?;
+        let alloc = instance.get_typed_func(&store, "alloc").map_err(|err| error(&err))?;
+        let decode = instance.get_typed_func(&store, "decode").map_err(|err| error(&err))?;
+        Ok(Plugin{name: path.to_string(), store: RefCell::new(store), memory, alloc, decode})
+    }
+
+    fn copy_in(&self, store: &mut Store<()>, data: &[u8]) -> Result<i32, String> {
+        let ptr = self.alloc.call(&mut *store, data.len() as i32).map_err(|err| self.error(&err))?;
+        self.memory.write(&mut *store, ptr as u32 as usize, data).map_err(|err| self.error(&err))?;
+        Ok(ptr)
+    }
+
+    fn error(&self, err: &dyn std::fmt::Display) -> String {
+        format!("plugin {}: {}", self.name, err)
+    }
+}
+
+impl FieldHook for Plugin {
+    fn on_field(&self, path: &[u64], field: &ProtoField<Span>) -> Result<HookAction, String> {
+        let data = match field.value {
+            WireValue::LengthPrefixed(d) => d,
+            _ => return Ok(HookAction::Default),
+        };
+        let path = path.iter().map(u64::to_string).collect::<Vec<_>>().join(".");
+
+        let mut store = self.store.borrow_mut();
+        let data_ptr = self.copy_in(&mut store, data.as_bytes())?;
+        let path_ptr = self.copy_in(&mut store, path.as_bytes())?;
+        let args = (data_ptr, data.as_bytes().len() as i32, path_ptr, path.len() as i32);
+        let result = self.decode.call(&mut *store, args).map_err(|err| self.error(&err))? as u64;
+        if result == 0 {
+            return Ok(HookAction::Default);
+        }
+
+        let mut rendered = vec![0u8; (result & 0xffff_ffff) as usize];
+        self.memory.read(&*store, (result >> 32) as usize, &mut rendered).map_err(|err| self.error(&err))?;
+        String::from_utf8(rendered)
+            .map(HookAction::Replace)
+            .map_err(|_| self.error(&"decode returned invalid UTF-8"))
+    }
+}
@@ -0,0 +1,564 @@
+//! A parser for `.proto` files, producing the same descriptors protoc would hand us.
+//!
+//! It understands the parts of the language that matter for decoding: packages, messages, enums,
+//! fields, maps, oneofs and services. Options are skipped apart from `json_name` and `default`,
+//! and groups and extensions aren't supported.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::builtin;
+use crate::descriptor::*;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    /// An identifier, keyword or number.
+    Word(String),
+    Str(String),
+    Symbol(char),
+}
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    let mut line = 1;
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => {}
+            '/' if chars.peek() == Some(&'/') => {
+                while chars.peek().is_some_and(|&c| c != '\n') {
+                    chars.next();
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = ' ';
+                loop {
+                    match chars.next() {
+                        Some('/') if last == '*' => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            last = c;
+                        }
+                        None => return Err(format!("line {}: unterminated comment", line)),
+                    }
+                }
+            }
+            '"' | '\'' => {
+                let mut str = String::new();
+                loop {
+                    match chars.next() {
+                        Some(end) if end == c => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => str.push('\n'),
+                            Some('t') => str.push('\t'),
+                            Some('r') => str.push('\r'),
+                            Some('0') => str.push('\0'),
+                            Some(c) => str.push(c),
+                            None => return Err(format!("line {}: unterminated string", line)),
+                        },
+                        Some('\n') | None => return Err(format!("line {}: unterminated string", line)),
+                        Some(c) => str.push(c),
+                    }
+                }
+                tokens.push((Token::Str(str), line));
+            }
+            c if c.is_alphanumeric() || c == '_' || c == '.' || (c == '-' && chars.peek().is_some_and(char::is_ascii_digit)) => {
+                let mut word = c.to_string();
+                while let Some(&next) = chars.peek() {
+                    let exponent = (next == '-' || next == '+') && word.starts_with(|c: char| c.is_ascii_digit() || c == '-')
+                        && word.ends_with(['e', 'E']);
+                    if next.is_alphanumeric() || next == '_' || next == '.' || exponent {
+                        word.push(next);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push((Token::Word(word), line));
+            }
+            c => tokens.push((Token::Symbol(c), line)),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn line(&self) -> usize {
+        self.tokens.get(self.pos).or_else(|| self.tokens.last()).map_or(1, |(_, line)| *line)
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T, String> {
+        Err(format!("line {}: {}", self.line(), message))
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        let token = self.peek().cloned();
+        self.pos += 1;
+        token.map_or_else(|| Err("unexpected end of file".to_string()), Ok)
+    }
+
+    fn is_word(&self, word: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(w)) if w == word)
+    }
+
+    fn is_symbol(&self, symbol: char) -> bool {
+        self.peek() == Some(&Token::Symbol(symbol))
+    }
+
+    fn word(&mut self) -> Result<String, String> {
+        match self.next()? {
+            Token::Word(word) => Ok(word),
+            _ => { self.pos -= 1; self.error("expected a name") }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        match self.next()? {
+            Token::Str(str) => Ok(str),
+            _ => { self.pos -= 1; self.error("expected a string") }
+        }
+    }
+
+    fn expect(&mut self, symbol: char) -> Result<(), String> {
+        if self.is_symbol(symbol) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            self.error(&format!("expected '{}'", symbol))
+        }
+    }
+
+    fn number(&mut self) -> Result<i64, String> {
+        let word = self.word()?;
+        let (negative, digits) = match word.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, word.as_str()),
+        };
+        let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+            i64::from_str_radix(hex, 16)
+        } else if digits.len() > 1 && digits.starts_with('0') {
+            i64::from_str_radix(&digits[1..], 8)
+        } else {
+            digits.parse()
+        };
+        match value {
+            Ok(value) => Ok(if negative { -value } else { value }),
+            Err(_) => { self.pos -= 1; self.error("expected a number") }
+        }
+    }
+
+    /// Skips a balanced run of tokens up to and including the next `;` at this level.
+    fn skip_statement(&mut self) -> Result<(), String> {
+        let mut depth = 0;
+        loop {
+            match self.next()? {
+                Token::Symbol('{') | Token::Symbol('[') | Token::Symbol('(') => depth += 1,
+                Token::Symbol('}') | Token::Symbol(']') | Token::Symbol(')') => depth -= 1,
+                Token::Symbol(';') if depth == 0 => return Ok(()),
+                _ => {}
+            }
+            if depth < 0 {
+                return self.error("unbalanced brackets");
+            }
+        }
+    }
+
+    /// Skips a `{ ... }` block, the opening brace included.
+    fn skip_block(&mut self) -> Result<(), String> {
+        self.expect('{')?;
+        let mut depth = 1;
+        while depth > 0 {
+            match self.next()? {
+                Token::Symbol('{') => depth += 1,
+                Token::Symbol('}') => depth -= 1,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Parses the `[...]` options after a field, keeping the ones that affect decoding.
+    fn field_options(&mut self, field: &mut FieldDescriptor) -> Result<(), String> {
+        if !self.is_symbol('[') {
+            return Ok(());
+        }
+        self.pos += 1;
+        loop {
+            let name = self.option_name()?;
+            self.expect('=')?;
+            let value = match self.next()? {
+                Token::Word(word) => word,
+                Token::Str(str) => str,
+                Token::Symbol('{') => {
+                    self.pos -= 1;
+                    self.skip_block()?;
+                    String::new()
+                }
+                _ => { self.pos -= 1; return self.error("expected an option value") }
+            };
+            match name.as_str() {
+                "json_name" => field.json_name = Some(value),
+                "default" => field.default_value = Some(value),
+                "packed" => field.packed = Some(value == "true"),
+                _ => {}
+            }
+            if self.is_symbol(',') {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        self.expect(']')
+    }
+
+    fn option_name(&mut self) -> Result<String, String> {
+        if self.is_symbol('(') {
+            self.pos += 1;
+            let name = self.word()?;
+            self.expect(')')?;
+            let mut name = format!("({})", name);
+            if let Some(Token::Word(rest)) = self.peek() {
+                name.push_str(rest);
+                self.pos += 1;
+            }
+            Ok(name)
+        } else {
+            self.word()
+        }
+    }
+
+    fn type_name(&mut self, field: &mut FieldDescriptor) -> Result<(), String> {
+        let name = self.word()?;
+        match TYPE_NAMES.iter().position(|&ty| ty == name) {
+            Some(ty) if !matches!(name.as_str(), "group" | "message" | "enum" | "") => field.ty = ty as u64,
+            _ => field.type_name = name,
+        }
+        Ok(())
+    }
+
+    /// Parses a field after its label, e.g. `string name = 1 [json_name = "n"];`.
+    fn field(&mut self, label: u64) -> Result<FieldDescriptor, String> {
+        let mut field = FieldDescriptor{label, ..FieldDescriptor::default()};
+        self.type_name(&mut field)?;
+        field.name = self.word()?;
+        self.expect('=')?;
+        field.number = self.number()? as u64;
+        self.field_options(&mut field)?;
+        self.expect(';')?;
+        Ok(field)
+    }
+
+    fn message(&mut self, proto3: bool) -> Result<MessageDescriptor, String> {
+        let mut message = MessageDescriptor{name: self.word()?, ..MessageDescriptor::default()};
+        self.expect('{')?;
+        while !self.is_symbol('}') {
+            let keyword = match self.peek() {
+                Some(Token::Word(word)) => word.clone(),
+                Some(Token::Symbol(';')) => { self.pos += 1; continue; }
+                _ => return self.error("expected a field or definition"),
+            };
+            match keyword.as_str() {
+                "message" => { self.pos += 1; message.nested_messages.push(self.message(proto3)?); }
+                "enum" => { self.pos += 1; message.enums.push(self.enumeration()?); }
+                "option" | "reserved" | "extensions" => self.skip_statement()?,
+                "extend" => { self.pos += 2; self.skip_block()?; }
+                "oneof" => {
+                    self.pos += 1;
+                    let index = message.oneofs.len() as u64;
+                    message.oneofs.push(self.word()?);
+                    self.expect('{')?;
+                    while !self.is_symbol('}') {
+                        if self.is_word("option") {
+                            self.skip_statement()?;
+                            continue;
+                        }
+                        let mut field = self.field(LABEL_OPTIONAL)?;
+                        field.oneof_index = Some(index);
+                        message.fields.push(field);
+                    }
+                    self.pos += 1;
+                }
+                "map" if self.tokens.get(self.pos + 1).map(|(t, _)| t) == Some(&Token::Symbol('<')) => {
+                    self.pos += 2;
+                    let mut key = FieldDescriptor{name: "key".to_string(), number: 1, label: LABEL_OPTIONAL, ..FieldDescriptor::default()};
+                    self.type_name(&mut key)?;
+                    self.expect(',')?;
+                    let mut value = FieldDescriptor{name: "value".to_string(), number: 2, label: LABEL_OPTIONAL, ..FieldDescriptor::default()};
+                    self.type_name(&mut value)?;
+                    self.expect('>')?;
+                    let mut field = FieldDescriptor{label: LABEL_REPEATED, ty: TYPE_MESSAGE, ..FieldDescriptor::default()};
+                    field.name = self.word()?;
+                    self.expect('=')?;
+                    field.number = self.number()? as u64;
+                    self.field_options(&mut field)?;
+                    self.expect(';')?;
+
+                    // Maps are sugar for a repeated entry message named after the field.
+                    let mut entry = String::new();
+                    for part in field.name.split('_') {
+                        let mut chars = part.chars();
+                        if let Some(first) = chars.next() {
+                            entry.extend(first.to_uppercase());
+                            entry.push_str(chars.as_str());
+                        }
+                    }
+                    entry.push_str("Entry");
+                    field.type_name = entry.clone();
+                    message.nested_messages.push(MessageDescriptor{name: entry, fields: vec![key, value], ..MessageDescriptor::default()});
+                    message.fields.push(field);
+                }
+                "group" => return self.error("groups aren't supported"),
+                "optional" | "required" | "repeated" => {
+                    self.pos += 1;
+                    let label = match keyword.as_str() {
+                        "required" => LABEL_REQUIRED,
+                        "repeated" => LABEL_REPEATED,
+                        _ => LABEL_OPTIONAL,
+                    };
+                    if self.is_word("group") {
+                        return self.error("groups aren't supported");
+                    }
+                    let mut field = self.field(label)?;
+                    field.proto3_optional = proto3 && label == LABEL_OPTIONAL;
+                    message.fields.push(field);
+                }
+                _ => message.fields.push(self.field(LABEL_OPTIONAL)?),
+            }
+        }
+        self.pos += 1;
+        Ok(message)
+    }
+
+    fn enumeration(&mut self) -> Result<EnumDescriptor, String> {
+        let mut descriptor = EnumDescriptor{name: self.word()?, ..EnumDescriptor::default()};
+        self.expect('{')?;
+        while !self.is_symbol('}') {
+            if self.is_word("option") || self.is_word("reserved") {
+                self.skip_statement()?;
+                continue;
+            }
+            if self.is_symbol(';') {
+                self.pos += 1;
+                continue;
+            }
+            let name = self.word()?;
+            self.expect('=')?;
+            let number = self.number()? as i32;
+            if self.is_symbol('[') {
+                self.skip_statement()?;
+            } else {
+                self.expect(';')?;
+            }
+            descriptor.values.push((name, number));
+        }
+        self.pos += 1;
+        Ok(descriptor)
+    }
+
+    fn service(&mut self) -> Result<ServiceDescriptor, String> {
+        let mut service = ServiceDescriptor{name: self.word()?, ..ServiceDescriptor::default()};
+        self.expect('{')?;
+        while !self.is_symbol('}') {
+            if self.is_word("option") {
+                self.skip_statement()?;
+                continue;
+            }
+            if self.is_symbol(';') {
+                self.pos += 1;
+                continue;
+            }
+            if !self.is_word("rpc") {
+                return self.error("expected rpc");
+            }
+            self.pos += 1;
+            let mut method = MethodDescriptor{name: self.word()?, ..MethodDescriptor::default()};
+            self.expect('(')?;
+            if self.is_word("stream") && !matches!(self.tokens.get(self.pos + 1), Some((Token::Symbol(')'), _))) {
+                self.pos += 1;
+                method.client_streaming = true;
+            }
+            method.input_type = self.word()?;
+            self.expect(')')?;
+            if !self.is_word("returns") {
+                return self.error("expected returns");
+            }
+            self.pos += 1;
+            self.expect('(')?;
+            if self.is_word("stream") && !matches!(self.tokens.get(self.pos + 1), Some((Token::Symbol(')'), _))) {
+                self.pos += 1;
+                method.server_streaming = true;
+            }
+            method.output_type = self.word()?;
+            self.expect(')')?;
+            if self.is_symbol('{') {
+                self.skip_block()?;
+            } else {
+                self.expect(';')?;
+            }
+            service.methods.push(method);
+        }
+        self.pos += 1;
+        Ok(service)
+    }
+
+    fn file(&mut self, name: &str) -> Result<FileDescriptor, String> {
+        let mut file = FileDescriptor{name: name.to_string(), ..FileDescriptor::default()};
+        while let Some(token) = self.peek().cloned() {
+            let keyword = match token {
+                Token::Word(word) => word,
+                Token::Symbol(';') => { self.pos += 1; continue; }
+                _ => return self.error("expected a definition"),
+            };
+            self.pos += 1;
+            match keyword.as_str() {
+                "syntax" | "edition" => {
+                    self.expect('=')?;
+                    let value = self.string()?;
+                    file.syntax = if keyword == "edition" { "editions".to_string() } else { value };
+                    self.expect(';')?;
+                }
+                "package" => {
+                    file.package = self.word()?;
+                    self.expect(';')?;
+                }
+                "import" => {
+                    if self.is_word("public") || self.is_word("weak") {
+                        self.pos += 1;
+                    }
+                    file.dependencies.push(self.string()?);
+                    self.expect(';')?;
+                }
+                "option" => self.skip_statement()?,
+                "message" => file.messages.push(self.message(file.syntax == "proto3")?),
+                "enum" => file.enums.push(self.enumeration()?),
+                "service" => file.services.push(self.service()?),
+                "extend" => { self.pos += 1; self.skip_block()?; }
+                other => { self.pos -= 1; return self.error(&format!("unexpected {}", other)); }
+            }
+        }
+        Ok(file)
+    }
+}
+
+/// Parses one `.proto` file. Type names in it are left as written until `resolve`.
+pub fn parse(name: &str, source: &str) -> Result<FileDescriptor, String> {
+    let tokens = tokenize(source).map_err(|err| format!("{}: {}", name, err))?;
+    Parser{tokens, pos: 0}.file(name).map_err(|err| format!("{}: {}", name, err))
+}
+
+/// Parses `.proto` files from disk along with everything they import, and resolves them. Imports
+/// are looked up in the include directories, the current directory if there are none, and then
+/// among the bundled files, which covers the well-known types. A file given by a path inside an
+/// include directory is named relative to it, as protoc names it.
+pub fn load(paths: &[String], include: &[String]) -> Result<Vec<FileDescriptor>, String> {
+    let include: Vec<&Path> = if include.is_empty() { vec![Path::new(".")] } else { include.iter().map(Path::new).collect() };
+    let mut pending: Vec<(String, Option<PathBuf>)> = paths.iter().map(|path| {
+        let name = include.iter().find_map(|dir| Path::new(path).strip_prefix(dir).ok())
+            .map_or_else(|| path.clone(), |name| name.to_string_lossy().into_owned());
+        (name, Some(PathBuf::from(path)))
+    }).collect();
+    pending.reverse();
+
+    let mut files = Vec::new();
+    let mut seen = HashSet::new();
+    while let Some((name, path)) = pending.pop() {
+        if !seen.insert(name.clone()) {
+            continue;
+        }
+        let path = path.filter(|path| path.is_file()).or_else(|| include.iter().map(|dir| dir.join(&name)).find(|path| path.is_file()));
+        let source = match path {
+            Some(path) => fs::read_to_string(&path).map_err(|err| format!("{}: {}", path.display(), err))?,
+            None => builtin::bundled(&name).ok_or_else(|| format!("{}: file not found", name))?.to_string(),
+        };
+        let file = parse(&name, &source)?;
+        pending.extend(file.dependencies.iter().rev().map(|dependency| (dependency.clone(), None)));
+        files.push(file);
+    }
+    resolve(&mut files)?;
+    Ok(files)
+}
+
+/// Resolves the type names in parsed files against each other, the way protoc does: relative names
+/// are looked up from the innermost scope outwards.
+pub fn resolve(files: &mut [FileDescriptor]) -> Result<(), String> {
+    // Fully qualified names, and whether each is an enum.
+    let mut types = HashMap::new();
+    for file in files.iter() {
+        let scope = file.package.clone();
+        for descriptor in file.enums.iter() {
+            types.insert(qualify(&scope, &descriptor.name), true);
+        }
+        let mut pending: Vec<(String, &MessageDescriptor)> = file.messages.iter().map(|m| (scope.clone(), m)).collect();
+        while let Some((scope, message)) = pending.pop() {
+            let name = qualify(&scope, &message.name);
+            for descriptor in message.enums.iter() {
+                types.insert(qualify(&name, &descriptor.name), true);
+            }
+            pending.extend(message.nested_messages.iter().map(|m| (name.clone(), m)));
+            types.insert(name, false);
+        }
+    }
+
+    let lookup = |scope: &str, name: &str| -> Option<(String, bool)> {
+        if let Some(absolute) = name.strip_prefix('.') {
+            return types.get(absolute).map(|&is_enum| (format!(".{}", absolute), is_enum));
+        }
+        let mut scope = scope;
+        loop {
+            let candidate = qualify(scope, name);
+            if let Some(&is_enum) = types.get(&candidate) {
+                return Some((format!(".{}", candidate), is_enum));
+            }
+            if scope.is_empty() {
+                return None;
+            }
+            scope = scope.rfind('.').map_or("", |i| &scope[..i]);
+        }
+    };
+
+    for file in files.iter_mut() {
+        let (package, file_name) = (file.package.clone(), file.name.clone());
+        let mut pending: Vec<(String, &mut MessageDescriptor)> = file.messages.iter_mut().map(|m| (package.clone(), m)).collect();
+        while let Some((scope, message)) = pending.pop() {
+            let name = qualify(&scope, &message.name);
+            for field in message.fields.iter_mut() {
+                if field.type_name.is_empty() {
+                    continue;
+                }
+                let (type_name, is_enum) = lookup(&name, &field.type_name)
+                    .ok_or_else(|| format!("{}: unknown type {} for {}.{}", file_name, field.type_name, name, field.name))?;
+                field.type_name = type_name;
+                field.ty = if is_enum { TYPE_ENUM } else { TYPE_MESSAGE };
+            }
+            pending.extend(message.nested_messages.iter_mut().map(|m| (name.clone(), m)));
+        }
+        for service in file.services.iter_mut() {
+            for method in service.methods.iter_mut() {
+                for ty in [&mut method.input_type, &mut method.output_type] {
+                    if let Some((resolved, _)) = lookup(&package, ty) {
+                        *ty = resolved;
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn qualify(scope: &str, name: &str) -> String {
+    if scope.is_empty() { name.to_string() } else { format!("{}.{}", scope, name) }
+}
@@ -0,0 +1,465 @@
+//! `--format proto-json`: messages decoded with a schema, in the proto3 JSON mapping that protobuf's
+//! own JSON printers use.
+//!
+//! Keys are fields' JSON names, repeated fields are arrays and maps are objects. 64-bit integers are
+//! strings, bytes are base64, enums are the names of their values, and Timestamp, Duration, the
+//! wrappers, Struct, Value, ListValue, FieldMask, Empty and Any have their special forms. A singular
+//! field that occurs more than once has its last value, or if it's a message, all of them merged, as
+//! a parser would. Unknown fields, which the mapping has no place for, are left out, as are fields whose
+//! message type the schema doesn't have.
+
+use std::fmt::Write as _;
+use std::io::Write;
+
+use nom::AsBytes;
+
+use crate::descriptor::*;
+use crate::schema::{packed_wire_type, unpack, Schema};
+use crate::warning::{WarningKind, Warnings};
+use crate::{base64, json, parse_message, units, wkt, Cursor, Options, PrintStats, ProtoField, Span, WireValue};
+
+/// A converted value, kept whole until it's printed since the last of a field's occurrences wins.
+enum Json {
+    Null,
+    Bool(bool),
+    /// A number, already formatted.
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+/// The wrapper types, by name, and the types of the value they wrap.
+const WRAPPERS: [(&str, &str); 9] = [
+    ("DoubleValue", "double"),
+    ("FloatValue", "float"),
+    ("Int64Value", "int64"),
+    ("UInt64Value", "uint64"),
+    ("Int32Value", "int32"),
+    ("UInt32Value", "uint32"),
+    ("BoolValue", "bool"),
+    ("StringValue", "string"),
+    ("BytesValue", "bytes"),
+];
+
+/// One occurrence of a field: its value, or a group's fields, where they are in its message.
+enum Occurrence<'a> {
+    Value(WireValue<Span<'a>>),
+    Group(Cursor<'a>),
+}
+
+struct Converter<'s> {
+    schema: &'s Schema,
+    options: &'s Options,
+    path: Vec<u64>,
+    stats: PrintStats,
+}
+
+/// Prints a message of type `message` as a proto3 JSON object.
+pub fn print(out: &mut dyn Write, fields: Vec<ProtoField<Span>>, schema: &Schema, message: &MessageDescriptor, options: &Options, warnings: &mut Warnings) -> PrintStats {
+    // Only a type's full name tells whether it's a well-known type.
+    let type_name = schema.message_names().find(|&name| schema.message(name).is_some_and(|m| std::ptr::eq(m, message)));
+    let mut converter = Converter{schema, options, path: Vec::new(), stats: PrintStats{fields: 0, max_depth: 0}};
+    let level = Cursor::message(fields);
+    let json = match type_name.and_then(|name| converter.well_known(name, &top_level(&level), warnings)) {
+        Some(json) => json,
+        None => converter.message(level, message, warnings),
+    };
+    let mut text = String::new();
+    write(&json, 0, &mut text);
+    outln!(out, "{}", text);
+    converter.stats
+}
+
+impl<'s> Converter<'s> {
+    /// Converts a message whose type is `type_name`, or returns `None` if the schema doesn't have it.
+    fn typed(&mut self, type_name: &str, level: Cursor, warnings: &mut Warnings) -> Option<Json> {
+        if let Some(json) = self.well_known(type_name, &top_level(&level), warnings) {
+            return Some(json);
+        }
+        let message = self.schema.message(type_name)?;
+        Some(self.message(level, message, warnings))
+    }
+
+    fn message(&mut self, mut level: Cursor, message: &MessageDescriptor, warnings: &mut Warnings) -> Json {
+        self.stats.max_depth = self.stats.max_depth.max(self.path.len());
+        // The occurrences of each declared field, in order of the first.
+        let mut declared: Vec<(&FieldDescriptor, Vec<Occurrence>)> = Vec::new();
+        while let Some(index) = level.next() {
+            let field = level.list.fields[index];
+            let occurrence = match field.value {
+                WireValue::StartGroup => Occurrence::Group(Cursor::new(level.list.clone(), level.list.group(index).0)),
+                WireValue::EndGroup => continue,
+                value => Occurrence::Value(value),
+            };
+            let descriptor = match message.fields.iter().find(|f| f.number == field.tag_number) {
+                Some(descriptor) => descriptor,
+                None => continue,
+            };
+            match declared.iter_mut().find(|(d, _)| d.number == descriptor.number) {
+                Some((_, occurrences)) => occurrences.push(occurrence),
+                None => declared.push((descriptor, vec![occurrence])),
+            }
+        }
+
+        let mut object = Vec::new();
+        for (field, occurrences) in declared {
+            self.path.push(field.number);
+            if let Some(json) = self.field(field, occurrences, warnings) {
+                self.stats.fields += 1;
+                object.push((field.json_name(), json));
+            }
+            self.path.pop();
+        }
+        Json::Object(object)
+    }
+
+    /// Converts all the occurrences of a field into its one value.
+    fn field(&mut self, field: &FieldDescriptor, occurrences: Vec<Occurrence>, warnings: &mut Warnings) -> Option<Json> {
+        let nested = field.ty == TYPE_MESSAGE || field.ty == TYPE_GROUP;
+        if nested && self.path.len() > self.options.max_depth {
+            let message = format!("nested deeper than --max-depth {}, left out", self.options.max_depth);
+            warnings.emit(WarningKind::DepthLimit, &self.path, &message);
+            return None;
+        }
+        if let Some((key, value)) = self.map_entry(field) {
+            let mut map = Vec::new();
+            for occurrence in occurrences {
+                let entry = match submessage(occurrence) {
+                    Some(entry) => top_level(&entry),
+                    None => continue,
+                };
+                let key = self.converted(key, &entry, warnings).and_then(map_key);
+                let value = self.converted(value, &entry, warnings).or_else(|| self.default(value));
+                if let (Some(key), Some(value)) = (key, value) {
+                    // A key that comes back replaces the value it had.
+                    map.retain(|(k, _): &(String, Json)| *k != key);
+                    map.push((key, value));
+                }
+            }
+            return Some(Json::Object(map));
+        }
+        if field.label == LABEL_REPEATED {
+            let mut values = Vec::new();
+            for occurrence in occurrences {
+                values.extend(self.values(field, occurrence, warnings));
+            }
+            return Some(Json::Array(values));
+        }
+        if nested {
+            // Every occurrence of a singular message is merged into one.
+            let level = merged(occurrences.into_iter().filter_map(submessage).collect());
+            return self.typed(&field.type_name, level, warnings);
+        }
+        occurrences.into_iter().flat_map(|occurrence| self.values(field, occurrence, warnings)).last()
+    }
+
+    /// The value of the field declared as `field` among an entry's fields, the last if there are more.
+    fn converted(&mut self, field: &FieldDescriptor, fields: &[ProtoField<Span>], warnings: &mut Warnings) -> Option<Json> {
+        let occurrences: Vec<Occurrence> = fields.iter().filter(|f| f.tag_number == field.number).map(|f| Occurrence::Value(f.value)).collect();
+        if occurrences.is_empty() {
+            return None;
+        }
+        self.path.push(field.number);
+        let json = self.field(field, occurrences, warnings);
+        self.path.pop();
+        json
+    }
+
+    /// The values in one occurrence of a field: none if its wire type doesn't fit, or several if it's
+    /// packed.
+    fn values(&mut self, field: &FieldDescriptor, occurrence: Occurrence, warnings: &mut Warnings) -> Vec<Json> {
+        if field.ty == TYPE_MESSAGE || field.ty == TYPE_GROUP {
+            let level = match submessage(occurrence) {
+                Some(level) => level,
+                None => return Vec::new(),
+            };
+            return self.typed(&field.type_name, level, warnings).into_iter().collect();
+        }
+        let value = match occurrence {
+            Occurrence::Value(value) => value,
+            Occurrence::Group(_) => return Vec::new(),
+        };
+        match (value, packed_wire_type(field.ty)) {
+            (WireValue::LengthPrefixed(data), Some(wire_type)) => unpack(data, wire_type)
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|value| self.scalar(field, value))
+                .collect(),
+            (value, _) => self.scalar(field, value).into_iter().collect(),
+        }
+    }
+
+    /// Converts a scalar by its declared type, or returns `None` if the wire type doesn't fit it.
+    fn scalar(&self, field: &FieldDescriptor, value: WireValue<Span>) -> Option<Json> {
+        let (number, string) = (Json::Number, Json::String);
+        Some(match (TYPE_NAMES.get(field.ty as usize).copied()?, value) {
+            ("double", WireValue::Int64(v)) => float(f64::from_bits(v)),
+            ("float", WireValue::Int32(v)) => {
+                let v = f32::from_bits(v);
+                if v.is_finite() { number(v.to_string()) } else { float(v as f64) }
+            }
+            ("int64", WireValue::VarInt(v)) | ("sfixed64", WireValue::Int64(v)) => string((v as i64).to_string()),
+            ("uint64", WireValue::VarInt(v)) | ("fixed64", WireValue::Int64(v)) => string(v.to_string()),
+            ("sint64", WireValue::VarInt(v)) => string((((v >> 1) as i64) ^ -((v & 1) as i64)).to_string()),
+            ("int32", WireValue::VarInt(v)) => number((v as i32).to_string()),
+            ("uint32", WireValue::VarInt(v)) => number((v as u32).to_string()),
+            ("fixed32", WireValue::Int32(v)) => number(v.to_string()),
+            ("sfixed32", WireValue::Int32(v)) => number((v as i32).to_string()),
+            ("sint32", WireValue::VarInt(v)) => number(((((v >> 1) as i64) ^ -((v & 1) as i64)) as i32).to_string()),
+            ("bool", WireValue::VarInt(v)) => Json::Bool(v != 0),
+            ("string", WireValue::LengthPrefixed(data)) => string(String::from_utf8_lossy(data.as_bytes()).into_owned()),
+            ("bytes", WireValue::LengthPrefixed(data)) => string(base64::encode(data.as_bytes())),
+            ("enum", WireValue::VarInt(_)) if field.type_name.trim_start_matches('.') == "google.protobuf.NullValue" => Json::Null,
+            ("enum", WireValue::VarInt(v)) => {
+                let value = v as i32;
+                let name = self.schema.enumeration(&field.type_name)
+                    .and_then(|descriptor| descriptor.values.iter().find(|(_, n)| *n == value));
+                match name {
+                    Some((name, _)) => string(name.clone()),
+                    None => number(value.to_string()),
+                }
+            }
+            _ => return None,
+        })
+    }
+
+    /// The value a map entry without one has: its type's default.
+    fn default(&self, field: &FieldDescriptor) -> Option<Json> {
+        Some(match TYPE_NAMES.get(field.ty as usize).copied()? {
+            "int64" | "uint64" | "sint64" | "fixed64" | "sfixed64" => Json::String("0".to_string()),
+            "bool" => Json::Bool(false),
+            "string" | "bytes" => Json::String(String::new()),
+            "enum" => match self.schema.enumeration(&field.type_name).and_then(|descriptor| descriptor.values.first()) {
+                Some((name, _)) => Json::String(name.clone()),
+                None => Json::Number("0".to_string()),
+            },
+            "message" | "group" => Json::Object(Vec::new()),
+            _ => Json::Number("0".to_string()),
+        })
+    }
+
+    /// The key and value fields of a map's entries, if `field` is a map.
+    fn map_entry(&self, field: &FieldDescriptor) -> Option<(&'s FieldDescriptor, &'s FieldDescriptor)> {
+        if field.label != LABEL_REPEATED || field.ty != TYPE_MESSAGE || !field.type_name.ends_with("Entry") {
+            return None;
+        }
+        let entry = self.schema.message(&field.type_name)?;
+        let key = entry.fields.iter().find(|f| f.number == 1 && f.name == "key")?;
+        let value = entry.fields.iter().find(|f| f.number == 2 && f.name == "value")?;
+        Some((key, value))
+    }
+
+    /// Converts a message of a well-known type with a JSON form of its own, or returns `None` if
+    /// `type_name` isn't one or the message doesn't fit it.
+    fn well_known(&mut self, type_name: &str, fields: &[ProtoField<Span>], warnings: &mut Warnings) -> Option<Json> {
+        let name = type_name.trim_start_matches('.').strip_prefix("google.protobuf.")?;
+        let last = |number: u64| fields.iter().rev().find(|f| f.tag_number == number).map(|f| f.value);
+        let varint = |number: u64| match last(number) {
+            Some(WireValue::VarInt(v)) => Some(v),
+            None => Some(0),
+            Some(_) => None,
+        };
+        let json = match name {
+            "Timestamp" => {
+                let (seconds, nanos) = (varint(1)? as i64, varint(2)? as i32);
+                if !(0..=999_999_999).contains(&nanos) {
+                    return None;
+                }
+                let seconds = units::format_timestamp(seconds, 0);
+                Json::String(format!("{}{}Z", seconds.trim_end_matches('Z'), fraction(nanos as u32)))
+            }
+            "Duration" => {
+                let (seconds, nanos) = (varint(1)? as i64, varint(2)? as i32);
+                let sign = if seconds < 0 || nanos < 0 { "-" } else { "" };
+                Json::String(format!("{}{}{}s", sign, seconds.unsigned_abs(), fraction(nanos.unsigned_abs())))
+            }
+            "FieldMask" => {
+                let paths: Vec<String> = fields.iter()
+                    .filter_map(|f| match f.value {
+                        WireValue::LengthPrefixed(path) if f.tag_number == 1 => Some(String::from_utf8_lossy(path.as_bytes()).into_owned()),
+                        _ => None,
+                    })
+                    .map(|path| path.split('.').map(lower_camel_case).collect::<Vec<_>>().join("."))
+                    .collect();
+                Json::String(paths.join(","))
+            }
+            "Empty" => Json::Object(Vec::new()),
+            "Struct" => {
+                let mut object = Vec::new();
+                for field in fields.iter().filter(|f| f.tag_number == 1) {
+                    let entry = match submessage(Occurrence::Value(field.value)) {
+                        Some(entry) => top_level(&entry),
+                        None => continue,
+                    };
+                    let key = entry.iter().rev().find_map(|f| match f.value {
+                        WireValue::LengthPrefixed(key) if f.tag_number == 1 => Some(String::from_utf8_lossy(key.as_bytes()).into_owned()),
+                        _ => None,
+                    });
+                    let value = merged(entry.iter().filter(|f| f.tag_number == 2).filter_map(|f| submessage(Occurrence::Value(f.value))).collect());
+                    let value = self.nested(1, "google.protobuf.Value", value, warnings)?;
+                    object.retain(|(k, _): &(String, Json)| Some(k) != key.as_ref());
+                    object.push((key.unwrap_or_default(), value));
+                }
+                Json::Object(object)
+            }
+            "Value" => {
+                let kind = fields.iter().rev().find(|f| (1..=6).contains(&f.tag_number));
+                match kind.map(|f| (f.tag_number, f.value)) {
+                    None | Some((1, WireValue::VarInt(_))) => Json::Null,
+                    Some((2, WireValue::Int64(v))) => float(f64::from_bits(v)),
+                    Some((3, WireValue::LengthPrefixed(s))) => Json::String(String::from_utf8_lossy(s.as_bytes()).into_owned()),
+                    Some((4, WireValue::VarInt(v))) => Json::Bool(v != 0),
+                    Some((number @ 5, value)) | Some((number @ 6, value)) => {
+                        let level = submessage(Occurrence::Value(value))?;
+                        let type_name = if number == 5 { "google.protobuf.Struct" } else { "google.protobuf.ListValue" };
+                        self.nested(number, type_name, level, warnings)?
+                    }
+                    Some(_) => return None,
+                }
+            }
+            "ListValue" => {
+                let mut values = Vec::new();
+                for field in fields.iter().filter(|f| f.tag_number == 1) {
+                    let level = submessage(Occurrence::Value(field.value))?;
+                    values.push(self.nested(1, "google.protobuf.Value", level, warnings)?);
+                }
+                Json::Array(values)
+            }
+            "Any" => {
+                let (type_url, value) = wkt::any(fields)?;
+                let inner = wkt::type_name(&type_url).to_string();
+                let json = parse_message(value.fragment(), false).ok().and_then(|fields| self.nested(2, &inner, Cursor::message(fields), warnings));
+                let mut object = vec![("@type".to_string(), Json::String(type_url))];
+                // Types with JSON forms of their own are in a value, since they may not be objects, and
+                // a type the schema doesn't have keeps its bytes.
+                let special = inner.strip_prefix("google.protobuf.")
+                    .is_some_and(has_json_form);
+                match json {
+                    Some(Json::Object(fields)) if !special => object.extend(fields),
+                    Some(json) => object.push(("value".to_string(), json)),
+                    None => object.push(("value".to_string(), Json::String(base64::encode(value.as_bytes())))),
+                }
+                Json::Object(object)
+            }
+            _ => {
+                let (_, ty) = WRAPPERS.iter().find(|(wrapper, _)| *wrapper == name)?;
+                let ty = TYPE_NAMES.iter().position(|t| t == ty).expect("wrapped types are scalars") as u64;
+                let value = FieldDescriptor{name: "value".to_string(), number: 1, ty, ..Default::default()};
+                match last(1) {
+                    Some(v) => self.scalar(&value, v)?,
+                    None => self.default(&value)?,
+                }
+            }
+        };
+        self.stats.max_depth = self.stats.max_depth.max(self.path.len());
+        Some(json)
+    }
+
+    /// Converts a message nested in a well-known type as its field `number`, within the depth limit.
+    fn nested(&mut self, number: u64, type_name: &str, level: Cursor, warnings: &mut Warnings) -> Option<Json> {
+        self.path.push(number);
+        let json = if self.path.len() > self.options.max_depth {
+            let message = format!("nested deeper than --max-depth {}, left out", self.options.max_depth);
+            warnings.emit(WarningKind::DepthLimit, &self.path, &message);
+            None
+        } else {
+            self.typed(type_name, level, warnings)
+        };
+        self.path.pop();
+        json
+    }
+}
+
+/// Whether a well-known type, named without its package, is printed as other than an object of its
+/// fields. Empty is only written specially for a schema that doesn't have it.
+fn has_json_form(name: &str) -> bool {
+    matches!(name, "Timestamp" | "Duration" | "FieldMask" | "Struct" | "Value" | "ListValue" | "Any")
+        || WRAPPERS.iter().any(|(wrapper, _)| *wrapper == name)
+}
+
+/// The fields of a submessage or group, or `None` if the occurrence isn't one.
+fn submessage(occurrence: Occurrence) -> Option<Cursor> {
+    match occurrence {
+        Occurrence::Group(level) => Some(level),
+        Occurrence::Value(WireValue::LengthPrefixed(data)) => parse_message(data.fragment(), false).ok().map(Cursor::message),
+        Occurrence::Value(_) => None,
+    }
+}
+
+/// A message's own fields, without those of its groups, for the well-known types and map entries,
+/// which are read by field number.
+fn top_level<'a>(level: &Cursor<'a>) -> Vec<ProtoField<Span<'a>>> {
+    level.rest().map(|i| level.list.fields[i]).collect()
+}
+
+/// The occurrences of a singular message as one message, their fields in turn.
+fn merged<'a>(mut levels: Vec<Cursor<'a>>) -> Cursor<'a> {
+    if levels.len() == 1 {
+        return levels.pop().expect("there is one");
+    }
+    let fields = levels.iter()
+        .flat_map(|level| level.ranges.iter().flat_map(move |range| level.list.fields[range.clone()].iter().copied()))
+        .collect();
+    Cursor::message(fields)
+}
+
+/// A map key as the string proto3 JSON keys maps by.
+fn map_key(key: Json) -> Option<String> {
+    match key {
+        Json::String(key) | Json::Number(key) => Some(key),
+        Json::Bool(key) => Some(key.to_string()),
+        _ => None,
+    }
+}
+
+/// A double or float, with the values JSON numbers can't hold as strings.
+fn float(v: f64) -> Json {
+    match v {
+        v if v.is_nan() => Json::String("NaN".to_string()),
+        v if v == f64::INFINITY => Json::String("Infinity".to_string()),
+        v if v == f64::NEG_INFINITY => Json::String("-Infinity".to_string()),
+        v => Json::Number(v.to_string()),
+    }
+}
+
+/// The fraction of a second in a Timestamp or Duration: none, or 3, 6 or 9 digits, as few as hold it.
+fn fraction(nanos: u32) -> String {
+    match nanos {
+        0 => String::new(),
+        n if n % 1_000_000 == 0 => format!(".{:03}", n / 1_000_000),
+        n if n % 1000 == 0 => format!(".{:06}", n / 1000),
+        n => format!(".{:09}", n),
+    }
+}
+
+fn lower_camel_case(name: &str) -> String {
+    let descriptor = FieldDescriptor{name: name.to_string(), ..Default::default()};
+    descriptor.json_name()
+}
+
+/// Writes a value indented two spaces a level, as `--format json` does.
+fn write(json: &Json, indent: usize, out: &mut String) {
+    let (open, close, items): (char, char, Vec<(Option<&str>, &Json)>) = match json {
+        Json::Null => return out.push_str("null"),
+        Json::Bool(v) => return out.push_str(&v.to_string()),
+        Json::Number(n) => return out.push_str(n),
+        Json::String(s) => return out.push_str(&json::quote(s)),
+        Json::Array(values) => ('[', ']', values.iter().map(|value| (None, value)).collect()),
+        Json::Object(fields) => ('{', '}', fields.iter().map(|(key, value)| (Some(key.as_str()), value)).collect()),
+    };
+    out.push(open);
+    for (i, (key, value)) in items.iter().enumerate() {
+        out.push_str(if i == 0 { "\n" } else { ",\n" });
+        out.push_str(&"  ".repeat(indent + 1));
+        if let Some(key) = key {
+            let _ = write!(out, "{}: ", json::quote(key));
+        }
+        write(value, indent + 1, out);
+    }
+    if !items.is_empty() {
+        out.push('\n');
+        out.push_str(&"  ".repeat(indent));
+    }
+    out.push(close);
+}
@@ -0,0 +1,70 @@
+//! protoc's `--decode_raw`, `--decode` and `--encode`, so protocat can stand in for protoc in
+//! scripts: input comes from stdin, output goes to stdout, and `.proto` files are given as
+//! arguments and found on `-I` paths.
+
+use std::io::{self, Read, Write};
+
+use crate::payload::{self, InputFormat};
+use crate::schema::Schema;
+use crate::{textformat, PrintStats};
+
+fn read_stdin() -> Result<Vec<u8>, String> {
+    let mut buffer = Vec::new();
+    io::stdin().read_to_end(&mut buffer).map_err(|err| format!("reading stdin: {}", err))?;
+    Ok(buffer)
+}
+
+fn load_schema(files: &[String], include: &[String]) -> Result<Schema, String> {
+    if files.is_empty() {
+        return Err("missing input file".to_string());
+    }
+    Schema::from_proto_files(files, include)
+}
+
+fn print_lines(out: &mut dyn Write, lines: Vec<String>) -> Result<(), String> {
+    for line in lines {
+        match writeln!(out, "{}", line) {
+            Ok(()) => {}
+            // Piped into head and the like; stop quietly.
+            Err(err) if err.kind() == io::ErrorKind::BrokenPipe => break,
+            Err(err) => return Err(err.to_string()),
+        }
+    }
+    Ok(())
+}
+
+pub fn decode_raw() -> Result<(), String> {
+    let data = read_stdin()?;
+    print_lines(&mut io::stdout().lock(), textformat::render(&data, None).ok_or("failed to parse input")?)
+}
+
+/// Prints data the way `protoc --decode_raw` would, for `--format decode-raw`.
+pub fn print_raw(out: &mut dyn Write, data: &[u8]) -> Result<PrintStats, String> {
+    // protoc refuses some inputs protocat accepts, such as ones with unbalanced groups.
+    let lines = textformat::render(data, None).ok_or("protoc --decode_raw would fail to parse this input")?;
+    // Every line but a closing brace is a field, indented two spaces a level.
+    let mut stats = PrintStats{fields: 0, max_depth: 0};
+    for line in lines.iter().filter(|line| line.trim_start() != "}") {
+        stats.fields += 1;
+        stats.max_depth = stats.max_depth.max((line.len() - line.trim_start().len()) / 2);
+    }
+    print_lines(out, lines)?;
+    Ok(stats)
+}
+
+pub fn decode(type_name: &str, files: &[String], include: &[String]) -> Result<(), String> {
+    let schema = load_schema(files, include)?;
+    let message = schema.message(type_name).ok_or_else(|| format!("type not defined: {}", type_name))?;
+    let data = read_stdin()?;
+    print_lines(&mut io::stdout().lock(), textformat::render(&data, Some((&schema, message))).ok_or("failed to parse input")?)
+}
+
+pub fn encode(type_name: &str, files: &[String], include: &[String]) -> Result<(), String> {
+    let schema = load_schema(files, include)?;
+    if schema.message(type_name).is_none() {
+        return Err(format!("type not defined: {}", type_name));
+    }
+    let text = read_stdin()?;
+    let (encoded, _) = payload::to_wire(&text, InputFormat::Text, Some((&schema, &[type_name])))?;
+    io::stdout().write_all(&encoded).map_err(|err| err.to_string())
+}
@@ -0,0 +1,242 @@
+//! protoc plugin mode.
+//!
+//! protoc runs `protoc-gen-protocat` for `--protocat_out`, writing a `CodeGeneratorRequest` to its
+//! stdin and reading a `CodeGeneratorResponse` from its stdout. protocat answers with one
+//! `.protocat.txt` report per file to generate, listing every descriptor protoc handed it.
+
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+
+use nom::AsBytes;
+
+use crate::descriptor::*;
+use crate::encode::{write_bytes, write_tag, write_varint};
+use crate::{parse_message, WireType, WireValue};
+
+// CodeGeneratorRequest field numbers.
+const REQUEST_FILE_TO_GENERATE: u64 = 1;
+const REQUEST_PARAMETER: u64 = 2;
+const REQUEST_COMPILER_VERSION: u64 = 3;
+const REQUEST_PROTO_FILE: u64 = 15;
+
+// CodeGeneratorResponse field numbers.
+const RESPONSE_ERROR: u64 = 1;
+const RESPONSE_SUPPORTED_FEATURES: u64 = 2;
+const RESPONSE_FILE: u64 = 15;
+const FILE_NAME: u64 = 1;
+const FILE_CONTENT: u64 = 15;
+
+// CodeGeneratorResponse.Feature values.
+const FEATURE_PROTO3_OPTIONAL: u64 = 1;
+
+/// Reads a request from stdin and writes the response to stdout.
+pub fn run() -> io::Result<()> {
+    let mut request = Vec::new();
+    io::stdin().lock().read_to_end(&mut request)?;
+
+    let mut response = Vec::new();
+    match respond(&request) {
+        Ok(files) => {
+            write_tag(&mut response, RESPONSE_SUPPORTED_FEATURES, WireType::VarInt);
+            write_varint(&mut response, FEATURE_PROTO3_OPTIONAL);
+            for (name, content) in files {
+                let mut file = Vec::new();
+                write_bytes(&mut file, FILE_NAME, name.as_bytes());
+                write_bytes(&mut file, FILE_CONTENT, content.as_bytes());
+                write_bytes(&mut response, RESPONSE_FILE, &file);
+            }
+        }
+        // protoc prints the error and fails the build, which is the best place to report it.
+        Err(err) => write_bytes(&mut response, RESPONSE_ERROR, err.as_bytes()),
+    }
+
+    let stdout = io::stdout();
+    let mut output = stdout.lock();
+    output.write_all(&response)?;
+    output.flush()
+}
+
+/// Returns the name and content of each report.
+fn respond(request: &[u8]) -> Result<Vec<(String, String)>, String> {
+    let fields = parse_message(request, false).map_err(|err| format!("malformed CodeGeneratorRequest: {}", err))?;
+
+    let mut to_generate = Vec::new();
+    let mut parameter = String::new();
+    let mut compiler_version = None;
+    let mut files = Vec::new();
+    for field in fields {
+        match (field.tag_number, field.value) {
+            (REQUEST_FILE_TO_GENERATE, WireValue::LengthPrefixed(d)) =>
+                to_generate.push(String::from_utf8_lossy(d.as_bytes()).into_owned()),
+            (REQUEST_PARAMETER, WireValue::LengthPrefixed(d)) =>
+                parameter = String::from_utf8_lossy(d.as_bytes()).into_owned(),
+            (REQUEST_COMPILER_VERSION, WireValue::LengthPrefixed(d)) => compiler_version = Some(version(d.as_bytes())?),
+            (REQUEST_PROTO_FILE, WireValue::LengthPrefixed(d)) => files.push(FileDescriptor::decode(d.as_bytes())?),
+            _ => {}
+        }
+    }
+
+    let mut reports = Vec::new();
+    for name in to_generate {
+        let file = files.iter().find(|file| file.name == name).ok_or_else(|| format!("no descriptor for {}", name))?;
+        let mut report = String::new();
+        if let Some(version) = &compiler_version {
+            let _ = writeln!(report, "compiler: protoc {}", version);
+        }
+        if !parameter.is_empty() {
+            let _ = writeln!(report, "parameter: {}", parameter);
+        }
+        write_file(&mut report, file);
+        reports.push((format!("{}.protocat.txt", name.trim_end_matches(".proto")), report));
+    }
+    Ok(reports)
+}
+
+/// Formats a `google.protobuf.compiler.Version` as major.minor.patch-suffix.
+fn version(data: &[u8]) -> Result<String, String> {
+    let mut parts = [0; 3];
+    let mut suffix = String::new();
+    for field in parse_message(data, false).map_err(|err| format!("malformed Version: {}", err))? {
+        match (field.tag_number, field.value) {
+            (tag @ 1..=3, WireValue::VarInt(v)) => parts[tag as usize - 1] = v,
+            (4, WireValue::LengthPrefixed(d)) => suffix = String::from_utf8_lossy(d.as_bytes()).into_owned(),
+            _ => {}
+        }
+    }
+    let mut version = format!("{}.{}.{}", parts[0], parts[1], parts[2]);
+    if !suffix.is_empty() {
+        version.push('-');
+        version.push_str(&suffix);
+    }
+    Ok(version)
+}
+
+fn write_file(out: &mut String, file: &FileDescriptor) {
+    let _ = writeln!(out, "file: {}", file.name);
+    if !file.package.is_empty() {
+        let _ = writeln!(out, "package: {}", file.package);
+    }
+    let _ = writeln!(out, "syntax: {}", if file.syntax.is_empty() { "proto2" } else { &file.syntax });
+    for dependency in file.dependencies.iter() {
+        let _ = writeln!(out, "import: {}", dependency);
+    }
+    write_options(out, 0, &file.options);
+
+    let prefix = if file.package.is_empty() { String::new() } else { format!("{}.", file.package) };
+    for message in file.messages.iter() {
+        write_message(out, 0, &prefix, message);
+    }
+    for descriptor in file.enums.iter() {
+        write_enum(out, 0, &prefix, descriptor);
+    }
+    for extension in file.extensions.iter() {
+        write_field(out, 0, extension, &[]);
+    }
+    for service in file.services.iter() {
+        let _ = writeln!(out, "\nservice {}{}", prefix, service.name);
+        write_options(out, 1, &service.options);
+        for method in service.methods.iter() {
+            let _ = writeln!(
+                out, "  rpc {}({}{}) returns ({}{})",
+                method.name,
+                if method.client_streaming { "stream " } else { "" }, method.input_type.trim_start_matches('.'),
+                if method.server_streaming { "stream " } else { "" }, method.output_type.trim_start_matches('.'),
+            );
+            write_options(out, 2, &method.options);
+        }
+    }
+}
+
+fn write_message(out: &mut String, depth: usize, prefix: &str, message: &MessageDescriptor) {
+    let indent = "  ".repeat(depth);
+    let name = format!("{}{}", prefix, message.name);
+    let _ = writeln!(out, "\n{}message {}", indent, name);
+    write_options(out, depth + 1, &message.options);
+    for field in message.fields.iter() {
+        write_field(out, depth + 1, field, &message.oneofs);
+    }
+    for extension in message.extensions.iter() {
+        write_field(out, depth + 1, extension, &[]);
+    }
+
+    let prefix = format!("{}.", name);
+    for nested in message.nested_messages.iter() {
+        write_message(out, depth + 1, &prefix, nested);
+    }
+    for descriptor in message.enums.iter() {
+        write_enum(out, depth + 1, &prefix, descriptor);
+    }
+}
+
+fn write_field(out: &mut String, depth: usize, field: &FieldDescriptor, oneofs: &[String]) {
+    let label = match field.label {
+        LABEL_REQUIRED => "required ",
+        LABEL_REPEATED => "repeated ",
+        LABEL_OPTIONAL if field.proto3_optional => "optional ",
+        _ => "",
+    };
+    let _ = write!(out, "{}{}{} {} = {}", "  ".repeat(depth), label, field.type_name(), field.name, field.number);
+    if !field.extendee.is_empty() {
+        let _ = write!(out, " (extends {})", field.extendee.trim_start_matches('.'));
+    }
+    if let Some(oneof) = field.oneof_index.and_then(|i| oneofs.get(i as usize)).filter(|_| !field.proto3_optional) {
+        let _ = write!(out, " (oneof {})", oneof);
+    }
+    if let Some(default) = &field.default_value {
+        let _ = write!(out, " (default {:?})", default);
+    }
+    if let Some(json_name) = &field.json_name {
+        let _ = write!(out, " (json {})", json_name);
+    }
+    out.push('\n');
+    write_options(out, depth + 1, &field.options);
+}
+
+fn write_enum(out: &mut String, depth: usize, prefix: &str, descriptor: &EnumDescriptor) {
+    let indent = "  ".repeat(depth);
+    let _ = writeln!(out, "\n{}enum {}{}", indent, prefix, descriptor.name);
+    write_options(out, depth + 1, &descriptor.options);
+    for (name, number) in descriptor.values.iter() {
+        let _ = writeln!(out, "{}  {} = {}", indent, name, number);
+    }
+}
+
+/// Writes options messages as a raw decode, since custom options can't be named without their
+/// definitions.
+fn write_options(out: &mut String, depth: usize, options: &[u8]) {
+    if !options.is_empty() {
+        let _ = writeln!(out, "{}options: {}", "  ".repeat(depth), compact(options));
+    }
+}
+
+/// Formats a message on a single line, as `{tag: value, ...}`.
+fn compact(data: &[u8]) -> String {
+    let fields = match parse_message(data, false) {
+        Ok(fields) => fields,
+        Err(_) => return hex(data),
+    };
+    let mut out = String::from("{");
+    for (i, field) in fields.into_iter().enumerate() {
+        if i > 0 {
+            out.push_str(", ");
+        }
+        let _ = write!(out, "{}: ", field.tag_number);
+        match field.value {
+            WireValue::VarInt(v) | WireValue::Int64(v) => { let _ = write!(out, "{}", v); }
+            WireValue::Int32(v) => { let _ = write!(out, "{}", v); }
+            WireValue::LengthPrefixed(d) => match std::str::from_utf8(d.as_bytes()) {
+                Ok(s) if !s.chars().any(char::is_control) => { let _ = write!(out, "{:?}", s); }
+                _ if parse_message(d.as_bytes(), false).is_ok() => out.push_str(&compact(d.as_bytes())),
+                _ => out.push_str(&hex(d.as_bytes())),
+            },
+            WireValue::StartGroup => out.push_str("group"),
+            WireValue::EndGroup => out.push_str("end group"),
+        }
+    }
+    out.push('}');
+    out
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,167 @@
+//! `--format protoscope`: output in the syntax of the protoscope tool, so that a decode can be edited
+//! and assembled back into bytes with it.
+//!
+//! Fields are written the way protoscope infers their wire types, as `1: 150`, `2: {"text"}`,
+//! `3: {...}`, `4: 5i64` and `5: 5i32`, with group boundaries as explicit `SGROUP` and `EGROUP` tags.
+//! Anything whose bytes that syntax wouldn't reproduce, such as a padded varint, bytes that aren't
+//! UTF-8 or a field a hook replaced, is written as a hex literal of the whole field instead, with a
+//! comment saying what it is.
+
+use std::io::Write;
+
+use nom::{AsBytes, InputLength};
+
+use crate::hook::{FieldHook, HookAction};
+use crate::text::StringEncoding;
+use crate::warning::Warnings;
+use crate::{alternates, check_field_number, check_overlong, interpret, plural, print_indent, run_hooks, Interpretation, Options, PrintStats, ProtoField, Span, WireType, WireValue};
+
+/// The number of bytes the minimal encoding of a varint takes.
+fn varint_len(value: u64) -> usize {
+    (64 - value.leading_zeros() as usize).div_ceil(7).max(1)
+}
+
+/// Whether the field is encoded in as few bytes as it can be, which is what protoscope produces.
+fn is_minimal(field: &ProtoField<Span>) -> bool {
+    let tag_len = varint_len(field.tag_number << 3);
+    let len = match field.value {
+        WireValue::VarInt(v) => tag_len + varint_len(v),
+        WireValue::Int64(_) => tag_len + 8,
+        WireValue::Int32(_) => tag_len + 4,
+        WireValue::LengthPrefixed(d) => tag_len + varint_len(d.input_len() as u64) + d.input_len(),
+        WireValue::StartGroup | WireValue::EndGroup => tag_len,
+    };
+    field.raw.input_len() == len
+}
+
+fn hex(data: &[u8]) -> String {
+    let digits: String = data.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("`{}`", digits)
+}
+
+/// Quotes a string in protoscope's syntax, escaping quotes, backslashes and control characters.
+fn quote(str: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in str.chars() {
+        match c {
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if (c as u32) < 0x20 || c == '\u{7f}' => quoted.push_str(&format!("\\x{:02x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Prints bytes that `--lenient` skipped as a hex literal, so they are still assembled back.
+pub fn print_skipped(out: &mut dyn Write, skipped: Span) {
+    outln!(out, "{} # unparseable, at offset {}", hex(skipped.as_bytes()), skipped.location_offset());
+}
+
+/// Prints a message in protoscope syntax, walking submessages with an explicit stack like
+/// `print_message` does. Fields between a group's start and end are indented beneath it.
+pub fn print(out: &mut dyn Write, fields: Vec<ProtoField<Span>>, options: &Options, hooks: &[Box<dyn FieldHook>], warnings: &mut Warnings) -> PrintStats {
+    let mut stack = vec![(fields.into_iter(), 0usize)];
+    let mut path = Vec::new();
+    let mut stats = PrintStats{fields: 0, max_depth: 0};
+
+    while !stack.is_empty() {
+        let depth = stack.len() - 1;
+        let (level, groups) = &mut stack[depth];
+        let field = match level.next() {
+            Some(field) => field,
+            None => {
+                stack.pop();
+                path.pop();
+                if !stack.is_empty() {
+                    let (_, groups) = stack[stack.len() - 1];
+                    print_indent(out, stack.len() - 1 + groups);
+                    outln!(out, "}}");
+                }
+                continue;
+            }
+        };
+        if let WireValue::EndGroup = field.value {
+            *groups = groups.saturating_sub(1);
+        }
+        let indent = depth + *groups;
+        stats.fields += 1;
+        stats.max_depth = stats.max_depth.max(depth);
+        path.push(field.tag_number);
+        check_field_number(field.tag_number, &path, warnings);
+        check_overlong(&field, &path, options, warnings);
+
+        match run_hooks(hooks, &path, &field) {
+            HookAction::Default => {}
+            HookAction::Replace(value) => {
+                print_indent(out, indent);
+                outln!(out, "{} # {}: {}", hex(field.raw.as_bytes()), field.tag_number, value);
+                path.pop();
+                continue;
+            }
+            HookAction::Skip => {
+                path.pop();
+                continue;
+            }
+        }
+        print_indent(out, indent);
+        if !is_minimal(&field) {
+            outln!(out, "{} # field {}, with a padded varint", hex(field.raw.as_bytes()), field.tag_number);
+            path.pop();
+            continue;
+        }
+
+        match field.value {
+            WireValue::VarInt(v) => outln!(out, "{}: {}", field.tag_number, v),
+            // Protoscope assembles finite floats with these suffixes into the same bits.
+            WireValue::Int64(v) => match alternates::double(v, options.floats).filter(|_| f64::from_bits(v).is_finite()) {
+                Some(double) => outln!(out, "{}: {}i64", field.tag_number, double),
+                None => outln!(out, "{}: {}i64", field.tag_number, v),
+            },
+            WireValue::Int32(v) => match alternates::float(v, options.floats).filter(|_| f32::from_bits(v).is_finite()) {
+                Some(float) => outln!(out, "{}: {}i32", field.tag_number, float),
+                None => outln!(out, "{}: {}i32", field.tag_number, v),
+            },
+            WireValue::StartGroup => {
+                outln!(out, "{}:SGROUP", field.tag_number);
+                *groups += 1;
+            }
+            WireValue::EndGroup => outln!(out, "{}:EGROUP", field.tag_number),
+            WireValue::LengthPrefixed(d) => match interpret(d, options, &path, warnings) {
+                Interpretation::Message(fields) if fields.is_empty() => outln!(out, "{}: {{}}", field.tag_number),
+                Interpretation::Message(fields) => {
+                    outln!(out, "{}: {{", field.tag_number);
+                    stack.push((fields.into_iter(), 0));
+                    continue;
+                }
+                Interpretation::String(str, StringEncoding::Utf8) => outln!(out, "{}: {{{}}}", field.tag_number, quote(&str)),
+                // Protoscope strings are UTF-8, so text in other encodings keeps its bytes.
+                Interpretation::String(str, encoding) => {
+                    outln!(out, "{}: {{{}}} # {} {}", field.tag_number, hex(d.as_bytes()), encoding.name(), quote(&str));
+                }
+                Interpretation::LossyString(str, invalid) => {
+                    outln!(out, 
+                        "{}: {{{}}} # {} with {} invalid UTF-8 sequence{}",
+                        field.tag_number, hex(d.as_bytes()), quote(&str), invalid, plural(invalid),
+                    );
+                }
+                Interpretation::Packed(values, wire_type) => {
+                    let suffix = match wire_type {
+                        WireType::Int64 => "i64",
+                        WireType::Int32 => "i32",
+                        _ => "",
+                    };
+                    let values: Vec<String> = values.iter().map(|v| format!("{}{}", v, suffix)).collect();
+                    outln!(out, "{}: {{{}}}", field.tag_number, values.join(" "));
+                }
+                Interpretation::Bytes => outln!(out, "{}: {{{}}}", field.tag_number, hex(d.as_bytes())),
+            },
+        }
+        path.pop();
+    }
+    stats
+}
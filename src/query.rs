@@ -0,0 +1,553 @@
+//! `--query`: a small jq-like language for picking values out of a decoded message, such as
+//! `.2[] | select(.1 == 5) | .3`.
+//!
+//! Queries see a message the way `--format json` shows it: `.N` is the value of field N, or an
+//! array of its values if it occurs more than once, or null if it doesn't occur at all. Since
+//! whether a field repeats is only known from the data, `[]` and `[i]` treat a lone value as an
+//! array of one. Length-delimited values get the usual heuristics, so submessages can be descended
+//! into. Looking up a field of anything but a message gives null rather than failing, so that a
+//! query can be run over inputs of different shapes.
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::Write;
+use std::ops::Range;
+use std::rc::Rc;
+
+use crate::warning::{WarningKind, Warnings};
+use crate::{encoded_bytes, interpret, json, json_output, print_message_at, text, Cursor, FieldList, Interpretation, Options, ProtoField, Span, WireValue};
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Dot,
+    Number(i128),
+    Str(String),
+    Word(String),
+    Op(&'static str),
+    Open(char),
+    Close(char),
+    Pipe,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Dot => f.write_str("'.'"),
+            Token::Number(number) => write!(f, "{}", number),
+            Token::Str(string) => write!(f, "{}", text::quote(string)),
+            Token::Word(word) => write!(f, "'{}'", word),
+            Token::Op(op) => write!(f, "'{}'", op),
+            Token::Open(c) | Token::Close(c) => write!(f, "'{}'", c),
+            Token::Pipe => f.write_str("'|'"),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '.' => {
+                chars.next();
+                tokens.push(Token::Dot);
+            }
+            '|' => {
+                chars.next();
+                tokens.push(Token::Pipe);
+            }
+            '(' | '[' => {
+                chars.next();
+                tokens.push(Token::Open(c));
+            }
+            ')' | ']' => {
+                chars.next();
+                tokens.push(Token::Close(c));
+            }
+            '0'..='9' | '-' => {
+                let mut digits = String::new();
+                digits.push(c);
+                chars.next();
+                while let Some(&c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+                    digits.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Number(digits.parse().map_err(|_| format!("invalid number '{}'", digits))?));
+            }
+            '"' => {
+                chars.next();
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => string.push('\n'),
+                            Some('t') => string.push('\t'),
+                            Some('r') => string.push('\r'),
+                            Some(c @ ('"' | '\\')) => string.push(c),
+                            _ => return Err("invalid escape in string".to_string()),
+                        },
+                        Some(c) => string.push(c),
+                        None => return Err("unterminated string".to_string()),
+                    }
+                }
+                tokens.push(Token::Str(string));
+            }
+            '=' | '!' | '<' | '>' => {
+                chars.next();
+                let equals = chars.peek() == Some(&'=');
+                if equals {
+                    chars.next();
+                }
+                tokens.push(Token::Op(match (c, equals) {
+                    ('=', true) => "==",
+                    ('!', true) => "!=",
+                    ('<', true) => "<=",
+                    ('>', true) => ">=",
+                    ('<', false) => "<",
+                    ('>', false) => ">",
+                    _ => return Err(format!("unexpected '{}'", c)),
+                }));
+            }
+            c if c.is_ascii_alphabetic() => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek().filter(|c| c.is_ascii_alphanumeric() || **c == '_') {
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+            c => return Err(format!("unexpected '{}'", c)),
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Identity,
+    Field(Box<Expr>, u64),
+    Iterate(Box<Expr>),
+    Index(Box<Expr>, i128),
+    Pipe(Box<Expr>, Box<Expr>),
+    Compare(Box<Expr>, &'static str, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Select(Box<Expr>),
+    Length(Box<Expr>),
+    Literal(Value<'static>),
+}
+
+/// A parsed query.
+pub struct Query {
+    expr: Expr,
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(format!("expected {}, found {}", expected, token)),
+            None => Err(format!("expected {} at the end of the query", expected)),
+        }
+    }
+
+    fn pipe(&mut self) -> Result<Expr, String> {
+        let mut expr = self.or()?;
+        while self.peek() == Some(&Token::Pipe) {
+            self.next();
+            expr = Expr::Pipe(Box::new(expr), Box::new(self.or()?));
+        }
+        Ok(expr)
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.peek() == Some(&Token::Word("or".to_string())) {
+            self.next();
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.compare()?;
+        while self.peek() == Some(&Token::Word("and".to_string())) {
+            self.next();
+            expr = Expr::And(Box::new(expr), Box::new(self.compare()?));
+        }
+        Ok(expr)
+    }
+
+    fn compare(&mut self) -> Result<Expr, String> {
+        let expr = self.term()?;
+        match self.peek() {
+            Some(&Token::Op(op)) => {
+                self.next();
+                Ok(Expr::Compare(Box::new(expr), op, Box::new(self.term()?)))
+            }
+            _ => Ok(expr),
+        }
+    }
+
+    fn term(&mut self) -> Result<Expr, String> {
+        let expr = match self.next() {
+            Some(Token::Dot) => match self.peek() {
+                Some(&Token::Number(number)) => {
+                    self.next();
+                    Expr::Field(Box::new(Expr::Identity), field_number(number)?)
+                }
+                _ => Expr::Identity,
+            },
+            Some(Token::Number(number)) => Expr::Literal(Value::Int(number)),
+            Some(Token::Str(string)) => Expr::Literal(Value::Str(string)),
+            Some(Token::Open('(')) => {
+                let expr = self.pipe()?;
+                self.expect(Token::Close(')'))?;
+                expr
+            }
+            Some(Token::Word(word)) => match word.as_str() {
+                "true" => Expr::Literal(Value::Bool(true)),
+                "false" => Expr::Literal(Value::Bool(false)),
+                "null" => Expr::Literal(Value::Null),
+                "length" => Expr::Length(Box::new(Expr::Identity)),
+                "not" => Expr::Not(Box::new(Expr::Identity)),
+                "select" => {
+                    self.expect(Token::Open('('))?;
+                    let condition = self.pipe()?;
+                    self.expect(Token::Close(')'))?;
+                    Expr::Select(Box::new(condition))
+                }
+                _ => return Err(format!("unknown function '{}'", word)),
+            },
+            Some(token) => return Err(format!("unexpected {}", token)),
+            None => return Err("unexpected end of the query".to_string()),
+        };
+        self.suffixes(expr)
+    }
+
+    /// Parses the field lookups, iterations and indexes after a term, as in `.2[].3[0]`.
+    fn suffixes(&mut self, mut expr: Expr) -> Result<Expr, String> {
+        loop {
+            match (self.peek(), self.tokens.get(self.pos + 1)) {
+                (Some(Token::Dot), Some(&Token::Number(number))) => {
+                    self.pos += 2;
+                    expr = Expr::Field(Box::new(expr), field_number(number)?);
+                }
+                (Some(Token::Open('[')), Some(Token::Close(']'))) => {
+                    self.pos += 2;
+                    expr = Expr::Iterate(Box::new(expr));
+                }
+                (Some(Token::Open('[')), Some(&Token::Number(index))) => {
+                    self.pos += 2;
+                    self.expect(Token::Close(']'))?;
+                    expr = Expr::Index(Box::new(expr), index);
+                }
+                _ => return Ok(expr),
+            }
+        }
+    }
+}
+
+fn field_number(number: i128) -> Result<u64, String> {
+    u64::try_from(number).map_err(|_| format!("invalid field number {}", number))
+}
+
+impl Query {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut parser = Parser{tokens: tokenize(source)?, pos: 0};
+        let expr = parser.pipe()?;
+        match parser.peek() {
+            None => Ok(Query{expr}),
+            Some(token) => Err(format!("unexpected {}", token)),
+        }
+    }
+
+    /// Runs the query over a message, printing each result: as JSON, one per line, with `--format
+    /// json`, and otherwise as protocat prints values, with messages in braces and arrays an element
+    /// at a time.
+    pub fn run(&self, out: &mut dyn Write, fields: Vec<ProtoField<Span>>, options: &Options, warnings: &mut Warnings) {
+        let mut cx = Context{options, warnings};
+        for value in eval(&self.expr, &Value::Message(Message::new(fields, Vec::new())), &mut cx) {
+            if options.format == crate::OutputFormat::Json {
+                let mut json = String::new();
+                to_json(&value, &mut json, &mut cx);
+                outln!(out, "{}", json);
+            } else {
+                print_text(out, value, &mut cx);
+            }
+        }
+    }
+}
+
+/// A value a query works on.
+#[derive(Clone, Debug)]
+enum Value<'a> {
+    Null,
+    Bool(bool),
+    Int(i128),
+    Str(String),
+    Bytes(Cow<'a, [u8]>),
+    Message(Message<'a>),
+    Array(Vec<Value<'a>>),
+}
+
+/// A message a query has reached: a run of fields of a list, which its groups share, and the path
+/// of field numbers it's at.
+#[derive(Clone, Debug)]
+struct Message<'a> {
+    list: Rc<FieldList<'a>>,
+    fields: Range<usize>,
+    path: Vec<u64>,
+}
+
+impl<'a> Message<'a> {
+    fn new(fields: Vec<ProtoField<Span<'a>>>, path: Vec<u64>) -> Self {
+        let list = FieldList::new(fields);
+        let len = list.fields.len();
+        Message{list, fields: 0..len, path}
+    }
+
+    /// The bytes the message was read from.
+    fn encoded(&self) -> Vec<u8> {
+        encoded_bytes(&self.list.fields[self.fields.clone()])
+    }
+}
+
+struct Context<'o, 'w> {
+    options: &'o Options,
+    warnings: &'w mut Warnings,
+}
+
+/// A message's fields grouped by number, in order of first occurrence, or just those numbered
+/// `only`. Length-delimited values are only interpreted once they're picked, at their path, and
+/// groups nested deeper than --max-depth are raw data, as they're printed.
+fn fields_by_number<'a>(message: &Message<'a>, only: Option<u64>, cx: &mut Context) -> Vec<(u64, Vec<Value<'a>>)> {
+    let mut numbers: Vec<(u64, Vec<Value>)> = Vec::new();
+    let mut level = Cursor::new(message.list.clone(), message.fields.clone());
+    let mut path = message.path.clone();
+    while let Some(index) = level.next() {
+        let field = level.list.fields[index];
+        if field.value == WireValue::EndGroup || only.is_some_and(|number| number != field.tag_number) {
+            continue;
+        }
+        path.push(field.tag_number);
+        let value = match field.value {
+            WireValue::VarInt(v) | WireValue::Int64(v) => Value::Int(v as i128),
+            WireValue::Int32(v) => Value::Int(v as i128),
+            WireValue::StartGroup => {
+                let (content, _) = level.list.group(index);
+                if path.len() > cx.options.max_depth {
+                    let message = format!("nested deeper than --max-depth {}, shown as raw data", cx.options.max_depth);
+                    cx.warnings.emit(WarningKind::DepthLimit, &path, &message);
+                    Value::Bytes(Cow::Owned(encoded_bytes(&level.list.fields[content])))
+                } else {
+                    Value::Message(Message{list: level.list.clone(), fields: content, path: path.clone()})
+                }
+            }
+            WireValue::EndGroup => unreachable!("ends of groups are skipped"),
+            WireValue::LengthPrefixed(d) => match interpret(d, cx.options, &path, cx.warnings) {
+                Interpretation::Message(fields) => Value::Message(Message::new(fields, path.clone())),
+                Interpretation::String(str, _) | Interpretation::LossyString(str, _) => Value::Str(str),
+                Interpretation::Packed(values, _) => Value::Array(values.into_iter().map(|v| Value::Int(v as i128)).collect()),
+                Interpretation::Bytes => Value::Bytes(Cow::Borrowed(d.fragment())),
+            },
+        };
+        path.pop();
+        match numbers.iter_mut().find(|(number, _)| *number == field.tag_number) {
+            Some((_, values)) => values.push(value),
+            None => numbers.push((field.tag_number, vec![value])),
+        }
+    }
+    numbers
+}
+
+/// A field's values as one value: null if there are none, the value if there's one and an array
+/// otherwise.
+fn collapse(mut values: Vec<Value>) -> Value {
+    match values.len() {
+        0 => Value::Null,
+        1 => values.pop().expect("there is one value"),
+        _ => Value::Array(values),
+    }
+}
+
+/// The elements of an array, or a lone value as an array of one.
+fn elements(value: Value) -> Vec<Value> {
+    match value {
+        Value::Null => Vec::new(),
+        Value::Array(values) => values,
+        value => vec![value],
+    }
+}
+
+fn truthy(value: &Value) -> bool {
+    !matches!(value, Value::Null | Value::Bool(false))
+}
+
+fn compare(left: &Value, right: &Value) -> Option<Ordering> {
+    match (left, right) {
+        (Value::Null, Value::Null) => Some(Ordering::Equal),
+        (Value::Bool(l), Value::Bool(r)) => Some(l.cmp(r)),
+        (Value::Int(l), Value::Int(r)) => Some(l.cmp(r)),
+        (Value::Str(l), Value::Str(r)) => Some(l.cmp(r)),
+        (Value::Bytes(l), Value::Bytes(r)) => Some(l.cmp(r)),
+        (Value::Bytes(l), Value::Str(r)) => Some(l.as_ref().cmp(r.as_bytes())),
+        (Value::Str(l), Value::Bytes(r)) => Some(l.as_bytes().cmp(r)),
+        (Value::Message(l), Value::Message(r)) => (l.encoded() == r.encoded()).then_some(Ordering::Equal),
+        (Value::Array(l), Value::Array(r)) if l.len() == r.len() => {
+            l.iter().zip(r).all(|(l, r)| compare(l, r) == Some(Ordering::Equal)).then_some(Ordering::Equal)
+        }
+        _ => None,
+    }
+}
+
+fn eval<'a>(expr: &Expr, input: &Value<'a>, cx: &mut Context) -> Vec<Value<'a>> {
+    match expr {
+        Expr::Identity => vec![input.clone()],
+        Expr::Literal(value) => vec![match value {
+            Value::Null => Value::Null,
+            Value::Bool(b) => Value::Bool(*b),
+            Value::Int(v) => Value::Int(*v),
+            Value::Str(s) => Value::Str(s.clone()),
+            _ => unreachable!("only scalars are written as literals"),
+        }],
+        Expr::Field(inner, number) => eval(inner, input, cx).into_iter().map(|value| match value {
+            Value::Message(message) => {
+                let values = fields_by_number(&message, Some(*number), cx).pop().map_or(Vec::new(), |(_, values)| values);
+                collapse(values)
+            }
+            _ => Value::Null,
+        }).collect(),
+        Expr::Iterate(inner) => eval(inner, input, cx).into_iter().flat_map(elements).collect(),
+        Expr::Index(inner, index) => eval(inner, input, cx).into_iter().map(|value| {
+            let mut values = elements(value);
+            let index = if *index < 0 { values.len() as i128 + index } else { *index };
+            usize::try_from(index).ok().filter(|&index| index < values.len()).map_or(Value::Null, |index| values.swap_remove(index))
+        }).collect(),
+        Expr::Pipe(left, right) => eval(left, input, cx).iter().flat_map(|value| eval(right, value, cx)).collect(),
+        Expr::Compare(left, op, right) => {
+            let (lefts, rights) = (eval(left, input, cx), eval(right, input, cx));
+            let mut results = Vec::new();
+            for l in &lefts {
+                for r in &rights {
+                    let ordering = compare(l, r);
+                    results.push(Value::Bool(match *op {
+                        "==" => ordering == Some(Ordering::Equal),
+                        "!=" => ordering != Some(Ordering::Equal),
+                        "<" => ordering == Some(Ordering::Less),
+                        "<=" => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+                        ">" => ordering == Some(Ordering::Greater),
+                        _ => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+                    }));
+                }
+            }
+            results
+        }
+        Expr::And(left, right) => {
+            let lefts = eval(left, input, cx);
+            lefts.iter().flat_map(|l| if truthy(l) {
+                eval(right, input, cx).iter().map(|r| Value::Bool(truthy(r))).collect()
+            } else {
+                vec![Value::Bool(false)]
+            }).collect()
+        }
+        Expr::Or(left, right) => {
+            let lefts = eval(left, input, cx);
+            lefts.iter().flat_map(|l| if truthy(l) {
+                vec![Value::Bool(true)]
+            } else {
+                eval(right, input, cx).iter().map(|r| Value::Bool(truthy(r))).collect()
+            }).collect()
+        }
+        Expr::Not(inner) => eval(inner, input, cx).iter().map(|value| Value::Bool(!truthy(value))).collect(),
+        Expr::Select(condition) => {
+            let matches = eval(condition, input, cx).iter().filter(|value| truthy(value)).count();
+            vec![input.clone(); matches]
+        }
+        Expr::Length(inner) => eval(inner, input, cx).into_iter().map(|value| Value::Int(match value {
+            Value::Null => 0,
+            Value::Bool(_) | Value::Int(_) => 1,
+            Value::Str(s) => s.chars().count() as i128,
+            Value::Bytes(b) => b.len() as i128,
+            Value::Message(message) => (message.fields.end - message.fields.start) as i128,
+            Value::Array(values) => values.len() as i128,
+        })).collect(),
+    }
+}
+
+fn to_json(value: &Value, out: &mut String, cx: &mut Context) {
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(b) => out.push_str(&b.to_string()),
+        Value::Int(v) => match u64::try_from(*v) {
+            Ok(v) => out.push_str(&json_output::number(v)),
+            Err(_) => out.push_str(&v.to_string()),
+        },
+        Value::Str(s) => out.push_str(&json::quote(s)),
+        Value::Bytes(b) => out.push_str(&cx.options.bytes_format.json(b)),
+        Value::Message(message) => {
+            out.push('{');
+            for (i, (number, values)) in fields_by_number(message, None, cx).into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&format!("\"{}\":", number));
+                to_json(&collapse(values), out, cx);
+            }
+            out.push('}');
+        }
+        Value::Array(values) => {
+            out.push('[');
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                to_json(value, out, cx);
+            }
+            out.push(']');
+        }
+    }
+}
+
+fn print_text(out: &mut dyn Write, value: Value, cx: &mut Context) {
+    match value {
+        Value::Null => outln!(out, "null"),
+        Value::Bool(b) => outln!(out, "{}", b),
+        Value::Int(v) => outln!(out, "{}", v),
+        Value::Str(s) => outln!(out, "{}", text::quote(&s)),
+        Value::Bytes(b) => outln!(out, "{}", cx.options.bytes_format.format(&b)),
+        Value::Message(message) => {
+            outln!(out, "{{");
+            let fields = message.list.fields[message.fields.clone()].to_vec();
+            print_message_at(out, fields, 1, &message.path, cx.options, &[], cx.warnings);
+            outln!(out, "}}");
+        }
+        Value::Array(values) => {
+            for value in values {
+                print_text(out, value, cx);
+            }
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! Interactive prompt for poking at a loaded message.
+
+use std::io::{self, BufRead, Write};
+
+use nom::combinator::all_consuming;
+use nom::AsBytes;
+
+use crate::open::open_with;
+use crate::path::{parse_path, select};
+use crate::warning::Warnings;
+use crate::{print_message, protobuf, text, Error, Options, ProtoField, Span, WireValue};
+
+const HELP: &str = "\
+commands:
+  get PATH        print the field(s) at PATH, e.g. get 1.2
+  hex PATH        hexdump the bytes of the field(s) at PATH
+  as PATH TYPE    reinterpret the field(s) at PATH as TYPE, one of string, bytes, message, uint64,
+                  int64, sint64, uint32, int32, sint32, bool, fixed32, sfixed32, float, fixed64,
+                  sfixed64 or double
+  open PATH       write the bytes of the field(s) at PATH to a temporary file and open it with
+                  the --open-with viewer
+  stats           summarize the whole message
+  help            show this help
+  quit            leave the prompt";
+
+pub fn run(name: &str, buffer: &[u8], fields: Vec<ProtoField<Span>>, options: &Options) -> io::Result<()> {
+    let mut warnings = Warnings::new(options.warnings_json);
+    warnings.set_input(name);
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("> ");
+        io::stdout().flush()?;
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => return Ok(()),
+        };
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let result = match words.as_slice() {
+            [] => Ok(()),
+            ["get", path] => with_path(&fields, path, |matches| {
+                print_message(&mut io::stdout(), matches, options, &[], &mut warnings);
+                Ok(())
+            }),
+            ["hex", path] => with_path(&fields, path, |matches| {
+                matches.iter().for_each(hexdump);
+                Ok(())
+            }),
+            ["as", path, ty] => with_path(&fields, path, |matches| {
+                for field in matches.iter() {
+                    reinterpret(field, ty, options, &mut warnings)?;
+                }
+                Ok(())
+            }),
+            ["open", path] => with_path(&fields, path, |matches| {
+                let command = options.open_with.as_deref().ok_or("no viewer given, pass --open-with or set PROTOCAT_OPEN_WITH")?;
+                open_with(command, name, path, &matches)
+            }),
+            ["stats"] => {
+                stats(buffer, &fields);
+                Ok(())
+            }
+            ["help"] => {
+                println!("{}", HELP);
+                Ok(())
+            }
+            ["quit"] | ["exit"] => return Ok(()),
+            _ => Err(format!("unrecognized command '{}', try help", line.trim())),
+        };
+        if let Err(err) = result {
+            println!("error: {}", err);
+        }
+    }
+}
+
+fn with_path<'a, F>(fields: &[ProtoField<Span<'a>>], path: &str, f: F) -> Result<(), String>
+where
+    F: FnOnce(Vec<ProtoField<Span<'a>>>) -> Result<(), String>,
+{
+    let matches = select(fields, &parse_path(path)?);
+    if matches.is_empty() {
+        return Err(format!("no field at {}", path));
+    }
+    f(matches)
+}
+
+fn hexdump(field: &ProtoField<Span>) {
+    let (data, offset) = field.value_bytes();
+    let base = offset.unwrap_or(0);
+    for (i, row) in data.chunks(16).enumerate() {
+        let hex: Vec<String> = row.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = row.iter().map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' }).collect();
+        println!("{:08x}: {:<47}  {}", base + i * 16, hex.join(" "), ascii);
+    }
+}
+
+fn reinterpret(field: &ProtoField<Span>, ty: &str, options: &Options, warnings: &mut Warnings) -> Result<(), String> {
+    let tag = field.tag_number;
+    match (ty, field.value) {
+        ("string", WireValue::LengthPrefixed(d)) => println!("{}: {}", tag, text::quote(&String::from_utf8_lossy(d.as_bytes()))),
+        ("bytes", WireValue::LengthPrefixed(d)) => println!("{}: {}", tag, options.bytes_format.format(d.as_bytes())),
+        ("message", WireValue::LengthPrefixed(d)) => match all_consuming(protobuf::<_, Error<_>>(false))(d) {
+            Ok((_, fields)) => {
+                print_message(&mut io::stdout(), fields, options, &[], warnings);
+            }
+            Err(_) => return Err(format!("field {} is not a valid message", tag)),
+        },
+        ("uint64", WireValue::VarInt(v)) => println!("{}: {}", tag, v),
+        ("int64", WireValue::VarInt(v)) => println!("{}: {}", tag, v as i64),
+        ("sint64", WireValue::VarInt(v)) => println!("{}: {}", tag, (v >> 1) as i64 ^ -((v & 1) as i64)),
+        ("uint32", WireValue::VarInt(v)) => println!("{}: {}", tag, v as u32),
+        ("int32", WireValue::VarInt(v)) => println!("{}: {}", tag, v as i32),
+        ("sint32", WireValue::VarInt(v)) => println!("{}: {}", tag, (v as u32 >> 1) as i32 ^ -((v & 1) as i32)),
+        ("bool", WireValue::VarInt(v)) => println!("{}: {}", tag, v != 0),
+        ("fixed32", WireValue::Int32(v)) => println!("{}: {}", tag, v),
+        ("sfixed32", WireValue::Int32(v)) => println!("{}: {}", tag, v as i32),
+        ("float", WireValue::Int32(v)) => println!("{}: {}", tag, f32::from_bits(v)),
+        ("fixed64", WireValue::Int64(v)) => println!("{}: {}", tag, v),
+        ("sfixed64", WireValue::Int64(v)) => println!("{}: {}", tag, v as i64),
+        ("double", WireValue::Int64(v)) => println!("{}: {}", tag, f64::from_bits(v)),
+        (ty, value) => {
+            let wire = match value {
+                WireValue::VarInt(_) => "a varint",
+                WireValue::Int64(_) => "a 64-bit fixed value",
+                WireValue::LengthPrefixed(_) => "length-prefixed",
+                WireValue::StartGroup | WireValue::EndGroup => "a group tag",
+                WireValue::Int32(_) => "a 32-bit fixed value",
+            };
+            return Err(format!("field {} is {}, which can't be read as {}", tag, wire, ty));
+        }
+    }
+    Ok(())
+}
+
+/// Prints a summary of the message, descending into every field that parses as a submessage.
+fn stats(buffer: &[u8], fields: &[ProtoField<Span>]) {
+    let mut counts = [0usize; 5];
+    let mut messages = 1;
+    let mut max_depth = 0;
+    let mut stack = vec![(fields.to_vec(), 0)];
+    while let Some((fields, depth)) = stack.pop() {
+        max_depth = max_depth.max(depth);
+        for field in fields {
+            match field.value {
+                WireValue::VarInt(_) => counts[0] += 1,
+                WireValue::Int64(_) => counts[1] += 1,
+                WireValue::LengthPrefixed(d) => {
+                    counts[2] += 1;
+                    if let Ok((_, fields)) = all_consuming(protobuf::<_, Error<_>>(false))(d) {
+                        messages += 1;
+                        stack.push((fields, depth + 1));
+                    }
+                }
+                WireValue::StartGroup | WireValue::EndGroup => counts[3] += 1,
+                WireValue::Int32(_) => counts[4] += 1,
+            }
+        }
+    }
+    println!("bytes: {}", buffer.len());
+    println!("fields: {}", counts.iter().sum::<usize>());
+    println!("messages: {}", messages);
+    println!("max depth: {}", max_depth);
+    println!("varint: {}, fixed64: {}, length-prefixed: {}, group tags: {}, fixed32: {}", counts[0], counts[1], counts[2], counts[3], counts[4]);
+}
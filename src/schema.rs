@@ -0,0 +1,419 @@
+//! Schema-aware decoding: message types from descriptors, used to name fields and decode values by
+//! their declared types rather than by guessing.
+
+use std::collections::HashMap;
+use std::io::Write;
+
+use nom::combinator::{all_consuming, complete, map};
+use nom::multi::many0;
+use nom::number::complete::{le_u32, le_u64};
+use nom::{AsBytes, InputLength};
+
+use crate::descriptor::*;
+use crate::color::Style;
+use crate::hook::{FieldHook, HookAction};
+use crate::warning::Warnings;
+use crate::{
+    annotation, base128_vlq, color, check_group_end, elided, parse_message, print_indent, print_message_indented, proto, protobuf, run_hooks, size_note, text, units, wkt, Error,
+    Cursor, Options, PrintStats, ProtoField, Span, WireType, WireValue,
+};
+
+/// Message and enum types by fully qualified name, without the leading dot.
+#[derive(Default)]
+pub struct Schema {
+    messages: HashMap<String, MessageDescriptor>,
+    /// The names of `messages` in the order they were declared, so that picking one doesn't depend
+    /// on how the map happens to be laid out.
+    message_order: Vec<String>,
+    enums: HashMap<String, EnumDescriptor>,
+}
+
+impl Schema {
+    pub fn add_file(&mut self, file: FileDescriptor) {
+        let prefix = if file.package.is_empty() { String::new() } else { format!("{}.", file.package) };
+        // Repeated scalars are packed by default from proto3 on.
+        let packed = file.syntax == "proto3" || file.syntax == "editions";
+        // A stack, reversed so that types come off it in the order they're declared, each followed by
+        // the ones nested in it.
+        let mut pending: Vec<(String, MessageDescriptor)> = file.messages.into_iter().rev().map(|m| (prefix.clone(), m)).collect();
+        for descriptor in file.enums {
+            self.enums.insert(format!("{}{}", prefix, descriptor.name), descriptor);
+        }
+        while let Some((prefix, mut message)) = pending.pop() {
+            let name = format!("{}{}", prefix, message.name);
+            for field in &mut message.fields {
+                field.packed.get_or_insert(packed && field.label == LABEL_REPEATED && packed_wire_type(field.ty).is_some());
+            }
+            for descriptor in message.enums.drain(..) {
+                self.enums.insert(format!("{}.{}", name, descriptor.name), descriptor);
+            }
+            let nested_prefix = format!("{}.", name);
+            pending.extend(message.nested_messages.drain(..).rev().map(|m| (nested_prefix.clone(), m)));
+            if self.messages.insert(name.clone(), message).is_none() {
+                self.message_order.push(name);
+            }
+        }
+    }
+
+    /// Loads `.proto` files from disk, with their imports found in the include directories.
+    pub fn from_proto_files(paths: &[String], include: &[String]) -> Result<Self, String> {
+        let mut schema = Schema::default();
+        for file in proto::load(paths, include)? {
+            schema.add_file(file);
+        }
+        Ok(schema)
+    }
+
+    /// Loads a serialized `google.protobuf.FileDescriptorSet`, as embedded in MCAP files or written
+    /// by `protoc --descriptor_set_out`.
+    pub fn from_descriptor_set(data: &[u8]) -> Result<Self, String> {
+        let mut schema = Schema::default();
+        schema.add_descriptor_set(data)?;
+        Ok(schema)
+    }
+
+    /// Adds the files in a serialized `google.protobuf.FileDescriptorSet`.
+    pub fn add_descriptor_set(&mut self, data: &[u8]) -> Result<(), String> {
+        for field in parse_message(data, false).map_err(|err| format!("malformed FileDescriptorSet: {}", err))? {
+            if let (1, WireValue::LengthPrefixed(d)) = (field.tag_number, field.value) {
+                self.add_file(FileDescriptor::decode(d.as_bytes())?);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn message(&self, name: &str) -> Option<&MessageDescriptor> {
+        self.messages.get(name.trim_start_matches('.'))
+    }
+
+    pub fn enumeration(&self, name: &str) -> Option<&EnumDescriptor> {
+        self.enums.get(name.trim_start_matches('.'))
+    }
+
+    /// Marks a `bytes` field as holding a serialized message of another type, so it's decoded as
+    /// one, e.g. the descriptors in a gRPC reflection response.
+    pub fn embed(&mut self, message: &str, field: &str, type_name: &str) {
+        let field = self.messages.get_mut(message).and_then(|message| message.fields.iter_mut().find(|f| f.name == field));
+        if let Some(field) = field {
+            field.type_name = format!(".{}", type_name);
+        }
+    }
+
+    /// The message type of the fields at the end of a path of tag numbers from `message`, following
+    /// the types of the submessage and group fields along it.
+    pub fn descend<'s>(&'s self, message: &'s MessageDescriptor, path: &[u64]) -> Option<&'s MessageDescriptor> {
+        path.iter().try_fold(message, |message, &tag_number| {
+            let field = message.fields.iter().find(|field| field.number == tag_number)?;
+            self.message(&field.type_name)
+        })
+    }
+
+    /// The names of every message type, in the order they were declared, each nested one after the
+    /// type it's in.
+    pub fn message_names(&self) -> impl Iterator<Item = &str> {
+        self.message_order.iter().map(String::as_str)
+    }
+
+    /// How many of the fields, submessages included, fit the message type and how many don't.
+    pub fn fit(&self, fields: &[ProtoField<Span>], name: &str) -> Option<(usize, usize)> {
+        self.message(name).map(|message| score(fields.to_vec(), self, message))
+    }
+
+    /// Picks the message type among `candidates` that fits the fields best, the first by name among
+    /// equally good fits. Types that more than one field in ten doesn't fit are never picked.
+    pub fn best_match<'s>(&self, fields: &[ProtoField<Span>], candidates: &[&'s str]) -> Option<&'s str> {
+        let best = candidates.iter()
+            .filter_map(|&name| self.fit(fields, name).map(|score| (score, name)))
+            .filter(|&((fits, misfits), _)| fits > 0 && misfits * 10 <= fits)
+            .max_by(|&((fits_a, misfits_a), a), &((fits_b, misfits_b), b)| {
+                (fits_a as i64 - misfits_a as i64).cmp(&(fits_b as i64 - misfits_b as i64)).then(b.cmp(a))
+            })
+            .map(|(_, name)| name);
+        trace_event!(?best, candidates = candidates.len(), "picked a message type");
+        best
+    }
+}
+
+/// Formats a scalar value according to its declared type, or returns `None` if the wire type doesn't
+/// fit it.
+fn format_scalar(schema: &Schema, field: &FieldDescriptor, value: WireValue<Span>, options: &Options) -> Option<String> {
+    let integer = |v: u64| format!("{}{}", v, annotation(options, || units::annotate_integer(v)));
+    let signed = |v: i64| match v {
+        v if v >= 0 => integer(v as u64),
+        v => v.to_string(),
+    };
+    Some(match (TYPE_NAMES.get(field.ty as usize).copied()?, value) {
+        ("double", WireValue::Int64(v)) => f64::from_bits(v).to_string(),
+        ("float", WireValue::Int32(v)) => f32::from_bits(v).to_string(),
+        ("int64", WireValue::VarInt(v)) => signed(v as i64),
+        ("uint64", WireValue::VarInt(v)) | ("fixed64", WireValue::Int64(v)) => integer(v),
+        ("int32", WireValue::VarInt(v)) => signed((v as i32) as i64),
+        ("uint32", WireValue::VarInt(v)) => integer((v as u32) as u64),
+        ("fixed32", WireValue::Int32(v)) => integer(v as u64),
+        ("sfixed32", WireValue::Int32(v)) => signed((v as i32) as i64),
+        ("sfixed64", WireValue::Int64(v)) => signed(v as i64),
+        ("sint32", WireValue::VarInt(v)) => signed((((v >> 1) as i64) ^ -((v & 1) as i64)) as i32 as i64),
+        ("sint64", WireValue::VarInt(v)) => signed(((v >> 1) as i64) ^ -((v & 1) as i64)),
+        ("bool", WireValue::VarInt(v)) => (v != 0).to_string(),
+        ("enum", WireValue::VarInt(v)) => {
+            let number = v as i32;
+            let name = schema.enumeration(&field.type_name)
+                .and_then(|descriptor| descriptor.values.iter().find(|(_, n)| *n == number));
+            match name {
+                Some((name, _)) => name.clone(),
+                None => number.to_string(),
+            }
+        }
+        _ => return None,
+    })
+}
+
+/// The wire type a packed repeated field of a scalar type is made of, if it can be packed.
+pub fn packed_wire_type(ty: u64) -> Option<WireType> {
+    match TYPE_NAMES.get(ty as usize).copied()? {
+        "double" | "fixed64" | "sfixed64" => Some(WireType::Int64),
+        "float" | "fixed32" | "sfixed32" => Some(WireType::Int32),
+        "string" | "bytes" | "message" | "group" => None,
+        _ => Some(WireType::VarInt),
+    }
+}
+
+/// Splits the data of a packed repeated field into its values.
+pub fn unpack(data: Span, wire_type: WireType) -> Option<Vec<WireValue<Span>>> {
+    let result = match wire_type {
+        WireType::VarInt => all_consuming(many0(complete(map(base128_vlq::<_, Error<_>>, WireValue::VarInt))))(data),
+        WireType::Int64 => all_consuming(many0(complete(map(le_u64::<_, Error<_>>, WireValue::Int64))))(data),
+        WireType::Int32 => all_consuming(many0(complete(map(le_u32::<_, Error<_>>, WireValue::Int32))))(data),
+        _ => return None,
+    };
+    result.ok().map(|(_, values)| values)
+}
+
+/// Counts the fields whose number and wire type fit a message type, and those that don't.
+fn score(fields: Vec<ProtoField<Span>>, schema: &Schema, message: &MessageDescriptor) -> (usize, usize) {
+    // Only a sample is needed to tell types apart, so large inputs stop early.
+    const MAX_FIELDS: usize = 10_000;
+    let mut stack = vec![(fields.into_iter(), message)];
+    let (mut fits, mut misfits, mut visited) = (0, 0, 0);
+    while let Some((level, message)) = stack.last_mut() {
+        let message = *message;
+        let field = match level.next() {
+            Some(field) if visited < MAX_FIELDS => field,
+            _ => {
+                stack.pop();
+                continue;
+            }
+        };
+        visited += 1;
+        let declared = match message.fields.iter().find(|f| f.number == field.tag_number) {
+            Some(declared) => declared,
+            None => {
+                misfits += 1;
+                continue;
+            }
+        };
+        let fit = match (declared.ty, field.value) {
+            (TYPE_MESSAGE, WireValue::LengthPrefixed(d)) => match all_consuming(protobuf::<_, Error<_>>(false))(d) {
+                Ok((_, fields)) => {
+                    if let Some(nested) = schema.message(&declared.type_name) {
+                        stack.push((fields.into_iter(), nested));
+                    }
+                    true
+                }
+                Err(_) => false,
+            },
+            (ty, WireValue::LengthPrefixed(d)) if TYPE_NAMES.get(ty as usize) == Some(&"string") => std::str::from_utf8(d.as_bytes()).is_ok(),
+            // Bytes marked as holding an embedded message are scored as that message if they parse.
+            (ty, WireValue::LengthPrefixed(d)) if TYPE_NAMES.get(ty as usize) == Some(&"bytes") => {
+                let nested = schema.message(&declared.type_name).zip(all_consuming(protobuf::<_, Error<_>>(false))(d).ok());
+                if let Some((nested, (_, fields))) = nested {
+                    stack.push((fields.into_iter(), nested));
+                }
+                true
+            }
+            (ty, WireValue::LengthPrefixed(d)) => packed_wire_type(ty).and_then(|wire_type| unpack(d, wire_type)).is_some(),
+            (ty, WireValue::VarInt(_)) => packed_wire_type(ty) == Some(WireType::VarInt),
+            (ty, WireValue::Int64(_)) => packed_wire_type(ty) == Some(WireType::Int64),
+            (ty, WireValue::Int32(_)) => packed_wire_type(ty) == Some(WireType::Int32),
+            _ => false,
+        };
+        if fit {
+            fits += 1;
+        } else {
+            misfits += 1;
+        }
+    }
+    (fits, misfits)
+}
+
+/// Prints a message as `print_message` does, but naming fields and decoding values declared in
+/// `message`, a type in `schema`. Fields the schema doesn't know about, or whose wire type doesn't
+/// match it, get the usual heuristic decode.
+pub fn print_typed(
+    out: &mut dyn Write, fields: Vec<ProtoField<Span>>, (schema, message): (&Schema, &MessageDescriptor), base: usize,
+    options: &Options, hooks: &[Box<dyn FieldHook>], warnings: &mut Warnings,
+) -> PrintStats {
+    // Walked with an explicit stack like `print_message`, each level carrying its message type.
+    let mut stack = vec![(Cursor::message(fields), Some(message))];
+    let mut path = Vec::new();
+    let mut stats = PrintStats{fields: 0, max_depth: 0};
+
+    while !stack.is_empty() {
+        let depth = stack.len() - 1;
+        let (level, message) = &mut stack[depth];
+        let message = *message;
+        let index = match level.next() {
+            Some(index) => index,
+            None => {
+                stack.pop();
+                path.pop();
+                if !stack.is_empty() {
+                    print_indent(out, base + stack.len() - 1);
+                    outln!(out, "{}", color::paint(options, Style::Brace, "}"));
+                }
+                continue;
+            }
+        };
+        let list = level.list.clone();
+        let field = list.fields[index];
+        let indent = base + depth;
+        // A group's fields are skipped over by the cursor, to be printed beneath it.
+        let group = match field.value {
+            WireValue::StartGroup => Some(list.group(index)),
+            _ => None,
+        };
+        let declared = message.and_then(|message| message.fields.iter().find(|f| f.number == field.tag_number));
+        let declared = match declared.filter(|declared| (declared.ty == TYPE_GROUP) == group.is_some()) {
+            Some(declared) => declared,
+            None => {
+                let raw = print_message_indented(out, list.fields[list.extent(index)].to_vec(), indent, options, hooks, warnings);
+                stats.fields += raw.fields;
+                stats.max_depth = stats.max_depth.max(depth + raw.max_depth);
+                continue;
+            }
+        };
+        stats.fields += 1;
+        stats.max_depth = stats.max_depth.max(depth);
+        path.push(field.tag_number);
+        match run_hooks(hooks, &path, &field) {
+            HookAction::Default => {}
+            HookAction::Replace(value) => {
+                print_indent(out, indent);
+                outln!(out, "{}: {}{}", color::paint(options, Style::Tag, &declared.name), value, size_note(options, field.raw.input_len()));
+                path.pop();
+                continue;
+            }
+            HookAction::Skip => {
+                path.pop();
+                continue;
+            }
+        }
+
+        let printed = match (declared.ty, field.value) {
+            (TYPE_MESSAGE, WireValue::LengthPrefixed(d)) => match all_consuming(protobuf::<_, Error<_>>(false))(d) {
+                Ok((_, fields)) => {
+                    print_indent(out, indent);
+                    outln!(out, "{}: {}{}", color::paint(options, Style::Tag, &declared.name), color::paint(options, Style::Brace, "{"), size_note(options, field.raw.input_len()));
+                    // An Any of a type the schema has is unpacked, under its type URL as text format
+                    // writes it. The Any gets a level with nothing left in it, to close it after its
+                    // value.
+                    let unpacked = (declared.type_name.trim_start_matches('.') == "google.protobuf.Any").then(|| wkt::any(&fields)).flatten()
+                        .and_then(|(type_url, value)| Some((schema.message(wkt::type_name(&type_url))?, type_url, value)))
+                        .and_then(|(embedded, type_url, value)| Some((embedded, type_url, all_consuming(protobuf::<_, Error<_>>(false))(value).ok()?.1)));
+                    match unpacked {
+                        Some((embedded, type_url, value)) => {
+                            print_indent(out, indent + 1);
+                            outln!(out, "[{}] {}", color::paint(options, Style::Tag, type_url), color::paint(options, Style::Brace, "{"));
+                            stack.push((Cursor::message(Vec::new()), None));
+                            path.push(2);
+                            stack.push((Cursor::message(value), Some(embedded)));
+                        }
+                        None => stack.push((Cursor::message(fields), schema.message(&declared.type_name))),
+                    }
+                    continue;
+                }
+                Err(_) => false,
+            },
+            (TYPE_GROUP, _) => {
+                let (content, end) = group.expect("only groups are declared as groups");
+                check_group_end(&end, &path, warnings);
+                // A group takes up everything through its end tag, or its last field if it has none.
+                let last = list.fields[list.extent(index).end - 1];
+                let len = last.raw.location_offset() + last.raw.input_len() - field.raw.location_offset();
+                print_indent(out, indent);
+                outln!(out, "{}: {}{}", color::paint(options, Style::Tag, &declared.name), color::paint(options, Style::Brace, "{"), size_note(options, len));
+                stack.push((Cursor::new(list, content), schema.message(&declared.type_name)));
+                continue;
+            }
+            (ty, WireValue::LengthPrefixed(d)) if TYPE_NAMES.get(ty as usize) == Some(&"string") => {
+                print_indent(out, indent);
+                let size = annotation(options, || units::annotate_length(d.input_len()));
+                let str = elided("string", d.as_bytes(), options).unwrap_or_else(|| text::quote(&String::from_utf8_lossy(d.as_bytes())));
+                outln!(out, "{}: {}{}{}", color::paint(options, Style::Tag, &declared.name), color::paint(options, Style::String, str), size, size_note(options, field.raw.input_len()));
+                true
+            }
+            (ty, WireValue::LengthPrefixed(d)) if TYPE_NAMES.get(ty as usize) == Some(&"bytes") => {
+                let embedded = schema.message(&declared.type_name)
+                    .and_then(|embedded| all_consuming(protobuf::<_, Error<_>>(false))(d).ok().map(|(_, fields)| (embedded, fields)));
+                if let Some((embedded, fields)) = embedded {
+                    print_indent(out, indent);
+                    outln!(out, "{}: {}{}", color::paint(options, Style::Tag, &declared.name), color::paint(options, Style::Brace, "{"), size_note(options, field.raw.input_len()));
+                    stack.push((Cursor::message(fields), Some(embedded)));
+                    continue;
+                }
+                print_indent(out, indent);
+                let size = annotation(options, || units::annotate_length(d.input_len()));
+                let bytes = elided("bytes", d.as_bytes(), options).unwrap_or_else(|| options.bytes_format.format(d.as_bytes()));
+                let bytes = color::paint(options, Style::Bytes, bytes);
+                outln!(out, "{}: {}{}{}", color::paint(options, Style::Tag, &declared.name), bytes, size, size_note(options, field.raw.input_len()));
+                true
+            }
+            (ty, WireValue::LengthPrefixed(d)) => {
+                let wire_type = packed_wire_type(ty);
+                let values = wire_type.and_then(|wire_type| unpack(d, wire_type));
+                let values: Option<Vec<String>> = values.and_then(|values| {
+                    values.into_iter().map(|v| format_scalar(schema, declared, v, options)).collect()
+                });
+                match values {
+                    Some(values) => {
+                        print_indent(out, indent);
+                        let values = format!("[{}]", values.join(", "));
+                        outln!(out, "{}: {}{}", color::paint(options, Style::Tag, &declared.name), color::paint(options, Style::of(wire_type.expect("only packable types unpack")), values), size_note(options, field.raw.input_len()));
+                        true
+                    }
+                    None => false,
+                }
+            }
+            (_, value) => match format_scalar(schema, declared, value, options) {
+                Some(value) => {
+                    print_indent(out, indent);
+                    outln!(out, "{}: {}{}", color::paint(options, Style::Tag, &declared.name), color::paint(options, Style::of_value(&field.value), value), size_note(options, field.raw.input_len()));
+                    true
+                }
+                None => false,
+            },
+        };
+        if !printed {
+            print_message_indented(out, vec![field], indent, options, &[], warnings);
+        }
+        path.pop();
+    }
+    stats
+}
+
+/// Prints a whole message of the named type, falling back to the usual heuristics if the schema
+/// doesn't have it.
+pub fn print_as(out: &mut dyn Write, data: &[u8], schema: &Schema, type_name: &str, base: usize, options: &Options, warnings: &mut Warnings) {
+    match (schema.message(type_name), parse_message(data, false)) {
+        (Some(message), Ok(fields)) => {
+            print_typed(out, fields, (schema, message), base, options, &[], warnings);
+        }
+        (None, Ok(fields)) => {
+            print_message_indented(out, fields, base, options, &[], warnings);
+        }
+        (_, Err(_)) => {
+            print_indent(out, base);
+            outln!(out, "{}", options.bytes_format.format(data));
+        }
+    }
+}
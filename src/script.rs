@@ -0,0 +1,87 @@
+//! Rhai scripting hooks for `--script`.
+//!
+//! A script can define `on_field(field)`, called for every field in wire order with a map of `path`,
+//! `tag`, `wire_type`, `offset` and either `value` (an integer, for scalars) or `bytes` (a blob, for
+//! length-prefixed data). Returning a string prints it as the field's value, returning `false` leaves
+//! the field out, and returning nothing keeps protocat's own rendering. `on_end()` is called after
+//! each input, e.g. to print derived totals. Both are called as methods on a map that persists across
+//! calls, so scripts can keep state in `this`.
+
+use std::cell::RefCell;
+use std::fs;
+
+use nom::AsBytes;
+use rhai::{Blob, CallFnOptions, Dynamic, Engine, Map, Scope, AST};
+
+use crate::hook::{FieldHook, HookAction};
+use crate::{ProtoField, Span, WireValue};
+
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    state: RefCell<Dynamic>,
+}
+
+impl Script {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let source = fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+        let engine = Engine::new();
+        let ast = engine.compile(&source).map_err(|err| format!("{}: {}", path, err))?;
+        Ok(Script{engine, ast, state: RefCell::new(Dynamic::from_map(Map::new()))})
+    }
+
+    fn has_fn(&self, name: &str, params: usize) -> bool {
+        self.ast.iter_functions().any(|f| f.name == name && f.params.len() == params)
+    }
+
+    fn call(&self, name: &str, args: impl rhai::FuncArgs) -> Result<Dynamic, String> {
+        let mut state = self.state.borrow_mut();
+        let options = CallFnOptions::new().eval_ast(false).bind_this_ptr(&mut state);
+        self.engine
+            .call_fn_with_options(options, &mut Scope::new(), &self.ast, name, args)
+            .map_err(|err| format!("script error in {}: {}", name, err))
+    }
+}
+
+impl FieldHook for Script {
+    fn on_field(&self, path: &[u64], field: &ProtoField<Span>) -> Result<HookAction, String> {
+        if !self.has_fn("on_field", 1) {
+            return Ok(HookAction::Default);
+        }
+
+        let mut map = Map::new();
+        let path = path.iter().map(u64::to_string).collect::<Vec<_>>().join(".");
+        map.insert("path".into(), path.into());
+        map.insert("tag".into(), (field.tag_number as i64).into());
+        map.insert("offset".into(), (field.raw.location_offset() as i64).into());
+        let (wire_type, value) = match field.value {
+            WireValue::VarInt(v) => ("varint", Dynamic::from(v as i64)),
+            WireValue::Int64(v) => ("fixed64", Dynamic::from(v as i64)),
+            WireValue::Int32(v) => ("fixed32", Dynamic::from(v as i64)),
+            WireValue::LengthPrefixed(d) => ("length-prefixed", Dynamic::from_blob(Blob::from(d.as_bytes()))),
+            WireValue::StartGroup => ("start-group", Dynamic::UNIT),
+            WireValue::EndGroup => ("end-group", Dynamic::UNIT),
+        };
+        map.insert("wire_type".into(), wire_type.into());
+        let key = if value.is_blob() { "bytes" } else { "value" };
+        map.insert(key.into(), value);
+
+        let result = self.call("on_field", (map,))?;
+        if result.is_unit() {
+            Ok(HookAction::Default)
+        } else if let Ok(keep) = result.as_bool() {
+            Ok(if keep { HookAction::Default } else { HookAction::Skip })
+        } else if result.is_string() {
+            Ok(HookAction::Replace(result.into_string().unwrap_or_default()))
+        } else {
+            Err(format!("on_field must return a string, a bool or nothing, not {}", result.type_name()))
+        }
+    }
+
+    fn on_input_end(&self) -> Result<(), String> {
+        if self.has_fn("on_end", 0) {
+            let _ = self.call("on_end", ())?;
+        }
+        Ok(())
+    }
+}
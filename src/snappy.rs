@@ -0,0 +1,61 @@
+//! Decompression of Snappy's block format, as used by Prometheus remote write.
+
+use std::convert::TryFrom;
+
+use crate::base128_vlq;
+use crate::Error;
+
+/// Decompresses a Snappy block, returning `None` if it isn't one.
+pub fn decompress(data: &[u8]) -> Option<Vec<u8>> {
+    let (mut input, len) = base128_vlq::<_, Error<_>>(data).ok()?;
+    // Every byte of input can produce at most a few dozen bytes of output, which bounds what a bogus
+    // length can make us allocate.
+    let len = usize::try_from(len).ok().filter(|&len| len <= data.len().saturating_mul(64))?;
+    let mut out = Vec::with_capacity(len);
+
+    while let Some((&tag, rest)) = input.split_first() {
+        input = rest;
+        let (length, offset) = match tag & 0x3 {
+            0 => {
+                let mut length = (tag >> 2) as usize + 1;
+                if length > 60 {
+                    let extra = length - 60;
+                    let bytes = input.get(..extra)?;
+                    length = bytes.iter().rev().fold(0, |acc, &b| acc << 8 | b as usize) + 1;
+                    input = &input[extra..];
+                }
+                let literal = input.get(..length)?;
+                out.extend_from_slice(literal);
+                input = &input[length..];
+                continue;
+            }
+            1 => {
+                let (&low, rest) = input.split_first()?;
+                input = rest;
+                (((tag >> 2) & 0x7) as usize + 4, ((tag as usize >> 5) << 8) | low as usize)
+            }
+            2 => {
+                let bytes = input.get(..2)?;
+                input = &input[2..];
+                ((tag >> 2) as usize + 1, u16::from_le_bytes([bytes[0], bytes[1]]) as usize)
+            }
+            _ => {
+                let bytes = input.get(..4)?;
+                input = &input[4..];
+                ((tag >> 2) as usize + 1, u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+            }
+        };
+        if offset == 0 || offset > out.len() || out.len() + length > len {
+            return None;
+        }
+        // Copies may overlap their own output, so they go a byte at a time.
+        let start = out.len() - offset;
+        for i in 0..length {
+            out.push(out[start + i]);
+        }
+        if out.len() > len {
+            return None;
+        }
+    }
+    if out.len() == len { Some(out) } else { None }
+}
@@ -0,0 +1,136 @@
+//! `stats`: a breakdown of what a message is made of, field by field, for finding out which fields
+//! dominate a large serialized blob.
+//!
+//! Fields are counted by path, so field 1 of the submessage in field 2 is `2.1`, and a field's size
+//! is its whole encoding, tag and length included, so the size at a path includes everything
+//! beneath it. Length-delimited values are taken for submessages as the decode takes them, with
+//! the same heuristics and `--max-depth`.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use nom::InputLength;
+
+use crate::units::format_size;
+use crate::warning::{WarningKind, Warnings};
+use crate::{interpret, plural, Cursor, Interpretation, Options, ProtoField, Span, WireValue};
+
+/// The fields seen at one path.
+struct PathStats {
+    count: usize,
+    bytes: u64,
+    min: u64,
+    max: u64,
+    /// How many of the fields had each wire type, in the order of `WIRE_TYPES`.
+    wire_types: [usize; 5],
+}
+
+const WIRE_TYPES: [&str; 5] = ["varint", "fixed64", "length-delimited", "group", "fixed32"];
+
+/// Statistics gathered over any number of inputs.
+#[derive(Default)]
+pub struct Stats {
+    inputs: usize,
+    bytes: u64,
+    fields: usize,
+    max_depth: usize,
+    wire_types: [usize; 5],
+    paths: BTreeMap<Vec<u64>, PathStats>,
+}
+
+impl Stats {
+    /// Adds an input of `size` bytes.
+    pub fn add(&mut self, size: usize, fields: Vec<ProtoField<Span>>, options: &Options, warnings: &mut Warnings) {
+        self.inputs += 1;
+        self.bytes += size as u64;
+        // Submessages and groups are walked with an explicit stack, as in print_message, each entry
+        // holding a message level and the path to it.
+        let mut stack = vec![(Cursor::message(fields), Vec::new())];
+        while let Some((mut level, mut path)) = stack.pop() {
+            while let Some(index) = level.next() {
+                let field = level.list.fields[index];
+                path.push(field.tag_number);
+                let offset = field.raw.location_offset();
+                let (wire_type, len) = match field.value {
+                    WireValue::VarInt(_) => (0, field.raw.input_len()),
+                    WireValue::Int64(_) => (1, field.raw.input_len()),
+                    WireValue::LengthPrefixed(d) => {
+                        if let Interpretation::Message(fields) = interpret(d, options, &path, warnings) {
+                            stack.push((Cursor::message(fields), path.clone()));
+                        }
+                        (2, field.raw.input_len())
+                    }
+                    WireValue::StartGroup => {
+                        // Groups count towards --max-depth as submessages do.
+                        if path.len() > options.max_depth {
+                            let message = format!("nested deeper than --max-depth {}, left out", options.max_depth);
+                            warnings.emit(WarningKind::DepthLimit, &path, &message);
+                        } else {
+                            let (content, _) = level.list.group(index);
+                            stack.push((Cursor::new(level.list.clone(), content), path.clone()));
+                        }
+                        // A group takes up everything through its end tag, or its last field if it has
+                        // none.
+                        let last = level.list.fields[level.list.extent(index).end - 1];
+                        (3, last.raw.location_offset() + last.raw.input_len() - offset)
+                    }
+                    WireValue::EndGroup => {
+                        path.pop();
+                        continue;
+                    }
+                    WireValue::Int32(_) => (4, field.raw.input_len()),
+                };
+                self.fields += 1;
+                self.max_depth = self.max_depth.max(path.len() - 1);
+                self.wire_types[wire_type] += 1;
+                let len = len as u64;
+                let stats = self.paths.entry(path.clone()).or_insert(PathStats{count: 0, bytes: 0, min: u64::MAX, max: 0, wire_types: [0; 5]});
+                stats.count += 1;
+                stats.bytes += len;
+                stats.min = stats.min.min(len);
+                stats.max = stats.max.max(len);
+                stats.wire_types[wire_type] += 1;
+                path.pop();
+            }
+        }
+    }
+
+    /// Prints the totals and a table of the paths, in path order or, with `by_size`, largest first.
+    pub fn print(&self, out: &mut dyn Write, by_size: bool) {
+        outln!(
+            out,
+            "{} input{}, {}, {} field{}, max depth {}",
+            self.inputs, plural(self.inputs), format_size(self.bytes), self.fields, plural(self.fields), self.max_depth,
+        );
+        outln!(out, "wire types: {}", wire_types(&self.wire_types));
+        if self.paths.is_empty() {
+            return;
+        }
+        let mut rows: Vec<(String, &PathStats)> = self.paths.iter()
+            .map(|(path, stats)| (path.iter().map(u64::to_string).collect::<Vec<_>>().join("."), stats))
+            .collect();
+        if by_size {
+            rows.sort_by_key(|(_, stats)| std::cmp::Reverse(stats.bytes));
+        }
+        let width = rows.iter().map(|(path, _)| path.len()).max().unwrap_or(0).max(4);
+        outln!(out);
+        outln!(out, "{:<width$} {:>8} {:>10} {:>6} {:>8} {:>8} {:>8}  wire types", "path", "count", "bytes", "share", "min", "max", "avg", width = width);
+        for (path, stats) in rows {
+            let share = if self.bytes == 0 { 0.0 } else { stats.bytes as f64 * 100.0 / self.bytes as f64 };
+            outln!(
+                out,
+                "{:<width$} {:>8} {:>10} {:>5.1}% {:>8} {:>8} {:>8.1}  {}",
+                path, stats.count, stats.bytes, share, stats.min, stats.max, stats.bytes as f64 / stats.count as f64,
+                wire_types(&stats.wire_types), width = width,
+            );
+        }
+    }
+}
+
+/// Lists the wire types with a count, like `varint 3, length-delimited 1`.
+fn wire_types(counts: &[usize; 5]) -> String {
+    let types: Vec<String> = WIRE_TYPES.iter().zip(counts).filter(|(_, &count)| count > 0)
+        .map(|(name, count)| format!("{} {}", name, count))
+        .collect();
+    if types.is_empty() { "none".to_string() } else { types.join(", ") }
+}
@@ -0,0 +1,133 @@
+//! Dumps of key/value stores whose values are protobuf: bbolt databases, and the etcd data files
+//! built on them.
+
+use std::collections::{BTreeMap, HashSet};
+use std::convert::TryInto;
+use std::io::Write;
+
+use crate::bbolt::{Bucket, Db};
+use crate::warning::Warnings;
+use crate::{format_packed, interpret, parse_message, plural, print_message_indented, text, Interpretation, Options, Span, WireValue};
+
+/// The etcd bucket holding every revision of every key.
+const ETCD_KEY_BUCKET: &[u8] = b"key";
+
+/// The prefix Kubernetes puts in front of protobuf-encoded objects.
+const KUBERNETES_MAGIC: &[u8] = b"k8s\0";
+
+/// Formats a key as text if it is text, and in the --bytes-format otherwise.
+fn display_key(key: &[u8], options: &Options) -> String {
+    match std::str::from_utf8(key) {
+        Ok(str) if !str.chars().any(char::is_control) => str.to_string(),
+        _ => options.bytes_format.format(key),
+    }
+}
+
+/// Prints a value with the usual heuristics, after a label.
+fn print_value(out: &mut dyn Write, label: &str, value: &[u8], options: &Options, warnings: &mut Warnings) {
+    match interpret(Span::new(value), options, &[], warnings) {
+        Interpretation::Message(fields) => {
+            outln!(out, "{}: {{", label);
+            print_message_indented(out, fields, 1, options, &[], warnings);
+            outln!(out, "}}");
+        }
+        Interpretation::String(str, _) => outln!(out, "{}: {}", label, text::quote(&str)),
+        Interpretation::LossyString(str, invalid) =>
+            outln!(out, "{}: {} ({} invalid UTF-8 sequence{})", label, text::quote(&str), invalid, plural(invalid)),
+        Interpretation::Packed(values, wire_type) => outln!(out, "{}: {}", label, format_packed(&values, wire_type)),
+        Interpretation::Bytes => outln!(out, "{}: {}", label, options.bytes_format.format(value)),
+    }
+}
+
+/// Prints every key/value pair in a bbolt database, or in one bucket of it named by a
+/// slash-separated path, along with those of the buckets nested inside.
+pub fn print_bbolt(out: &mut dyn Write, data: &[u8], bucket: Option<&str>, prefix: Option<&str>, options: &Options, warnings: &mut Warnings) -> Result<(), String> {
+    let db = Db::open(data)?;
+    let names: Vec<&[u8]> = bucket.map_or_else(Vec::new, |path| path.split('/').map(str::as_bytes).collect());
+    let start = db.bucket(db.root(), &names)?.ok_or_else(|| format!("no bucket {}", bucket.unwrap_or_default()))?;
+    let prefix = prefix.unwrap_or_default().as_bytes();
+
+    let mut pending = vec![(bucket.map(str::to_string), start)];
+    let mut visited = HashSet::new();
+    while let Some((path, bucket)) = pending.pop() {
+        if let Bucket::Root(root) = bucket {
+            if !visited.insert(root) {
+                return Err(format!("bucket {} is contained in itself", path.unwrap_or_default()));
+            }
+        }
+        let mut nested = Vec::new();
+        for entry in db.entries(bucket)? {
+            let key = display_key(entry.key, options);
+            let label = match &path {
+                Some(path) => format!("{}/{}", path, key),
+                None => key,
+            };
+            if entry.is_bucket {
+                nested.push((Some(label), db.as_bucket(entry.value)?));
+            } else if entry.key.starts_with(prefix) {
+                print_value(out, &label, entry.value, options, warnings);
+            }
+        }
+        pending.extend(nested.into_iter().rev());
+    }
+    Ok(())
+}
+
+/// One revision of an etcd key, from its `mvccpb.KeyValue`.
+struct Revision<'a> {
+    revision: u64,
+    value: Option<&'a [u8]>,
+}
+
+/// Prints the keys in an etcd data file or snapshot, at their latest revision unless
+/// `all_revisions` is set. Deleted keys are left out of the latest revisions.
+pub fn print_etcd(out: &mut dyn Write, data: &[u8], prefix: Option<&str>, all_revisions: bool, options: &Options, warnings: &mut Warnings) -> Result<(), String> {
+    let db = Db::open(data)?;
+    let bucket = db.bucket(db.root(), &[ETCD_KEY_BUCKET])?.ok_or("no key bucket, so not an etcd database")?;
+    let prefix = prefix.unwrap_or_default().as_bytes();
+
+    // Revision keys are an 8-byte big endian main revision, an underscore and an 8-byte sub
+    // revision, followed by a 't' for deletions. They sort in revision order.
+    let mut latest = BTreeMap::new();
+    for entry in db.entries(bucket)? {
+        let revision = entry.key.get(..8).map_or(0, |main| u64::from_be_bytes(main.try_into().unwrap()));
+        let deleted = entry.key.get(17) == Some(&b't');
+        let fields = parse_message(entry.value, false).map_err(|err| format!("revision {}: malformed KeyValue: {}", revision, err))?;
+        let (mut key, mut value): (&[u8], _) = (b"", None);
+        for field in fields {
+            match (field.tag_number, field.value) {
+                (1, WireValue::LengthPrefixed(d)) => key = d.fragment(),
+                (5, WireValue::LengthPrefixed(d)) => value = Some(*d.fragment()),
+                _ => {}
+            }
+        }
+        if !key.starts_with(prefix) {
+            continue;
+        }
+        let value = if deleted { None } else { Some(value.unwrap_or_default()) };
+        if all_revisions {
+            print_revision(out, key, &Revision{revision, value}, options, warnings);
+        } else {
+            latest.insert(key, Revision{revision, value});
+        }
+    }
+
+    for (key, revision) in latest {
+        if revision.value.is_some() {
+            print_revision(out, key, &revision, options, warnings);
+        }
+    }
+    Ok(())
+}
+
+fn print_revision(out: &mut dyn Write, key: &[u8], revision: &Revision, options: &Options, warnings: &mut Warnings) {
+    let key = display_key(key, options);
+    match revision.value {
+        Some(value) => match value.strip_prefix(KUBERNETES_MAGIC) {
+            Some(object) => print_value(out, &format!("{} (revision {}, kubernetes)", key, revision.revision), object, options, warnings),
+            None => print_value(out, &format!("{} (revision {})", key, revision.revision), value, options, warnings),
+        },
+        None => outln!(out, "{} (revision {}): deleted", key, revision.revision),
+    }
+}
+
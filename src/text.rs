@@ -0,0 +1,129 @@
+//! Decoding of length-prefixed data as text.
+
+use clap::ValueEnum;
+use encoding_rs::SHIFT_JIS;
+
+#[derive(Copy, Clone, Debug, PartialEq, ValueEnum)]
+pub enum StringEncoding {
+    Utf8,
+    Latin1,
+    ShiftJis,
+    #[value(name = "utf16le")]
+    Utf16Le,
+    /// Try each of the other encodings in turn, strictest first.
+    Auto,
+}
+
+impl StringEncoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            StringEncoding::Utf8 => "utf8",
+            StringEncoding::Latin1 => "latin1",
+            StringEncoding::ShiftJis => "shift-jis",
+            StringEncoding::Utf16Le => "utf16le",
+            StringEncoding::Auto => "auto",
+        }
+    }
+}
+
+/// Decodes data as a string in the given encoding, returning the text and the encoding that matched.
+///
+/// UTF-8 is accepted when it is valid and at least `printable` of its characters, as a fraction, are
+/// printable, except in auto mode where control characters make it fall through to the other
+/// encodings (ASCII text in UTF-16 is valid UTF-8 full of NUL bytes). The legacy encodings will happily
+/// decode nearly anything, so for those the text must also be free of control characters other than
+/// whitespace, and in auto mode UTF-16 must be mostly ASCII and Shift-JIS must contain multibyte
+/// characters, to keep binary data from turning into mojibake.
+pub fn decode(data: &[u8], encoding: StringEncoding, printable: f64) -> Option<(String, StringEncoding)> {
+    let decoded = match encoding {
+        StringEncoding::Utf8 => {
+            return String::from_utf8(data.to_vec()).ok().filter(|s| mostly_printable(s, printable)).map(|s| (s, encoding));
+        }
+        StringEncoding::Latin1 => Some(data.iter().map(|&b| b as char).collect()),
+        StringEncoding::ShiftJis => SHIFT_JIS
+            .decode_without_bom_handling_and_without_replacement(data)
+            .map(|s| s.into_owned()),
+        StringEncoding::Utf16Le => decode_utf16le(data),
+        StringEncoding::Auto => {
+            return decode(data, StringEncoding::Utf8, printable)
+                .filter(|(s, _)| !has_control_chars(s))
+                .or_else(|| decode(data, StringEncoding::Utf16Le, printable).filter(|_| mostly_ascii_utf16(data)))
+                .or_else(|| decode(data, StringEncoding::ShiftJis, printable).filter(|(s, _)| !s.is_ascii()))
+                .or_else(|| decode(data, StringEncoding::Latin1, printable));
+        }
+    };
+    decoded.filter(|s| !has_control_chars(s)).map(|s| (s, encoding))
+}
+
+/// Whether data decodes as text free of control characters, which it then almost certainly is.
+pub fn is_clean(data: &[u8], encoding: StringEncoding) -> bool {
+    decode(data, encoding, 1.0).is_some_and(|(s, _)| !has_control_chars(&s))
+}
+
+fn decode_utf16le(data: &[u8]) -> Option<String> {
+    if !data.len().is_multiple_of(2) {
+        return None;
+    }
+    let units = data.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]]));
+    char::decode_utf16(units).collect::<Result<String, _>>().ok()
+}
+
+fn mostly_ascii_utf16(data: &[u8]) -> bool {
+    let ascii = data.chunks_exact(2).filter(|c| c[1] == 0 && c[0] < 0x80).count();
+    ascii * 2 >= data.len() / 2
+}
+
+fn is_unprintable(c: char) -> bool {
+    c.is_control() && !matches!(c, '\t' | '\n' | '\r')
+}
+
+fn has_control_chars(s: &str) -> bool {
+    s.chars().any(is_unprintable)
+}
+
+/// Whether at least `threshold` of the characters of text, as a fraction, are printable.
+pub fn mostly_printable(s: &str, threshold: f64) -> bool {
+    let (total, unprintable) = s.chars().fold((0, 0), |(total, unprintable), c| (total + 1, unprintable + is_unprintable(c) as usize));
+    (total - unprintable) as f64 >= threshold * total as f64
+}
+
+/// Quotes text for the output, escaping quotes, backslashes, tabs and line breaks as C does and other
+/// control characters as `\x1b` or `\u{85}`, so that no string can garble the output or the terminal.
+pub fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if !c.is_control() => out.push(c),
+            c if c.is_ascii() => out += &format!("\\x{:02x}", c as u32),
+            c => out += &format!("\\u{{{:x}}}", c as u32),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Decodes almost-text data with U+FFFD replacement characters, returning the string along with the
+/// number of invalid sequences, or `None` if more than a tenth of the bytes are invalid.
+pub fn lossy_utf8(data: &[u8]) -> Option<(String, usize)> {
+    let mut text = String::with_capacity(data.len());
+    let mut invalid_sequences = 0;
+    let mut invalid_bytes = 0;
+    for chunk in data.utf8_chunks() {
+        text.push_str(chunk.valid());
+        if !chunk.invalid().is_empty() {
+            text.push(char::REPLACEMENT_CHARACTER);
+            invalid_sequences += 1;
+            invalid_bytes += chunk.invalid().len();
+        }
+    }
+    if invalid_bytes * 10 > data.len() {
+        return None;
+    }
+    Some((text, invalid_sequences))
+}
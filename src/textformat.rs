@@ -0,0 +1,518 @@
+//! Protobuf text format, as printed by `protoc --decode` and `--decode_raw` and by most protobuf
+//! libraries' debug output: a parser for reading it as input, and a printer matching protoc's.
+
+use std::convert::TryFrom;
+use std::ops::Range;
+use std::rc::Rc;
+
+use nom::combinator::all_consuming;
+use nom::{AsBytes, InputLength};
+
+use crate::descriptor::*;
+use crate::payload::{Value, MAX_NESTING};
+use crate::schema::{packed_wire_type, unpack, Schema};
+use crate::{protobuf, Cursor, Error, FieldList, Span, WireType, WireValue};
+
+/// Parses a text format message.
+pub fn parse(source: &str) -> Result<Vec<(String, Value)>, String> {
+    let mut parser = Parser{source: source.as_bytes(), pos: 0};
+    parser.fields(None, 0)
+}
+
+struct Parser<'a> {
+    source: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error<T>(&self, message: &str) -> Result<T, String> {
+        let line = self.source[..self.pos].iter().filter(|&&b| b == b'\n').count() + 1;
+        Err(format!("text format error on line {}: {}", line, message))
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_space();
+        self.source.get(self.pos).copied()
+    }
+
+    /// Skips whitespace and `#` comments.
+    fn skip_space(&mut self) {
+        loop {
+            match self.source.get(self.pos) {
+                Some(b' ' | b'\t' | b'\n' | b'\r' | b'\x0b' | b'\x0c') => self.pos += 1,
+                Some(b'#') => {
+                    while !matches!(self.source.get(self.pos), None | Some(b'\n')) {
+                        self.pos += 1;
+                    }
+                }
+                _ => return,
+            }
+        }
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        if self.peek() == Some(byte) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// A run of identifier characters, which covers names, numbers and the start of floats.
+    fn word(&mut self) -> Option<String> {
+        self.skip_space();
+        let start = self.pos;
+        while matches!(self.source.get(self.pos), Some(b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'_' | b'.')) {
+            self.pos += 1;
+        }
+        // Exponents like 1e-5 have a sign in the middle.
+        while matches!(self.source.get(self.pos), Some(b'-' | b'+')) && matches!(self.source.get(self.pos - 1), Some(b'e' | b'E'))
+            && self.source[start].is_ascii_digit() {
+            self.pos += 1;
+            while matches!(self.source.get(self.pos), Some(b'0'..=b'9')) {
+                self.pos += 1;
+            }
+        }
+        if self.pos == start {
+            None
+        } else {
+            Some(String::from_utf8_lossy(&self.source[start..self.pos]).into_owned())
+        }
+    }
+
+    /// Parses fields up to the closing delimiter, or the end of input at the top level.
+    fn fields(&mut self, close: Option<u8>, depth: usize) -> Result<Vec<(String, Value)>, String> {
+        if depth > MAX_NESTING {
+            return self.error("nested too deeply");
+        }
+        let mut fields = Vec::new();
+        loop {
+            match (self.peek(), close) {
+                (None, None) => return Ok(fields),
+                (None, Some(_)) => return self.error("unterminated message"),
+                (Some(byte), Some(close)) if byte == close => {
+                    self.pos += 1;
+                    return Ok(fields);
+                }
+                _ => {}
+            }
+            let name = if self.eat(b'[') {
+                // An extension or Any type URL, kept bracketed for the encoder to reject or expand.
+                let start = self.pos;
+                while !matches!(self.source.get(self.pos), None | Some(b']')) {
+                    self.pos += 1;
+                }
+                let name = String::from_utf8_lossy(&self.source[start..self.pos]).trim().to_string();
+                if !self.eat(b']') {
+                    return self.error("unterminated [name]");
+                }
+                format!("[{}]", name)
+            } else {
+                match self.word() {
+                    Some(name) => name,
+                    None => return self.error("expected a field name"),
+                }
+            };
+            let colon = self.eat(b':');
+            let value = match self.peek() {
+                Some(open @ (b'{' | b'<')) => {
+                    self.pos += 1;
+                    Value::Message(self.fields(Some(if open == b'{' { b'}' } else { b'>' }), depth + 1)?)
+                }
+                _ if !colon => return self.error(&format!("expected ':' or '{{' after {}", name)),
+                Some(b'[') => {
+                    self.pos += 1;
+                    let mut values = Vec::new();
+                    if !self.eat(b']') {
+                        loop {
+                            values.push(self.value(depth + 1)?);
+                            if self.eat(b']') {
+                                break;
+                            }
+                            if !self.eat(b',') {
+                                return self.error("expected ',' or ']'");
+                            }
+                        }
+                    }
+                    Value::List(values)
+                }
+                _ => self.value(depth + 1)?,
+            };
+            fields.push((name, value));
+            if !self.eat(b';') {
+                self.eat(b',');
+            }
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, String> {
+        match self.peek() {
+            Some(open @ (b'{' | b'<')) => {
+                self.pos += 1;
+                Ok(Value::Message(self.fields(Some(if open == b'{' { b'}' } else { b'>' }), depth + 1)?))
+            }
+            Some(b'"' | b'\'') => {
+                // Adjacent string literals are concatenated.
+                let mut bytes = Vec::new();
+                while matches!(self.peek(), Some(b'"' | b'\'')) {
+                    self.string(&mut bytes)?;
+                }
+                Ok(Value::String(bytes))
+            }
+            Some(b'-') => {
+                self.pos += 1;
+                match self.word() {
+                    Some(word) => Ok(Value::Number(format!("-{}", word))),
+                    None => self.error("expected a number after '-'"),
+                }
+            }
+            Some(b'0'..=b'9' | b'.') => Ok(Value::Number(self.word().expect("a digit starts a word"))),
+            _ => match self.word() {
+                Some(word) if matches!(word.to_ascii_lowercase().as_str(), "inf" | "infinity" | "nan") => Ok(Value::Number(word)),
+                Some(word) => Ok(Value::Ident(word)),
+                None => self.error("expected a value"),
+            },
+        }
+    }
+
+    fn string(&mut self, out: &mut Vec<u8>) -> Result<(), String> {
+        let quote = self.source[self.pos];
+        self.pos += 1;
+        loop {
+            let byte = match self.source.get(self.pos) {
+                None | Some(b'\n') => return self.error("unterminated string"),
+                Some(&byte) => byte,
+            };
+            self.pos += 1;
+            if byte == quote {
+                return Ok(());
+            }
+            if byte != b'\\' {
+                out.push(byte);
+                continue;
+            }
+            let escape = match self.source.get(self.pos) {
+                Some(&escape) => escape,
+                None => return self.error("unterminated string"),
+            };
+            self.pos += 1;
+            match escape {
+                b'n' => out.push(b'\n'),
+                b'r' => out.push(b'\r'),
+                b't' => out.push(b'\t'),
+                b'a' => out.push(0x07),
+                b'b' => out.push(0x08),
+                b'f' => out.push(0x0c),
+                b'v' => out.push(0x0b),
+                b'0'..=b'7' => {
+                    let start = self.pos - 1;
+                    while self.pos - start < 3 && matches!(self.source.get(self.pos), Some(b'0'..=b'7')) {
+                        self.pos += 1;
+                    }
+                    let digits = std::str::from_utf8(&self.source[start..self.pos]).expect("octal digits are ASCII");
+                    out.push(u32::from_str_radix(digits, 8).expect("digits are octal") as u8);
+                }
+                b'x' => out.push(self.hex_escape(2)? as u8),
+                b'u' | b'U' => {
+                    let code = self.hex_escape(if escape == b'u' { 4 } else { 8 })?;
+                    let c = char::try_from(code).unwrap_or(char::REPLACEMENT_CHARACTER);
+                    out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                other => out.push(other),
+            }
+        }
+    }
+
+    /// Reads up to `max` hex digits of an escape; `\x` takes one or two, `\u` and `\U` exactly `max`.
+    fn hex_escape(&mut self, max: usize) -> Result<u32, String> {
+        let start = self.pos;
+        while self.pos - start < max && self.source.get(self.pos).is_some_and(|b| b.is_ascii_hexdigit()) {
+            self.pos += 1;
+        }
+        if self.pos == start || (max > 2 && self.pos - start < max) {
+            return self.error("malformed escape");
+        }
+        let digits = std::str::from_utf8(&self.source[start..self.pos]).expect("hex digits are ASCII");
+        Ok(u32::from_str_radix(digits, 16).expect("digits are hex"))
+    }
+}
+
+/// How deeply protoc parses nested messages and groups before giving up on the input.
+const PROTOC_NESTING_LIMIT: usize = 100;
+
+/// How deep protoc recurses into length-prefixed fields it has no type for before printing them as
+/// strings.
+const PROTOC_UNKNOWN_RECURSION_LIMIT: usize = 10;
+
+/// Renders a message in text format the way protoc does, indented by two spaces a level, or returns
+/// None if protoc would fail to parse it. With a type, fields come out in field number order with
+/// unknown fields after them, repeated occurrences of a singular field are merged, and packed fields
+/// are expanded; without one, this is `protoc --decode_raw`.
+pub fn render(data: &[u8], schema: Option<(&Schema, &MessageDescriptor)>) -> Option<Vec<String>> {
+    let mut printer = Printer{schema: schema.map(|(schema, _)| schema), lines: Vec::new()};
+    match schema {
+        Some((_, message)) => printer.typed(data, message, 0)?,
+        None => {
+            let list = read(data, PROTOC_NESTING_LIMIT)?;
+            printer.raw(&list, data, &refs(&items(&list, data)), 0, PROTOC_UNKNOWN_RECURSION_LIMIT);
+        }
+    }
+    Some(printer.lines)
+}
+
+/// A field, or a group with the range of its fields in the list and the bytes they were read from.
+enum Item<'a> {
+    Value(WireValue<Span<'a>>),
+    Group(Range<usize>, &'a [u8]),
+}
+
+/// Parses data as protoc does with a recursion limit of `limit`, or returns None if it isn't a
+/// message with balanced groups nested at most that deep.
+fn read(data: &[u8], limit: usize) -> Option<Rc<FieldList<'_>>> {
+    let (_, fields) = all_consuming(protobuf::<_, Error<_>>(false))(Span::new(data)).ok()?;
+    let mut open = Vec::new();
+    for field in fields.iter() {
+        match field.value {
+            WireValue::StartGroup if open.len() == limit => return None,
+            WireValue::StartGroup => open.push(field.tag_number),
+            WireValue::EndGroup if open.pop() != Some(field.tag_number) => return None,
+            _ => {}
+        }
+    }
+    if open.is_empty() { Some(FieldList::new(fields)) } else { None }
+}
+
+/// The items of the message `list` was read from `data`, its groups' fields left in the list.
+fn items<'a>(list: &Rc<FieldList<'a>>, data: &'a [u8]) -> Vec<(u64, Item<'a>)> {
+    group_items(list, 0..list.fields.len(), data)
+}
+
+/// The items of a run of fields of `list`, which was read from `data`.
+fn group_items<'a>(list: &Rc<FieldList<'a>>, fields: Range<usize>, data: &'a [u8]) -> Vec<(u64, Item<'a>)> {
+    let mut level = Cursor::new(list.clone(), fields);
+    let mut items = Vec::new();
+    while let Some(i) = level.next() {
+        let field = list.fields[i];
+        let item = match field.value {
+            WireValue::StartGroup => {
+                // Groups that were read are balanced, so each has an end, which its content runs up to.
+                let (content, end) = list.group(i);
+                let start = field.raw.location_offset() + field.raw.input_len();
+                let end = end.expect("groups that were read are ended").raw.location_offset();
+                Item::Group(content, &data[start..end])
+            }
+            value => Item::Value(value),
+        };
+        items.push((field.tag_number, item));
+    }
+    items
+}
+
+struct Printer<'s> {
+    schema: Option<&'s Schema>,
+    lines: Vec<String>,
+}
+
+impl<'s> Printer<'s> {
+    fn line(&mut self, depth: usize, text: String) {
+        self.lines.push(format!("{}{}", "  ".repeat(depth), text));
+    }
+
+    /// Prints fields protoc has no type for, which are items of `list` as read from `data`, trying
+    /// length-prefixed data as a message until the budget runs out. protoc reads that data with the
+    /// budget as its recursion limit, so a group nested deeper in it leaves it a string.
+    fn raw(&mut self, list: &Rc<FieldList>, data: &[u8], fields: &[(u64, &Item)], depth: usize, budget: usize) {
+        for &(tag, item) in fields {
+            match item {
+                Item::Value(WireValue::VarInt(v)) => self.line(depth, format!("{}: {}", tag, v)),
+                Item::Value(WireValue::Int64(v)) => self.line(depth, format!("{}: 0x{:016x}", tag, v)),
+                Item::Value(WireValue::Int32(v)) => self.line(depth, format!("{}: 0x{:08x}", tag, v)),
+                Item::Value(WireValue::LengthPrefixed(d)) => {
+                    let nested = Some(d.fragment()).filter(|d| !d.is_empty() && budget > 0).and_then(|d| Some((read(d, budget)?, d)));
+                    match nested {
+                        Some((nested, d)) => {
+                            self.line(depth, format!("{} {{", tag));
+                            self.raw(&nested, d, &refs(&items(&nested, d)), depth + 1, budget - 1);
+                            self.line(depth, "}".to_string());
+                        }
+                        None => self.line(depth, format!("{}: \"{}\"", tag, escape(d.as_bytes()))),
+                    }
+                }
+                Item::Group(content, _) => {
+                    // As in protoc, groups already kept to the recursion limit when they were read, and
+                    // only take from the budget here.
+                    self.line(depth, format!("{} {{", tag));
+                    let nested = group_items(list, content.clone(), data);
+                    self.raw(list, data, &refs(&nested), depth + 1, budget.saturating_sub(1));
+                    self.line(depth, "}".to_string());
+                }
+                Item::Value(WireValue::StartGroup | WireValue::EndGroup) => unreachable!("groups are gathered into items"),
+            }
+        }
+    }
+
+    fn typed(&mut self, data: &[u8], message: &MessageDescriptor, depth: usize) -> Option<()> {
+        if depth > PROTOC_NESTING_LIMIT {
+            return None;
+        }
+        let list = read(data, PROTOC_NESTING_LIMIT - depth)?;
+        let items = items(&list, data);
+        let mut known: Vec<(&FieldDescriptor, Vec<&Item>)> = Vec::new();
+        let mut unknown = Vec::new();
+        for (tag, item) in items.iter() {
+            match message.fields.iter().find(|f| f.number == *tag).filter(|declared| fits(declared, item)) {
+                Some(declared) => match known.iter_mut().find(|(f, _)| f.number == *tag) {
+                    Some((_, values)) => values.push(item),
+                    None => known.push((declared, vec![item])),
+                },
+                None => unknown.push((*tag, item)),
+            }
+        }
+        known.sort_by_key(|(declared, _)| declared.number);
+
+        for (declared, values) in known {
+            let nested = match declared.ty {
+                TYPE_MESSAGE | TYPE_GROUP => self.schema.and_then(|schema| schema.message(&declared.type_name)),
+                _ => None,
+            };
+            if let (Some(nested), true) = (nested, declared.label != LABEL_REPEATED) {
+                // Merging occurrences of a singular message is the same as concatenating them.
+                let data: Vec<u8> = values.iter().flat_map(|item| item_bytes(item).iter().copied()).collect();
+                self.nested(declared, &data, nested, depth)?;
+                continue;
+            }
+            let values = if declared.label == LABEL_REPEATED { &values[..] } else { &values[values.len() - 1..] };
+            for item in values {
+                match (nested, item) {
+                    (Some(nested), item) => self.nested(declared, item_bytes(item), nested, depth)?,
+                    (None, Item::Value(WireValue::LengthPrefixed(d))) if packed_wire_type(declared.ty).is_some() => {
+                        let wire_type = packed_wire_type(declared.ty).expect("checked above");
+                        for value in unpack(*d, wire_type).expect("fits checks packed data") {
+                            let text = self.scalar(declared, value)?;
+                            self.line(depth, format!("{}: {}", declared.name, text));
+                        }
+                    }
+                    (None, Item::Value(value)) => {
+                        let text = self.scalar(declared, *value)?;
+                        self.line(depth, format!("{}: {}", declared.name, text));
+                    }
+                    (None, Item::Group(..)) => return None,
+                }
+            }
+        }
+
+        self.raw(&list, data, &unknown, depth, PROTOC_UNKNOWN_RECURSION_LIMIT);
+        Some(())
+    }
+
+    fn nested(&mut self, declared: &FieldDescriptor, data: &[u8], nested: &MessageDescriptor, depth: usize) -> Option<()> {
+        // Groups are printed by their type's name, which is the field name capitalized.
+        let name = if declared.ty == TYPE_GROUP { &nested.name } else { &declared.name };
+        self.line(depth, format!("{} {{", name));
+        self.typed(data, nested, depth + 1)?;
+        self.line(depth, "}".to_string());
+        Some(())
+    }
+
+    fn scalar(&self, declared: &FieldDescriptor, value: WireValue<Span>) -> Option<String> {
+        let zigzag = |v: u64| ((v >> 1) as i64) ^ -((v & 1) as i64);
+        Some(match (TYPE_NAMES.get(declared.ty as usize).copied()?, value) {
+            ("double", WireValue::Int64(v)) => float(f64::from_bits(v), [15, 17], |s| s.parse() == Ok(f64::from_bits(v))),
+            ("float", WireValue::Int32(v)) => float(f32::from_bits(v).into(), [6, 9], |s| s.parse() == Ok(f32::from_bits(v))),
+            ("int64", WireValue::VarInt(v)) => (v as i64).to_string(),
+            ("uint64", WireValue::VarInt(v)) | ("fixed64", WireValue::Int64(v)) => v.to_string(),
+            ("int32", WireValue::VarInt(v)) => (v as i32).to_string(),
+            ("uint32", WireValue::VarInt(v)) => (v as u32).to_string(),
+            ("fixed32", WireValue::Int32(v)) => v.to_string(),
+            ("sfixed32", WireValue::Int32(v)) => (v as i32).to_string(),
+            ("sfixed64", WireValue::Int64(v)) => (v as i64).to_string(),
+            ("sint32", WireValue::VarInt(v)) => (zigzag(v) as i32).to_string(),
+            ("sint64", WireValue::VarInt(v)) => zigzag(v).to_string(),
+            ("bool", WireValue::VarInt(v)) => (v != 0).to_string(),
+            ("enum", WireValue::VarInt(v)) => {
+                let descriptor = self.schema.and_then(|schema| schema.enumeration(&declared.type_name));
+                match descriptor.and_then(|descriptor| descriptor.values.iter().find(|(_, n)| *n == v as i32)) {
+                    Some((name, _)) => name.clone(),
+                    None => (v as i32).to_string(),
+                }
+            }
+            ("string" | "bytes", WireValue::LengthPrefixed(d)) => format!("\"{}\"", escape(d.as_bytes())),
+            _ => return None,
+        })
+    }
+}
+
+/// Whether an item has a wire type protoc would accept for the field; anything else is kept as an
+/// unknown field.
+fn fits(declared: &FieldDescriptor, item: &Item) -> bool {
+    match (declared.ty, item) {
+        (TYPE_GROUP, item) => matches!(item, Item::Group(..)),
+        (TYPE_MESSAGE, item) => matches!(item, Item::Value(WireValue::LengthPrefixed(_))),
+        (ty, Item::Value(WireValue::LengthPrefixed(d))) => match packed_wire_type(ty) {
+            Some(wire_type) => declared.label == LABEL_REPEATED && unpack(*d, wire_type).is_some(),
+            None => true,
+        },
+        (ty, Item::Value(WireValue::VarInt(_))) => packed_wire_type(ty) == Some(WireType::VarInt),
+        (ty, Item::Value(WireValue::Int64(_))) => packed_wire_type(ty) == Some(WireType::Int64),
+        (ty, Item::Value(WireValue::Int32(_))) => packed_wire_type(ty) == Some(WireType::Int32),
+        _ => false,
+    }
+}
+
+fn refs<'i, 'a>(items: &'i [(u64, Item<'a>)]) -> Vec<(u64, &'i Item<'a>)> {
+    items.iter().map(|(tag, item)| (*tag, item)).collect()
+}
+
+fn item_bytes<'a>(item: &'a Item) -> &'a [u8] {
+    match item {
+        Item::Value(WireValue::LengthPrefixed(d)) => d.fragment(),
+        Item::Group(_, content) => content,
+        Item::Value(_) => &[],
+    }
+}
+
+/// Formats a float or double the way protoc does: with C's `%g` at the first of `precisions`, 6 or
+/// 15 significant digits, or at the second if that doesn't read back as the same value.
+fn float(v: f64, precisions: [usize; 2], round_trips: impl Fn(&str) -> bool) -> String {
+    if v.is_nan() {
+        "nan".to_string()
+    } else if v.is_infinite() {
+        if v > 0.0 { "inf".to_string() } else { "-inf".to_string() }
+    } else {
+        let short = format_g(v, precisions[0]);
+        if round_trips(&short) { short } else { format_g(v, precisions[1]) }
+    }
+}
+
+/// Formats a finite `v` as C's `%.{precision}g` does: in exponent form, as `1e+20`, if its
+/// exponent is below -4 or at least the precision, and without trailing zeros either way.
+fn format_g(v: f64, precision: usize) -> String {
+    let scientific = format!("{:.*e}", precision - 1, v);
+    let (mantissa, exponent) = scientific.split_once('e').expect("{:e} writes an exponent");
+    let exponent: i32 = exponent.parse().expect("{:e} writes an integer exponent");
+    let trim = |digits: &str| if digits.contains('.') { digits.trim_end_matches('0').trim_end_matches('.').to_string() } else { digits.to_string() };
+    if exponent < -4 || exponent >= precision as i32 {
+        format!("{}e{}{:02}", trim(mantissa), if exponent < 0 { '-' } else { '+' }, exponent.abs())
+    } else {
+        trim(&format!("{:.*}", (precision as i32 - 1 - exponent) as usize, v))
+    }
+}
+
+/// C-escapes bytes the way protoc does.
+pub fn escape(bytes: &[u8]) -> String {
+    let mut escaped = String::new();
+    for &byte in bytes {
+        match byte {
+            b'\n' => escaped.push_str("\\n"),
+            b'\r' => escaped.push_str("\\r"),
+            b'\t' => escaped.push_str("\\t"),
+            b'"' => escaped.push_str("\\\""),
+            b'\'' => escaped.push_str("\\'"),
+            b'\\' => escaped.push_str("\\\\"),
+            0x20..=0x7e => escaped.push(byte as char),
+            _ => escaped.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,153 @@
+//! Decoded messages as a tree for serde, with the `serde` feature: `Tree` serializes a message's
+//! fields, and `to_json_value` converts them to a `serde_json::Value`.
+//!
+//! A message is a sequence of its fields in wire order, each a map with its `number`, `wireType`
+//! (`varint`, `fixed64`, `length-delimited`, `group` or `fixed32`), and the `offset` and `length` of its
+//! whole encoding in the input, tag included. Scalars have a `value`. A group's end is in the group
+//! it ends; one that ends nothing, as `parse_lenient` lets through, is an `end-group` of its own. Groups and length-delimited
+//! values that parse as a message have the fields in them as a `message`, valid UTF-8 that is
+//! mostly printable is a `string`, and anything else is `bytes` in hex, the same heuristics the
+//! command line applies without a schema.
+
+use std::fmt::Write as _;
+
+use serde::ser::{Serialize, SerializeMap, SerializeSeq, Serializer};
+
+use crate::{parse, Field, Value};
+
+/// How deep submessages and groups are decoded, as with the command line's default `--max-depth`;
+/// anything deeper is left as bytes.
+const MAX_DEPTH: usize = 100;
+
+/// The fields of a message, as returned by `parse`, serialized as a tree of submessages and groups.
+pub struct Tree<'a> {
+    fields: &'a [Field<'a>],
+    /// Where the message starts in the input, for the offsets of submessages' fields.
+    base: usize,
+    depth: usize,
+}
+
+impl<'a> Tree<'a> {
+    /// The tree of a top-level message, whose offsets are from its start.
+    pub fn new(fields: &'a [Field<'a>]) -> Self {
+        Tree{fields, base: 0, depth: 0}
+    }
+}
+
+/// Converts the fields of a message, as returned by `parse`, to JSON as `Tree` serializes them.
+///
+/// ```
+/// let fields = protocat::parse(&[0x08, 0x96, 0x01, 0x12, 0x02, 0x08, 0x01]).unwrap();
+/// let json = protocat::to_json_value(&fields);
+/// assert_eq!(json[0]["value"], 150);
+/// assert_eq!(json[1]["message"][0]["offset"], 5);
+/// ```
+pub fn to_json_value(fields: &[Field]) -> serde_json::Value {
+    serde_json::to_value(Tree::new(fields)).expect("trees always serialize")
+}
+
+/// A field and, for a group, the fields in it.
+struct Node<'a> {
+    field: &'a Field<'a>,
+    group: Option<&'a [Field<'a>]>,
+    /// The length of the field's encoding, or for a group, of everything through its end tag.
+    length: usize,
+    base: usize,
+    depth: usize,
+}
+
+impl Serialize for Tree<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(None)?;
+        let mut i = 0;
+        while i < self.fields.len() {
+            let field = &self.fields[i];
+            i += 1;
+            let (group, length) = match field.value {
+                Value::StartGroup => {
+                    // A group runs to the end that balances it, if there is one.
+                    let start = i;
+                    let mut open = 1;
+                    while i < self.fields.len() && open > 0 {
+                        match self.fields[i].value {
+                            Value::StartGroup => open += 1,
+                            Value::EndGroup => open -= 1,
+                            _ => {}
+                        }
+                        i += 1;
+                    }
+                    let last = &self.fields[i - 1];
+                    let content = if open == 0 { &self.fields[start..i - 1] } else { &self.fields[start..i] };
+                    (Some(content), last.offset + last.raw.len() - field.offset)
+                }
+                _ => (None, field.raw.len()),
+            };
+            seq.serialize_element(&Node{field, group, length, base: self.base, depth: self.depth})?;
+        }
+        seq.end()
+    }
+}
+
+impl Serialize for Node<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let field = self.field;
+        let offset = self.base + field.offset;
+        let wire_type = match field.value {
+            Value::Varint(_) => "varint",
+            Value::Fixed64(_) => "fixed64",
+            Value::Bytes(_) => "length-delimited",
+            Value::StartGroup => "group",
+            Value::EndGroup => "end-group",
+            Value::Fixed32(_) => "fixed32",
+        };
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("number", &field.number)?;
+        map.serialize_entry("wireType", wire_type)?;
+        map.serialize_entry("offset", &offset)?;
+        map.serialize_entry("length", &self.length)?;
+        match field.value {
+            Value::Varint(v) | Value::Fixed64(v) => map.serialize_entry("value", &v)?,
+            Value::Fixed32(v) => map.serialize_entry("value", &v)?,
+            Value::StartGroup => {
+                let content = self.group.unwrap_or_default();
+                if self.depth < MAX_DEPTH {
+                    map.serialize_entry("message", &Tree{fields: content, base: self.base, depth: self.depth + 1})?;
+                } else {
+                    // The fields of a group are all there is between its tags.
+                    let bytes: Vec<u8> = content.iter().flat_map(|field| field.raw.iter().copied()).collect();
+                    map.serialize_entry("bytes", &hex(&bytes))?;
+                }
+            }
+            Value::EndGroup => {}
+            Value::Bytes(content) => {
+                let base = offset + field.raw.len() - content.len();
+                match parse(content) {
+                    Ok(fields) if self.depth < MAX_DEPTH => {
+                        map.serialize_entry("message", &Tree{fields: &fields, base, depth: self.depth + 1})?;
+                    }
+                    _ => match std::str::from_utf8(content).ok().filter(|s| mostly_printable(s)) {
+                        Some(s) => map.serialize_entry("string", s)?,
+                        None => map.serialize_entry("bytes", &hex(content))?,
+                    },
+                }
+            }
+        }
+        map.end()
+    }
+}
+
+/// Whether at least 90% of the characters of text are printable, counting tabs and line breaks.
+fn mostly_printable(s: &str) -> bool {
+    let (total, unprintable) = s.chars().fold((0, 0), |(total, unprintable), c| {
+        (total + 1, unprintable + (c.is_control() && !matches!(c, '\t' | '\n' | '\r')) as usize)
+    });
+    (total - unprintable) * 10 >= total * 9
+}
+
+fn hex(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len() * 2);
+    for b in data {
+        let _ = write!(out, "{:02x}", b);
+    }
+    out
+}
@@ -0,0 +1,111 @@
+//! Human-friendly annotations of values for `--annotate-units`.
+
+use std::ops::Range;
+
+use crate::{ProtoField, Span, WireValue};
+
+/// Seconds since the Unix epoch for 2000-01-01 and 2100-01-01; integers in that range (in any of the
+/// usual resolutions) are almost certainly timestamps.
+pub const PLAUSIBLE_EPOCH: Range<u64> = 946_684_800..4_102_444_800;
+
+/// Formats a byte count with binary units, e.g. `4.2 KiB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else if value.fract() == 0.0 {
+        format!("{} {}", value, UNITS[unit])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Formats a point in time as RFC 3339 in UTC, with as many fractional digits as needed.
+pub fn format_timestamp(seconds: i64, nanos: u32) -> String {
+    let days = seconds.div_euclid(86_400);
+    let secs_of_day = seconds.rem_euclid(86_400);
+
+    // Civil date from days since the epoch, per Howard Hinnant's algorithm.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}Z",
+        year, month, day, secs_of_day / 3600, secs_of_day / 60 % 60, secs_of_day % 60, format_fraction(nanos)
+    )
+}
+
+/// Formats a span of time compactly, e.g. `2m3s` or `1h0m0.5s`.
+pub fn format_duration(seconds: u64, nanos: u32) -> String {
+    let (hours, minutes, secs) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    let mut out = String::new();
+    if hours > 0 {
+        out += &format!("{}h", hours);
+    }
+    if hours > 0 || minutes > 0 {
+        out += &format!("{}m", minutes);
+    }
+    out.push_str(&format!("{}{}s", secs, format_fraction(nanos)));
+    out
+}
+
+fn format_fraction(nanos: u32) -> String {
+    if nanos == 0 {
+        return String::new();
+    }
+    let digits = format!("{:09}", nanos);
+    format!(".{}", digits.trim_end_matches('0'))
+}
+
+/// Annotates a varint that looks like a timestamp, a byte size or an enum value.
+pub fn annotate_varint(v: u64) -> Option<String> {
+    annotate_integer(v).or_else(|| if v < 16 { Some("enum?".to_string()) } else { None })
+}
+
+/// Annotates a fixed-width integer that looks like a timestamp or a byte size.
+pub fn annotate_integer(v: u64) -> Option<String> {
+    for scale in [1, 1_000, 1_000_000, 1_000_000_000] {
+        if PLAUSIBLE_EPOCH.contains(&(v / scale)) {
+            let nanos = (v % scale) * (1_000_000_000 / scale);
+            return Some(format_timestamp((v / scale) as i64, nanos as u32));
+        }
+    }
+    if v >= 1024 && v.is_multiple_of(1024) {
+        return Some(format_size(v));
+    }
+    None
+}
+
+/// Annotates length-prefixed data that is big enough for its size to matter.
+pub fn annotate_length(len: usize) -> Option<String> {
+    if len >= 1024 {
+        Some(format_size(len as u64))
+    } else {
+        None
+    }
+}
+
+/// Annotates a submessage shaped like a duration: seconds in field 1 that are too small to be a
+/// timestamp, and nanoseconds in field 2. Both are required, since a lone small integer in field 1
+/// is far too common to mean anything.
+pub fn annotate_message(fields: &[ProtoField<Span>]) -> Option<String> {
+    match fields {
+        [ProtoField{tag_number: 1, value: WireValue::VarInt(seconds), ..}, ProtoField{tag_number: 2, value: WireValue::VarInt(nanos), ..}]
+            if *seconds < PLAUSIBLE_EPOCH.start && *nanos < 1_000_000_000 =>
+            Some(format_duration(*seconds, *nanos as u32)),
+        _ => None,
+    }
+}
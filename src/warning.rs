@@ -0,0 +1,86 @@
+//! Warnings about heuristic fallbacks and anomalies in the input.
+//!
+//! Warnings go to stderr, separate from the decode on stdout, either as plain text or as one JSON
+//! object per line, so that automation can tell "decoded, but suspicious" apart from a clean decode.
+
+use crate::json;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum WarningKind {
+    /// A field number of 0 or above the maximum, accepted in lenient mode.
+    InvalidFieldNumber,
+    /// A field number in the range reserved for the protobuf implementation.
+    ReservedFieldNumber,
+    /// Length-prefixed data was neither a submessage nor a string, and is shown as raw data.
+    StringGuessFailed,
+    /// Length-prefixed data was shown as a string despite containing invalid UTF-8.
+    LossyString,
+    /// A group was never ended, or ended without being started, accepted in lenient mode.
+    UnbalancedGroup,
+    /// Bytes that didn't parse were skipped to carry on decoding after them, in lenient mode.
+    Resynced,
+    /// A varint was encoded in more bytes than it needs, with `--warn-overlong`.
+    OverlongVarint,
+    /// Length-prefixed data nested deeper than `--max-depth` was shown as raw data.
+    DepthLimit,
+}
+
+impl WarningKind {
+    /// A stable identifier for the warning, used in JSON output.
+    pub fn code(self) -> &'static str {
+        match self {
+            WarningKind::InvalidFieldNumber => "invalid-field-number",
+            WarningKind::ReservedFieldNumber => "reserved-field-number",
+            WarningKind::StringGuessFailed => "string-guess-failed",
+            WarningKind::LossyString => "lossy-string",
+            WarningKind::UnbalancedGroup => "unbalanced-group",
+            WarningKind::Resynced => "resynced",
+            WarningKind::OverlongVarint => "overlong-varint",
+            WarningKind::DepthLimit => "depth-limit",
+        }
+    }
+}
+
+pub struct Warnings {
+    json: bool,
+    input: String,
+    count: usize,
+}
+
+impl Warnings {
+    pub fn new(json: bool) -> Self {
+        Warnings{json, input: String::new(), count: 0}
+    }
+
+    /// Sets the name of the input that subsequent warnings refer to.
+    pub fn set_input(&mut self, input: &str) {
+        self.input = input.to_string();
+    }
+
+    /// The name of the input that warnings refer to now.
+    pub fn input(&self) -> &str {
+        &self.input
+    }
+
+    /// The number of warnings emitted so far.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Emits a warning about the field at `path`, given as tag numbers from the top-level message, or
+    /// about the input as a whole if `path` is empty.
+    pub fn emit(&mut self, kind: WarningKind, path: &[u64], message: &str) {
+        self.count += 1;
+        let path = path.iter().map(u64::to_string).collect::<Vec<_>>().join(".");
+        if self.json {
+            eprintln!(
+                "{{\"warning\":\"{}\",\"input\":{},\"path\":\"{}\",\"message\":{}}}",
+                kind.code(), json::quote(&self.input), path, json::quote(message)
+            );
+        } else if path.is_empty() {
+            eprintln!("warning: {}: {}", self.input, message);
+        } else {
+            eprintln!("warning: {}: {}: {}", self.input, path, message);
+        }
+    }
+}
@@ -0,0 +1,28 @@
+//! The library built for the browser with the `wasm` feature: `decode` gives the fields of a message
+//! as JSON, for a JavaScript wire-format inspector to show.
+//!
+//! The JSON is a message's `Tree`, except that integers above 2^53, which JavaScript numbers can't
+//! hold exactly, are strings.
+
+use wasm_bindgen::prelude::*;
+
+use crate::{parse, to_json_value};
+
+/// Decodes a message into JSON describing its fields, or throws an error giving where it doesn't
+/// parse.
+#[wasm_bindgen]
+pub fn decode(bytes: &[u8]) -> Result<String, JsError> {
+    let fields = parse(bytes).map_err(|err| JsError::new(&err.to_string()))?;
+    let mut json = to_json_value(&fields);
+    stringify_large_integers(&mut json);
+    Ok(json.to_string())
+}
+
+fn stringify_large_integers(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Number(n) if n.as_u64().is_some_and(|n| n > 1 << 53) => *value = serde_json::Value::String(n.to_string()),
+        serde_json::Value::Array(values) => values.iter_mut().for_each(stringify_large_integers),
+        serde_json::Value::Object(map) => map.values_mut().for_each(stringify_large_integers),
+        _ => {}
+    }
+}
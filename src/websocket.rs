@@ -0,0 +1,163 @@
+//! Decoding of captured WebSocket traffic, whose messages are often protobuf.
+//!
+//! The input is the raw byte stream of one connection, optionally starting with the HTTP upgrade
+//! handshake. Frames are unmasked, fragmented messages are reassembled, and messages compressed
+//! with permessage-deflate are inflated.
+
+use std::convert::{TryFrom, TryInto};
+use std::io::Write;
+
+use flate2::{Decompress, FlushDecompress};
+
+use crate::warning::Warnings;
+use crate::{parse_message, print_message_indented, Options};
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+struct Frame<'a> {
+    fin: bool,
+    compressed: bool,
+    opcode: u8,
+    masked: bool,
+    payload: Vec<u8>,
+    rest: &'a [u8],
+}
+
+fn parse_frame(data: &[u8]) -> Result<Frame<'_>, String> {
+    let truncated = || "truncated frame".to_string();
+    let header = data.get(..2).ok_or_else(truncated)?;
+    let (fin, compressed, opcode) = (header[0] & 0x80 != 0, header[0] & 0x40 != 0, header[0] & 0x0F);
+    let masked = header[1] & 0x80 != 0;
+    let (len, mut pos) = match header[1] & 0x7F {
+        126 => (u16::from_be_bytes(data.get(2..4).ok_or_else(truncated)?.try_into().unwrap()) as u64, 4),
+        127 => (u64::from_be_bytes(data.get(2..10).ok_or_else(truncated)?.try_into().unwrap()), 10),
+        len => (len as u64, 2),
+    };
+    let mask = if masked {
+        let mask: [u8; 4] = data.get(pos..pos + 4).ok_or_else(truncated)?.try_into().unwrap();
+        pos += 4;
+        Some(mask)
+    } else {
+        None
+    };
+    let end = usize::try_from(len).ok().and_then(|len| pos.checked_add(len)).filter(|&end| end <= data.len()).ok_or_else(truncated)?;
+    let mut payload = data[pos..end].to_vec();
+    if let Some(mask) = mask {
+        for (i, b) in payload.iter_mut().enumerate() {
+            *b ^= mask[i % 4];
+        }
+    }
+    Ok(Frame{fin, compressed, opcode, masked, payload, rest: &data[end..]})
+}
+
+/// Skips an HTTP upgrade request and/or response at the start of a capture.
+fn skip_handshake(mut data: &[u8]) -> &[u8] {
+    while data.starts_with(b"GET ") || data.starts_with(b"HTTP/1.1 101") {
+        match data.windows(4).position(|w| w == b"\r\n\r\n") {
+            Some(end) => data = &data[end + 4..],
+            None => break,
+        }
+    }
+    data
+}
+
+/// Inflates a permessage-deflate message, keeping the window between messages as the extension
+/// allows by default.
+fn inflate(decompress: &mut Decompress, payload: &[u8]) -> Result<Vec<u8>, String> {
+    let mut input = payload.to_vec();
+    input.extend_from_slice(&[0x00, 0x00, 0xFF, 0xFF]);
+    let mut out = Vec::new();
+    let mut consumed = 0;
+    loop {
+        out.reserve(out.len().max(4096));
+        let (in_before, out_before) = (decompress.total_in(), decompress.total_out());
+        decompress.decompress_vec(&input[consumed..], &mut out, FlushDecompress::Sync)
+            .map_err(|err| format!("bad deflate data: {}", err))?;
+        consumed += (decompress.total_in() - in_before) as usize;
+        // Done once all input is in and the output wasn't cut short by running out of room.
+        let stuck = decompress.total_in() == in_before && decompress.total_out() == out_before;
+        if (consumed == input.len() && out.len() < out.capacity()) || stuck {
+            break;
+        }
+    }
+    Ok(out)
+}
+
+/// Prints each message in a WebSocket capture.
+pub fn run(out: &mut dyn Write, data: &[u8], options: &Options, warnings: &mut Warnings) -> Result<(), String> {
+    let mut data = skip_handshake(data);
+    // Client and server compress independently, and their frames are told apart by masking.
+    let mut client_inflater = Decompress::new(false);
+    let mut server_inflater = Decompress::new(false);
+    // The message being reassembled: opcode, whether it's compressed, whether it's the client's,
+    // and the payload so far.
+    let mut partial: Option<(u8, bool, bool, Vec<u8>)> = None;
+    let mut count = 0;
+
+    while !data.is_empty() {
+        let frame = parse_frame(data)?;
+        data = frame.rest;
+        let sender = if frame.masked { "client" } else { "server" };
+        match frame.opcode {
+            OPCODE_CLOSE => {
+                match frame.payload.get(..2) {
+                    Some(code) => outln!(
+                        out,
+                        "close ({}): {} {}", sender, u16::from_be_bytes([code[0], code[1]]), String::from_utf8_lossy(&frame.payload[2..])
+                    ),
+                    None => outln!(out, "close ({})", sender),
+                }
+                continue;
+            }
+            OPCODE_PING | OPCODE_PONG => {
+                let kind = if frame.opcode == OPCODE_PING { "ping" } else { "pong" };
+                outln!(out, "{} ({}, {} byte{})", kind, sender, frame.payload.len(), crate::plural(frame.payload.len()));
+                continue;
+            }
+            OPCODE_TEXT | OPCODE_BINARY => {
+                if partial.is_some() {
+                    return Err("new message started before the last one finished".to_string());
+                }
+                partial = Some((frame.opcode, frame.compressed, frame.masked, frame.payload));
+            }
+            OPCODE_CONTINUATION => match &mut partial {
+                Some((_, _, _, payload)) => payload.extend_from_slice(&frame.payload),
+                None => return Err("continuation frame without a message to continue".to_string()),
+            },
+            opcode => return Err(format!("unknown opcode {}", opcode)),
+        }
+        if !frame.fin {
+            continue;
+        }
+
+        let (opcode, compressed, masked, payload) = partial.take().unwrap();
+        let payload = if compressed {
+            inflate(if masked { &mut client_inflater } else { &mut server_inflater }, &payload)?
+        } else {
+            payload
+        };
+        count += 1;
+        let sender = if masked { "client" } else { "server" };
+        let kind = if opcode == OPCODE_TEXT { "text" } else { "binary" };
+        match parse_message(&payload, options.lenient) {
+            Ok(fields) if opcode == OPCODE_BINARY => {
+                outln!(out, "message {} ({}, {}, {} byte{}): {{", count, kind, sender, payload.len(), crate::plural(payload.len()));
+                print_message_indented(out, fields, 1, options, &[], warnings);
+                outln!(out, "}}");
+            }
+            _ if opcode == OPCODE_TEXT => {
+                outln!(out, "message {} ({}, {}): {}", count, kind, sender, String::from_utf8_lossy(&payload));
+            }
+            _ => outln!(out, "message {} ({}, {}): {}", count, kind, sender, options.bytes_format.format(&payload)),
+        }
+    }
+    if partial.is_some() {
+        eprintln!("protocat: capture ends partway through a message");
+    }
+    Ok(())
+}
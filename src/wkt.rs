@@ -0,0 +1,64 @@
+//! Recognizing well-known types by their shape, for `--wkt-heuristics`: submessages whose fields
+//! fit `google.protobuf.Timestamp` or `Duration` are shown with the time they hold, and those that
+//! fit `google.protobuf.Any` with their type URL.
+
+use nom::AsBytes;
+
+use crate::units::{format_duration, format_timestamp, PLAUSIBLE_EPOCH};
+use crate::{ProtoField, Span, WireValue};
+
+/// The range of a Duration's seconds, about 10,000 years either way.
+const MAX_DURATION_SECONDS: i64 = 315_576_000_000;
+
+/// Names the well-known type a submessage looks like, with its value, e.g.
+/// `google.protobuf.Timestamp: 2024-05-01T12:00:00Z`. Seconds in the years 2000 to 2100 make a
+/// timestamp; fewer make a duration, which must have its nanoseconds too, since a lone small integer
+/// in field 1 is far too common to mean anything.
+pub fn recognize(fields: &[ProtoField<Span>]) -> Option<String> {
+    if let Some((type_url, _)) = any(fields) {
+        return Some(format!("google.protobuf.Any: {}", type_url));
+    }
+    let (seconds, nanos) = match fields {
+        [ProtoField{tag_number: 1, value: WireValue::VarInt(seconds), ..}] => (*seconds, None),
+        [ProtoField{tag_number: 1, value: WireValue::VarInt(seconds), ..}, ProtoField{tag_number: 2, value: WireValue::VarInt(nanos), ..}] =>
+            (*seconds, Some(*nanos as i64)),
+        _ => return None,
+    };
+    // Nanoseconds are an int32, so negative ones are sign-extended to ten bytes.
+    if nanos.is_some_and(|nanos| nanos.abs() >= 1_000_000_000) {
+        return None;
+    }
+    if PLAUSIBLE_EPOCH.contains(&seconds) && nanos.unwrap_or(0) >= 0 {
+        let timestamp = format_timestamp(seconds as i64, nanos.unwrap_or(0) as u32);
+        return Some(format!("google.protobuf.Timestamp: {}", timestamp));
+    }
+    // A Duration's seconds and nanoseconds have the same sign.
+    let (seconds, nanos) = (seconds as i64, nanos?);
+    if seconds.abs() > MAX_DURATION_SECONDS || (seconds < 0 && nanos > 0) || (seconds > 0 && nanos < 0) {
+        return None;
+    }
+    let sign = if seconds < 0 || nanos < 0 { "-" } else { "" };
+    Some(format!("google.protobuf.Duration: {}{}", sign, format_duration(seconds.unsigned_abs(), nanos.unsigned_abs() as u32)))
+}
+
+/// The type URL and value of a submessage shaped like a `google.protobuf.Any`: a URL in field 1
+/// ending in a qualified type name, and the message in field 2 unless it is empty.
+pub fn any<'a>(fields: &[ProtoField<Span<'a>>]) -> Option<(String, Span<'a>)> {
+    let (type_url, value) = match fields {
+        [ProtoField{tag_number: 1, value: WireValue::LengthPrefixed(type_url), ..}] => (type_url, Span::new(&[])),
+        [ProtoField{tag_number: 1, value: WireValue::LengthPrefixed(type_url), ..}, ProtoField{tag_number: 2, value: WireValue::LengthPrefixed(value), ..}] =>
+            (type_url, *value),
+        _ => return None,
+    };
+    let type_url = std::str::from_utf8(type_url.as_bytes()).ok()?;
+    let name = type_url.contains('/').then(|| type_name(type_url))?;
+    let qualified = name.split('.').count() > 1
+        && name.split('.').all(|part| part.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'));
+    qualified.then(|| (type_url.to_string(), value))
+}
+
+/// The name of the type a type URL refers to, after its last slash.
+pub fn type_name(type_url: &str) -> &str {
+    type_url.rsplit('/').next().unwrap_or(type_url)
+}
@@ -0,0 +1,97 @@
+//! The small subset of YAML that manifests and the config file are written in: top-level keys, each
+//! holding a list whose items are scalars or flat mappings of scalars. Comments and quoted scalars
+//! are understood; nothing else is.
+
+/// A list item: a bare scalar, or `key: value` pairs each with the line they're on.
+pub enum Item {
+    Scalar(String),
+    Mapping(Vec<Pair>),
+}
+
+pub struct Pair {
+    pub line: usize,
+    pub key: String,
+    pub value: String,
+}
+
+/// Parses a document into its top-level lists, in order. A list that isn't under a key, as when the
+/// document is just a list, comes back under the empty key.
+pub fn lists(text: &str) -> Result<Vec<(String, Vec<Item>)>, String> {
+    let mut lists: Vec<(String, Vec<Item>)> = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let error = |message: &str| format!("line {}: {}", i + 1, message);
+        let line = strip_comment(line).trim_end();
+        let content = line.trim_start();
+        if content.is_empty() {
+            continue;
+        }
+        if content.len() == line.len() && !content.starts_with('-') {
+            match key_value(content) {
+                Some((key, "")) => {
+                    lists.push((key.to_string(), Vec::new()));
+                    continue;
+                }
+                _ => return Err(error("expected a key holding a list")),
+            }
+        }
+        if lists.is_empty() {
+            lists.push((String::new(), Vec::new()));
+        }
+        let (_, items) = lists.last_mut().expect("a list was just added");
+        let body = match content.strip_prefix('-') {
+            Some(rest) if rest.is_empty() || rest.starts_with(' ') => {
+                let rest = rest.trim();
+                if key_value(rest).is_none() {
+                    items.push(Item::Scalar(scalar(rest)));
+                    continue;
+                }
+                items.push(Item::Mapping(Vec::new()));
+                rest
+            }
+            _ => content,
+        };
+        let (key, value) = key_value(body).ok_or_else(|| error("expected key: value"))?;
+        match items.last_mut() {
+            Some(Item::Mapping(pairs)) => pairs.push(Pair{line: i + 1, key: key.to_string(), value: scalar(value)}),
+            _ => return Err(error("expected a list item")),
+        }
+    }
+    Ok(lists)
+}
+
+/// Splits `key: value`, where the key is a plain word; anything else is a scalar.
+fn key_value(text: &str) -> Option<(&str, &str)> {
+    let colon = text.find(':')?;
+    let (key, rest) = (&text[..colon], &text[colon + 1..]);
+    let plain = !key.is_empty() && key.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+    (plain && (rest.is_empty() || rest.starts_with(' '))).then(|| (key, rest.trim()))
+}
+
+/// A scalar with its quotes, if any, removed.
+fn scalar(text: &str) -> String {
+    let quoted = |quote: char| text.len() >= 2 && text.starts_with(quote) && text.ends_with(quote);
+    if quoted('\'') {
+        text[1..text.len() - 1].replace("''", "'")
+    } else if quoted('"') {
+        text[1..text.len() - 1].replace("\\\"", "\"").replace("\\\\", "\\")
+    } else {
+        text.to_string()
+    }
+}
+
+/// Drops a `#` comment, which has to start the line or follow whitespace and be outside quotes.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut previous = ' ';
+    for (i, c) in line.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None if c == '#' && previous.is_whitespace() => return &line[..i],
+            None => {}
+        }
+        previous = c;
+    }
+    line
+}
@@ -0,0 +1,40 @@
+//! Output to a reader that stops early, like `head`, which ends the run quietly rather than with a
+//! panic.
+
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, Stdio};
+
+/// Runs protocat on a file holding `input`, reads one line of its output and closes the pipe,
+/// returning its exit code and warnings.
+fn protocat_head(args: &[&str], name: &str, input: &[u8]) -> (Option<i32>, String) {
+    let path = std::env::temp_dir().join(format!("protocat-broken-pipe-{}-{}", std::process::id(), name));
+    std::fs::File::create(&path).and_then(|mut file| file.write_all(input)).expect("input is written");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_protocat"))
+        .args(args)
+        .arg(&path)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("protocat runs");
+    let mut line = String::new();
+    BufReader::new(child.stdout.take().expect("stdout is piped")).read_line(&mut line).expect("a line is read");
+    let output = child.wait_with_output().expect("protocat finishes");
+    std::fs::remove_file(&path).ok();
+    (output.status.code(), String::from_utf8(output.stderr).expect("warnings are UTF-8"))
+}
+
+#[test]
+fn http() {
+    let request = b"POST /x HTTP/1.1\r\nContent-Type: application/x-protobuf\r\nContent-Length: 5\r\n\r\n\x0a\x03abc";
+    let (code, stderr) = protocat_head(&["http"], "http", &request.repeat(20000));
+    assert_eq!(code, Some(0), "{}", stderr);
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+}
+
+#[test]
+fn follow() {
+    // `1: 1`, delimited.
+    let (code, stderr) = protocat_head(&["--follow"], "follow", &b"\x02\x08\x01".repeat(100000));
+    assert_eq!(code, Some(0), "{}", stderr);
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+}
@@ -0,0 +1,47 @@
+//! Compressed gRPC frames, which aren't decoded in any output mode rather than being read as plain
+//! protobuf.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Runs protocat on `input`, returning its exit code and output.
+fn protocat(args: &[&str], input: &[u8]) -> (Option<i32>, String, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_protocat"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("protocat runs");
+    child.stdin.take().expect("stdin is piped").write_all(input).expect("input is written");
+    let output = child.wait_with_output().expect("protocat finishes");
+    let stdout = String::from_utf8(output.stdout).expect("output is UTF-8");
+    let stderr = String::from_utf8(output.stderr).expect("warnings are UTF-8");
+    (output.status.code(), stdout, stderr)
+}
+
+/// `1: 1`, then a frame flagged as compressed holding `1: "A"`, which would decode if it were read
+/// as it stands.
+const FRAMES: &[u8] = b"\x00\x00\x00\x00\x02\x08\x01\x01\x00\x00\x00\x03\x0a\x01\x41";
+
+#[test]
+fn json() {
+    let (code, stdout, stderr) = protocat(&["--grpc", "--format", "json"], FRAMES);
+    assert_eq!(code, Some(0), "{}", stderr);
+    assert_eq!(stdout, "{\n  \"1\": 1\n}\n\"CgFB\"\n");
+}
+
+#[test]
+fn query() {
+    let (code, stdout, stderr) = protocat(&["--grpc", "--query", ".1"], FRAMES);
+    assert_eq!(code, Some(0), "{}", stderr);
+    assert_eq!(stdout, "1\n");
+    assert!(stderr.contains("message 1 is compressed, not decoded"), "{}", stderr);
+}
+
+#[test]
+fn extract() {
+    let (code, stdout, stderr) = protocat(&["--grpc", "--extract", "1"], FRAMES);
+    assert_eq!(code, Some(0), "{}", stderr);
+    assert_eq!(stdout, "\x01");
+}
@@ -0,0 +1,131 @@
+//! Inputs nested thousands of levels deep, which every command has to get through without
+//! overflowing the stack or taking quadratic time, stopping at `--max-depth`.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Runs protocat on `input`, returning its exit code and output.
+fn protocat(args: &[&str], input: &[u8]) -> (Option<i32>, String, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_protocat"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("protocat runs");
+    child.stdin.take().expect("stdin is piped").write_all(input).expect("input is written");
+    let output = child.wait_with_output().expect("protocat finishes");
+    let stdout = String::from_utf8(output.stdout).expect("output is UTF-8");
+    let stderr = String::from_utf8(output.stderr).expect("warnings are UTF-8");
+    (output.status.code(), stdout, stderr)
+}
+
+/// Field 1 as a group, nested `depth` deep.
+fn nested_groups(depth: usize) -> Vec<u8> {
+    [vec![0x0b; depth], vec![0x0c; depth]].concat()
+}
+
+/// Field 1 as a submessage, nested `depth` deep around `1: 1`.
+fn nested_messages(depth: usize) -> Vec<u8> {
+    let mut message = vec![0x08, 0x01];
+    for _ in 0..depth {
+        let mut len = message.len();
+        let mut field = vec![0x0a];
+        while len >= 0x80 {
+            field.push(len as u8 | 0x80);
+            len >>= 7;
+        }
+        field.push(len as u8);
+        field.extend(message);
+        message = field;
+    }
+    message
+}
+
+#[test]
+fn infer_schema_nested_groups() {
+    let (code, stdout, stderr) = protocat(&["infer-schema", "--max-depth", "2"], &nested_groups(4000));
+    assert_eq!(code, Some(0), "{}", stderr);
+    assert_eq!(stdout.matches("optional group Field1 = 1").count(), 3);
+    assert!(stderr.contains("nested deeper than --max-depth 2"), "{}", stderr);
+}
+
+#[test]
+fn stats_nested_groups() {
+    let (code, stdout, stderr) = protocat(&["stats", "--max-depth", "2"], &nested_groups(4000));
+    assert_eq!(code, Some(0), "{}", stderr);
+    assert!(stdout.starts_with("1 input, 7.8 KiB, 3 fields, max depth 2\n"), "{}", stdout);
+    assert!(stderr.contains(": 1.1.1: nested deeper than --max-depth 2"), "{}", stderr);
+}
+
+#[test]
+fn stats_submessage_heuristic() {
+    let (code, stdout, stderr) = protocat(&["stats", "--no-submessage-heuristic"], &nested_messages(2));
+    assert_eq!(code, Some(0), "{}", stderr);
+    assert!(stdout.starts_with("1 input, 6 B, 1 field, max depth 0\n"), "{}", stdout);
+}
+
+#[test]
+fn diff_nested_groups() {
+    let path = std::env::temp_dir().join(format!("protocat-deep-nesting-{}", std::process::id()));
+    std::fs::write(&path, nested_groups(20000)).expect("input is written");
+    let path = path.to_str().expect("the path is UTF-8");
+    let (code, stdout, stderr) = protocat(&["diff", path, path], b"");
+    std::fs::remove_file(path).ok();
+    assert_eq!(code, Some(0), "{}", stderr);
+    assert_eq!(stdout, "");
+}
+
+/// `1: {2: gzip({3: bytes that aren't text})}`.
+const COMPRESSED: &[u8] = b"\x0a\x1c\x12\x1a\x1f\x8b\x08\x00\x00\x00\x00\x00\x02\x03\x93\x62\xf9\xff\x8f\xa1\x01\x00\x95\xfd\xe8\x6a\x06\x00\x00\x00";
+
+#[test]
+fn compressed_payload_path() {
+    let (code, stdout, stderr) = protocat(&["--decompress"], COMPRESSED);
+    assert_eq!(code, Some(0), "{}", stderr);
+    assert!(stdout.contains("    3: [ff, fe, 0, 80]"), "{}", stdout);
+    assert!(stderr.contains(": 1.2.3: not a submessage or text"), "{}", stderr);
+}
+
+#[test]
+fn compressed_payload_max_depth() {
+    let (code, stdout, stderr) = protocat(&["--decompress", "--max-depth", "1"], COMPRESSED);
+    assert_eq!(code, Some(0), "{}", stderr);
+    assert!(!stdout.contains("decompressed"), "{}", stdout);
+}
+
+#[test]
+fn query_nested_messages() {
+    let (code, stdout, stderr) = protocat(&["--query", ".", "--format", "json", "--max-depth", "3"], &nested_messages(20000));
+    assert_eq!(code, Some(0), "{}", stderr);
+    assert!(stdout.starts_with("{\"1\":{\"1\":{\"1\":{\"1\":\""), "{}", stdout);
+    assert!(stderr.contains(": 1.1.1.1: nested deeper than --max-depth 3"), "{}", stderr);
+}
+
+#[test]
+fn query_nested_groups() {
+    let (code, stdout, stderr) = protocat(&["--query", ".1.1", "--format", "json", "--max-depth", "3"], &nested_groups(20000));
+    assert_eq!(code, Some(0), "{}", stderr);
+    assert!(stdout.starts_with("{\"1\":{\"1\":\""), "{}", stdout);
+    assert!(stderr.contains(": 1.1.1.1: nested deeper than --max-depth 3"), "{}", stderr);
+}
+
+#[test]
+fn query_interprets_only_selected_fields() {
+    // 1: bytes that aren't text, 2: 5
+    let (code, stdout, stderr) = protocat(&["--query", ".2"], b"\x0a\x04\xff\xfe\x00\x80\x10\x05");
+    assert_eq!(code, Some(0), "{}", stderr);
+    assert_eq!(stdout, "5\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn decode_raw_nested_groups() {
+    let (code, stdout, stderr) = protocat(&["--format", "decode-raw"], &nested_groups(100));
+    assert_eq!(code, Some(0), "{}", stderr);
+    assert_eq!(stdout.lines().count(), 200);
+    // protoc stops reading groups past its recursion limit of 100.
+    let (code, _, stderr) = protocat(&["--format", "decode-raw"], &nested_groups(4000));
+    assert_eq!(code, Some(1));
+    assert!(stderr.contains("protoc --decode_raw would fail to parse this input"), "{}", stderr);
+}
@@ -0,0 +1,47 @@
+//! `--deterministic`: the same input and options give the same bytes, run after run, on the paths
+//! where a hash map's order or a clock could get in.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Runs protocat on `input`, returning its exit code and output.
+fn protocat(args: &[&str], input: &[u8]) -> (Option<i32>, Vec<u8>, Vec<u8>) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_protocat"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("protocat runs");
+    child.stdin.take().expect("stdin is piped").write_all(input).expect("input is written");
+    let output = child.wait_with_output().expect("protocat finishes");
+    (output.status.code(), output.stdout, output.stderr)
+}
+
+/// Runs protocat on `input` a few times, checking that every run prints the same as the first.
+fn assert_stable(args: &[&str], input: &[u8]) {
+    let first = protocat(args, input);
+    assert_eq!(first.0, Some(0), "{}", String::from_utf8_lossy(&first.2));
+    for _ in 0..4 {
+        assert!(protocat(args, input) == first, "{:?} printed something else on another run", args);
+    }
+}
+
+/// Fields of several kinds, nested, which many of the bundled schemas' types fit about as well.
+const MESSAGE: &[u8] = b"\x0a\x0b\x0a\x03abc\x10\x01\x1a\x02\x08\x02\x10\x05\x1d\x00\x00\x80\x3f\x22\x04\x0a\x02\x08\x03";
+
+#[test]
+fn summary() {
+    assert_stable(&["--deterministic", "--summary"], MESSAGE);
+}
+
+#[test]
+fn infer_schema() {
+    assert_stable(&["infer-schema", "--deterministic", "--delimited", "-"], &[&[MESSAGE.len() as u8], MESSAGE, &[MESSAGE.len() as u8], MESSAGE].concat());
+}
+
+#[test]
+fn schema_fit() {
+    assert_stable(&["--deterministic", "--builtin", "otlp"], MESSAGE);
+    assert_stable(&["--deterministic", "--builtin", "descriptor", "--format", "json"], MESSAGE);
+}
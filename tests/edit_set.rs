@@ -0,0 +1,36 @@
+//! `edit --set` on fields that aren't there yet, which are added along with the submessages they
+//! go in.
+
+use std::process::Command;
+
+/// Runs `protocat edit` on a file holding `input`, returning its exit code and output.
+fn edit(args: &[&str], name: &str, input: &[u8]) -> (Option<i32>, Vec<u8>, String) {
+    let path = std::env::temp_dir().join(format!("protocat-edit-set-{}-{}", std::process::id(), name));
+    std::fs::write(&path, input).expect("input is written");
+    let output = Command::new(env!("CARGO_BIN_EXE_protocat")).arg("edit").args(args).arg(&path).output().expect("protocat runs");
+    std::fs::remove_file(&path).ok();
+    (output.status.code(), output.stdout, String::from_utf8(output.stderr).expect("warnings are UTF-8"))
+}
+
+#[test]
+fn missing_submessage() {
+    // `1: 1`, given `7: {1: 1}`.
+    let (code, stdout, stderr) = edit(&["--set", "7.1=1"], "missing", b"\x08\x01");
+    assert_eq!(code, Some(0), "{}", stderr);
+    assert_eq!(stdout, b"\x08\x01\x3a\x02\x08\x01");
+}
+
+#[test]
+fn missing_submessages_shared() {
+    // Sets in the same missing submessage go in one, where the last set of each field is.
+    let (code, stdout, stderr) = edit(&["--set", "7.1=1", "--set", "7.2.3=2", "--set", "7.1=4"], "shared", b"\x08\x01");
+    assert_eq!(code, Some(0), "{}", stderr);
+    assert_eq!(stdout, b"\x08\x01\x3a\x06\x12\x02\x18\x02\x08\x04");
+}
+
+#[test]
+fn not_a_submessage() {
+    let (code, _, stderr) = edit(&["--set", "1.1=1"], "scalar", b"\x08\x01");
+    assert_eq!(code, Some(1), "{}", stderr);
+    assert!(stderr.contains("1 isn't a submessage"), "{}", stderr);
+}
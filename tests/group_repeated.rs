@@ -0,0 +1,82 @@
+//! `--group-repeated`, which gathers a field's values into one list where it first occurs, taking a
+//! repeated group's fields between its start and end tags along with it.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+fn protocat(args: &[&str], input: &[u8]) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_protocat"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("protocat runs");
+    child.stdin.take().expect("stdin is piped").write_all(input).expect("input is written");
+    let output = child.wait_with_output().expect("protocat finishes");
+    assert!(output.status.code().is_some(), "protocat was killed: {:?}", output.status);
+    String::from_utf8(output.stdout).expect("output is UTF-8")
+}
+
+#[test]
+fn repeated_fields() {
+    // 1: 1, 2: {1: 7}, 1: 3, 2: "A"
+    let input = b"\x08\x01\x12\x02\x08\x07\x08\x03\x12\x01\x41";
+    let output = protocat(&["--group-repeated", "--show-offsets"], input);
+    assert_eq!(output, "\
+[0+8] 1: [
+  [0+2] 1
+  [6+2] 3
+]
+[2+9] 2: [
+  [2+4] {
+    [4+2] 1: 7
+  }
+  [8+3] \"A\"
+]
+");
+}
+
+#[test]
+fn repeated_groups() {
+    // 3: {1: 1}, 1: 5, 3: {1: 2}
+    let input = b"\x1b\x08\x01\x1c\x08\x05\x1b\x08\x02\x1c";
+    let output = protocat(&["--group-repeated", "--show-offsets"], input);
+    assert_eq!(output, "\
+[0+10] 3: [
+  [0+4] {
+    [1+2] 1: 1
+  }
+  [6+4] {
+    [7+2] 1: 2
+  }
+]
+[4+2] 1: 5
+");
+}
+
+#[test]
+fn repeated_unterminated_groups() {
+    // 3: {1: 1}, 1: 5, 3: {3: {1: 2}, 1: 3, 3: {1: 4}, with the last two groups never ended
+    let input = b"\x1b\x08\x01\x1c\x08\x05\x1b\x1b\x08\x02\x1c\x08\x03\x1b\x08\x04";
+    let output = protocat(&["--group-repeated", "--show-offsets", "--lenient"], input);
+    assert_eq!(output, "\
+[0+16] 3: [
+  [0+4] {
+    [1+2] 1: 1
+  }
+  [6+10] {
+    [7+9] 3: [
+      [7+4] {
+        [8+2] 1: 2
+      }
+      [13+3] {
+        [14+2] 1: 4
+      }
+    ]
+    [11+2] 1: 3
+  }
+]
+[4+2] 1: 5
+");
+}
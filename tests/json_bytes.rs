@@ -0,0 +1,40 @@
+//! Bytes in `--format json` output, which are base64, encoded back with `encode --from json`.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Runs protocat on `input`, returning its exit code and output.
+fn protocat(args: &[&str], input: &[u8]) -> (Option<i32>, Vec<u8>, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_protocat"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("protocat runs");
+    child.stdin.take().expect("stdin is piped").write_all(input).expect("input is written");
+    let output = child.wait_with_output().expect("protocat finishes");
+    let stderr = String::from_utf8(output.stderr).expect("warnings are UTF-8");
+    (output.status.code(), output.stdout, stderr)
+}
+
+/// `1: [ff, fe, 0, 80]`, a `google.protobuf.BytesValue`.
+const MESSAGE: &[u8] = b"\x0a\x04\xff\xfe\x00\x80";
+
+#[test]
+fn with_schema() {
+    let (code, json, stderr) = protocat(&["--format", "json"], MESSAGE);
+    assert_eq!(code, Some(0), "{}", stderr);
+    assert_eq!(json, b"{\n  \"1\": \"//4AgA==\"\n}\n");
+    let (code, encoded, stderr) = protocat(&["encode", "--from", "json", "--builtin", "wkt", "--type", "google.protobuf.BytesValue"], &json);
+    assert_eq!(code, Some(0), "{}", stderr);
+    assert_eq!(encoded, MESSAGE);
+}
+
+#[test]
+fn without_schema() {
+    // Without a schema there's nothing to say the string is base64, so it's encoded as text.
+    let (code, encoded, stderr) = protocat(&["encode", "--from", "json"], b"{\"1\": \"//4AgA==\"}");
+    assert_eq!(code, Some(0), "{}", stderr);
+    assert_eq!(encoded, b"\x0a\x08//4AgA==");
+}